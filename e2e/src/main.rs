@@ -0,0 +1,218 @@
+// End-to-end tests: golden serial transcripts
+// Each case in e2e/cases is a keystroke script (NAME.keys) and the console
+// output it must produce (NAME.out). The harness builds the kernel, boots it,
+// waits for the first prompt, plays the script step by step and compares
+// everything printed after boot with the golden file.
+//
+//   cargo e2e [--hosted] [--bless] [CASE...]
+//
+// --hosted runs the hosted build instead of QEMU; --bless rewrites the
+// golden files from the current output.
+
+mod script;
+mod session;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::Duration;
+
+use session::Session;
+
+const BOOT_TIMEOUT: Duration = Duration::from_secs(30);
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Options {
+    hosted: bool,
+    bless: bool,
+    cases: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        hosted: false,
+        bless: false,
+        cases: Vec::new(),
+    };
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--hosted" => options.hosted = true,
+            "--bless" => options.bless = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => options.cases.push(arg),
+        }
+    }
+    Ok(options)
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().to_path_buf()
+}
+
+fn host_tuple() -> Result<String, String> {
+    let output = Command::new("rustc")
+        .arg("-vV")
+        .output()
+        .map_err(|e| format!("cannot run rustc: {}", e))?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+        .ok_or_else(|| "rustc did not report the host".to_string())
+}
+
+/// Build the kernel (or the hosted binary) and return the path to it
+fn build(root: &Path, hosted: bool) -> Result<PathBuf, String> {
+    let target = if hosted { host_tuple()? } else { "aarch64-unknown-none".to_string() };
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut command = Command::new(cargo);
+    command.current_dir(root).args(["build", "-p", "jamos", "--target", &target]);
+    if hosted {
+        command.args(["--features", "hosted"]);
+    }
+    let status = command.status().map_err(|e| format!("cannot run cargo: {}", e))?;
+    if !status.success() {
+        return Err("kernel build failed".to_string());
+    }
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.join("target"));
+    Ok(target_dir.join(target).join("debug").join("jamos"))
+}
+
+/// Make a transcript diffable: drop carriage returns and show other control
+/// characters the way `cat -v` does (ESC becomes ^[); UTF-8 is kept as is
+fn printable(output: &[u8]) -> String {
+    let mut text = String::new();
+    for c in String::from_utf8_lossy(output).chars() {
+        match c {
+            '\r' => {}
+            '\n' | '\t' => text.push(c),
+            '\x00'..='\x1F' => {
+                text.push('^');
+                text.push((c as u8 + 0x40) as char);
+            }
+            '\x7F' => text.push_str("^?"),
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+fn run_case(binary: &Path, hosted: bool, keys: &Path) -> Result<String, String> {
+    let source = fs::read_to_string(keys).map_err(|e| format!("cannot read {}: {}", keys.display(), e))?;
+    let steps = script::parse(&source)?;
+
+    let mut session = if hosted { Session::hosted(binary)? } else { Session::qemu(binary)? };
+    // The boot banner differs between QEMU and the hosted build; only what
+    // follows the first prompt is compared
+    let boot = session.settle(BOOT_TIMEOUT)?;
+    // The bar's row is kept out of the way by drawing the desktop row by
+    // row, which leaves the blank after the prompt to an erase
+    if !printable(&boot).contains("]$") {
+        return Err("no shell prompt after boot".to_string());
+    }
+    // The status bar shows the time and free memory, which change from run
+    // to run; the transcripts are taken without it
+    session.send(b"statusbar off\r")?;
+    if !printable(&session.settle(STEP_TIMEOUT)?).ends_with("$ ") {
+        return Err("no shell prompt after turning the status bar off".to_string());
+    }
+
+    let mut transcript = String::new();
+    for step in steps {
+        session.send(&step)?;
+        transcript.push_str(&printable(&session.settle(STEP_TIMEOUT)?));
+    }
+    Ok(transcript)
+}
+
+/// Print the first line where the transcripts part ways
+fn report_difference(expected: &str, actual: &str) {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for number in 1.. {
+        let (e, a) = (expected_lines.next(), actual_lines.next());
+        if e != a {
+            println!("    first difference at line {}:", number);
+            println!("    - {}", e.unwrap_or("<end of transcript>"));
+            println!("    + {}", a.unwrap_or("<end of transcript>"));
+            return;
+        }
+        if e.is_none() {
+            // Same lines, different line endings at the very end
+            println!("    transcripts differ in trailing whitespace");
+            return;
+        }
+    }
+}
+
+fn run() -> Result<bool, String> {
+    let options = parse_args()?;
+    let root = workspace_root();
+    let cases_dir = root.join("e2e").join("cases");
+
+    let mut cases: Vec<String> = fs::read_dir(&cases_dir)
+        .map_err(|e| format!("cannot read {}: {}", cases_dir.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_suffix(".keys").map(str::to_string)
+        })
+        .filter(|name| options.cases.is_empty() || options.cases.contains(name))
+        .collect();
+    cases.sort();
+    if cases.is_empty() {
+        return Err("no matching test cases".to_string());
+    }
+
+    let binary = build(&root, options.hosted)?;
+    let actual_dir = binary.parent().unwrap().join("e2e");
+    let mut failed = 0;
+
+    println!("\nrunning {} e2e cases ({})", cases.len(), if options.hosted { "hosted" } else { "qemu" });
+    for name in &cases {
+        let golden = cases_dir.join(format!("{}.out", name));
+        let transcript = match run_case(&binary, options.hosted, &cases_dir.join(format!("{}.keys", name))) {
+            Ok(transcript) => transcript,
+            Err(e) => {
+                println!("e2e {} ... FAILED\n    {}", name, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        if options.bless {
+            fs::write(&golden, &transcript).map_err(|e| format!("cannot write {}: {}", golden.display(), e))?;
+            println!("e2e {} ... blessed", name);
+            continue;
+        }
+
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        if transcript == expected {
+            println!("e2e {} ... ok", name);
+        } else {
+            // Keep the actual output around for a full diff
+            let _ = fs::create_dir_all(&actual_dir);
+            let actual = actual_dir.join(format!("{}.out", name));
+            let _ = fs::write(&actual, &transcript);
+            println!("e2e {} ... FAILED (output in {})", name, actual.display());
+            report_difference(&expected, &transcript);
+            failed += 1;
+        }
+    }
+
+    println!("\ne2e result: {}. {} passed; {} failed", if failed == 0 { "ok" } else { "FAILED" }, cases.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("e2e: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
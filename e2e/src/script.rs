@@ -0,0 +1,129 @@
+// Keystroke scripts
+// One step per line: the line's bytes are sent in one go and the harness then
+// waits for the output to settle. Nothing is appended, so a command needs an
+// explicit <enter>. Lines starting with '#' and empty lines are skipped.
+//
+//   \r \n \t \e \\ \< \xNN   escapes
+//   <enter> <esc> <tab> <backspace> <up> <down> <left> <right>
+//   <ctrl-right> <ctrl-left> <shift-pageup> <shift-pagedown> <ctrl-a> ... <ctrl-z>
+//   <alt-shift-right> ... <ctrl-alt-right> ... <alt-x> <alt-|> (Alt and a printable character)
+
+pub fn parse(script: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut steps = Vec::new();
+    for (number, line) in script.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let step = parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn parse_line(line: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '\\' => {
+                let (escaped, len) = parse_escape(&rest[1..])?;
+                bytes.push(escaped);
+                rest = &rest[1 + len..];
+            }
+            '<' => {
+                let end = rest.find('>').ok_or("unterminated <key>")?;
+                bytes.extend(key_bytes(&rest[1..end])?);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode the escape after a backslash; returns the byte and the characters used
+fn parse_escape(s: &str) -> Result<(u8, usize), String> {
+    match s.chars().next() {
+        Some('r') => Ok((b'\r', 1)),
+        Some('n') => Ok((b'\n', 1)),
+        Some('t') => Ok((b'\t', 1)),
+        Some('e') => Ok((0x1B, 1)),
+        Some('\\') => Ok((b'\\', 1)),
+        Some('<') => Ok((b'<', 1)),
+        Some('x') => {
+            let hex = s.get(1..3).ok_or("\\x needs two hex digits")?;
+            let byte = u8::from_str_radix(hex, 16).map_err(|_| format!("bad hex escape \\x{}", hex))?;
+            Ok((byte, 3))
+        }
+        Some(c) => Err(format!("unknown escape \\{}", c)),
+        None => Err("trailing backslash".to_string()),
+    }
+}
+
+fn key_bytes(name: &str) -> Result<Vec<u8>, String> {
+    let bytes: &[u8] = match name {
+        "enter" => b"\r",
+        "esc" => b"\x1b",
+        "tab" => b"\t",
+        "backspace" => b"\x7f",
+        "up" => b"\x1b[A",
+        "down" => b"\x1b[B",
+        "right" => b"\x1b[C",
+        "left" => b"\x1b[D",
+        "home" => b"\x1b[H",
+        "end" => b"\x1b[F",
+        "delete" => b"\x1b[3~",
+        "ctrl-right" => b"\x1b[1;5C",
+        "ctrl-left" => b"\x1b[1;5D",
+        "shift-pageup" => b"\x1b[5;2~",
+        "shift-pagedown" => b"\x1b[6;2~",
+        "alt-shift-right" => b"\x1b[1;4C",
+        "alt-shift-left" => b"\x1b[1;4D",
+        "alt-shift-up" => b"\x1b[1;4A",
+        "alt-shift-down" => b"\x1b[1;4B",
+        "ctrl-alt-right" => b"\x1b[1;7C",
+        "ctrl-alt-left" => b"\x1b[1;7D",
+        "ctrl-alt-up" => b"\x1b[1;7A",
+        "ctrl-alt-down" => b"\x1b[1;7B",
+        "ctrl-meta-right" => b"\x1b[1;13C",
+        "ctrl-meta-left" => b"\x1b[1;13D",
+        "ctrl-meta-up" => b"\x1b[1;13A",
+        "ctrl-meta-down" => b"\x1b[1;13B",
+        _ => match (name.strip_prefix("ctrl-").map(str::as_bytes), name.strip_prefix("alt-").map(str::as_bytes)) {
+            (Some(&[letter]), _) if letter.is_ascii_lowercase() => return Ok(vec![letter & 0x1F]),
+            // Alt is ESC before the key
+            (_, Some(&[c])) if c.is_ascii_graphic() => return Ok(vec![0x1B, c]),
+            _ => return Err(format!("unknown key <{}>", name)),
+        },
+    };
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_keys_and_escapes() {
+        let steps = parse("ls<enter>\n<ctrl-right>\n<ctrl-s>\\e[A\\x7f\\<x>\n").unwrap();
+        assert_eq!(steps, [b"ls\r".to_vec(), b"\x1b[1;5C".to_vec(), b"\x13\x1b[A\x7f<x>".to_vec()]);
+        assert_eq!(parse("<alt-|><alt-shift-left>\n").unwrap(), [b"\x1b|\x1b[1;4D".to_vec()]);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let steps = parse("# setup\n\ninfo<enter>\n").unwrap();
+        assert_eq!(steps, [b"info\r".to_vec()]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(parse("ok\n<bogus>\n").unwrap_err(), "line 2: unknown key <bogus>");
+        assert!(parse("<enter").is_err());
+        assert!(parse("\\xZZ").is_err());
+    }
+}
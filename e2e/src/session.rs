@@ -0,0 +1,175 @@
+// A running Jamos instance driven over its serial console
+// QEMU is started paused with the serial port on a pty and the monitor on
+// stdio; the harness opens the pty, then lets the CPU run with `cont`, so no
+// boot output is lost. The hosted build is simply a child process with pipes,
+// run in an empty directory of its own: it reads and writes host files such
+// as jamos.history in its working directory, which must not carry over from
+// one case to the next.
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Output counts as settled after this long without a new byte
+const QUIET: Duration = Duration::from_millis(300);
+const POLL: Duration = Duration::from_millis(20);
+// QEMU checks for a client on the pty once a second and drops output until
+// it has seen one
+const PTY_CONNECT: Duration = Duration::from_millis(1500);
+
+pub struct Session {
+    child: Child,
+    console: Box<dyn Write>,
+    // QEMU monitor; None for the hosted build
+    monitor: Option<ChildStdin>,
+    // Working directory of the hosted build, removed afterwards
+    workdir: Option<PathBuf>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Session {
+    pub fn qemu(kernel: &Path) -> Result<Session, String> {
+        let mut child = Command::new("qemu-system-aarch64")
+            .args(["-machine", "virt", "-cpu", "cortex-a57", "-display", "none"])
+            .args(["-serial", "pty", "-monitor", "stdio", "-S", "-kernel"])
+            .arg(kernel)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot start qemu-system-aarch64: {}", e))?;
+
+        // QEMU announces the pty as "char device redirected to /dev/pts/N
+        // (label serial0)"; depending on the version on stdout or stderr
+        let (lines, announced) = mpsc::channel();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        forward_lines(stdout, lines.clone());
+        forward_lines(stderr, lines);
+        let pty = loop {
+            let line = announced
+                .recv_timeout(Duration::from_secs(10))
+                .map_err(|_| "QEMU did not report a serial pty".to_string())?;
+            if let Some(rest) = line.split("redirected to ").nth(1) {
+                break rest.split_whitespace().next().unwrap_or("").to_string();
+            }
+        };
+
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&pty)
+            .map_err(|e| format!("cannot open {}: {}", pty, e))?;
+        let reader = port.try_clone().map_err(|e| e.to_string())?;
+        let output = collect(reader);
+        thread::sleep(PTY_CONNECT);
+
+        let mut monitor = child.stdin.take().unwrap();
+        monitor.write_all(b"cont\n").map_err(|e| e.to_string())?;
+
+        Ok(Session {
+            child,
+            console: Box::new(port),
+            monitor: Some(monitor),
+            workdir: None,
+            output,
+        })
+    }
+
+    pub fn hosted(binary: &Path) -> Result<Session, String> {
+        let workdir = std::env::temp_dir().join(format!("jamos-e2e-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&workdir);
+        fs::create_dir_all(&workdir).map_err(|e| format!("cannot create {}: {}", workdir.display(), e))?;
+        let mut child = Command::new(binary)
+            .current_dir(&workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("cannot start {}: {}", binary.display(), e))?;
+        let output = collect(child.stdout.take().unwrap());
+        let console = Box::new(child.stdin.take().unwrap());
+        Ok(Session {
+            child,
+            console,
+            monitor: None,
+            workdir: Some(workdir),
+            output,
+        })
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.console
+            .write_all(bytes)
+            .and_then(|_| self.console.flush())
+            .map_err(|e| format!("cannot write to the console: {}", e))
+    }
+
+    /// Wait until no output has arrived for a while, then return what came
+    /// in since the last call
+    pub fn settle(&mut self, timeout: Duration) -> Result<Vec<u8>, String> {
+        let start = Instant::now();
+        let mut seen = 0;
+        let mut last_change = start;
+        loop {
+            thread::sleep(POLL);
+            let len = self.output.lock().unwrap().len();
+            if len != seen {
+                seen = len;
+                last_change = Instant::now();
+            }
+            let exited = matches!(self.child.try_wait(), Ok(Some(_)));
+            if last_change.elapsed() >= QUIET || exited {
+                break;
+            }
+            if start.elapsed() >= timeout {
+                return Err(format!("output did not settle within {:?}", timeout));
+            }
+        }
+        Ok(std::mem::take(&mut *self.output.lock().unwrap()))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(monitor) = self.monitor.as_mut() {
+            let _ = monitor.write_all(b"quit\n");
+            thread::sleep(POLL);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(workdir) = &self.workdir {
+            let _ = fs::remove_dir_all(workdir);
+        }
+    }
+}
+
+fn forward_lines(stream: impl Read + Send + 'static, lines: mpsc::Sender<String>) {
+    thread::spawn(move || {
+        // Keep draining after the receiver is gone so QEMU never blocks
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            let _ = lines.send(line);
+        }
+    });
+}
+
+/// Append everything the stream produces to a shared buffer
+fn collect(mut stream: impl Read + Send + 'static) -> Arc<Mutex<Vec<u8>>> {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let buffer = output.clone();
+    thread::spawn(move || {
+        let mut chunk = [0u8; 1024];
+        while let Ok(n) = stream.read(&mut chunk) {
+            if n == 0 {
+                return;
+            }
+            buffer.lock().unwrap().extend_from_slice(&chunk[..n]);
+        }
+    });
+    output
+}
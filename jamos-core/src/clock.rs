@@ -0,0 +1,16 @@
+// Time source for input timeouts. The kernel backs it with the ARM generic
+// timer; host tests use a clock they advance by hand.
+
+use crate::console::Disconnected;
+
+pub trait Clock {
+    /// Milliseconds since an arbitrary, fixed starting point
+    fn now_ms(&self) -> u64;
+}
+
+/// Time stands still without a clock: nothing ever times out
+impl Clock for Disconnected {
+    fn now_ms(&self) -> u64 {
+        0
+    }
+}
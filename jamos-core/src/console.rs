@@ -0,0 +1,40 @@
+// Byte-stream endpoints a terminal can be attached to.
+// The kernel's serial console implements both; remote sessions provide their own.
+
+/// Destination for terminal output
+pub trait CharSink {
+    fn putc(&self, c: u8);
+
+    fn puts(&self, s: &str) {
+        for byte in s.bytes() {
+            self.putc(byte);
+        }
+    }
+}
+
+/// Source of raw input bytes for the keyboard decoder
+pub trait CharSource {
+    fn getc(&self) -> Option<u8>;
+}
+
+/// Endpoint with nothing attached: output is dropped and input never arrives.
+/// Terminals start out on it until they are given a real sink or source.
+pub struct Disconnected;
+
+impl CharSink for Disconnected {
+    fn putc(&self, _c: u8) {}
+}
+
+impl CharSource for Disconnected {
+    fn getc(&self) -> Option<u8> {
+        None
+    }
+}
+
+#[cfg(feature = "std")]
+impl CharSink for std::io::Stdout {
+    fn putc(&self, c: u8) {
+        use std::io::Write;
+        let _ = self.lock().write_all(&[c]);
+    }
+}
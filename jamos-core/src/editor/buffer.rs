@@ -0,0 +1,471 @@
+// Simple nano-like text editor
+use crate::terminal::Screen;
+use crate::keyboard::{KeyEvent, Key};
+use crate::keymap::{Action, Keymap, Scope};
+use crate::text::{self, FixedString};
+
+const MAX_BUFFER_SIZE: usize = 2048;
+const MAX_FILENAME_LEN: usize = 32;
+
+pub struct TextEditor {
+    buffer: [u8; MAX_BUFFER_SIZE],
+    buffer_len: usize,
+    cursor_pos: usize,
+    filename: FixedString<MAX_FILENAME_LEN>,
+    modified: bool,
+    // Text before the last edit; undoing swaps it with the buffer, so
+    // undoing again redoes
+    undo: [u8; MAX_BUFFER_SIZE],
+    undo_len: usize,
+    undo_cursor: usize,
+    can_undo: bool,
+}
+
+impl Default for TextEditor {
+    fn default() -> Self {
+        TextEditor::new()
+    }
+}
+
+impl TextEditor {
+    pub const fn empty() -> Self {
+        TextEditor {
+            buffer: [0; MAX_BUFFER_SIZE],
+            buffer_len: 0,
+            cursor_pos: 0,
+            filename: FixedString::new(),
+            modified: false,
+            undo: [0; MAX_BUFFER_SIZE],
+            undo_len: 0,
+            undo_cursor: 0,
+            can_undo: false,
+        }
+    }
+
+    pub fn new() -> Self {
+        TextEditor::empty()
+    }
+
+    pub fn set_filename(&mut self, name: &str) {
+        self.filename = FixedString::from_str_truncated(name);
+    }
+
+    pub fn filename(&self) -> FixedString<MAX_FILENAME_LEN> {
+        self.filename
+    }
+
+    pub fn load_content(&mut self, data: &[u8]) {
+        // Don't cut a character in half when the file is too big
+        let len = text::floor_char_boundary(data, MAX_BUFFER_SIZE);
+        self.buffer[..len].copy_from_slice(&data[..len]);
+        self.buffer_len = len;
+        self.cursor_pos = len;
+        self.modified = false;
+        self.can_undo = false;
+    }
+
+    pub fn get_content(&self) -> &[u8] {
+        &self.buffer[..self.buffer_len]
+    }
+
+    /// Insert at the cursor; false if the buffer is full
+    pub fn insert_char(&mut self, c: char) -> bool {
+        let mut encoded = [0u8; 4];
+        let bytes = c.encode_utf8(&mut encoded).as_bytes();
+        let len = bytes.len();
+        if self.buffer_len + len <= MAX_BUFFER_SIZE && self.cursor_pos <= self.buffer_len {
+            // Shift characters to the right
+            self.buffer.copy_within(self.cursor_pos..self.buffer_len, self.cursor_pos + len);
+            self.buffer[self.cursor_pos..self.cursor_pos + len].copy_from_slice(bytes);
+            self.buffer_len += len;
+            self.cursor_pos += len;
+            self.modified = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Insert pasted text at the cursor as a single edit, so one undo takes
+    /// it all back. Whatever does not fit is dropped.
+    pub fn paste(&mut self, text: &str) {
+        self.checkpoint();
+        for c in text.chars() {
+            if !self.insert_char(c) {
+                break;
+            }
+        }
+    }
+
+    /// Remember the text as it is before an edit
+    fn checkpoint(&mut self) {
+        self.undo[..self.buffer_len].copy_from_slice(&self.buffer[..self.buffer_len]);
+        self.undo_len = self.buffer_len;
+        self.undo_cursor = self.cursor_pos;
+        self.can_undo = true;
+    }
+
+    /// Undo the last edit (a key press or a whole paste); false if there is
+    /// nothing to undo
+    pub fn undo(&mut self) -> bool {
+        if !self.can_undo {
+            return false;
+        }
+        core::mem::swap(&mut self.buffer, &mut self.undo);
+        core::mem::swap(&mut self.buffer_len, &mut self.undo_len);
+        core::mem::swap(&mut self.cursor_pos, &mut self.undo_cursor);
+        // Undoing back to the saved text still leaves a change to save
+        self.modified = true;
+        true
+    }
+
+    /// Delete the character (with its combining marks) before the cursor
+    pub fn delete_char(&mut self) -> bool {
+        if self.cursor_pos > 0 && self.buffer_len > 0 {
+            let start = text::previous_grapheme(&self.buffer[..self.buffer_len], self.cursor_pos);
+            // Shift characters to the left
+            self.buffer.copy_within(self.cursor_pos..self.buffer_len, start);
+            self.buffer_len -= self.cursor_pos - start;
+            self.cursor_pos = start;
+            self.modified = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn move_cursor_left(&mut self) -> bool {
+        if self.cursor_pos > 0 {
+            self.cursor_pos = text::previous_grapheme(&self.buffer[..self.buffer_len], self.cursor_pos);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn move_cursor_right(&mut self) -> bool {
+        if self.cursor_pos < self.buffer_len {
+            self.cursor_pos = text::next_grapheme(&self.buffer[..self.buffer_len], self.cursor_pos);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+
+    pub fn render(&self, screen: &mut Screen) {
+        screen.clear();
+        screen.puts("=== Jamos Text Editor ===\n");
+        screen.puts("File: ");
+        screen.puts(self.filename.as_str());
+        if self.modified {
+            screen.puts(" [Modified]");
+        }
+        screen.puts("\n");
+        screen.puts("Ctrl+S: Save | Ctrl+Q: Quit | Ctrl+X: Exit and Save | Ctrl+Z: Undo\n");
+        screen.puts("---\n");
+
+        // Display buffer content
+        for &byte in &self.buffer[..self.buffer_len] {
+            screen.putc(byte);
+        }
+        
+        screen.puts("\n---\n");
+    }
+
+    pub fn handle_key(&mut self, event: &KeyEvent, keymap: &Keymap, screen: &mut Screen) -> EditorAction {
+        // Shortcuts (Ctrl+S/Q/X/Z by default) come from the keymap
+        match keymap.action(event, Scope::Editor) {
+            Some(Action::EditorSave) => return EditorAction::Save,
+            Some(Action::EditorQuit) => return EditorAction::Quit,
+            Some(Action::EditorSaveAndQuit) => return EditorAction::SaveAndQuit,
+            Some(Action::EditorUndo) => {
+                // Undoing again redoes
+                if self.undo() {
+                    self.render(screen);
+                }
+                return EditorAction::Continue;
+            }
+            _ => {}
+        }
+
+        // Handle navigation
+        match event.key {
+            Key::Left => {
+                self.move_cursor_left();
+                self.render(screen);
+            }
+            Key::Right => {
+                self.move_cursor_right();
+                self.render(screen);
+            }
+            Key::Backspace => {
+                if self.cursor_pos > 0 {
+                    self.checkpoint();
+                    self.delete_char();
+                    self.render(screen);
+                }
+            }
+            Key::Char(c) if !event.has_command_modifier() => {
+                self.checkpoint();
+                self.insert_char(c);
+                self.render(screen);
+            }
+            Key::Tab => {
+                self.checkpoint();
+                self.insert_char('\t');
+                self.render(screen);
+            }
+            Key::Enter => {
+                self.checkpoint();
+                self.insert_char('\n');
+                self.render(screen);
+            }
+            _ => {}
+        }
+
+        EditorAction::Continue
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditorAction {
+    Continue,
+    Save,
+    Quit,
+    SaveAndQuit,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    fn key(key: Key) -> KeyEvent {
+        KeyEvent::new(key)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent { ctrl: true, ..KeyEvent::new(Key::Char(c)) }
+    }
+
+    fn default_keymap() -> Keymap {
+        let mut keymap = Keymap::empty();
+        keymap.init();
+        keymap
+    }
+
+    fn editor_with(content: &[u8]) -> Box<TextEditor> {
+        let mut editor = Box::new(TextEditor::new());
+        editor.load_content(content);
+        editor
+    }
+
+    #[test]
+    fn load_content_puts_cursor_at_end() {
+        let mut editor = editor_with(b"abc");
+        assert!(!editor.is_modified());
+        editor.insert_char('d');
+        assert_eq!(editor.get_content(), b"abcd");
+        assert!(editor.is_modified());
+    }
+
+    #[test]
+    fn typing_inserts_at_cursor() {
+        let mut editor = editor_with(b"ac");
+        editor.move_cursor_left();
+        editor.insert_char('b');
+        assert_eq!(editor.get_content(), b"abc");
+    }
+
+    #[test]
+    fn backspace_deletes_before_cursor() {
+        let mut editor = editor_with(b"abc");
+        editor.move_cursor_left();
+        assert!(editor.delete_char());
+        assert_eq!(editor.get_content(), b"ac");
+
+        editor.move_cursor_left();
+        assert!(!editor.delete_char());
+        assert_eq!(editor.get_content(), b"ac");
+    }
+
+    #[test]
+    fn cursor_stops_at_buffer_ends() {
+        let mut editor = editor_with(b"ab");
+        assert!(!editor.move_cursor_right());
+        assert!(editor.move_cursor_left());
+        assert!(editor.move_cursor_left());
+        assert!(!editor.move_cursor_left());
+        editor.insert_char('>');
+        assert_eq!(editor.get_content(), b">ab");
+    }
+
+    #[test]
+    fn buffer_is_bounded() {
+        let mut editor = editor_with(&[b'x'; MAX_BUFFER_SIZE + 10]);
+        assert_eq!(editor.get_content().len(), MAX_BUFFER_SIZE);
+        editor.insert_char('y');
+        assert_eq!(editor.get_content().len(), MAX_BUFFER_SIZE);
+        assert!(!editor.is_modified());
+    }
+
+    #[test]
+    fn keys_edit_the_buffer() {
+        let mut editor = editor_with(b"");
+        let mut screen = Screen::new(CaptureSink::leak());
+        let keymap = default_keymap();
+        let events = [
+            key(Key::Char('h')),
+            key(Key::Enter),
+            key(Key::Char('i')),
+            key(Key::Left),
+            key(Key::Char('!')),
+            key(Key::Right),
+            key(Key::Backspace),
+        ];
+        for event in events {
+            assert_eq!(editor.handle_key(&event, &keymap, &mut screen), EditorAction::Continue);
+        }
+        assert_eq!(editor.get_content(), b"h\n!");
+    }
+
+    #[test]
+    fn control_keys_return_actions() {
+        let mut editor = editor_with(b"keep");
+        let mut screen = Screen::new(CaptureSink::leak());
+        let keymap = default_keymap();
+        assert_eq!(editor.handle_key(&ctrl('s'), &keymap, &mut screen), EditorAction::Save);
+        assert_eq!(editor.handle_key(&ctrl('S'), &keymap, &mut screen), EditorAction::Save);
+        assert_eq!(editor.handle_key(&ctrl('q'), &keymap, &mut screen), EditorAction::Quit);
+        assert_eq!(editor.handle_key(&ctrl('x'), &keymap, &mut screen), EditorAction::SaveAndQuit);
+        assert_eq!(editor.handle_key(&ctrl('X'), &keymap, &mut screen), EditorAction::SaveAndQuit);
+        assert_eq!(editor.get_content(), b"keep");
+    }
+
+    #[test]
+    fn shortcuts_follow_the_keymap() {
+        let mut editor = editor_with(b"");
+        let mut screen = Screen::new(CaptureSink::leak());
+        let mut keymap = default_keymap();
+        keymap.apply("unbind ctrl+s").unwrap();
+        keymap.apply("bind f2 editor.save").unwrap();
+        assert_eq!(editor.handle_key(&ctrl('s'), &keymap, &mut screen), EditorAction::Continue);
+        assert_eq!(editor.handle_key(&key(Key::F(2)), &keymap, &mut screen), EditorAction::Save);
+        assert_eq!(editor.get_content(), b"");
+    }
+
+    #[test]
+    fn plain_letters_are_not_commands() {
+        let mut editor = editor_with(b"");
+        let mut screen = Screen::new(CaptureSink::leak());
+        let keymap = default_keymap();
+        assert_eq!(editor.handle_key(&key(Key::Char('s')), &keymap, &mut screen), EditorAction::Continue);
+        assert_eq!(editor.get_content(), b"s");
+    }
+
+    #[test]
+    fn unbound_shortcuts_insert_nothing() {
+        let mut editor = editor_with(b"");
+        let mut screen = Screen::new(CaptureSink::leak());
+        let keymap = default_keymap();
+        let alt = KeyEvent { alt: true, ..key(Key::Char('f')) };
+        assert_eq!(editor.handle_key(&ctrl('a'), &keymap, &mut screen), EditorAction::Continue);
+        assert_eq!(editor.handle_key(&alt, &keymap, &mut screen), EditorAction::Continue);
+        editor.handle_key(&key(Key::Tab), &keymap, &mut screen);
+        assert_eq!(editor.get_content(), b"\t");
+    }
+
+    #[test]
+    fn utf8_editing_works_on_whole_characters() {
+        let mut editor = editor_with("aé日".as_bytes());
+        editor.move_cursor_left();
+        editor.insert_char('ö');
+        assert_eq!(editor.get_content(), "aéö日".as_bytes());
+        assert!(editor.delete_char());
+        assert!(editor.delete_char());
+        assert_eq!(editor.get_content(), "a日".as_bytes());
+
+        // An accent typed as a combining mark goes with its letter
+        let mut editor = editor_with(b"");
+        editor.insert_char('e');
+        editor.insert_char('\u{301}');
+        editor.delete_char();
+        assert_eq!(editor.get_content(), b"");
+    }
+
+    #[test]
+    fn oversized_content_is_cut_at_a_character_boundary() {
+        let mut data = vec![b'x'; MAX_BUFFER_SIZE - 1];
+        data.extend_from_slice("日".as_bytes());
+        let editor = editor_with(&data);
+        assert_eq!(editor.get_content().len(), MAX_BUFFER_SIZE - 1);
+    }
+
+    #[test]
+    fn paste_is_undone_as_one_edit() {
+        let mut editor = editor_with(b"ab");
+        let mut screen = Screen::new(CaptureSink::leak());
+        let keymap = default_keymap();
+        editor.move_cursor_left();
+        editor.handle_key(&key(Key::Char('x')), &keymap, &mut screen);
+        editor.paste("one\ntwo\n");
+        assert_eq!(editor.get_content(), b"axone\ntwo\nb");
+
+        editor.handle_key(&ctrl('z'), &keymap, &mut screen);
+        assert_eq!(editor.get_content(), b"axb");
+
+        // Undoing again redoes
+        editor.handle_key(&ctrl('z'), &keymap, &mut screen);
+        assert_eq!(editor.get_content(), b"axone\ntwo\nb");
+        editor.handle_key(&key(Key::Backspace), &keymap, &mut screen);
+        editor.handle_key(&ctrl('z'), &keymap, &mut screen);
+        assert_eq!(editor.get_content(), b"axone\ntwo\nb");
+    }
+
+    #[test]
+    fn nothing_to_undo_after_loading() {
+        let mut editor = editor_with(b"abc");
+        assert!(!editor.undo());
+        editor.paste(&"y".repeat(MAX_BUFFER_SIZE));
+        assert_eq!(editor.get_content().len(), MAX_BUFFER_SIZE);
+        assert!(editor.undo());
+        assert_eq!(editor.get_content(), b"abc");
+    }
+
+    #[test]
+    fn render_shows_file_and_state() {
+        let sink = CaptureSink::leak();
+        let mut screen = Screen::new(sink);
+        let keymap = default_keymap();
+        let mut editor = editor_with(b"line one\nline two");
+        editor.set_filename("notes.txt");
+
+        editor.render(&mut screen);
+        let text = sink.text();
+        assert!(text.contains("File: notes.txt\n"));
+        assert!(text.contains("line one\nline two"));
+        assert!(!text.contains("[Modified]"));
+
+        sink.clear();
+        editor.handle_key(&key(Key::Char('!')), &keymap, &mut screen);
+        assert!(sink.text().contains("File: notes.txt [Modified]"));
+    }
+
+    #[test]
+    fn mark_saved_clears_modified() {
+        let mut editor = editor_with(b"");
+        editor.set_filename("notes.txt");
+        editor.insert_char('x');
+        editor.mark_saved();
+        assert!(!editor.is_modified());
+        assert_eq!(editor.filename().as_str(), "notes.txt");
+    }
+}
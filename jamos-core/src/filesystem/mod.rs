@@ -1,5 +1,5 @@
 pub mod vfs;
 pub mod metadata;
 
-pub use vfs::{VirtualFileSystem, FileHandle};
+pub use vfs::{VirtualFileSystem, FileHandle, MAX_FILE_SIZE};
 pub use metadata::{Inode, FileType, Metadata};
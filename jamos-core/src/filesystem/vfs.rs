@@ -0,0 +1,397 @@
+// Virtual File System with PostgreSQL-inspired metadata
+use super::metadata::{Inode, FileType, Metadata};
+use crate::text;
+
+const MAX_INODES: usize = 64;
+const MAX_FILES: usize = 32;
+const MAX_FILENAME_LEN: usize = 32;
+const DATA_BLOCK_SIZE: usize = 512;
+const MAX_DATA_BLOCKS: usize = 32;
+
+/// Largest file the VFS can hold (one data block)
+pub const MAX_FILE_SIZE: usize = DATA_BLOCK_SIZE;
+
+#[derive(Clone, Copy)]
+pub struct FileEntry {
+    pub name: [u8; MAX_FILENAME_LEN],
+    pub name_len: usize,
+    pub inode_id: usize,
+    pub is_valid: bool,
+}
+
+impl FileEntry {
+    pub const fn empty() -> Self {
+        FileEntry {
+            name: [0; MAX_FILENAME_LEN],
+            name_len: 0,
+            inode_id: 0,
+            is_valid: false,
+        }
+    }
+
+    pub fn new(name: &str, inode_id: usize) -> Self {
+        let mut entry = FileEntry::empty();
+        let name = text::truncate(name, MAX_FILENAME_LEN);
+        entry.name_len = name.len();
+        entry.name[..entry.name_len].copy_from_slice(name.as_bytes());
+        entry.inode_id = inode_id;
+        entry.is_valid = true;
+        entry
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.is_valid && self.name_len == name.len() && &self.name[..self.name_len] == name.as_bytes()
+    }
+}
+
+pub struct FileHandle {
+    pub inode_id: usize,
+    pub offset: usize,
+}
+
+pub struct VirtualFileSystem {
+    inodes: [Inode; MAX_INODES],
+    files: [FileEntry; MAX_FILES],
+    data_storage: [[u8; DATA_BLOCK_SIZE]; MAX_DATA_BLOCKS],
+    next_inode_id: usize,
+    timestamp_counter: u64,
+}
+
+impl VirtualFileSystem {
+    pub const fn empty() -> Self {
+        VirtualFileSystem {
+            inodes: [Inode::empty(); MAX_INODES],
+            files: [FileEntry::empty(); MAX_FILES],
+            data_storage: [[0; DATA_BLOCK_SIZE]; MAX_DATA_BLOCKS],
+            next_inode_id: 1,
+            timestamp_counter: 0,
+        }
+    }
+
+    pub fn init(&mut self) {
+        // Create root directory (inode 0)
+        self.inodes[0] = Inode::new(0, FileType::Directory, 0);
+        self.inodes[0].metadata.created_at = self.get_timestamp();
+        self.inodes[0].metadata.modified_at = self.inodes[0].metadata.created_at;
+    }
+
+    fn get_timestamp(&mut self) -> u64 {
+        self.timestamp_counter += 1;
+        self.timestamp_counter
+    }
+
+    fn allocate_inode(&mut self, file_type: FileType, parent_id: usize) -> Option<usize> {
+        if self.next_inode_id >= MAX_INODES {
+            return None;
+        }
+        let id = self.next_inode_id;
+        self.next_inode_id += 1;
+        
+        self.inodes[id] = Inode::new(id, file_type, parent_id);
+        self.inodes[id].metadata.created_at = self.get_timestamp();
+        self.inodes[id].metadata.modified_at = self.inodes[id].metadata.created_at;
+        
+        Some(id)
+    }
+
+    fn allocate_data_block(&self) -> Option<usize> {
+        (0..MAX_DATA_BLOCKS).find(|&block| {
+            !self.inodes.iter().any(|inode| {
+                inode.is_valid && inode.metadata.file_type == FileType::Regular && inode.data_offset == block
+            })
+        })
+    }
+
+    fn find_file_entry(&self, name: &str) -> Option<&FileEntry> {
+        self.files.iter().find(|e| e.matches(name))
+    }
+
+    fn find_free_file_entry(&mut self) -> Option<usize> {
+        self.files.iter().position(|e| !e.is_valid)
+    }
+
+    pub fn create_file(&mut self, name: &str) -> Result<usize, &'static str> {
+        // Check if file already exists
+        if self.find_file_entry(name).is_some() {
+            return Err("File already exists");
+        }
+
+        // Each file gets a data block of its own
+        let block_id = self.allocate_data_block()
+            .ok_or("No more data blocks available")?;
+
+        // Allocate inode
+        let inode_id = self.allocate_inode(FileType::Regular, 0)
+            .ok_or("No more inodes available")?;
+
+        // Find free file entry
+        let entry_idx = self.find_free_file_entry()
+            .ok_or("No more file entries available")?;
+
+        // Create file entry
+        self.files[entry_idx] = FileEntry::new(name, inode_id);
+        self.inodes[inode_id].data_offset = block_id;
+
+        Ok(inode_id)
+    }
+
+    pub fn write_file(&mut self, inode_id: usize, data: &[u8]) -> Result<usize, &'static str> {
+        if inode_id >= MAX_INODES || !self.inodes[inode_id].is_valid {
+            return Err("Invalid inode");
+        }
+
+        if self.inodes[inode_id].metadata.file_type != FileType::Regular {
+            return Err("Not a regular file");
+        }
+
+        // Find or allocate data block
+        let block_id = self.inodes[inode_id].data_offset;
+        if block_id >= MAX_DATA_BLOCKS {
+            return Err("No data blocks available");
+        }
+
+        let bytes_to_write = data.len().min(DATA_BLOCK_SIZE);
+        self.data_storage[block_id][..bytes_to_write].copy_from_slice(&data[..bytes_to_write]);
+        
+        let timestamp = self.get_timestamp();
+        self.inodes[inode_id].metadata.size = bytes_to_write;
+        self.inodes[inode_id].metadata.modified_at = timestamp;
+
+        Ok(bytes_to_write)
+    }
+
+    /// Replace the contents of `name`, creating the file if needed
+    pub fn save_file(&mut self, name: &str, data: &[u8]) -> Result<usize, &'static str> {
+        if data.len() > MAX_FILE_SIZE {
+            return Err("File too large");
+        }
+        let inode_id = match self.find_inode_by_name(name) {
+            Some(id) => id,
+            None => self.create_file(name)?,
+        };
+        self.write_file(inode_id, data)
+    }
+
+    pub fn read_file(&self, inode_id: usize, buf: &mut [u8]) -> Result<usize, &'static str> {
+        if inode_id >= MAX_INODES || !self.inodes[inode_id].is_valid {
+            return Err("Invalid inode");
+        }
+
+        let inode = &self.inodes[inode_id];
+        if inode.metadata.file_type != FileType::Regular {
+            return Err("Not a regular file");
+        }
+
+        let block_id = inode.data_offset;
+        if block_id >= MAX_DATA_BLOCKS {
+            return Err("Invalid data block");
+        }
+
+        let bytes_to_read = inode.metadata.size.min(buf.len());
+        buf[..bytes_to_read].copy_from_slice(&self.data_storage[block_id][..bytes_to_read]);
+
+        Ok(bytes_to_read)
+    }
+
+    pub fn list_files(&self, buf: &mut [[u8; MAX_FILENAME_LEN]; MAX_FILES]) -> usize {
+        let mut count = 0;
+        for entry in &self.files {
+            if entry.is_valid && count < MAX_FILES {
+                buf[count][..entry.name_len].copy_from_slice(&entry.name[..entry.name_len]);
+                // Fill rest with zeros
+                buf[count][entry.name_len..].fill(0);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn get_file_metadata(&self, name: &str) -> Option<Metadata> {
+        let entry = self.find_file_entry(name)?;
+        if entry.inode_id >= MAX_INODES || !self.inodes[entry.inode_id].is_valid {
+            return None;
+        }
+        Some(self.inodes[entry.inode_id].metadata)
+    }
+
+    pub fn find_inode_by_name(&self, name: &str) -> Option<usize> {
+        self.find_file_entry(name).map(|e| e.inode_id)
+    }
+
+    pub fn delete_file(&mut self, name: &str) -> Result<(), &'static str> {
+        let entry_idx = self.files.iter().position(|e| e.matches(name))
+            .ok_or("File not found")?;
+
+        let inode_id = self.files[entry_idx].inode_id;
+        
+        // Mark file entry as invalid
+        self.files[entry_idx].is_valid = false;
+        
+        // Mark inode as invalid
+        if inode_id < MAX_INODES {
+            self.inodes[inode_id].is_valid = false;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_vfs() -> Box<VirtualFileSystem> {
+        let mut vfs = Box::new(VirtualFileSystem::empty());
+        vfs.init();
+        vfs
+    }
+
+    fn read_to_vec(vfs: &VirtualFileSystem, name: &str) -> Vec<u8> {
+        let inode_id = vfs.find_inode_by_name(name).expect("file exists");
+        let mut buf = [0u8; MAX_FILE_SIZE];
+        let len = vfs.read_file(inode_id, &mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let mut vfs = new_vfs();
+        let inode_id = vfs.create_file("notes.txt").unwrap();
+        assert_eq!(vfs.write_file(inode_id, b"hello"), Ok(5));
+        assert_eq!(read_to_vec(&vfs, "notes.txt"), b"hello");
+    }
+
+    #[test]
+    fn new_files_are_empty() {
+        let mut vfs = new_vfs();
+        vfs.create_file("empty").unwrap();
+        assert_eq!(read_to_vec(&vfs, "empty"), b"");
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let mut vfs = new_vfs();
+        vfs.create_file("a").unwrap();
+        assert_eq!(vfs.create_file("a"), Err("File already exists"));
+    }
+
+    #[test]
+    fn files_do_not_share_data() {
+        let mut vfs = new_vfs();
+        let a = vfs.create_file("a").unwrap();
+        let b = vfs.create_file("b").unwrap();
+        vfs.write_file(a, b"first").unwrap();
+        vfs.write_file(b, b"second").unwrap();
+        assert_eq!(read_to_vec(&vfs, "a"), b"first");
+        assert_eq!(read_to_vec(&vfs, "b"), b"second");
+    }
+
+    #[test]
+    fn rewriting_replaces_contents() {
+        let mut vfs = new_vfs();
+        let inode_id = vfs.create_file("f").unwrap();
+        vfs.write_file(inode_id, b"a long first version").unwrap();
+        vfs.write_file(inode_id, b"short").unwrap();
+        assert_eq!(read_to_vec(&vfs, "f"), b"short");
+    }
+
+    #[test]
+    fn writes_are_limited_to_one_block() {
+        let mut vfs = new_vfs();
+        let inode_id = vfs.create_file("big").unwrap();
+        assert_eq!(vfs.write_file(inode_id, &[b'x'; MAX_FILE_SIZE + 100]), Ok(MAX_FILE_SIZE));
+        assert_eq!(read_to_vec(&vfs, "big").len(), MAX_FILE_SIZE);
+    }
+
+    #[test]
+    fn short_buffers_get_a_partial_read() {
+        let mut vfs = new_vfs();
+        let inode_id = vfs.create_file("f").unwrap();
+        vfs.write_file(inode_id, b"0123456789").unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(vfs.read_file(inode_id, &mut buf), Ok(4));
+        assert_eq!(&buf, b"0123");
+    }
+
+    #[test]
+    fn invalid_inodes_are_rejected() {
+        let mut vfs = new_vfs();
+        let mut buf = [0u8; 4];
+        assert_eq!(vfs.read_file(MAX_INODES, &mut buf), Err("Invalid inode"));
+        assert_eq!(vfs.read_file(5, &mut buf), Err("Invalid inode"));
+        assert_eq!(vfs.write_file(5, b"x"), Err("Invalid inode"));
+        // Inode 0 is the root directory
+        assert_eq!(vfs.read_file(0, &mut buf), Err("Not a regular file"));
+        assert_eq!(vfs.write_file(0, b"x"), Err("Not a regular file"));
+    }
+
+    #[test]
+    fn save_file_creates_and_replaces() {
+        let mut vfs = new_vfs();
+        assert_eq!(vfs.save_file("log", b"one two three"), Ok(13));
+        assert_eq!(vfs.save_file("log", b"four"), Ok(4));
+        assert_eq!(read_to_vec(&vfs, "log"), b"four");
+        assert_eq!(vfs.save_file("big", &[0; MAX_FILE_SIZE + 1]), Err("File too large"));
+        assert_eq!(vfs.find_inode_by_name("big"), None);
+    }
+
+    #[test]
+    fn deleted_files_are_gone() {
+        let mut vfs = new_vfs();
+        vfs.create_file("tmp").unwrap();
+        assert_eq!(vfs.delete_file("tmp"), Ok(()));
+        assert_eq!(vfs.find_inode_by_name("tmp"), None);
+        assert!(vfs.get_file_metadata("tmp").is_none());
+        assert_eq!(vfs.delete_file("tmp"), Err("File not found"));
+    }
+
+    #[test]
+    fn names_can_be_reused_after_delete() {
+        let mut vfs = new_vfs();
+        vfs.save_file("tmp", b"old").unwrap();
+        vfs.delete_file("tmp").unwrap();
+        vfs.create_file("tmp").unwrap();
+        assert_eq!(read_to_vec(&vfs, "tmp"), b"");
+    }
+
+    #[test]
+    fn data_blocks_run_out_and_are_freed_by_delete() {
+        let mut vfs = new_vfs();
+        for i in 0..MAX_DATA_BLOCKS {
+            vfs.create_file(&format!("f{}", i)).unwrap();
+        }
+        assert_eq!(vfs.create_file("one-more"), Err("No more data blocks available"));
+
+        vfs.delete_file("f7").unwrap();
+        vfs.create_file("one-more").unwrap();
+    }
+
+    #[test]
+    fn metadata_tracks_size_and_times() {
+        let mut vfs = new_vfs();
+        let inode_id = vfs.create_file("f").unwrap();
+        let created = vfs.get_file_metadata("f").unwrap();
+        assert_eq!(created.size, 0);
+        assert_eq!(created.file_type, FileType::Regular);
+        assert_eq!(created.permissions, 0o644);
+        assert_eq!(created.created_at, created.modified_at);
+
+        vfs.write_file(inode_id, b"abc").unwrap();
+        let written = vfs.get_file_metadata("f").unwrap();
+        assert_eq!(written.size, 3);
+        assert_eq!(written.created_at, created.created_at);
+        assert!(written.modified_at > created.modified_at);
+    }
+
+    #[test]
+    fn list_files_returns_names() {
+        let mut vfs = new_vfs();
+        vfs.create_file("x").unwrap();
+        vfs.create_file("yz").unwrap();
+
+        let mut names = [[0u8; MAX_FILENAME_LEN]; MAX_FILES];
+        assert_eq!(vfs.list_files(&mut names), 2);
+        assert_eq!(&names[0][..2], b"x\0");
+        assert_eq!(&names[1][..3], b"yz\0");
+    }
+}
@@ -0,0 +1,749 @@
+// Keyboard driver - decodes input bytes from a CharSource (the UART in QEMU)
+// Understands what xterm-compatible terminals send: control characters,
+// CSI (`ESC [`) and SS3 (`ESC O`) sequences with modifier parameters, and
+// Alt+key as `ESC <key>`. Text arrives as UTF-8 and is decoded to chars. A
+// lone ESC is the Escape key once no further byte has followed it for
+// ESCAPE_TIMEOUT_MS. Text between the bracketed paste markers (`ESC[200~` and
+// `ESC[201~`) is collected and delivered as a single Paste event.
+// This will be extended to support PS/2 keyboard or virtio-input in the future
+
+use crate::clock::Clock;
+use crate::console::CharSource;
+use crate::text::FixedString;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    F(u8),      // Function keys F1-F12
+    Meta,       // Win/Super key
+    Paste,      // Bracketed paste; the text is in Keyboard::pasted()
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub meta: bool,  // Meta/Win key pressed
+    pub ctrl: bool,  // Ctrl key pressed
+    pub shift: bool, // Shift key pressed
+    pub alt: bool,   // Alt key pressed
+}
+
+impl KeyEvent {
+    pub const fn new(key: Key) -> Self {
+        KeyEvent {
+            key,
+            meta: false,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    /// Ctrl, Alt or Meta held: the key is a shortcut rather than text
+    pub fn has_command_modifier(&self) -> bool {
+        self.ctrl || self.alt || self.meta
+    }
+}
+
+// Terminals send a whole sequence at once; a gap this long after ESC means
+// the Escape key was pressed on its own
+pub const ESCAPE_TIMEOUT_MS: u64 = 50;
+
+// CSI parameters beyond the second (key code; modifiers) carry nothing for keys
+const MAX_PARAMS: usize = 2;
+
+// Enough for a full editor buffer; a longer paste is cut off
+pub const MAX_PASTE_LEN: usize = 2048;
+
+// ESC [ 200 ~ and ESC [ 201 ~ around pasted text
+const PASTE_START: u16 = 200;
+const PASTE_END: u16 = 201;
+
+pub struct Keyboard {
+    // None for a keyboard that was never given a terminal
+    source: Option<&'static dyn CharSource>,
+    clock: Option<&'static dyn Clock>,
+    meta_pressed: bool,
+    escape_sequence: EscapeSequence,
+    // When the last byte of the unfinished sequence arrived
+    sequence_time: u64,
+    // A byte read after the sequence timed out, decoded on the next poll
+    held: Option<u8>,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    // Private marker or intermediate bytes: a sequence that is not a key
+    foreign: bool,
+    // Multi-byte UTF-8 character being collected
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_needed: usize,
+    // The character being collected was prefixed with ESC (Alt)
+    utf8_alt: bool,
+    // Inside a bracketed paste, and what has been pasted so far
+    pasting: bool,
+    paste: FixedString<MAX_PASTE_LEN>,
+    paste_after_cr: bool,
+    // Something did not fit; the rest of the paste is dropped
+    paste_full: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeSequence {
+    None,
+    Escape,  // After ESC
+    Csi,     // After ESC[, collecting parameters
+    Ss3,     // After ESCO
+}
+
+impl Keyboard {
+    /// All zeros, so that statics holding keyboards go in .bss
+    pub const fn empty() -> Self {
+        Keyboard {
+            source: None,
+            clock: None,
+            meta_pressed: false,
+            escape_sequence: EscapeSequence::None,
+            sequence_time: 0,
+            held: None,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            foreign: false,
+            utf8: [0; 4],
+            utf8_len: 0,
+            utf8_needed: 0,
+            utf8_alt: false,
+            pasting: false,
+            paste: FixedString::new(),
+            paste_after_cr: false,
+            paste_full: false,
+        }
+    }
+
+    pub fn new(source: &'static dyn CharSource, clock: &'static dyn Clock) -> Self {
+        Keyboard {
+            source: Some(source),
+            clock: Some(clock),
+            ..Keyboard::empty()
+        }
+    }
+
+    pub fn poll(&mut self) -> Option<KeyEvent> {
+        let event = self.decode();
+        if !self.pasting {
+            return event;
+        }
+        // Keys cannot happen in the middle of a paste; whatever else the
+        // terminal sent along is dropped
+        None
+    }
+
+    /// Text of the last Paste event, newlines as '\n'
+    pub fn pasted(&self) -> &str {
+        self.paste.as_str()
+    }
+
+    fn decode(&mut self) -> Option<KeyEvent> {
+        let Some(c) = self.held.take().or_else(|| self.source.and_then(|source| source.getc())) else {
+            return self.check_timeout();
+        };
+        if self.escape_sequence != EscapeSequence::None && self.timed_out() {
+            // The byte was not read until after the timeout, as when the
+            // main loop was busy: it starts something new
+            self.held = Some(c);
+            return self.settle();
+        }
+        if self.pasting && self.escape_sequence == EscapeSequence::None && c != 0x1B {
+            self.paste_byte(c);
+            return None;
+        }
+        if self.escape_sequence != EscapeSequence::None {
+            self.sequence_time = self.now_ms();
+        }
+
+        match self.escape_sequence {
+            EscapeSequence::None => {
+                if c == 0x1B {  // ESC
+                    self.escape_sequence = EscapeSequence::Escape;
+                    self.utf8_needed = 0;
+                    self.sequence_time = self.now_ms();
+                    return None;
+                }
+                self.process_char(c)
+            }
+            EscapeSequence::Escape => {
+                match c {
+                    b'[' => {
+                        self.start_sequence(EscapeSequence::Csi);
+                        None
+                    }
+                    b'O' => {
+                        self.start_sequence(EscapeSequence::Ss3);
+                        None
+                    }
+                    0x1B => {
+                        // ESC ESC: the first one was the Escape key itself
+                        Some(KeyEvent::new(Key::Escape))
+                    }
+                    _ => {
+                        // ESC <key> is how terminals send Alt+key
+                        self.escape_sequence = EscapeSequence::None;
+                        self.alt_char(c)
+                    }
+                }
+            }
+            EscapeSequence::Csi | EscapeSequence::Ss3 => self.sequence_byte(c),
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.clock.map_or(0, |clock| clock.now_ms())
+    }
+
+    fn timed_out(&self) -> bool {
+        self.now_ms().wrapping_sub(self.sequence_time) >= ESCAPE_TIMEOUT_MS
+    }
+
+    /// Settle an unfinished sequence once input has paused for long enough
+    fn check_timeout(&mut self) -> Option<KeyEvent> {
+        if self.escape_sequence == EscapeSequence::None || !self.timed_out() {
+            return None;
+        }
+        self.settle()
+    }
+
+    /// End the unfinished sequence with what it has so far
+    fn settle(&mut self) -> Option<KeyEvent> {
+        let kind = self.escape_sequence;
+        self.escape_sequence = EscapeSequence::None;
+        match kind {
+            EscapeSequence::Escape => Some(KeyEvent::new(Key::Escape)),
+            // Nothing after ESC [ or ESC O: that was Alt+[ or Alt+O
+            EscapeSequence::Csi if self.param_count == 0 && !self.foreign => self.alt_char(b'['),
+            EscapeSequence::Ss3 if self.param_count == 0 => self.alt_char(b'O'),
+            // A sequence cut short: drop it
+            _ => None,
+        }
+    }
+
+    fn alt_char(&mut self, c: u8) -> Option<KeyEvent> {
+        match self.process_char(c) {
+            Some(mut event) => {
+                event.alt = true;
+                Some(event)
+            }
+            None => {
+                // The rest of a multi-byte character follows
+                self.utf8_alt = true;
+                None
+            }
+        }
+    }
+
+    fn start_sequence(&mut self, kind: EscapeSequence) {
+        self.escape_sequence = kind;
+        self.params = [0; MAX_PARAMS];
+        self.param_count = 0;
+        self.foreign = false;
+    }
+
+    fn sequence_byte(&mut self, c: u8) -> Option<KeyEvent> {
+        match c {
+            b'0'..=b'9' => {
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+                if let Some(param) = self.params.get_mut(self.param_count - 1) {
+                    *param = param.saturating_mul(10).saturating_add((c - b'0') as u16);
+                }
+                None
+            }
+            b';' => {
+                // An empty first parameter still counts: ESC[;5C
+                self.param_count = self.param_count.max(1) + 1;
+                None
+            }
+            b'<'..=b'?' | b' '..=b'/' | b':' => {
+                // Private markers (ESC[?...), intermediates and sub-parameters
+                self.foreign = true;
+                None
+            }
+            b'~' if !self.foreign && matches!(self.param(0), PASTE_START | PASTE_END) => {
+                self.escape_sequence = EscapeSequence::None;
+                self.paste_marker(self.param(0) == PASTE_START)
+            }
+            0x40..=0x7E => {
+                let kind = self.escape_sequence;
+                self.escape_sequence = EscapeSequence::None;
+                Some(self.finish_sequence(kind, c))
+            }
+            0x1B => {
+                // Broken sequence; start over
+                self.escape_sequence = EscapeSequence::Escape;
+                None
+            }
+            _ => {
+                // Not part of any sequence: give up on it and decode the byte
+                self.escape_sequence = EscapeSequence::None;
+                self.process_char(c)
+            }
+        }
+    }
+
+    fn paste_marker(&mut self, start: bool) -> Option<KeyEvent> {
+        if start {
+            self.pasting = true;
+            self.paste.clear();
+            self.paste_after_cr = false;
+            self.paste_full = false;
+            self.utf8_needed = 0;
+            None
+        } else if self.pasting {
+            self.pasting = false;
+            self.utf8_needed = 0;
+            Some(KeyEvent::new(Key::Paste))
+        } else {
+            // An end marker without a start
+            None
+        }
+    }
+
+    /// One byte of pasted text. Line breaks arrive as CR (or CR LF) and are
+    /// stored as '\n'; other control characters except Tab are dropped.
+    fn paste_byte(&mut self, c: u8) {
+        let after_cr = core::mem::replace(&mut self.paste_after_cr, c == b'\r');
+        let decoded = match c {
+            b'\r' => Some('\n'),
+            b'\n' if after_cr => None,
+            b'\n' | b'\t' => Some(c as char),
+            0x00..=0x1F | 0x7F => None,
+            _ => match self.process_char(c) {
+                Some(KeyEvent { key: Key::Char(ch), .. }) => Some(ch),
+                _ => None,
+            },
+        };
+        if let Some(ch) = decoded {
+            if !self.paste_full && !self.paste.push(ch) {
+                self.paste_full = true;
+            }
+        }
+    }
+
+    fn param(&self, index: usize) -> u16 {
+        if index < self.param_count { self.params[index] } else { 0 }
+    }
+
+    fn finish_sequence(&self, kind: EscapeSequence, final_byte: u8) -> KeyEvent {
+        if self.foreign {
+            return KeyEvent::new(Key::Unknown);
+        }
+
+        let key = match final_byte {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'P'..=b'S' => Key::F(final_byte - b'P' + 1),
+            b'Z' if kind == EscapeSequence::Csi => Key::Tab, // Shift+Tab
+            b'~' if kind == EscapeSequence::Csi => tilde_key(self.param(0)),
+            _ => Key::Unknown,
+        };
+        // CSI has the modifier second (ESC[1;5C); SS3 only ever has that one
+        // parameter (ESC O5A, from some terminals)
+        let modifiers = if kind == EscapeSequence::Ss3 { self.param(0) } else { self.param(1) };
+
+        // xterm modifier parameter: 1 + (Shift 1 | Alt 2 | Ctrl 4 | Meta 8)
+        let bits = modifiers.saturating_sub(1);
+        KeyEvent {
+            key,
+            meta: self.meta_pressed || bits & 8 != 0,
+            ctrl: bits & 4 != 0,
+            shift: bits & 1 != 0 || final_byte == b'Z',
+            alt: bits & 2 != 0,
+        }
+    }
+
+    fn process_char(&mut self, c: u8) -> Option<KeyEvent> {
+        if c >= 0x80 {
+            return self.utf8_byte(c);
+        }
+        // Anything else cuts a multi-byte character short
+        self.utf8_needed = 0;
+
+        // Handle Ctrl+key combinations (Ctrl+A = 0x01, etc.)
+        if c < 0x20 || c == 0x7F {
+            let event = match c {
+                0x0A | 0x0D => KeyEvent::new(Key::Enter),  // Enter (LF or CR)
+                0x7F | 0x08 => KeyEvent::new(Key::Backspace),  // Backspace or DEL
+                0x09 => KeyEvent::new(Key::Tab),
+                0x01..=0x1A => {
+                    // Ctrl+letter: report the letter, as a keyboard would
+                    KeyEvent {
+                        ctrl: true,
+                        ..KeyEvent::new(Key::Char((b'a' + c - 1) as char))
+                    }
+                }
+                _ => {
+                    // Other control characters
+                    KeyEvent {
+                        ctrl: true,
+                        ..KeyEvent::new(Key::Char(c as char))
+                    }
+                }
+            };
+            return Some(event);
+        }
+
+        // Regular printable character
+        Some(self.char_event(c as char))
+    }
+
+    fn char_event(&self, c: char) -> KeyEvent {
+        KeyEvent {
+            meta: self.meta_pressed,
+            shift: c.is_uppercase(),
+            ..KeyEvent::new(Key::Char(c))
+        }
+    }
+
+    fn utf8_byte(&mut self, c: u8) -> Option<KeyEvent> {
+        if c & 0xC0 == 0x80 {
+            // Continuation byte
+            if self.utf8_needed == 0 {
+                return Some(self.char_event(char::REPLACEMENT_CHARACTER));
+            }
+            self.utf8[self.utf8_len] = c;
+            self.utf8_len += 1;
+            if self.utf8_len < self.utf8_needed {
+                return None;
+            }
+            self.utf8_needed = 0;
+            // from_utf8 rejects overlong forms and surrogates
+            let decoded = core::str::from_utf8(&self.utf8[..self.utf8_len])
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            let mut event = self.char_event(decoded);
+            event.alt = core::mem::take(&mut self.utf8_alt);
+            return Some(event);
+        }
+
+        // Lead byte; an unfinished character before it is dropped
+        self.utf8_needed = match c {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => 0,
+        };
+        if self.utf8_needed == 0 {
+            return Some(self.char_event(char::REPLACEMENT_CHARACTER));
+        }
+        self.utf8[0] = c;
+        self.utf8_len = 1;
+        self.utf8_alt = false;
+        None
+    }
+}
+
+/// Keys sent as ESC [ <code> ~ (VT220 style)
+fn tilde_key(code: u16) -> Key {
+    match code {
+        1 | 7 => Key::Home,
+        2 => Key::Insert,
+        3 => Key::Delete,
+        4 | 8 => Key::End,
+        5 => Key::PageUp,
+        6 => Key::PageDown,
+        11..=15 => Key::F((code - 10) as u8),
+        17..=21 => Key::F((code - 11) as u8),
+        23 | 24 => Key::F((code - 12) as u8),
+        _ => Key::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ManualClock, ScriptedInput};
+
+    /// Poll until the script runs dry, collecting the decoded events
+    fn decode(bytes: &'static [u8]) -> Vec<KeyEvent> {
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(bytes), ManualClock::leak());
+        (0..bytes.len() + 1).filter_map(|_| keyboard.poll()).collect()
+    }
+
+    fn keys(bytes: &'static [u8]) -> Vec<Key> {
+        decode(bytes).iter().map(|e| e.key).collect()
+    }
+
+    #[test]
+    fn printable_characters() {
+        let events = decode(b"aZ 9~");
+        let keys: Vec<Key> = events.iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Char('a'), Key::Char('Z'), Key::Char(' '), Key::Char('9'), Key::Char('~')]);
+        assert!(!events[0].shift);
+        assert!(events[1].shift);
+        assert!(events.iter().all(|e| !e.ctrl && !e.meta));
+    }
+
+    #[test]
+    fn enter_and_backspace() {
+        assert_eq!(keys(b"\r\n\x08\x7f"), [Key::Enter, Key::Enter, Key::Backspace, Key::Backspace]);
+    }
+
+    #[test]
+    fn control_characters_set_ctrl() {
+        let events = decode(b"\x01\x03\x13");
+        let keys: Vec<Key> = events.iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Char('a'), Key::Char('c'), Key::Char('s')]);
+        assert!(events.iter().all(|e| e.ctrl));
+    }
+
+    #[test]
+    fn arrow_keys() {
+        let events = decode(b"\x1b[A\x1b[B\x1b[C\x1b[D");
+        let keys: Vec<Key> = events.iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Up, Key::Down, Key::Right, Key::Left]);
+        assert!(events.iter().all(|e| !e.ctrl));
+    }
+
+    #[test]
+    fn ctrl_arrow_keys() {
+        let events = decode(b"\x1b[1;5A\x1b[1;5B\x1b[1;5C\x1b[1;5D");
+        let keys: Vec<Key> = events.iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Up, Key::Down, Key::Right, Key::Left]);
+        assert!(events.iter().all(|e| e.ctrl));
+    }
+
+    #[test]
+    fn sequences_produce_nothing_until_complete() {
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(b"\x1b[1;5C"), ManualClock::leak());
+        for _ in 0..5 {
+            assert_eq!(keyboard.poll(), None);
+        }
+        assert_eq!(keyboard.poll().map(|e| e.key), Some(Key::Right));
+        assert_eq!(keyboard.poll(), None);
+    }
+
+    #[test]
+    fn unknown_final_bytes() {
+        assert_eq!(keys(b"\x1b[Y\x1b[1;5Y\x1bOZ\x1b[99~"), [Key::Unknown; 4]);
+    }
+
+    #[test]
+    fn escape_followed_by_a_key_is_alt() {
+        let events = decode(b"\x1bx\x1bX\x1b\x7f\x1b\x01");
+        let keys: Vec<Key> = events.iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Char('x'), Key::Char('X'), Key::Backspace, Key::Char('a')]);
+        assert!(events.iter().all(|e| e.alt));
+        assert!(events[1].shift);
+        assert!(events[3].ctrl);
+    }
+
+    #[test]
+    fn double_escape_is_the_escape_key() {
+        assert_eq!(keys(b"\x1b\x1b[A"), [Key::Escape, Key::Up]);
+    }
+
+    #[test]
+    fn editing_keys() {
+        assert_eq!(
+            keys(b"\x1b[H\x1b[F\x1b[1~\x1b[4~\x1b[7~\x1b[8~\x1bOH\x1bOF"),
+            [Key::Home, Key::End, Key::Home, Key::End, Key::Home, Key::End, Key::Home, Key::End]
+        );
+        assert_eq!(
+            keys(b"\x1b[2~\x1b[3~\x1b[5~\x1b[6~"),
+            [Key::Insert, Key::Delete, Key::PageUp, Key::PageDown]
+        );
+    }
+
+    #[test]
+    fn function_keys() {
+        let expected: Vec<Key> = (1..=12).map(Key::F).collect();
+        assert_eq!(
+            keys(b"\x1bOP\x1bOQ\x1bOR\x1bOS\x1b[15~\x1b[17~\x1b[18~\x1b[19~\x1b[20~\x1b[21~\x1b[23~\x1b[24~"),
+            expected
+        );
+        assert_eq!(keys(b"\x1b[11~\x1b[12~\x1b[13~\x1b[14~"), expected[..4]);
+    }
+
+    #[test]
+    fn modifier_parameters() {
+        let events = decode(b"\x1b[1;2A\x1b[1;3B\x1b[1;6C\x1b[3;5~\x1b[1;8P\x1b[1;9H\x1bO5D");
+        let flags: Vec<(Key, bool, bool, bool, bool)> =
+            events.iter().map(|e| (e.key, e.shift, e.alt, e.ctrl, e.meta)).collect();
+        assert_eq!(
+            flags,
+            [
+                (Key::Up, true, false, false, false),
+                (Key::Down, false, true, false, false),
+                (Key::Right, true, false, true, false),
+                (Key::Delete, false, false, true, false),
+                (Key::F(1), true, true, true, false),
+                (Key::Home, false, false, false, true),
+                (Key::Left, false, false, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn tab_and_shift_tab() {
+        let events = decode(b"\t\x1b[Z");
+        assert_eq!(events.iter().map(|e| e.key).collect::<Vec<_>>(), [Key::Tab, Key::Tab]);
+        assert!(!events[0].shift && events[1].shift);
+    }
+
+    #[test]
+    fn private_sequences_are_not_keys() {
+        // Mouse report and a sub-parameter form; the text after them survives
+        assert_eq!(keys(b"\x1b[<0;3;4M\x1b[1:2Aok"), [Key::Unknown, Key::Unknown, Key::Char('o'), Key::Char('k')]);
+    }
+
+    #[test]
+    fn interrupted_sequences_recover() {
+        // A new ESC restarts the parse; a control byte ends it and is decoded
+        assert_eq!(keys(b"\x1b[1;\x1b[B\x1b[2\r"), [Key::Down, Key::Enter]);
+    }
+
+    #[test]
+    fn text_after_a_sequence_decodes_normally() {
+        assert_eq!(keys(b"\x1b[Cok\r"), [Key::Right, Key::Char('o'), Key::Char('k'), Key::Enter]);
+    }
+
+    #[test]
+    fn lone_escape_after_timeout() {
+        let clock = ManualClock::leak();
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(b"\x1bn"), clock);
+        assert_eq!(keyboard.poll(), None);
+        clock.advance(ESCAPE_TIMEOUT_MS - 1);
+        // What poll does while the source is idle
+        assert_eq!(keyboard.check_timeout(), None);
+        clock.advance(1);
+        assert_eq!(keyboard.check_timeout(), Some(KeyEvent::new(Key::Escape)));
+        // The next keystroke is a key of its own, not swallowed or Alt+n
+        assert_eq!(keyboard.poll(), Some(KeyEvent::new(Key::Char('n'))));
+    }
+
+    #[test]
+    fn timeout_needs_an_idle_source() {
+        let clock = ManualClock::leak();
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(b"\x1b"), clock);
+        assert_eq!(keyboard.poll(), None);
+        assert_eq!(keyboard.poll(), None);
+        clock.advance(ESCAPE_TIMEOUT_MS);
+        assert_eq!(keyboard.poll(), Some(KeyEvent::new(Key::Escape)));
+        assert_eq!(keyboard.poll(), None);
+    }
+
+    #[test]
+    fn byte_read_after_the_timeout_is_not_alt() {
+        let clock = ManualClock::leak();
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(b"\x1bn\x1b[A"), clock);
+        assert_eq!(keyboard.poll(), None);
+        // Busy elsewhere: the source is never seen idle before 'n' is read
+        clock.advance(ESCAPE_TIMEOUT_MS);
+        assert_eq!(keyboard.poll(), Some(KeyEvent::new(Key::Escape)));
+        assert_eq!(keyboard.poll(), Some(KeyEvent::new(Key::Char('n'))));
+        // A sequence read in one go still decodes
+        assert_eq!(keyboard.poll(), None);
+        assert_eq!(keyboard.poll(), None);
+        assert_eq!(keyboard.poll().map(|e| e.key), Some(Key::Up));
+    }
+
+    #[test]
+    fn escape_bracket_alone_is_alt_bracket() {
+        let clock = ManualClock::leak();
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(b"\x1b["), clock);
+        keyboard.poll();
+        keyboard.poll();
+        clock.advance(ESCAPE_TIMEOUT_MS);
+        let event = keyboard.poll().unwrap();
+        assert_eq!((event.key, event.alt), (Key::Char('['), true));
+    }
+
+    #[test]
+    fn truncated_sequence_is_dropped() {
+        let clock = ManualClock::leak();
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(b"\x1b[1;5"), clock);
+        for _ in 0..5 {
+            assert_eq!(keyboard.poll(), None);
+        }
+        clock.advance(ESCAPE_TIMEOUT_MS);
+        assert_eq!(keyboard.poll(), None);
+        assert_eq!(keyboard.escape_sequence, EscapeSequence::None);
+    }
+
+    #[test]
+    fn utf8_characters() {
+        let events = decode("åÄ日😀".as_bytes());
+        let keys: Vec<Key> = events.iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Char('å'), Key::Char('Ä'), Key::Char('日'), Key::Char('😀')]);
+        assert!(!events[0].shift && events[1].shift);
+    }
+
+    #[test]
+    fn invalid_utf8_becomes_replacement_characters() {
+        // Stray continuation, invalid lead, overlong encoding, cut-short character
+        assert_eq!(
+            keys(b"\x80\xff\xc0\xe0\x80\x80\xc3a"),
+            [
+                Key::Char('\u{FFFD}'),
+                Key::Char('\u{FFFD}'),
+                Key::Char('\u{FFFD}'),
+                Key::Char('\u{FFFD}'),
+                Key::Char('a'),
+            ]
+        );
+    }
+
+    #[test]
+    fn bracketed_paste_is_one_event() {
+        let mut keyboard = Keyboard::new(
+            ScriptedInput::leak("x\x1b[200~ls -l\r\nrm *\rå\t\x03\x1b[A\x1b[201~y".as_bytes()),
+            ManualClock::leak(),
+        );
+        let keys: Vec<Key> = (0..40).filter_map(|_| keyboard.poll()).map(|e| e.key).collect();
+        // Enter, Ctrl+C and the arrow inside the paste are text or dropped, not keys
+        assert_eq!(keys, [Key::Char('x'), Key::Paste, Key::Char('y')]);
+        assert_eq!(keyboard.pasted(), "ls -l\nrm *\nå\t");
+    }
+
+    #[test]
+    fn paste_is_cut_off_when_full() {
+        let mut input = b"\x1b[200~".to_vec();
+        input.extend(vec![b'a'; MAX_PASTE_LEN - 1]);
+        input.extend_from_slice("日b\x1b[201~".as_bytes());
+        let mut keyboard = Keyboard::new(ScriptedInput::leak(input.leak()), ManualClock::leak());
+        let keys: Vec<Key> = (0..MAX_PASTE_LEN + 20).filter_map(|_| keyboard.poll()).map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Paste]);
+        assert_eq!(keyboard.pasted().len(), MAX_PASTE_LEN - 1);
+    }
+
+    #[test]
+    fn paste_end_without_start_is_ignored() {
+        assert_eq!(keys(b"\x1b[201~a"), [Key::Char('a')]);
+    }
+
+    #[test]
+    fn alt_with_a_multibyte_character() {
+        let events = decode("\x1bå\x1b[Cö".as_bytes());
+        let keys: Vec<(Key, bool)> = events.iter().map(|e| (e.key, e.alt)).collect();
+        assert_eq!(keys, [(Key::Char('å'), true), (Key::Right, false), (Key::Char('ö'), false)]);
+    }
+}
@@ -0,0 +1,593 @@
+// Key bindings - maps key combinations to named actions
+// The shell and the editor ask the keymap what a key press means instead of
+// checking for particular keys, so shortcuts can be changed at runtime with
+// `bind`/`unbind` or from a config file of the same commands, which are
+// applied on top of the defaults:
+//
+//   # comment
+//   bind alt+right desktop.next
+//   unbind ctrl+n
+
+use core::fmt::{self, Write};
+
+use crate::keyboard::{Key, KeyEvent};
+
+pub const MAX_BINDINGS: usize = 64;
+
+/// Where an action applies; a key can mean one thing in the shell and
+/// another in the editor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Desktop,
+    // The shell's input line, for keys the desktop does not use
+    Line,
+    Editor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    DesktopNext,
+    DesktopPrevious,
+    DesktopRename,
+    DesktopMoveLeft,
+    DesktopMoveRight,
+    // Straight to the desktop with this number, from 1
+    DesktopJump(usize),
+    ScrollbackUp,
+    ScrollbackDown,
+    PaneSplitVertical,
+    PaneSplitHorizontal,
+    PaneClose,
+    PaneNext,
+    PanePrevious,
+    PaneResizeLeft,
+    PaneResizeRight,
+    PaneResizeUp,
+    PaneResizeDown,
+    PaneFocusLeft,
+    PaneFocusRight,
+    PaneFocusUp,
+    PaneFocusDown,
+    PaneSwapNext,
+    PaneSwapPrevious,
+    PaneZoom,
+    LineStart,
+    LineEnd,
+    LineWordLeft,
+    LineWordRight,
+    LineKillToEnd,
+    LineKillToStart,
+    LineKillWord,
+    LineComplete,
+    HistoryPrevious,
+    HistoryNext,
+    HistorySearch,
+    EditorSave,
+    EditorQuit,
+    EditorSaveAndQuit,
+    EditorUndo,
+}
+
+const ACTIONS: [(Action, &str); 47] = [
+    (Action::DesktopNext, "desktop.next"),
+    (Action::DesktopPrevious, "desktop.previous"),
+    (Action::DesktopRename, "desktop.rename"),
+    (Action::DesktopMoveLeft, "desktop.move-left"),
+    (Action::DesktopMoveRight, "desktop.move-right"),
+    (Action::DesktopJump(1), "desktop.jump-1"),
+    (Action::DesktopJump(2), "desktop.jump-2"),
+    (Action::DesktopJump(3), "desktop.jump-3"),
+    (Action::DesktopJump(4), "desktop.jump-4"),
+    (Action::DesktopJump(5), "desktop.jump-5"),
+    (Action::DesktopJump(6), "desktop.jump-6"),
+    (Action::DesktopJump(7), "desktop.jump-7"),
+    (Action::DesktopJump(8), "desktop.jump-8"),
+    (Action::DesktopJump(9), "desktop.jump-9"),
+    (Action::ScrollbackUp, "scrollback.up"),
+    (Action::ScrollbackDown, "scrollback.down"),
+    (Action::PaneSplitVertical, "pane.split-vertical"),
+    (Action::PaneSplitHorizontal, "pane.split-horizontal"),
+    (Action::PaneClose, "pane.close"),
+    (Action::PaneNext, "pane.next"),
+    (Action::PanePrevious, "pane.previous"),
+    (Action::PaneResizeLeft, "pane.resize-left"),
+    (Action::PaneResizeRight, "pane.resize-right"),
+    (Action::PaneResizeUp, "pane.resize-up"),
+    (Action::PaneResizeDown, "pane.resize-down"),
+    (Action::PaneFocusLeft, "pane.focus-left"),
+    (Action::PaneFocusRight, "pane.focus-right"),
+    (Action::PaneFocusUp, "pane.focus-up"),
+    (Action::PaneFocusDown, "pane.focus-down"),
+    (Action::PaneSwapNext, "pane.swap-next"),
+    (Action::PaneSwapPrevious, "pane.swap-previous"),
+    (Action::PaneZoom, "pane.zoom"),
+    (Action::LineStart, "line.start"),
+    (Action::LineEnd, "line.end"),
+    (Action::LineWordLeft, "line.word-left"),
+    (Action::LineWordRight, "line.word-right"),
+    (Action::LineKillToEnd, "line.kill-to-end"),
+    (Action::LineKillToStart, "line.kill-to-start"),
+    (Action::LineKillWord, "line.kill-word"),
+    (Action::LineComplete, "line.complete"),
+    (Action::HistoryPrevious, "history.previous"),
+    (Action::HistoryNext, "history.next"),
+    (Action::HistorySearch, "history.search"),
+    (Action::EditorSave, "editor.save"),
+    (Action::EditorQuit, "editor.quit"),
+    (Action::EditorSaveAndQuit, "editor.save-and-quit"),
+    (Action::EditorUndo, "editor.undo"),
+];
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        ACTIONS.iter().find(|(action, _)| *action == self).map_or("", |(_, name)| name)
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|(_, n)| *n == name).map(|(action, _)| *action)
+    }
+
+    /// Every action, for listing
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTIONS.iter().map(|(action, _)| *action)
+    }
+
+    pub fn scope(self) -> Scope {
+        match self {
+            Action::DesktopNext
+            | Action::DesktopPrevious
+            | Action::DesktopRename
+            | Action::DesktopMoveLeft
+            | Action::DesktopMoveRight
+            | Action::DesktopJump(_)
+            | Action::ScrollbackUp
+            | Action::ScrollbackDown
+            | Action::PaneSplitVertical
+            | Action::PaneSplitHorizontal
+            | Action::PaneClose
+            | Action::PaneNext
+            | Action::PanePrevious
+            | Action::PaneResizeLeft
+            | Action::PaneResizeRight
+            | Action::PaneResizeUp
+            | Action::PaneResizeDown
+            | Action::PaneFocusLeft
+            | Action::PaneFocusRight
+            | Action::PaneFocusUp
+            | Action::PaneFocusDown
+            | Action::PaneSwapNext
+            | Action::PaneSwapPrevious
+            | Action::PaneZoom => Scope::Desktop,
+            Action::LineStart
+            | Action::LineEnd
+            | Action::LineWordLeft
+            | Action::LineWordRight
+            | Action::LineKillToEnd
+            | Action::LineKillToStart
+            | Action::LineKillWord
+            | Action::LineComplete
+            | Action::HistoryPrevious
+            | Action::HistoryNext
+            | Action::HistorySearch => Scope::Line,
+            _ => Scope::Editor,
+        }
+    }
+}
+
+/// A key with modifiers, written like `ctrl+shift+f5`. Letters match in
+/// either case, and Shift only counts when the pattern asks for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPattern {
+    pub key: Key,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+}
+
+const KEY_NAMES: [(Key, &str); 16] = [
+    (Key::Enter, "enter"),
+    (Key::Tab, "tab"),
+    (Key::Backspace, "backspace"),
+    (Key::Escape, "esc"),
+    (Key::Up, "up"),
+    (Key::Down, "down"),
+    (Key::Left, "left"),
+    (Key::Right, "right"),
+    (Key::Home, "home"),
+    (Key::End, "end"),
+    (Key::Insert, "insert"),
+    (Key::Delete, "delete"),
+    (Key::PageUp, "pageup"),
+    (Key::PageDown, "pagedown"),
+    (Key::Char(' '), "space"),
+    (Key::Char('+'), "plus"),
+];
+
+impl KeyPattern {
+    pub const fn new(key: Key) -> Self {
+        KeyPattern { key, ctrl: false, alt: false, shift: false, meta: false }
+    }
+
+    pub const fn ctrl(key: Key) -> Self {
+        KeyPattern { ctrl: true, ..KeyPattern::new(key) }
+    }
+
+    pub const fn alt(key: Key) -> Self {
+        KeyPattern { alt: true, ..KeyPattern::new(key) }
+    }
+
+    pub const fn meta(key: Key) -> Self {
+        KeyPattern { meta: true, ..KeyPattern::new(key) }
+    }
+
+    pub const fn shift(key: Key) -> Self {
+        KeyPattern { shift: true, ..KeyPattern::new(key) }
+    }
+
+    /// Parse `ctrl+alt+x`, `shift+f5`, `meta+left`...
+    pub fn parse(text: &str) -> Result<KeyPattern, &'static str> {
+        let mut pattern = KeyPattern::new(Key::Unknown);
+        let mut parts = text.split('+').peekable();
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                pattern.key = parse_key(part).ok_or("unknown key name")?;
+                break;
+            }
+            match part {
+                "ctrl" => pattern.ctrl = true,
+                "alt" => pattern.alt = true,
+                "shift" => pattern.shift = true,
+                "meta" => pattern.meta = true,
+                _ => return Err("unknown modifier (ctrl, alt, shift or meta)"),
+            }
+        }
+        Ok(pattern)
+    }
+
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let key = match event.key {
+            Key::Char(c) => Key::Char(c.to_ascii_lowercase()),
+            key => key,
+        };
+        key == self.key
+            && event.ctrl == self.ctrl
+            && event.alt == self.alt
+            && event.meta == self.meta
+            && (event.shift || !self.shift)
+    }
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    if let Some((key, _)) = KEY_NAMES.iter().find(|(_, n)| *n == name) {
+        return Some(*key);
+    }
+    if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        return (1..=12).contains(&number).then_some(Key::F(number));
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_control() => Some(Key::Char(c.to_ascii_lowercase())),
+        _ => None,
+    }
+}
+
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (held, name) in [(self.ctrl, "ctrl+"), (self.alt, "alt+"), (self.shift, "shift+"), (self.meta, "meta+")] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        match KEY_NAMES.iter().find(|(key, _)| *key == self.key) {
+            Some((_, name)) => f.write_str(name),
+            None => match self.key {
+                Key::F(n) => write!(f, "f{}", n),
+                Key::Char(c) => f.write_char(c),
+                _ => f.write_str("?"),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub pattern: KeyPattern,
+    pub action: Action,
+}
+
+const DEFAULT_BINDINGS: [Binding; 53] = [
+    Binding { pattern: KeyPattern::ctrl(Key::Right), action: Action::DesktopNext },
+    Binding { pattern: KeyPattern::ctrl(Key::Left), action: Action::DesktopPrevious },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('n')), action: Action::DesktopRename },
+    Binding { pattern: KeyPattern { shift: true, ..KeyPattern::ctrl(Key::Left) }, action: Action::DesktopMoveLeft },
+    Binding { pattern: KeyPattern { shift: true, ..KeyPattern::ctrl(Key::Right) }, action: Action::DesktopMoveRight },
+    Binding { pattern: KeyPattern::alt(Key::Char('1')), action: Action::DesktopJump(1) },
+    Binding { pattern: KeyPattern::alt(Key::Char('2')), action: Action::DesktopJump(2) },
+    Binding { pattern: KeyPattern::alt(Key::Char('3')), action: Action::DesktopJump(3) },
+    Binding { pattern: KeyPattern::alt(Key::Char('4')), action: Action::DesktopJump(4) },
+    Binding { pattern: KeyPattern::alt(Key::Char('5')), action: Action::DesktopJump(5) },
+    Binding { pattern: KeyPattern::alt(Key::Char('6')), action: Action::DesktopJump(6) },
+    Binding { pattern: KeyPattern::alt(Key::Char('7')), action: Action::DesktopJump(7) },
+    Binding { pattern: KeyPattern::alt(Key::Char('8')), action: Action::DesktopJump(8) },
+    Binding { pattern: KeyPattern::alt(Key::Char('9')), action: Action::DesktopJump(9) },
+    Binding { pattern: KeyPattern::shift(Key::PageUp), action: Action::ScrollbackUp },
+    Binding { pattern: KeyPattern::shift(Key::PageDown), action: Action::ScrollbackDown },
+    Binding { pattern: KeyPattern::alt(Key::Char('|')), action: Action::PaneSplitVertical },
+    Binding { pattern: KeyPattern::alt(Key::Char('-')), action: Action::PaneSplitHorizontal },
+    Binding { pattern: KeyPattern::alt(Key::Char('x')), action: Action::PaneClose },
+    Binding { pattern: KeyPattern::alt(Key::Char('o')), action: Action::PaneNext },
+    Binding { pattern: KeyPattern::alt(Key::Char('p')), action: Action::PanePrevious },
+    Binding { pattern: KeyPattern { shift: true, ..KeyPattern::alt(Key::Left) }, action: Action::PaneResizeLeft },
+    Binding { pattern: KeyPattern { shift: true, ..KeyPattern::alt(Key::Right) }, action: Action::PaneResizeRight },
+    Binding { pattern: KeyPattern { shift: true, ..KeyPattern::alt(Key::Up) }, action: Action::PaneResizeUp },
+    Binding { pattern: KeyPattern { shift: true, ..KeyPattern::alt(Key::Down) }, action: Action::PaneResizeDown },
+    Binding { pattern: KeyPattern { meta: true, ..KeyPattern::ctrl(Key::Left) }, action: Action::PaneFocusLeft },
+    Binding { pattern: KeyPattern { meta: true, ..KeyPattern::ctrl(Key::Right) }, action: Action::PaneFocusRight },
+    Binding { pattern: KeyPattern { meta: true, ..KeyPattern::ctrl(Key::Up) }, action: Action::PaneFocusUp },
+    Binding { pattern: KeyPattern { meta: true, ..KeyPattern::ctrl(Key::Down) }, action: Action::PaneFocusDown },
+    // For terminals that cannot send Meta
+    Binding { pattern: KeyPattern { alt: true, ..KeyPattern::ctrl(Key::Left) }, action: Action::PaneFocusLeft },
+    Binding { pattern: KeyPattern { alt: true, ..KeyPattern::ctrl(Key::Right) }, action: Action::PaneFocusRight },
+    Binding { pattern: KeyPattern { alt: true, ..KeyPattern::ctrl(Key::Up) }, action: Action::PaneFocusUp },
+    Binding { pattern: KeyPattern { alt: true, ..KeyPattern::ctrl(Key::Down) }, action: Action::PaneFocusDown },
+    Binding { pattern: KeyPattern::alt(Key::Char('}')), action: Action::PaneSwapNext },
+    Binding { pattern: KeyPattern::alt(Key::Char('{')), action: Action::PaneSwapPrevious },
+    Binding { pattern: KeyPattern::alt(Key::Char('z')), action: Action::PaneZoom },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('a')), action: Action::LineStart },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('e')), action: Action::LineEnd },
+    Binding { pattern: KeyPattern::alt(Key::Char('b')), action: Action::LineWordLeft },
+    Binding { pattern: KeyPattern::alt(Key::Left), action: Action::LineWordLeft },
+    Binding { pattern: KeyPattern::alt(Key::Char('f')), action: Action::LineWordRight },
+    Binding { pattern: KeyPattern::alt(Key::Right), action: Action::LineWordRight },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('k')), action: Action::LineKillToEnd },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('u')), action: Action::LineKillToStart },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('w')), action: Action::LineKillWord },
+    Binding { pattern: KeyPattern::new(Key::Tab), action: Action::LineComplete },
+    Binding { pattern: KeyPattern::new(Key::Up), action: Action::HistoryPrevious },
+    Binding { pattern: KeyPattern::new(Key::Down), action: Action::HistoryNext },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('r')), action: Action::HistorySearch },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('s')), action: Action::EditorSave },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('q')), action: Action::EditorQuit },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('x')), action: Action::EditorSaveAndQuit },
+    Binding { pattern: KeyPattern::ctrl(Key::Char('z')), action: Action::EditorUndo },
+];
+
+#[derive(Clone, Copy)]
+pub struct Keymap {
+    bindings: [Option<Binding>; MAX_BINDINGS],
+}
+
+impl Keymap {
+    pub const fn empty() -> Self {
+        Keymap { bindings: [None; MAX_BINDINGS] }
+    }
+
+    /// Install the default bindings, dropping any changes
+    pub fn init(&mut self) {
+        self.bindings = [None; MAX_BINDINGS];
+        for (slot, binding) in self.bindings.iter_mut().zip(DEFAULT_BINDINGS) {
+            *slot = Some(binding);
+        }
+    }
+
+    /// What the key press means in `scope`, if anything
+    pub fn action(&self, event: &KeyEvent, scope: Scope) -> Option<Action> {
+        self.iter()
+            .find(|b| b.action.scope() == scope && b.pattern.matches(event))
+            .map(|b| b.action)
+    }
+
+    /// Bind a key; it replaces what the key did in the action's scope
+    pub fn bind(&mut self, pattern: KeyPattern, action: Action) -> Result<(), &'static str> {
+        let slot = self
+            .bindings
+            .iter()
+            .position(|b| matches!(b, Some(b) if b.pattern == pattern && b.action.scope() == action.scope()))
+            .or_else(|| self.bindings.iter().position(|b| b.is_none()))
+            .ok_or("too many key bindings")?;
+        self.bindings[slot] = Some(Binding { pattern, action });
+        Ok(())
+    }
+
+    /// Remove every binding of the key; false if it had none
+    pub fn unbind(&mut self, pattern: KeyPattern) -> bool {
+        let mut found = false;
+        for slot in self.bindings.iter_mut() {
+            if matches!(slot, Some(b) if b.pattern == pattern) {
+                *slot = None;
+                found = true;
+            }
+        }
+        found
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Binding> {
+        self.bindings.iter().flatten()
+    }
+
+    /// Apply one `bind <keys> <action>` or `unbind <keys>` command
+    pub fn apply(&mut self, command: &str) -> Result<(), &'static str> {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("bind"), Some(keys), Some(action), None) => {
+                let action = Action::from_name(action).ok_or("unknown action")?;
+                self.bind(KeyPattern::parse(keys)?, action)
+            }
+            (Some("unbind"), Some(keys), None, _) => {
+                if self.unbind(KeyPattern::parse(keys)?) {
+                    Ok(())
+                } else {
+                    Err("key is not bound")
+                }
+            }
+            _ => Err("expected `bind <keys> <action>` or `unbind <keys>`"),
+        }
+    }
+
+    fn contains(&self, binding: &Binding) -> bool {
+        self.iter().any(|b| b == binding)
+    }
+
+    /// Replace the bindings with the defaults changed by a config file of
+    /// bind/unbind lines; blank lines and `#` comments are skipped. On a bad
+    /// line nothing changes and its number (from 1) comes back with the error.
+    pub fn load(&mut self, config: &str) -> Result<usize, (usize, &'static str)> {
+        let mut keymap = Keymap::empty();
+        keymap.init();
+        let mut applied = 0;
+        for (number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            keymap.apply(line).map_err(|e| (number + 1, e))?;
+            applied += 1;
+        }
+        *self = keymap;
+        Ok(applied)
+    }
+
+    /// How the bindings differ from the defaults, as a config file for `load`
+    pub fn save(&self, out: &mut impl Write) -> fmt::Result {
+        let mut defaults = Keymap::empty();
+        defaults.init();
+        // All unbinds first: unbinding a key drops it from every scope
+        for binding in defaults.iter().filter(|b| !self.contains(b)) {
+            writeln!(out, "unbind {}", binding.pattern)?;
+        }
+        for binding in self.iter().filter(|b| !defaults.contains(b)) {
+            writeln!(out, "bind {} {}", binding.pattern, binding.action.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::FixedString;
+
+    fn describe(pattern: &KeyPattern) -> FixedString<32> {
+        let mut text = FixedString::new();
+        write!(text, "{}", pattern).unwrap();
+        text
+    }
+
+    fn keymap() -> Keymap {
+        let mut keymap = Keymap::empty();
+        keymap.init();
+        keymap
+    }
+
+    fn ctrl(key: Key) -> KeyEvent {
+        KeyEvent { ctrl: true, ..KeyEvent::new(key) }
+    }
+
+    #[test]
+    fn defaults() {
+        let keymap = keymap();
+        assert_eq!(keymap.action(&ctrl(Key::Right), Scope::Desktop), Some(Action::DesktopNext));
+        assert_eq!(keymap.action(&ctrl(Key::Char('N')), Scope::Desktop), Some(Action::DesktopRename));
+        assert_eq!(keymap.action(&ctrl(Key::Char('s')), Scope::Editor), Some(Action::EditorSave));
+        // Scopes and modifiers keep keys apart
+        assert_eq!(keymap.action(&ctrl(Key::Char('s')), Scope::Desktop), None);
+        assert_eq!(keymap.action(&KeyEvent::new(Key::Char('s')), Scope::Editor), None);
+        let meta = KeyEvent { meta: true, ..ctrl(Key::Right) };
+        assert_eq!(keymap.action(&meta, Scope::Desktop), Some(Action::PaneFocusRight));
+        let alt = KeyEvent { alt: true, ..ctrl(Key::Up) };
+        assert_eq!(keymap.action(&alt, Scope::Desktop), Some(Action::PaneFocusUp));
+    }
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        for text in ["ctrl+right", "alt+x", "ctrl+shift+f5", "meta+space", "esc", "ctrl+alt+pageup", "ctrl+plus"] {
+            let pattern = KeyPattern::parse(text).unwrap();
+            assert_eq!(describe(&pattern).as_str(), text);
+        }
+        assert_eq!(KeyPattern::parse("ctrl+S"), Ok(KeyPattern::ctrl(Key::Char('s'))));
+        assert!(KeyPattern::parse("hyper+x").is_err());
+        assert!(KeyPattern::parse("ctrl+f13").is_err());
+        assert!(KeyPattern::parse("ctrl+").is_err());
+    }
+
+    #[test]
+    fn shift_only_counts_when_asked_for() {
+        let mut keymap = keymap();
+        let shifted = KeyEvent { shift: true, ..ctrl(Key::Right) };
+        assert_eq!(keymap.action(&shifted, Scope::Desktop), Some(Action::DesktopNext));
+        keymap.bind(KeyPattern::parse("ctrl+shift+f1").unwrap(), Action::EditorQuit).unwrap();
+        assert_eq!(keymap.action(&ctrl(Key::F(1)), Scope::Editor), None);
+        let event = KeyEvent { shift: true, ..ctrl(Key::F(1)) };
+        assert_eq!(keymap.action(&event, Scope::Editor), Some(Action::EditorQuit));
+    }
+
+    #[test]
+    fn bind_replaces_within_a_scope() {
+        let mut keymap = keymap();
+        let ctrl_n = KeyPattern::ctrl(Key::Char('n'));
+        keymap.bind(ctrl_n, Action::DesktopNext).unwrap();
+        keymap.bind(ctrl_n, Action::EditorSave).unwrap();
+        assert_eq!(keymap.action(&ctrl(Key::Char('n')), Scope::Desktop), Some(Action::DesktopNext));
+        assert_eq!(keymap.action(&ctrl(Key::Char('n')), Scope::Editor), Some(Action::EditorSave));
+        assert_eq!(keymap.iter().filter(|b| b.pattern == ctrl_n).count(), 2);
+
+        assert!(keymap.unbind(ctrl_n));
+        assert!(!keymap.unbind(ctrl_n));
+        assert_eq!(keymap.action(&ctrl(Key::Char('n')), Scope::Desktop), None);
+    }
+
+    #[test]
+    fn table_is_bounded() {
+        let mut keymap = Keymap::empty();
+        for n in 0..MAX_BINDINGS {
+            let key = Key::Char(char::from(b'a' + (n % 26) as u8));
+            let pattern = KeyPattern { alt: n / 26 == 1, shift: n >= 52, ..KeyPattern::ctrl(key) };
+            keymap.bind(pattern, Action::EditorSave).unwrap();
+        }
+        assert_eq!(keymap.bind(KeyPattern::new(Key::F(1)), Action::EditorSave), Err("too many key bindings"));
+    }
+
+    #[test]
+    fn load_config() {
+        let mut keymap = keymap();
+        let config = "# swap the desktop keys\nbind alt+right desktop.next\n\n  unbind ctrl+right\n";
+        assert_eq!(keymap.load(config), Ok(2));
+        let alt_right = KeyEvent { alt: true, ..KeyEvent::new(Key::Right) };
+        assert_eq!(keymap.action(&alt_right, Scope::Desktop), Some(Action::DesktopNext));
+        assert_eq!(keymap.action(&ctrl(Key::Right), Scope::Desktop), None);
+
+        // A bad file leaves the bindings alone
+        assert_eq!(keymap.load("bind ctrl+y editor.undo\nbind ctrl+y editor.redo\n"), Err((2, "unknown action")));
+        assert_eq!(keymap.load("bind ctrl+y"), Err((1, "expected `bind <keys> <action>` or `unbind <keys>`")));
+        assert_eq!(keymap.action(&ctrl(Key::Char('y')), Scope::Editor), None);
+        assert_eq!(keymap.action(&alt_right, Scope::Desktop), Some(Action::DesktopNext));
+
+        // Loading starts over from the defaults
+        assert_eq!(keymap.load(""), Ok(0));
+        assert_eq!(keymap.action(&ctrl(Key::Right), Scope::Desktop), Some(Action::DesktopNext));
+        assert_eq!(keymap.action(&alt_right, Scope::Desktop), None);
+    }
+
+    #[test]
+    fn save_writes_the_changes_and_loads_back() {
+        let mut keymap = keymap();
+        let mut config = String::new();
+        keymap.save(&mut config).unwrap();
+        assert_eq!(config, "");
+
+        keymap.apply("bind alt+u editor.undo").unwrap();
+        keymap.apply("bind ctrl+n desktop.next").unwrap();
+        keymap.apply("unbind ctrl+q").unwrap();
+        keymap.save(&mut config).unwrap();
+        assert_eq!(
+            config,
+            "unbind ctrl+n\nunbind ctrl+q\nbind ctrl+n desktop.next\nbind alt+u editor.undo\n"
+        );
+
+        let mut copy = Keymap::empty();
+        assert_eq!(copy.load(&config), Ok(4));
+        assert_eq!(copy.iter().count(), keymap.iter().count());
+        assert!(copy.iter().all(|b| keymap.contains(b)));
+    }
+}
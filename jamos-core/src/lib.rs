@@ -0,0 +1,21 @@
+// Hardware-independent parts of Jamos: terminal, filesystem, editor and
+// Wayland compositor. Everything talks to the outside world through the
+// CharSink/CharSource traits in `console` and the `Clock` in `clock`, so the
+// kernel plugs in its UART, timer or telnet sessions and host tests plug in
+// buffers.
+
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+pub mod clock;
+pub mod console;
+pub mod keyboard;
+pub mod keymap;
+pub mod terminal;
+pub mod filesystem;
+pub mod editor;
+pub mod wayland;
+pub mod text;
+pub mod utils;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,318 @@
+// Tab completion for the shell prompt
+// The word before the cursor is completed from a list of candidates: the
+// registered command names for the first word, and for the words after it
+// whatever the command's completer offers (subcommands, file names...).
+// The commands come from the shell's own registry through CommandSet, each
+// with its completer if it has one, so new commands bring their completion
+// with them.
+// One Tab fills in what all the candidates share; a second Tab with nothing
+// more to fill in lists them, as in bash.
+use core::fmt::{self, Write};
+
+use super::vdesktop::VirtualDesktop;
+use crate::text::{self, FixedString};
+
+pub const MAX_CANDIDATES: usize = 64;
+// Longer candidates (none of the commands, files or actions) are left out
+const MAX_CANDIDATE_LEN: usize = 32;
+
+/// Offers candidates for a word after the command; `Candidates::add` keeps
+/// those that fit what was typed
+pub type Completer = fn(&CompletionContext, &mut Candidates);
+
+/// The line up to the cursor, split into words at spaces
+pub struct CompletionContext<'a> {
+    line: &'a str,
+    // Start of the word being completed
+    start: usize,
+}
+
+impl<'a> CompletionContext<'a> {
+    pub fn new(line: &'a str) -> Self {
+        let start = line.rfind(' ').map_or(0, |space| space + 1);
+        CompletionContext { line, start }
+    }
+
+    /// Which word is being completed: 0 for the command, 1 for its first
+    /// argument and so on
+    pub fn position(&self) -> usize {
+        self.line[..self.start].split_whitespace().count()
+    }
+
+    /// Word `n` before the one being completed, 0 being the command
+    pub fn word(&self, n: usize) -> Option<&'a str> {
+        self.line[..self.start].split_whitespace().nth(n)
+    }
+
+    /// What has been typed of the word being completed
+    pub fn prefix(&self) -> &'a str {
+        &self.line[self.start..]
+    }
+}
+
+/// The candidates that start with the word typed, sorted and without repeats
+pub struct Candidates<'a> {
+    prefix: &'a str,
+    names: [FixedString<MAX_CANDIDATE_LEN>; MAX_CANDIDATES],
+    count: usize,
+}
+
+impl<'a> Candidates<'a> {
+    pub fn new(prefix: &'a str) -> Self {
+        Candidates {
+            prefix,
+            names: [FixedString::new(); MAX_CANDIDATES],
+            count: 0,
+        }
+    }
+
+    /// Offer a candidate; it is dropped if it does not start with the word
+    /// typed or there is no more room
+    pub fn add(&mut self, name: &str) {
+        if !name.starts_with(self.prefix) || name.len() > MAX_CANDIDATE_LEN || self.count == MAX_CANDIDATES {
+            return;
+        }
+        let names = &mut self.names[..self.count];
+        let Err(position) = names.binary_search_by(|candidate| candidate.as_str().cmp(name)) else {
+            return;
+        };
+        self.names.copy_within(position..self.count, position + 1);
+        self.names[position] = FixedString::from_str_truncated(name);
+        self.count += 1;
+    }
+
+    pub fn add_all(&mut self, names: &[&str]) {
+        for name in names {
+            self.add(name);
+        }
+    }
+
+    pub fn prefix(&self) -> &'a str {
+        self.prefix
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.names[..self.count].iter().map(|name| name.as_str())
+    }
+
+    /// The longest start all the candidates share, at least the prefix
+    pub fn common(&self) -> &str {
+        let Some(first) = self.iter().next() else {
+            return self.prefix;
+        };
+        let mut len = first.len();
+        for name in self.iter() {
+            len = first.bytes().zip(name.bytes()).take(len).take_while(|(a, b)| a == b).count();
+        }
+        &first[..text::floor_char_boundary(first.as_bytes(), len)]
+    }
+
+    /// The candidates in columns down then across, as bash lists them, in
+    /// `width` columns of screen
+    pub fn write_columns(&self, out: &mut impl Write, width: usize) -> fmt::Result {
+        let widest = self.iter().map(|name| text::display_width(name.as_bytes())).max().unwrap_or(0);
+        let column_width = widest + 2;
+        let columns = (width / column_width).max(1);
+        let rows = self.count.div_ceil(columns);
+        for row in 0..rows {
+            let mut pending = 0;
+            for index in (row..self.count).step_by(rows) {
+                let name = self.names[index].as_str();
+                for _ in 0..pending {
+                    out.write_char(' ')?;
+                }
+                out.write_str(name)?;
+                pending = column_width - text::display_width(name.as_bytes());
+            }
+            out.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+/// The commands Tab completes: their names for the first word, and the
+/// completer of the one named for the words after it
+pub trait CommandSet {
+    fn names(&self) -> impl Iterator<Item = &str>;
+
+    fn completer(&self, name: &str) -> Option<Completer>;
+}
+
+/// The candidates for the word that ends `line`
+pub fn complete<'a>(commands: &impl CommandSet, line: &'a str) -> Candidates<'a> {
+    let context = CompletionContext::new(line);
+    let mut candidates = Candidates::new(context.prefix());
+    if context.position() == 0 {
+        for name in commands.names() {
+            candidates.add(name);
+        }
+    } else if let Some(completer) = context.word(0).and_then(|name| commands.completer(name)) {
+        completer(&context, &mut candidates);
+    }
+    candidates
+}
+
+/// What a Tab press did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TabOutcome {
+    // The word was completed as far as it goes, or there is nothing to offer
+    Completed,
+    // The candidates were listed below the line; the caller draws the
+    // prompt again followed by the line
+    Listed,
+}
+
+/// Tab on the input line: the word before the cursor is completed as far
+/// as the candidates agree, with a space after a complete word. When they
+/// part ways the terminal beeps, and Tab straight away again lists them.
+pub struct TabCompletion {
+    // The last key was a Tab that left several candidates
+    ambiguous: bool,
+}
+
+impl TabCompletion {
+    pub const fn empty() -> Self {
+        TabCompletion { ambiguous: false }
+    }
+
+    /// A key other than Tab: the next Tab does not list
+    pub fn reset(&mut self) {
+        self.ambiguous = false;
+    }
+
+    pub fn complete(&mut self, commands: &impl CommandSet, desktop: &mut VirtualDesktop) -> TabOutcome {
+        let line = desktop.input();
+        let candidates = complete(commands, &line.as_str()[..desktop.input_cursor()]);
+        let typed = candidates.prefix().len();
+        let listed = self.ambiguous;
+        self.ambiguous = candidates.len() > 1;
+        match candidates.len() {
+            0 => desktop.screen_mut().putc(0x07),
+            1 => {
+                let mut rest = FixedString::<{ MAX_CANDIDATE_LEN + 1 }>::new();
+                let _ = write!(rest, "{} ", &candidates.common()[typed..]);
+                desktop.insert_input(rest.as_str());
+            }
+            _ if candidates.common().len() > typed => {
+                desktop.insert_input(&candidates.common()[typed..]);
+            }
+            _ if listed => {
+                desktop.finish_input();
+                let screen = desktop.screen_mut();
+                let width = screen.columns();
+                screen.puts("\n");
+                let _ = candidates.write_columns(screen, width);
+                return TabOutcome::Listed;
+            }
+            _ => desktop.screen_mut().putc(0x07),
+        }
+        TabOutcome::Completed
+    }
+}
+
+impl Default for TabCompletion {
+    fn default() -> Self {
+        TabCompletion::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete_wayland(context: &CompletionContext, out: &mut Candidates) {
+        if context.position() == 1 {
+            out.add_all(&["start", "stop", "status"]);
+        }
+    }
+
+    fn complete_file(_: &CompletionContext, out: &mut Candidates) {
+        out.add_all(&["notes.txt", "notes.md", "keys.conf"]);
+    }
+
+    struct Table(&'static [(&'static str, Option<Completer>)]);
+
+    impl CommandSet for Table {
+        fn names(&self) -> impl Iterator<Item = &str> {
+            self.0.iter().map(|(name, _)| *name)
+        }
+
+        fn completer(&self, name: &str) -> Option<Completer> {
+            self.0.iter().find(|(n, _)| *n == name).and_then(|(_, completer)| *completer)
+        }
+    }
+
+    const COMMANDS: Table = Table(&[
+        ("wayland", Some(complete_wayland)),
+        ("cat", Some(complete_file)),
+        ("clear", None),
+    ]);
+
+    fn candidates(line: &str) -> Vec<String> {
+        complete(&COMMANDS, line).iter().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn first_word_completes_command_names() {
+        assert_eq!(candidates(""), ["cat", "clear", "wayland"]);
+        assert_eq!(candidates("c"), ["cat", "clear"]);
+        assert_eq!(candidates("w"), ["wayland"]);
+        assert!(candidates("x").is_empty());
+    }
+
+    #[test]
+    fn later_words_ask_the_commands_completer() {
+        assert_eq!(candidates("wayland st"), ["start", "status", "stop"]);
+        assert_eq!(candidates("wayland  sto"), ["stop"]);
+        assert!(candidates("wayland start s").is_empty());
+        assert_eq!(candidates("cat no"), ["notes.md", "notes.txt"]);
+        assert!(candidates("clear x").is_empty());
+        assert!(candidates("nothing x").is_empty());
+    }
+
+    #[test]
+    fn context_splits_words() {
+        let context = CompletionContext::new("history -w  hi");
+        assert_eq!(context.position(), 2);
+        assert_eq!(context.word(0), Some("history"));
+        assert_eq!(context.word(1), Some("-w"));
+        assert_eq!(context.word(2), None);
+        assert_eq!(context.prefix(), "hi");
+        assert_eq!(CompletionContext::new("ls ").prefix(), "");
+    }
+
+    #[test]
+    fn common_start_of_candidates() {
+        let mut candidates = Candidates::new("s");
+        assert_eq!(candidates.common(), "s");
+        candidates.add_all(&["start", "status", "stop", "start", "other"]);
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates.common(), "st");
+        let mut candidates = Candidates::new("");
+        candidates.add_all(&["påse", "påsk"]);
+        assert_eq!(candidates.common(), "pås");
+        let mut candidates = Candidates::new("");
+        candidates.add_all(&["ö", "ä"]);
+        assert_eq!(candidates.common(), "");
+    }
+
+    #[test]
+    fn lists_in_columns_down_then_across() {
+        let mut candidates = Candidates::new("");
+        candidates.add_all(&["a", "bb", "ccc", "d", "e"]);
+        let mut out = String::new();
+        candidates.write_columns(&mut out, 16).unwrap();
+        assert_eq!(out, "a    ccc  e\nbb   d\n");
+        out.clear();
+        candidates.write_columns(&mut out, 3).unwrap();
+        assert_eq!(out, "a\nbb\nccc\nd\ne\n");
+    }
+}
@@ -0,0 +1,489 @@
+// Command history - the lines run on a desktop
+// Entries are kept one after another in a fixed buffer, oldest first, each
+// ended by a newline; when a new one does not fit the oldest make room.
+// They are numbered from 1 as in bash and the numbers keep counting when old
+// entries go, so `!n` always means the command `history` listed as n.
+// HistorySearch is Ctrl+R's reverse incremental search over them.
+use core::fmt;
+
+use super::vdesktop::{VirtualDesktop, MAX_INPUT_LEN};
+use crate::keyboard::{Key, KeyEvent};
+use crate::keymap::{Action, Keymap, Scope};
+use crate::text::{self, FixedString};
+
+// Bytes of entries a desktop keeps, newlines included
+pub const HISTORY_BYTES: usize = 4096;
+
+const MAX_QUERY_LEN: usize = 32;
+
+pub struct History {
+    buf: [u8; HISTORY_BYTES],
+    // Bytes of `buf` in use
+    used: usize,
+    // Entries dropped for room; the oldest one kept is number `dropped + 1`
+    dropped: usize,
+    count: usize,
+    // While stepping through with Up/Down: the entry on the input line, and
+    // the line that was being typed, to come back to after the newest
+    browsing: Option<usize>,
+    draft: FixedString<MAX_INPUT_LEN>,
+    // Whether entries came or went since `take_changed`
+    changed: bool,
+}
+
+impl History {
+    pub const fn empty() -> Self {
+        History {
+            buf: [0; HISTORY_BYTES],
+            used: 0,
+            dropped: 0,
+            count: 0,
+            browsing: None,
+            draft: FixedString::new(),
+            changed: false,
+        }
+    }
+
+    /// Forget every entry; numbering starts again from 1
+    pub fn clear(&mut self) {
+        self.used = 0;
+        self.dropped = 0;
+        self.count = 0;
+        self.browsing = None;
+        self.changed = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Whether an entry was added or the entries cleared since the last
+    /// call, for saving the history only when there is something new
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.changed, false)
+    }
+
+    /// Number the next entry will get
+    pub fn next_number(&self) -> usize {
+        self.first() + self.count
+    }
+
+    /// Add a command after the newest entry. Blank lines and repeats of the
+    /// newest entry are not recorded.
+    pub fn push(&mut self, line: &str) {
+        self.browsing = None;
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() || line.contains('\n') || self.iter_newest_first().next().map(|(_, newest)| newest) == Some(line) {
+            return;
+        }
+        let needed = line.len() + 1;
+        if needed > HISTORY_BYTES {
+            return;
+        }
+        while self.used + needed > HISTORY_BYTES {
+            self.drop_oldest();
+        }
+        self.buf[self.used..self.used + line.len()].copy_from_slice(line.as_bytes());
+        self.buf[self.used + line.len()] = b'\n';
+        self.used += needed;
+        self.count += 1;
+        self.changed = true;
+    }
+
+    fn drop_oldest(&mut self) {
+        let end = self.buf[..self.used].iter().position(|&b| b == b'\n').map_or(self.used, |end| end + 1);
+        self.buf.copy_within(end..self.used, 0);
+        self.used -= end;
+        self.dropped += 1;
+        self.count -= 1;
+    }
+
+    /// Number of the oldest entry kept
+    fn first(&self) -> usize {
+        self.dropped + 1
+    }
+
+    /// The entries with their numbers, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        (self.first()..).zip(self.entries().lines())
+    }
+
+    /// The entries with their numbers, newest first
+    pub fn iter_newest_first(&self) -> impl Iterator<Item = (usize, &str)> {
+        (self.first()..self.next_number()).rev().zip(self.entries().lines().rev())
+    }
+
+    fn entries(&self) -> &str {
+        // Only whole lines from a &str are ever stored
+        core::str::from_utf8(&self.buf[..self.used]).unwrap_or("")
+    }
+
+    /// Entry number `number`, if it is still kept
+    pub fn get(&self, number: usize) -> Option<&str> {
+        if number < self.first() {
+            return None;
+        }
+        self.iter().nth(number - self.first()).map(|(_, line)| line)
+    }
+
+    /// Newest entry before number `before` that contains `query`
+    pub fn search(&self, query: &str, before: usize) -> Option<(usize, &str)> {
+        self.iter_newest_first().find(|(number, line)| *number < before && line.contains(query))
+    }
+
+    /// Step back to the entry before the one on the input line; the first
+    /// step keeps `line`, what was being typed. None at the oldest entry.
+    pub fn older(&mut self, line: &str) -> Option<&str> {
+        let number = match self.browsing {
+            Some(number) if number > self.first() => number - 1,
+            Some(_) => return None,
+            None if self.count > 0 => {
+                self.draft = FixedString::from_str_truncated(line);
+                self.next_number() - 1
+            }
+            None => return None,
+        };
+        self.browsing = Some(number);
+        self.get(number)
+    }
+
+    /// Step forward again; after the newest entry comes back the line that
+    /// was being typed. None when not stepping through the history.
+    pub fn newer(&mut self) -> Option<&str> {
+        let number = self.browsing? + 1;
+        if number == self.next_number() {
+            self.browsing = None;
+            return Some(self.draft.as_str());
+        }
+        self.browsing = Some(number);
+        self.get(number)
+    }
+
+    /// Back to the newest entry for the next Up, as after running a line
+    pub fn stop_browsing(&mut self) {
+        self.browsing = None;
+    }
+
+    /// Replace `!!` (the newest entry), `!n` (entry n) and `!-n` (the nth
+    /// newest) in `line`, as bash does; any other `!` stays as it is.
+    /// Returns whether anything changed.
+    pub fn expand<'a>(&self, line: &'a str, out: &mut FixedString<MAX_INPUT_LEN>) -> Result<bool, ExpandError<'a>> {
+        out.clear();
+        let mut expanded = false;
+        let mut rest = line;
+        while let Some(bang) = rest.find('!') {
+            let (before, event) = rest.split_at(bang);
+            push_str(out, before)?;
+            let body = &event[1..];
+            let (number, used) = if body.starts_with('!') {
+                (self.next_number().checked_sub(1), 1)
+            } else {
+                let negative = body.starts_with('-');
+                let digits = body[negative as usize..].bytes().take_while(u8::is_ascii_digit).count();
+                if digits == 0 {
+                    push_str(out, "!")?;
+                    rest = body;
+                    continue;
+                }
+                let used = negative as usize + digits;
+                let n: usize = body[negative as usize..used].parse().unwrap_or(usize::MAX);
+                let number = if negative { self.next_number().checked_sub(n) } else { Some(n) };
+                (number, used)
+            };
+            let event = &event[..1 + used];
+            let entry = number.and_then(|number| self.get(number)).ok_or(ExpandError::NotFound(event))?;
+            push_str(out, entry)?;
+            expanded = true;
+            rest = &body[used..];
+        }
+        push_str(out, rest)?;
+        Ok(expanded)
+    }
+
+    /// The newest entries that fit in `buf`, one per line, for `load`;
+    /// returns the bytes written and how many entries that is
+    pub fn write_to(&self, buf: &mut [u8]) -> (usize, usize) {
+        let mut start = 0;
+        let mut skipped = 0;
+        while self.used - start > buf.len() {
+            start += self.buf[start..self.used].iter().position(|&b| b == b'\n').map_or(self.used - start, |end| end + 1);
+            skipped += 1;
+        }
+        let size = self.used - start;
+        buf[..size].copy_from_slice(&self.buf[start..self.used]);
+        (size, self.count - skipped)
+    }
+
+    /// Add the lines of a file from `write_to` after the entries there are;
+    /// returns how many were added
+    pub fn load(&mut self, text: &str) -> usize {
+        let before = self.next_number();
+        for line in text.lines() {
+            self.push(line);
+        }
+        self.next_number() - before
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::empty()
+    }
+}
+
+fn push_str(out: &mut FixedString<MAX_INPUT_LEN>, s: &str) -> Result<(), ExpandError<'static>> {
+    if out.insert_str(out.len(), s) {
+        Ok(())
+    } else {
+        Err(ExpandError::TooLong)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpandError<'a> {
+    // The event as typed, like `!12`
+    NotFound(&'a str),
+    TooLong,
+}
+
+impl fmt::Display for ExpandError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpandError::NotFound(event) => write!(f, "{}: event not found", event),
+            ExpandError::TooLong => f.write_str("expanded line too long"),
+        }
+    }
+}
+
+/// What became of a reverse search after a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOutcome {
+    Searching,
+    // The match is on the input line for editing
+    Accepted,
+    // The match is on the input line and should be run, as after Enter
+    Run,
+    // The input line is as it was before the search
+    Cancelled,
+}
+
+/// Ctrl+R: the line is replaced by `(reverse-i-search)`query': match`
+/// while typing narrows the search; Ctrl+R again finds an older match.
+/// Enter runs the match, Esc or Ctrl+G go back to the line as it was and
+/// other keys leave the match on the line to edit. When it ends the prompt
+/// has been wiped, so the caller draws it again followed by the line.
+pub struct HistorySearch {
+    query: FixedString<MAX_QUERY_LEN>,
+    // Number of the entry that matches, if any
+    found: Option<usize>,
+    failed: bool,
+}
+
+impl HistorySearch {
+    pub const fn empty() -> Self {
+        HistorySearch {
+            query: FixedString::new(),
+            found: None,
+            failed: false,
+        }
+    }
+
+    /// Start a search, wiping the prompt and input line from the screen
+    pub fn enter(&mut self, desktop: &mut VirtualDesktop) {
+        self.query.clear();
+        self.found = None;
+        self.failed = false;
+        desktop.erase_input();
+        self.draw(desktop);
+    }
+
+    pub fn handle_key(&mut self, event: &KeyEvent, keymap: &Keymap, desktop: &mut VirtualDesktop) -> SearchOutcome {
+        if keymap.action(event, Scope::Line) == Some(Action::HistorySearch) {
+            // Again: the next older match
+            let before = self.found.unwrap_or(usize::MAX);
+            self.find(desktop, before);
+            self.draw(desktop);
+            return SearchOutcome::Searching;
+        }
+        let cancel = event.ctrl && matches!(event.key, Key::Char('g' | 'G'));
+        match event.key {
+            Key::Escape => return self.finish(desktop, SearchOutcome::Cancelled),
+            _ if cancel => return self.finish(desktop, SearchOutcome::Cancelled),
+            Key::Enter => return self.finish(desktop, SearchOutcome::Run),
+            Key::Backspace => {
+                self.query.pop_grapheme();
+                self.find(desktop, usize::MAX);
+            }
+            Key::Char(c) if !event.has_command_modifier() => {
+                if self.query.push(c) {
+                    // Typing narrows from the match shown, as in readline
+                    let before = self.found.map_or(usize::MAX, |number| number + 1);
+                    self.find(desktop, before);
+                }
+            }
+            _ => return self.finish(desktop, SearchOutcome::Accepted),
+        }
+        self.draw(desktop);
+        SearchOutcome::Searching
+    }
+
+    /// Paste into the query
+    pub fn paste(&mut self, pasted: &str, desktop: &mut VirtualDesktop) {
+        for c in pasted.chars().filter(|c| !c.is_control()) {
+            if !self.query.push(c) {
+                break;
+            }
+        }
+        let before = self.found.map_or(usize::MAX, |number| number + 1);
+        self.find(desktop, before);
+        self.draw(desktop);
+    }
+
+    fn find(&mut self, desktop: &VirtualDesktop, before: usize) {
+        match desktop.history().search(self.query.as_str(), before) {
+            Some((number, _)) => {
+                self.found = Some(number);
+                self.failed = false;
+            }
+            None => self.failed = !self.query.is_empty(),
+        }
+    }
+
+    fn finish(&mut self, desktop: &mut VirtualDesktop, outcome: SearchOutcome) -> SearchOutcome {
+        desktop.screen_mut().puts("\r\x1B[K");
+        let found = self.found.and_then(|number| desktop.history().get(number).map(FixedString::<MAX_INPUT_LEN>::from_str_truncated));
+        match (outcome, found) {
+            (SearchOutcome::Cancelled, _) => SearchOutcome::Cancelled,
+            (_, Some(line)) => {
+                desktop.set_input_text(line.as_str());
+                outcome
+            }
+            // Nothing found: the line stays as it was
+            (SearchOutcome::Run, None) => SearchOutcome::Run,
+            (_, None) => SearchOutcome::Accepted,
+        }
+    }
+
+    /// The search line, cut to fit one row
+    fn draw(&self, desktop: &mut VirtualDesktop) {
+        let mut line = FixedString::<{ MAX_QUERY_LEN + MAX_INPUT_LEN }>::new();
+        let label = if self.failed { "(failed reverse-i-search)`" } else { "(reverse-i-search)`" };
+        let found = self.found.and_then(|number| desktop.history().get(number)).unwrap_or("");
+        for part in [label, self.query.as_str(), "': ", found] {
+            line.insert_str(line.len(), part);
+        }
+        let screen = desktop.screen_mut();
+        let fitting = fit_width(line.as_str(), screen.columns().saturating_sub(1));
+        screen.puts("\r");
+        screen.puts(fitting);
+        screen.puts("\x1B[K");
+    }
+}
+
+/// Longest prefix of `s` that takes at most `columns` columns
+fn fit_width(s: &str, columns: usize) -> &str {
+    let bytes = s.as_bytes();
+    let mut end = 0;
+    while end < bytes.len() {
+        let next = text::next_grapheme(bytes, end);
+        if text::display_width(&bytes[..next]) > columns {
+            break;
+        }
+        end = next;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::empty();
+        for line in lines {
+            history.push(line);
+        }
+        history
+    }
+
+    fn expand<'a>(history: &History, line: &'a str) -> Result<(bool, String), ExpandError<'a>> {
+        let mut out = FixedString::new();
+        history.expand(line, &mut out).map(|changed| (changed, out.as_str().to_string()))
+    }
+
+    #[test]
+    fn numbers_keep_counting_when_old_entries_go() {
+        let mut history = history(&["ls", "ls", "  ", "cat a"]);
+        assert_eq!(history.iter().collect::<Vec<_>>(), [(1, "ls"), (2, "cat a")]);
+
+        let long = "x".repeat(1000);
+        for _ in 0..5 {
+            history.push(&long);
+            history.push("ls");
+        }
+        assert_eq!(history.get(1), None);
+        assert_eq!(history.next_number(), 13);
+        assert_eq!(history.get(12), Some("ls"));
+        assert_eq!(history.iter().next(), Some((4, "ls")));
+    }
+
+    #[test]
+    fn up_and_down_come_back_to_the_typed_line() {
+        let mut history = history(&["ls", "cat a"]);
+        assert_eq!(history.older("ca"), Some("cat a"));
+        assert_eq!(history.older("ignored"), Some("ls"));
+        assert_eq!(history.older(""), None);
+        assert_eq!(history.newer(), Some("cat a"));
+        assert_eq!(history.newer(), Some("ca"));
+        assert_eq!(history.newer(), None);
+
+        history.older("");
+        history.push("rm a");
+        assert_eq!(history.older(""), Some("rm a"));
+        assert_eq!(History::empty().older("x"), None);
+    }
+
+    #[test]
+    fn bang_expansion() {
+        let history = history(&["ls", "cat notes", "touch b"]);
+        assert_eq!(expand(&history, "!!"), Ok((true, "touch b".to_string())));
+        assert_eq!(expand(&history, "!2 && !-3"), Ok((true, "cat notes && ls".to_string())));
+        assert_eq!(expand(&history, "echo hi! a != b"), Ok((false, "echo hi! a != b".to_string())));
+        assert_eq!(expand(&history, "!9"), Err(ExpandError::NotFound("!9")));
+        assert_eq!(expand(&history, "!-4"), Err(ExpandError::NotFound("!-4")));
+        assert!(matches!(expand(&History::empty(), "!!"), Err(ExpandError::NotFound(_))));
+    }
+
+    #[test]
+    fn search_finds_the_newest_match_first() {
+        let history = history(&["cat a", "ls", "cat b"]);
+        assert_eq!(history.search("cat", usize::MAX), Some((3, "cat b")));
+        assert_eq!(history.search("cat", 3), Some((1, "cat a")));
+        assert_eq!(history.search("cat", 1), None);
+        assert_eq!(history.search("", usize::MAX), Some((3, "cat b")));
+    }
+
+    #[test]
+    fn write_keeps_the_newest_entries_that_fit() {
+        let history = history(&["one", "two", "three"]);
+        let mut buf = [0u8; 10];
+        assert_eq!(history.write_to(&mut buf), (10, 2));
+        assert_eq!(&buf, b"two\nthree\n");
+
+        let mut copy = History::empty();
+        copy.push("zero");
+        assert_eq!(copy.load("two\nthree\n"), 2);
+        assert_eq!(copy.iter().map(|(_, line)| line).collect::<Vec<_>>(), ["zero", "two", "three"]);
+    }
+
+    #[test]
+    fn fit_width_counts_columns() {
+        assert_eq!(fit_width("abc", 2), "ab");
+        assert_eq!(fit_width("日本", 3), "日");
+        assert_eq!(fit_width("abc", 5), "abc");
+    }
+}
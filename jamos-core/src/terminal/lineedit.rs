@@ -0,0 +1,413 @@
+// Line editor for the shell prompt
+// Holds the input line and a cursor anywhere in it. Each edit redraws only
+// the text from the first changed character on, with relative cursor moves,
+// so it works on the console, in a pane and over telnet alike. Positions on
+// screen are counted in columns from where the line starts, wrapping at the
+// screen's width.
+use core::fmt::Write;
+
+use super::screen::Screen;
+use super::vdesktop::MAX_INPUT_LEN;
+use crate::keyboard::{Key, KeyEvent};
+use crate::keymap::{Action, Keymap, Scope};
+use crate::text::{self, FixedString};
+
+#[derive(Clone, Copy)]
+pub struct LineEditor {
+    line: FixedString<MAX_INPUT_LEN>,
+    // Byte offset, always at a grapheme boundary
+    cursor: usize,
+    // Screen column the line starts at, just after the prompt
+    start: usize,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor {
+            line: FixedString::new(),
+            cursor: 0,
+            start: 0,
+        }
+    }
+
+    pub fn line(&self) -> &FixedString<MAX_INPUT_LEN> {
+        &self.line
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.line.as_str()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    /// Byte offset of the cursor
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Forget the line; the next prompt starts a new one
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+    }
+
+    /// Apply an editing key; false if it is not one, so the caller can
+    /// handle it
+    pub fn handle_key(&mut self, event: &KeyEvent, keymap: &Keymap, screen: &mut Screen) -> bool {
+        // Readline shortcuts (Ctrl+A/E/K/U/W, Alt+B/F by default) come from
+        // the keymap
+        match keymap.action(event, Scope::Line) {
+            Some(Action::LineStart) => self.move_to(screen, 0),
+            Some(Action::LineEnd) => self.move_to(screen, self.line.len()),
+            Some(Action::LineWordLeft) => self.move_to(screen, self.word_start()),
+            Some(Action::LineWordRight) => self.move_to(screen, self.word_end()),
+            Some(Action::LineKillToEnd) => self.replace(screen, self.cursor, self.line.len(), ""),
+            Some(Action::LineKillToStart) => self.replace(screen, 0, self.cursor, ""),
+            Some(Action::LineKillWord) => self.replace(screen, self.rubout_start(), self.cursor, ""),
+            _ => return self.handle_plain_key(event, screen),
+        };
+        true
+    }
+
+    fn handle_plain_key(&mut self, event: &KeyEvent, screen: &mut Screen) -> bool {
+        if event.has_command_modifier() {
+            return false;
+        }
+        let bytes = self.line.as_str().as_bytes();
+        match event.key {
+            Key::Char(c) => {
+                let mut encoded = [0; 4];
+                self.insert(screen, c.encode_utf8(&mut encoded));
+            }
+            Key::Backspace => {
+                let start = text::previous_grapheme(bytes, self.cursor);
+                self.replace(screen, start, self.cursor, "");
+            }
+            Key::Delete => {
+                let end = text::next_grapheme(bytes, self.cursor);
+                self.replace(screen, self.cursor, end, "");
+            }
+            Key::Left => self.move_to(screen, text::previous_grapheme(bytes, self.cursor)),
+            Key::Right => self.move_to(screen, text::next_grapheme(bytes, self.cursor)),
+            Key::Home => self.move_to(screen, 0),
+            Key::End => self.move_to(screen, self.line.len()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Type `s` at the cursor; false and unchanged if the line would not
+    /// have room
+    pub fn insert(&mut self, screen: &mut Screen, s: &str) -> bool {
+        if self.line.len() + s.len() > MAX_INPUT_LEN {
+            return false;
+        }
+        if self.line.is_empty() {
+            self.start = screen.cursor().1;
+        }
+        self.replace(screen, self.cursor, self.cursor, s);
+        true
+    }
+
+    /// Put `text` in place of the whole line, as when recalling history
+    pub fn set(&mut self, screen: &mut Screen, text: &str) {
+        if self.line.is_empty() {
+            self.start = screen.cursor().1;
+        }
+        self.replace(screen, 0, self.line.len(), text);
+    }
+
+    /// Replace the line without drawing it, the cursor at its end; `redraw`
+    /// shows it after the next prompt
+    pub fn set_text(&mut self, text: &str) {
+        self.line = FixedString::from_str_truncated(text);
+        self.cursor = self.line.len();
+    }
+
+    /// Wipe the line and the prompt before it from the screen, leaving the
+    /// cursor where the prompt started; the line itself is kept
+    pub fn erase(&mut self, screen: &mut Screen) {
+        self.move_between(screen, self.width(self.cursor), 0);
+        screen.puts("\r\x1B[J");
+    }
+
+    /// Draw the whole line after a fresh prompt, with the cursor where it was
+    pub fn redraw(&mut self, screen: &mut Screen) {
+        self.start = screen.cursor().1;
+        let end = self.print_from(screen, 0);
+        self.move_between(screen, end, self.width(self.cursor));
+    }
+
+    /// Put the cursor after the last character, as before a line break
+    pub fn move_to_end(&mut self, screen: &mut Screen) {
+        self.move_to(screen, self.line.len());
+    }
+
+    fn move_to(&mut self, screen: &mut Screen, cursor: usize) {
+        let from = self.width(self.cursor);
+        self.cursor = cursor;
+        self.move_between(screen, from, self.width(cursor));
+    }
+
+    /// Put `with` in place of the bytes from `start` to `end`, leaving the
+    /// cursor after it, and draw what changed
+    fn replace(&mut self, screen: &mut Screen, start: usize, end: usize, with: &str) {
+        if start == end && with.is_empty() {
+            return;
+        }
+        let cursor_width = self.width(self.cursor);
+        let old_width = self.width(self.line.len());
+        self.line.remove(start, end);
+        if !self.line.insert_str(start, with) {
+            return;
+        }
+        self.cursor = start + with.len();
+
+        self.move_between(screen, cursor_width, self.width(start));
+        let mut shown = self.print_from(screen, start);
+        // Blank out what is left of a longer line
+        if old_width > shown {
+            for _ in shown..old_width {
+                screen.putc(b' ');
+            }
+            shown = old_width;
+            self.settle(screen, shown);
+        }
+        self.move_between(screen, shown, self.width(self.cursor));
+    }
+
+    /// Print the line from byte `start`; returns the column reached
+    fn print_from(&self, screen: &mut Screen, start: usize) -> usize {
+        let end = self.width(self.line.len());
+        if start < self.line.len() {
+            screen.puts(&self.line.as_str()[start..]);
+            if end > self.width(start) {
+                self.settle(screen, end);
+            }
+        }
+        end
+    }
+
+    /// A terminal holds the cursor on the last column of a full row until
+    /// the next character; move it down so it is where the counting says
+    fn settle(&self, screen: &mut Screen, column: usize) {
+        if (self.start + column).is_multiple_of(screen.columns()) {
+            screen.putc(b'\n');
+        }
+    }
+
+    /// Move the cursor between two columns of the line
+    fn move_between(&self, screen: &mut Screen, from: usize, to: usize) {
+        let columns = screen.columns();
+        let (from, to) = (self.start + from, self.start + to);
+        let (from_row, from_column) = (from / columns, from % columns);
+        let (to_row, to_column) = (to / columns, to % columns);
+        let mut moves = FixedString::<24>::new();
+        if to_row < from_row {
+            let _ = write!(moves, "\x1B[{}A", from_row - to_row);
+        } else if to_row > from_row {
+            let _ = write!(moves, "\x1B[{}B", to_row - from_row);
+        }
+        if to_column > from_column {
+            let _ = write!(moves, "\x1B[{}C", to_column - from_column);
+        } else if to_column < from_column {
+            let _ = write!(moves, "\x1B[{}D", from_column - to_column);
+        }
+        if !moves.is_empty() {
+            screen.puts(moves.as_str());
+        }
+    }
+
+    /// Columns taken by the line up to byte `end`
+    fn width(&self, end: usize) -> usize {
+        text::display_width(&self.line.as_str().as_bytes()[..end])
+    }
+
+    /// Start of the word before the cursor, skipping punctuation first, as
+    /// Alt+B does in readline
+    fn word_start(&self) -> usize {
+        let bytes = self.line.as_str().as_bytes();
+        let mut pos = self.cursor;
+        let mut in_word = false;
+        while pos > 0 {
+            let start = text::previous_grapheme(bytes, pos);
+            let is_word = is_word_char(bytes[start]);
+            if in_word && !is_word {
+                break;
+            }
+            in_word |= is_word;
+            pos = start;
+        }
+        pos
+    }
+
+    /// End of the word after the cursor
+    fn word_end(&self) -> usize {
+        let bytes = self.line.as_str().as_bytes();
+        let mut pos = self.cursor;
+        let mut in_word = false;
+        while pos < bytes.len() {
+            let is_word = is_word_char(bytes[pos]);
+            if in_word && !is_word {
+                break;
+            }
+            in_word |= is_word;
+            pos = text::next_grapheme(bytes, pos);
+        }
+        pos
+    }
+
+    /// Start of what Ctrl+W removes: the blanks before the cursor and the
+    /// space-separated word before them
+    fn rubout_start(&self) -> usize {
+        let bytes = self.line.as_str().as_bytes();
+        let mut pos = self.cursor;
+        while pos > 0 && bytes[pos - 1] == b' ' {
+            pos -= 1;
+        }
+        while pos > 0 && bytes[pos - 1] != b' ' {
+            pos -= 1;
+        }
+        pos
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        LineEditor::new()
+    }
+}
+
+/// Letters and digits; any non-ASCII character counts as a letter
+fn is_word_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte >= 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    fn screen(columns: usize) -> Box<Screen> {
+        let mut screen = Box::new(Screen::empty());
+        screen.init(CaptureSink::leak());
+        screen.set_size(columns, 5);
+        screen
+    }
+
+    fn keymap() -> Keymap {
+        let mut keymap = Keymap::empty();
+        keymap.init();
+        keymap
+    }
+
+    fn row(screen: &Screen, row: usize) -> String {
+        let cells = (0..screen.columns()).map(|column| screen.cell(row, column).glyph());
+        cells.collect::<String>().trim_end().to_string()
+    }
+
+    fn press(editor: &mut LineEditor, screen: &mut Screen, events: &[KeyEvent]) {
+        for event in events {
+            assert!(editor.handle_key(event, &keymap(), screen));
+        }
+    }
+
+    fn typed(text: &str) -> Vec<KeyEvent> {
+        text.chars().map(|c| KeyEvent::new(Key::Char(c))).collect()
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent { ctrl: true, ..KeyEvent::new(Key::Char(c)) }
+    }
+
+    fn alt(c: char) -> KeyEvent {
+        KeyEvent { alt: true, ..KeyEvent::new(Key::Char(c)) }
+    }
+
+    #[test]
+    fn inserts_and_deletes_in_the_middle() {
+        let mut screen = screen(40);
+        let mut editor = LineEditor::new();
+        screen.puts("$ ");
+        press(&mut editor, &mut screen, &typed("cat fle"));
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::Left), KeyEvent::new(Key::Left)]);
+        press(&mut editor, &mut screen, &typed("i"));
+        assert_eq!(row(&screen, 0), "$ cat file");
+        assert_eq!(screen.cursor(), (0, 8));
+
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::Home), KeyEvent::new(Key::Delete)]);
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::End), KeyEvent::new(Key::Backspace)]);
+        assert_eq!(editor.as_str(), "at fil");
+        assert_eq!(row(&screen, 0), "$ at fil");
+        assert_eq!(screen.cursor(), (0, 8));
+    }
+
+    #[test]
+    fn backspace_on_an_empty_line_does_nothing() {
+        let mut screen = screen(40);
+        let mut editor = LineEditor::new();
+        screen.puts("$ ");
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::Backspace), KeyEvent::new(Key::Left)]);
+        assert_eq!(row(&screen, 0), "$");
+        assert_eq!(screen.cursor(), (0, 2));
+    }
+
+    #[test]
+    fn readline_shortcuts_move_and_kill() {
+        let mut screen = screen(40);
+        let mut editor = LineEditor::new();
+        screen.puts("$ ");
+        press(&mut editor, &mut screen, &typed("nc -l 8080"));
+        press(&mut editor, &mut screen, &[alt('b'), alt('b')]);
+        assert_eq!(editor.cursor(), 4);
+        press(&mut editor, &mut screen, &[alt('f')]);
+        assert_eq!(editor.cursor(), 5);
+        press(&mut editor, &mut screen, &[ctrl('k')]);
+        assert_eq!(row(&screen, 0), "$ nc -l");
+
+        press(&mut editor, &mut screen, &[ctrl('a'), ctrl('e'), ctrl('w')]);
+        assert_eq!(editor.as_str(), "nc ");
+        press(&mut editor, &mut screen, &typed("x"));
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::Left), ctrl('u')]);
+        assert_eq!(editor.as_str(), "x");
+        assert_eq!(row(&screen, 0), "$ x");
+        assert_eq!(screen.cursor(), (0, 2));
+    }
+
+    #[test]
+    fn lines_longer_than_a_row_wrap() {
+        let mut screen = screen(10);
+        let mut editor = LineEditor::new();
+        screen.puts("$ ");
+        press(&mut editor, &mut screen, &typed("abcdefghij"));
+        assert_eq!(row(&screen, 0), "$ abcdefgh");
+        assert_eq!(row(&screen, 1), "ij");
+        press(&mut editor, &mut screen, &[ctrl('a')]);
+        assert_eq!(screen.cursor(), (0, 2));
+        press(&mut editor, &mut screen, &typed("12345678"));
+        assert_eq!(row(&screen, 1), "abcdefghij");
+        assert_eq!(screen.cursor(), (1, 0));
+
+        press(&mut editor, &mut screen, &[ctrl('u')]);
+        assert_eq!(row(&screen, 0), "$ abcdefgh");
+        assert_eq!(row(&screen, 1), "ij");
+        assert_eq!(screen.cursor(), (0, 2));
+        press(&mut editor, &mut screen, &[ctrl('k')]);
+        assert_eq!(row(&screen, 1), "");
+    }
+
+    #[test]
+    fn wide_characters_move_as_one() {
+        let mut screen = screen(40);
+        let mut editor = LineEditor::new();
+        screen.puts("$ ");
+        press(&mut editor, &mut screen, &typed("日e\u{301}"));
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::Left)]);
+        assert_eq!(screen.cursor(), (0, 4));
+        press(&mut editor, &mut screen, &[KeyEvent::new(Key::Backspace)]);
+        assert_eq!(editor.as_str(), "e\u{301}");
+        assert_eq!(screen.cursor(), (0, 2));
+    }
+}
@@ -0,0 +1,16 @@
+pub mod vdesktop;
+pub mod screen;
+pub mod scrollback;
+pub mod tiling;
+pub mod pool;
+pub mod statusbar;
+pub mod lineedit;
+pub mod history;
+pub mod completion;
+
+pub use vdesktop::VirtualDesktopManager;
+pub use screen::Screen;
+pub use tiling::TilingManager;
+pub use statusbar::StatusBar;
+pub use lineedit::LineEditor;
+pub use history::History;
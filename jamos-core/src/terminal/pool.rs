@@ -0,0 +1,80 @@
+// Pane storage shared by every desktop
+// A pane's screen is the big part of a desktop, so the screens are not kept
+// per desktop for the most panes it could have: desktops (the console's and
+// the remote ones) take panes from one pool as they split and give them
+// back as panes and desktops close. What limits the desktops is then the
+// panes actually open.
+use core::cell::{Cell, UnsafeCell};
+
+use super::tiling::Pane;
+
+/// Panes open at once over all desktops, about 260 KiB each
+pub const POOL_PANES: usize = 64;
+
+pub struct PanePool {
+    panes: [UnsafeCell<Pane>; POOL_PANES],
+    // Whether a desktop holds the pane; only then is there a reference to it
+    taken: [Cell<bool>; POOL_PANES],
+}
+
+impl PanePool {
+    /// All zeros, so that the static holding the pool goes in .bss
+    pub const fn empty() -> Self {
+        PanePool {
+            panes: [const { UnsafeCell::new(Pane::empty()) }; POOL_PANES],
+            taken: [const { Cell::new(false) }; POOL_PANES],
+        }
+    }
+
+    /// A pane no desktop holds, until it is given back; None when all are
+    /// in use
+    // The taken flag, not the borrow, keeps the pane to one holder
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(&'static self) -> Option<&'static mut Pane> {
+        let slot = self.taken.iter().position(|taken| !taken.get())?;
+        self.taken[slot].set(true);
+        // Not taken until now, so this is the only reference to it
+        Some(unsafe { &mut *self.panes[slot].get() })
+    }
+
+    /// Return a pane from `take` for another desktop to use
+    pub fn give_back(&self, pane: &'static mut Pane) {
+        let pane: *const Pane = pane;
+        if let Some(slot) = self.panes.iter().position(|cell| core::ptr::eq(cell.get(), pane)) {
+            self.taken[slot].set(false);
+        }
+    }
+
+    /// Panes left to take
+    pub fn free(&self) -> usize {
+        self.taken.iter().filter(|taken| !taken.get()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panes_are_handed_out_once() {
+        let thread = std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            let pool: &'static PanePool = Box::leak(Box::new(PanePool::empty()));
+            let first = pool.take().unwrap();
+            let second = pool.take().unwrap();
+            assert!(!core::ptr::eq(&*first, &*second));
+            assert_eq!(pool.free(), POOL_PANES - 2);
+
+            let first_address: *const Pane = &*first;
+            pool.give_back(first);
+            assert_eq!(pool.free(), POOL_PANES - 1);
+            // The freed pane is the next one out
+            assert!(core::ptr::eq(&*pool.take().unwrap(), first_address));
+
+            while pool.take().is_some() {}
+            assert_eq!(pool.free(), 0);
+            pool.give_back(second);
+            assert!(pool.take().is_some());
+        });
+        thread.unwrap().join().unwrap();
+    }
+}
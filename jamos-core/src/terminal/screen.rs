@@ -0,0 +1,1305 @@
+// Screen buffer and terminal emulation
+// Output goes straight to the sink (when the screen is visible) and is also
+// interpreted the way a VT100/xterm would: the grid of cells follows cursor
+// movement, erasing, colours, scroll regions and the alternate screen, so a
+// desktop that was hidden can be drawn again exactly as it was with
+// `render`. Lines scrolling off the top go into the scrollback, which the
+// screen can show instead of the live output.
+//
+// A screen can also be a pane: a rectangle of the terminal given with
+// `set_origin`. Output to a pane is not passed through (it would land
+// anywhere on the terminal); the rows it changed are drawn from the grid
+// inside the rectangle instead.
+use core::fmt::Write;
+
+use super::scrollback::{Scrollback, LINE_BYTES};
+use crate::console::{CharSink, Disconnected};
+use crate::text::{self, FixedString};
+
+pub const DEFAULT_COLUMNS: usize = 80;
+pub const DEFAULT_ROWS: usize = 24;
+
+// Largest terminal the grid can hold; bigger clients are treated as this size
+pub const MAX_COLUMNS: usize = 132;
+pub const MAX_ROWS: usize = 50;
+
+const TAB_WIDTH: usize = 8;
+
+// Numbers in one control sequence; any more are ignored
+const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Default,
+    // The 8 standard colours, 8 bright ones, then the xterm 256-colour cube
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// How a cell is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Attributes {
+    pub const DEFAULT: Attributes = Attributes {
+        foreground: Color::Default,
+        background: Color::Default,
+        bold: false,
+        underline: false,
+        reverse: false,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    // '\0' for a cell nothing was written to, drawn as a space; zero so that
+    // statics holding screens go in .bss
+    pub ch: char,
+    pub attributes: Attributes,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell { ch: '\0', attributes: Attributes::DEFAULT };
+    // Right half of a wide character; `print` never stores control characters
+    const WIDE_TAIL: char = '\u{1}';
+
+    /// The character drawn for the cell
+    pub fn glyph(&self) -> char {
+        if self.ch == '\0' { ' ' } else { self.ch }
+    }
+
+    fn is_blank(&self) -> bool {
+        self.glyph() == ' ' && self.attributes == Attributes::DEFAULT
+    }
+}
+
+type Grid = [[Cell; MAX_COLUMNS]; MAX_ROWS];
+
+// Escape sequences are passed through to the terminal and applied to the
+// grid as they are parsed
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Parse {
+    Ground,
+    Escape,
+    // ESC ( B and the like: one more byte, ignored
+    Charset,
+    Csi,
+    // Operating system command and other strings, up to BEL or ESC \
+    Osc,
+}
+
+/// What DECSC (ESC 7) saves and DECRC (ESC 8) restores
+#[derive(Clone, Copy)]
+struct SavedCursor {
+    row: usize,
+    column: usize,
+    attributes: Attributes,
+}
+
+impl SavedCursor {
+    const HOME: SavedCursor = SavedCursor { row: 0, column: 0, attributes: Attributes::DEFAULT };
+}
+
+/// Output written to the sink alone, bypassing the grid
+pub(crate) struct Raw(pub(crate) &'static dyn CharSink);
+
+impl Write for Raw {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.puts(s);
+        Ok(())
+    }
+}
+
+pub struct Screen {
+    // None until `init`
+    sink: Option<&'static dyn CharSink>,
+    columns: usize,
+    rows: usize,
+    // Top left corner on the terminal of a pane; None for the whole terminal
+    origin: Option<(usize, usize)>,
+    visible: bool,
+    // The grid being shown; the other one is kept in `alternate`
+    cells: Grid,
+    alternate: Grid,
+    on_alternate: bool,
+    row: usize,
+    column: usize,
+    // The last column was written; the next character goes on a new line
+    pending_wrap: bool,
+    attributes: Attributes,
+    saved: SavedCursor,
+    // Scroll region, first and last row
+    top: usize,
+    bottom: usize,
+    autowrap: bool,
+    cursor_visible: bool,
+    parse: Parse,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    // Private marker (`?` in CSI ? 25 h) and intermediate byte of a sequence
+    marker: u8,
+    intermediate: u8,
+    // The terminal has to be drawn again from the grid once the current
+    // output is through, e.g. after switching to the alternate screen
+    redraw: bool,
+    // Rows of a pane changed by the current output
+    dirty: [bool; MAX_ROWS],
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_needed: usize,
+    scrollback: Scrollback,
+    // Lines scrolled back from the live output; nothing is written while
+    // this is above 0
+    view: usize,
+}
+
+impl Screen {
+    /// All zeros, so that the big static arrays of screens take no room in
+    /// the kernel image; `init` before use
+    pub const fn empty() -> Self {
+        Screen {
+            sink: None,
+            columns: 0,
+            rows: 0,
+            origin: None,
+            visible: false,
+            cells: [[Cell::BLANK; MAX_COLUMNS]; MAX_ROWS],
+            alternate: [[Cell::BLANK; MAX_COLUMNS]; MAX_ROWS],
+            on_alternate: false,
+            row: 0,
+            column: 0,
+            pending_wrap: false,
+            attributes: Attributes::DEFAULT,
+            saved: SavedCursor::HOME,
+            top: 0,
+            bottom: 0,
+            autowrap: false,
+            cursor_visible: false,
+            parse: Parse::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            marker: 0,
+            intermediate: 0,
+            redraw: false,
+            dirty: [false; MAX_ROWS],
+            utf8: [0; 4],
+            utf8_len: 0,
+            utf8_needed: 0,
+            scrollback: Scrollback::empty(),
+            view: 0,
+        }
+    }
+
+    pub fn new(sink: &'static dyn CharSink) -> Self {
+        let mut screen = Screen::empty();
+        screen.init(sink);
+        screen
+    }
+
+    /// Reset in place for a new sink, keeping the size once there is one;
+    /// the grids and the scrollback are too big to move around
+    pub fn init(&mut self, sink: &'static dyn CharSink) {
+        self.sink = Some(sink);
+        if self.columns == 0 {
+            self.columns = DEFAULT_COLUMNS;
+            self.rows = DEFAULT_ROWS;
+        }
+        self.origin = None;
+        self.visible = true;
+        self.parse = Parse::Ground;
+        self.utf8_needed = 0;
+        self.scrollback.init();
+        self.view = 0;
+        self.reset();
+        self.redraw = false;
+        self.dirty = [false; MAX_ROWS];
+    }
+
+    fn sink(&self) -> &'static dyn CharSink {
+        self.sink.unwrap_or(&Disconnected)
+    }
+
+    /// Terminal dimensions as reported by the client (e.g. telnet NAWS)
+    pub fn set_size(&mut self, columns: usize, rows: usize) {
+        if columns > 0 && rows > 0 {
+            self.columns = columns.min(MAX_COLUMNS);
+            self.rows = rows.min(MAX_ROWS);
+            self.row = self.row.min(self.rows - 1);
+            self.column = self.column.min(self.columns - 1);
+            self.pending_wrap = false;
+            self.top = 0;
+            self.bottom = self.rows - 1;
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Make the screen a pane with its top left corner at (row, column) of
+    /// the terminal, or the whole terminal again with None. Nothing is
+    /// drawn until the next `render`.
+    pub fn set_origin(&mut self, origin: Option<(usize, usize)>) {
+        self.origin = origin;
+    }
+
+    pub fn origin(&self) -> Option<(usize, usize)> {
+        self.origin
+    }
+
+    /// A hidden screen keeps its grid up to date but writes nothing
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Cursor position as (row, column), from 0
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    pub fn cell(&self, row: usize, column: usize) -> Cell {
+        self.cells[row][column]
+    }
+
+    /// Whether a program switched to the alternate screen (as full-screen
+    /// programs do); the normal one comes back when it switches back
+    pub fn is_alternate(&self) -> bool {
+        self.on_alternate
+    }
+
+    pub fn clear(&mut self) {
+        // Clear screen using ANSI escape codes
+        self.puts("\x1B[2J\x1B[H");
+    }
+
+    /// Output reaches the terminal only while the live screen is shown
+    fn writes(&self) -> bool {
+        self.visible && self.view == 0
+    }
+
+    /// Output passed straight through, as opposed to drawn from the grid
+    fn passes_through(&self) -> bool {
+        self.writes() && self.origin.is_none()
+    }
+
+    pub fn scrollback(&self) -> &Scrollback {
+        &self.scrollback
+    }
+
+    pub fn scrollback_mut(&mut self) -> &mut Scrollback {
+        self.view = self.view.min(self.scrollback.len());
+        &mut self.scrollback
+    }
+
+    /// How many lines the display is scrolled back; 0 is the live output
+    pub fn view(&self) -> usize {
+        self.view
+    }
+
+    /// Scroll the display back `lines` from the live output (as far as the
+    /// scrollback goes) and redraw it
+    pub fn set_view(&mut self, lines: usize) {
+        let lines = lines.min(self.scrollback.len());
+        if lines != self.view {
+            self.view = lines;
+            if self.visible {
+                self.render();
+            }
+        }
+    }
+
+    /// Show a message on the bottom line until the next redraw; it is not
+    /// part of the grid
+    pub fn draw_status(&self, message: &str) {
+        if !self.visible {
+            return;
+        }
+        let mut out = Raw(self.sink());
+        match self.origin {
+            None => {
+                let _ = write!(out, "\x1B[{};1H\x1B[0;7m{}\x1B[0m\x1B[K", self.rows, message);
+            }
+            // Erasing the line would erase the panes next to this one
+            Some((row, column)) => {
+                let _ = write!(out, "\x1B[{};{}H\x1B[0;7m", row + self.rows, column + 1);
+                let width = write_clipped(&mut out, message, self.columns);
+                let _ = write!(out, "\x1B[0m\x1B[{}X", self.columns - width);
+            }
+        }
+    }
+
+    /// The normal screen's grid, also while the alternate one is shown
+    fn main_cells(&self) -> &Grid {
+        if self.on_alternate { &self.alternate } else { &self.cells }
+    }
+
+    /// Lines of history: the scrollback, then the screen down to the
+    /// cursor, leaving out blank lines at the end
+    pub fn history_len(&self) -> usize {
+        let mut len = self.scrollback.len() + self.row + 1;
+        while len > 0 && self.history_line(len - 1).is_empty() {
+            len -= 1;
+        }
+        len
+    }
+
+    /// Text of a line of history, oldest first
+    pub fn history_line(&self, index: usize) -> FixedString<LINE_BYTES> {
+        if index < self.scrollback.len() {
+            return *self.scrollback.line(index);
+        }
+        let mut line = FixedString::new();
+        row_text(&self.main_cells()[index - self.scrollback.len()][..self.columns], &mut line);
+        line
+    }
+
+    /// Write the history into `buf`, a line break after each line. When it
+    /// does not fit the oldest lines are left out. Returns the bytes and
+    /// lines written.
+    pub fn write_history(&self, buf: &mut [u8]) -> (usize, usize) {
+        let len = self.history_len();
+        let mut first = len;
+        let mut size = 0;
+        while first > 0 && size + self.history_line(first - 1).len() < buf.len() {
+            size += self.history_line(first - 1).len() + 1;
+            first -= 1;
+        }
+        let mut written = 0;
+        for index in first..len {
+            let line = self.history_line(index);
+            buf[written..written + line.len()].copy_from_slice(line.as_str().as_bytes());
+            buf[written + line.len()] = b'\n';
+            written += line.len() + 1;
+        }
+        (written, len - first)
+    }
+
+    /// Ask the terminal to mark pasted text (xterm bracketed paste), so a
+    /// paste arrives as one Paste event instead of keystrokes
+    pub fn set_bracketed_paste(&mut self, enabled: bool) {
+        self.sink().puts(if enabled { "\x1B[?2004h" } else { "\x1B[?2004l" });
+    }
+
+    pub fn putc(&mut self, c: u8) {
+        if self.passes_through() {
+            self.sink().putc(c);
+        }
+        self.feed(c);
+        self.finish_output();
+    }
+
+    pub fn puts(&mut self, s: &str) {
+        if self.passes_through() {
+            self.sink().puts(s);
+        }
+        for byte in s.bytes() {
+            self.feed(byte);
+        }
+        self.finish_output();
+    }
+
+    /// Write one character, UTF-8 encoded
+    pub fn put_char(&mut self, c: char) {
+        let mut buf = [0u8; 4];
+        self.puts(c.encode_utf8(&mut buf));
+    }
+
+    fn finish_output(&mut self) {
+        if self.redraw {
+            self.redraw = false;
+            self.dirty = [false; MAX_ROWS];
+            if self.writes() {
+                self.render();
+            }
+            return;
+        }
+        let Some((top, left)) = self.origin else {
+            return;
+        };
+        if self.writes() {
+            let mut out = Raw(self.sink());
+            for row in 0..self.rows {
+                if self.dirty[row] {
+                    self.draw_pane_row(&mut out, row, top, left);
+                }
+            }
+            self.draw_cursor(&mut out, top, left);
+        }
+        self.dirty = [false; MAX_ROWS];
+    }
+
+    /// Draw the whole grid on the terminal and put the cursor back, after
+    /// setting the terminal's modes to match. When scrolled back, the
+    /// scrollback lines above the grid are drawn instead with the position
+    /// in the top right corner. A pane draws only its rectangle and leaves
+    /// the terminal's modes alone.
+    pub fn render(&self) {
+        let mut out = Raw(self.sink());
+        if let Some((top, left)) = self.origin {
+            for row in 0..self.rows {
+                self.draw_pane_row(&mut out, row, top, left);
+            }
+            if self.view > 0 {
+                self.draw_position(&mut out, top, left);
+            } else {
+                self.draw_cursor(&mut out, top, left);
+            }
+            return;
+        }
+
+        let screen = if self.on_alternate { "\x1B[?1049h" } else { "\x1B[?1049l" };
+        let _ = write!(out, "{}\x1B[0m\x1B[r\x1B[?7h\x1B[?25h\x1B[2J", screen);
+        let top = self.scrollback.len() - self.view;
+        for row in 0..self.rows {
+            let index = top + row;
+            if index < self.scrollback.len() {
+                let line = self.scrollback.line(index);
+                if !line.is_empty() {
+                    let _ = write!(out, "\x1B[{};1H{}", row + 1, line.as_str());
+                }
+                continue;
+            }
+            let cells = &self.cells[index - self.scrollback.len()][..self.columns];
+            let Some(end) = cells.iter().rposition(|cell| !cell.is_blank()) else {
+                continue;
+            };
+            let _ = write!(out, "\x1B[{};1H", row + 1);
+            write_cells(&mut out, &cells[..=end]);
+        }
+        if self.view > 0 {
+            self.draw_position(&mut out, 0, 0);
+            return;
+        }
+        if (self.top, self.bottom) != (0, self.rows - 1) {
+            let _ = write!(out, "\x1B[{};{}r", self.top + 1, self.bottom + 1);
+        }
+        if !self.autowrap {
+            let _ = out.write_str("\x1B[?7l");
+        }
+        if !self.cursor_visible {
+            let _ = out.write_str("\x1B[?25l");
+        }
+        self.draw_cursor(&mut out, 0, 0);
+    }
+
+    /// One row of a pane, all the way across so it covers what was there;
+    /// the blanks at the end are erased (ECH) rather than written
+    fn draw_pane_row(&self, out: &mut Raw, row: usize, top: usize, left: usize) {
+        let _ = write!(out, "\x1B[{};{}H", top + row + 1, left + 1);
+        let index = self.scrollback.len() - self.view + row;
+        let width = if index < self.scrollback.len() {
+            write_clipped(out, self.scrollback.line(index).as_str(), self.columns)
+        } else {
+            let cells = &self.cells[index - self.scrollback.len()][..self.columns];
+            let end = cells.iter().rposition(|cell| !cell.is_blank()).map_or(0, |end| end + 1);
+            write_cells(out, &cells[..end]);
+            end
+        };
+        if width < self.columns {
+            let _ = write!(out, "\x1B[{}X", self.columns - width);
+        }
+    }
+
+    /// How far back the display is, in the top right corner, with the
+    /// cursor left on the bottom line
+    fn draw_position(&self, out: &mut Raw, top: usize, left: usize) {
+        let mut position = FixedString::<24>::new();
+        let _ = write!(position, "[{}/{}]", self.view, self.scrollback.len());
+        let column = left + self.columns.saturating_sub(position.len()) + 1;
+        let _ = write!(out, "\x1B[{};{}H\x1B[7m{}\x1B[0m", top + 1, column, position.as_str());
+        let _ = write!(out, "\x1B[{};{}H", top + self.rows, left + 1);
+    }
+
+    fn draw_cursor(&self, out: &mut Raw, top: usize, left: usize) {
+        let _ = write!(out, "\x1B[{};{}H", top + self.row + 1, left + self.column + 1);
+        if self.attributes != Attributes::DEFAULT {
+            let _ = write_sgr(out, &self.attributes);
+        }
+    }
+
+    /// Update the grid with one byte of output
+    fn feed(&mut self, byte: u8) {
+        match self.parse {
+            Parse::Ground => {}
+            Parse::Escape => return self.escape(byte),
+            Parse::Charset => {
+                self.parse = Parse::Ground;
+                return;
+            }
+            Parse::Csi => return self.csi_byte(byte),
+            Parse::Osc => {
+                match byte {
+                    0x07 => self.parse = Parse::Ground,
+                    0x1B => self.parse = Parse::Escape,
+                    _ => {}
+                }
+                return;
+            }
+        }
+
+        if byte >= 0x80 {
+            self.utf8_byte(byte);
+            return;
+        }
+        self.utf8_needed = 0;
+        match byte {
+            0x1B => self.parse = Parse::Escape,
+            0x20..=0x7E => self.print(byte as char),
+            _ => self.control(byte),
+        }
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            b'\n' | 0x0B | 0x0C => {
+                // The console turns LF into CR LF
+                self.column = 0;
+                self.index();
+            }
+            b'\r' => {
+                self.column = 0;
+                self.pending_wrap = false;
+            }
+            0x08 => {
+                self.column = self.column.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            b'\t' => {
+                self.column = ((self.column / TAB_WIDTH + 1) * TAB_WIDTH).min(self.columns - 1);
+                self.pending_wrap = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// The byte after ESC
+    fn escape(&mut self, byte: u8) {
+        self.parse = Parse::Ground;
+        match byte {
+            b'[' => {
+                self.parse = Parse::Csi;
+                self.params = [0; MAX_PARAMS];
+                self.param_count = 0;
+                self.marker = 0;
+                self.intermediate = 0;
+            }
+            b']' | b'P' | b'X' | b'^' | b'_' => self.parse = Parse::Osc,
+            b'(' | b')' | b'*' | b'+' | b'#' | b'%' => self.parse = Parse::Charset,
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.index(),
+            b'E' => {
+                self.column = 0;
+                self.index();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn csi_byte(&mut self, byte: u8) {
+        match byte {
+            b'0'..=b'9' => {
+                self.param_count = self.param_count.max(1);
+                let param = &mut self.params[self.param_count - 1];
+                *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+            }
+            b';' | b':' => {
+                self.param_count = (self.param_count.max(1) + 1).min(MAX_PARAMS);
+            }
+            b'<'..=b'?' => self.marker = byte,
+            0x20..=0x2F => self.intermediate = byte,
+            0x40..=0x7E => {
+                self.parse = Parse::Ground;
+                self.csi_dispatch(byte);
+            }
+            0x1B => self.parse = Parse::Escape,
+            // CAN and SUB abort the sequence
+            0x18 | 0x1A => self.parse = Parse::Ground,
+            _ => self.control(byte),
+        }
+    }
+
+    /// Parameter `index` of the sequence, with 0 or a missing one read as
+    /// `default`
+    fn param(&self, index: usize, default: usize) -> usize {
+        match self.params[index] {
+            0 => default,
+            n => n as usize,
+        }
+    }
+
+    fn csi_dispatch(&mut self, final_byte: u8) {
+        if self.intermediate != 0 {
+            return;
+        }
+        if self.marker == b'?' {
+            if final_byte == b'h' || final_byte == b'l' {
+                for index in 0..self.param_count.max(1) {
+                    self.set_mode(self.params[index], final_byte == b'h');
+                }
+            }
+            return;
+        }
+        if self.marker != 0 {
+            return;
+        }
+
+        let n = self.param(0, 1);
+        // Vertical movement stops at the scroll region when it starts inside
+        let up_limit = if self.row >= self.top { self.top } else { 0 };
+        let down_limit = if self.row <= self.bottom { self.bottom } else { self.rows - 1 };
+        match final_byte {
+            b'A' => self.move_to(self.row.saturating_sub(n).max(up_limit), self.column),
+            b'B' | b'e' => self.move_to((self.row + n).min(down_limit), self.column),
+            b'C' | b'a' => self.move_to(self.row, self.column + n),
+            b'D' => self.move_to(self.row, self.column.saturating_sub(n)),
+            b'E' => self.move_to((self.row + n).min(down_limit), 0),
+            b'F' => self.move_to(self.row.saturating_sub(n).max(up_limit), 0),
+            b'G' | b'`' => self.move_to(self.row, n - 1),
+            b'H' | b'f' => self.move_to(n - 1, self.param(1, 1) - 1),
+            b'd' => self.move_to(n - 1, self.column),
+            b'J' => self.erase_display(self.params[0]),
+            b'K' => self.erase_line(self.params[0]),
+            b'L' if (self.top..=self.bottom).contains(&self.row) => {
+                self.scroll_down(self.row, self.bottom, n);
+                self.column = 0;
+            }
+            b'M' if (self.top..=self.bottom).contains(&self.row) => {
+                self.scroll_up(self.row, self.bottom, n);
+                self.column = 0;
+            }
+            b'@' => {
+                let (row, column) = (self.row, self.column);
+                let n = n.min(self.columns - column);
+                self.cells[row][column..self.columns].rotate_right(n);
+                self.erase_cells(row, column, column + n);
+            }
+            b'P' => {
+                let (row, column) = (self.row, self.column);
+                let n = n.min(self.columns - column);
+                self.cells[row][column..self.columns].rotate_left(n);
+                self.erase_cells(row, self.columns - n, self.columns);
+            }
+            b'X' => self.erase_cells(self.row, self.column, (self.column + n).min(self.columns)),
+            b'S' => {
+                self.save_lines(self.top, n);
+                self.scroll_up(self.top, self.bottom, n);
+            }
+            b'T' => self.scroll_down(self.top, self.bottom, n),
+            b'm' => self.select_graphic_rendition(),
+            b'r' => {
+                let top = self.param(0, 1) - 1;
+                let bottom = self.param(1, self.rows).min(self.rows) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    /// DEC private modes (CSI ? n h / l)
+    fn set_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            7 => self.autowrap = enabled,
+            25 => self.cursor_visible = enabled,
+            47 | 1047 => self.use_alternate(enabled),
+            1049 => {
+                // Save the cursor and start with a clear alternate screen
+                if enabled && !self.on_alternate {
+                    self.save_cursor();
+                    self.use_alternate(true);
+                    self.erase_display(2);
+                } else if !enabled && self.on_alternate {
+                    self.use_alternate(false);
+                    self.restore_cursor();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn use_alternate(&mut self, enabled: bool) {
+        if enabled != self.on_alternate {
+            self.cells.swap_with_slice(&mut self.alternate);
+            self.on_alternate = enabled;
+            // The terminal may not keep an alternate screen the way the
+            // grid does (another desktop may have drawn over it)
+            self.redraw = true;
+        }
+    }
+
+    /// Hard reset (RIS): modes, colours and both screens
+    fn reset(&mut self) {
+        self.use_alternate(false);
+        self.attributes = Attributes::DEFAULT;
+        self.saved = SavedCursor::HOME;
+        self.top = 0;
+        self.bottom = self.rows - 1;
+        self.autowrap = true;
+        self.cursor_visible = true;
+        for grid in [&mut self.cells, &mut self.alternate] {
+            for row in grid.iter_mut() {
+                row.fill(Cell::BLANK);
+            }
+        }
+        self.dirty = [true; MAX_ROWS];
+        self.move_to(0, 0);
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = SavedCursor { row: self.row, column: self.column, attributes: self.attributes };
+    }
+
+    fn restore_cursor(&mut self) {
+        self.attributes = self.saved.attributes;
+        self.move_to(self.saved.row, self.saved.column);
+    }
+
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns - 1);
+        self.pending_wrap = false;
+    }
+
+    /// A blank as erasing leaves it: in the current background colour
+    fn blank(&self) -> Cell {
+        Cell {
+            attributes: Attributes { background: self.attributes.background, ..Attributes::DEFAULT },
+            ..Cell::BLANK
+        }
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        let blank = self.blank();
+        self.cells[row][from..to].fill(blank);
+        self.dirty[row] = true;
+    }
+
+    /// ED: 0 from the cursor, 1 up to the cursor, 2 everything, 3 the
+    /// scrollback
+    fn erase_display(&mut self, mode: u16) {
+        let (row, column, columns) = (self.row, self.column, self.columns);
+        match mode {
+            0 => {
+                self.erase_cells(row, column, columns);
+                for below in row + 1..self.rows {
+                    self.erase_cells(below, 0, columns);
+                }
+            }
+            1 => {
+                for above in 0..row {
+                    self.erase_cells(above, 0, columns);
+                }
+                self.erase_cells(row, 0, column + 1);
+            }
+            2 => {
+                for row in 0..self.rows {
+                    self.erase_cells(row, 0, columns);
+                }
+            }
+            3 => {
+                self.scrollback.clear();
+                if self.view > 0 {
+                    self.view = 0;
+                    self.redraw = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// EL: 0 from the cursor, 1 up to the cursor, 2 the whole line
+    fn erase_line(&mut self, mode: u16) {
+        let (row, column, columns) = (self.row, self.column, self.columns);
+        match mode {
+            0 => self.erase_cells(row, column, columns),
+            1 => self.erase_cells(row, 0, column + 1),
+            2 => self.erase_cells(row, 0, columns),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        let count = self.param_count.max(1);
+        let mut index = 0;
+        while index < count {
+            let attributes = &mut self.attributes;
+            match self.params[index] {
+                0 => *attributes = Attributes::DEFAULT,
+                1 => attributes.bold = true,
+                4 => attributes.underline = true,
+                7 => attributes.reverse = true,
+                22 => attributes.bold = false,
+                24 => attributes.underline = false,
+                27 => attributes.reverse = false,
+                n @ 30..=37 => attributes.foreground = Color::Indexed((n - 30) as u8),
+                39 => attributes.foreground = Color::Default,
+                n @ 40..=47 => attributes.background = Color::Indexed((n - 40) as u8),
+                49 => attributes.background = Color::Default,
+                n @ 90..=97 => attributes.foreground = Color::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => attributes.background = Color::Indexed((n - 100 + 8) as u8),
+                kind @ (38 | 48) => {
+                    // 38;5;n picks from the 256 colours, 38;2;r;g;b is RGB
+                    let value = |offset: usize| self.params[(index + offset).min(MAX_PARAMS - 1)].min(255) as u8;
+                    let (color, used) = match value(1) {
+                        5 => (Some(Color::Indexed(value(2))), 2),
+                        2 => (Some(Color::Rgb(value(2), value(3), value(4))), 4),
+                        _ => (None, 1),
+                    };
+                    match (color, kind) {
+                        (Some(color), 38) => self.attributes.foreground = color,
+                        (Some(color), _) => self.attributes.background = color,
+                        (None, _) => {}
+                    }
+                    index += used;
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    fn utf8_byte(&mut self, byte: u8) {
+        if byte & 0xC0 == 0x80 {
+            if self.utf8_needed == 0 {
+                self.print(char::REPLACEMENT_CHARACTER);
+                return;
+            }
+            self.utf8[self.utf8_len] = byte;
+            self.utf8_len += 1;
+            if self.utf8_len == self.utf8_needed {
+                self.utf8_needed = 0;
+                let c = core::str::from_utf8(&self.utf8[..self.utf8_len])
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                self.print(c);
+            }
+            return;
+        }
+        self.utf8_needed = match byte {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => 0,
+        };
+        if self.utf8_needed == 0 {
+            self.print(char::REPLACEMENT_CHARACTER);
+        } else {
+            self.utf8[0] = byte;
+            self.utf8_len = 1;
+        }
+    }
+
+    /// Put a character at the cursor and move past it. Combining marks and
+    /// other zero-width characters are not kept.
+    fn print(&mut self, c: char) {
+        let width = text::char_width(c);
+        if width == 0 || width > self.columns {
+            return;
+        }
+        if self.pending_wrap || self.column + width > self.columns {
+            if self.autowrap {
+                self.column = 0;
+                self.index();
+            } else {
+                self.column = self.columns - width;
+            }
+        }
+        let (row, column) = (self.row, self.column);
+        // Writing over half of a wide character blanks the other half
+        if self.cells[row][column].ch == Cell::WIDE_TAIL && column > 0 {
+            self.erase_cells(row, column - 1, column);
+        }
+        if column + width < self.columns && self.cells[row][column + width].ch == Cell::WIDE_TAIL {
+            self.erase_cells(row, column + width, column + width + 1);
+        }
+        self.cells[row][column] = Cell { ch: c, attributes: self.attributes };
+        self.dirty[row] = true;
+        if width == 2 {
+            self.cells[row][column + 1] = Cell { ch: Cell::WIDE_TAIL, attributes: self.attributes };
+        }
+        self.column += width;
+        if self.column >= self.columns {
+            self.column = self.columns - 1;
+            self.pending_wrap = self.autowrap;
+        }
+    }
+
+    /// Move down a line, scrolling at the bottom of the scroll region
+    fn index(&mut self) {
+        self.pending_wrap = false;
+        if self.row == self.bottom {
+            self.save_lines(self.top, 1);
+            self.scroll_up(self.top, self.bottom, 1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    /// Move up a line, scrolling at the top of the scroll region
+    fn reverse_index(&mut self) {
+        self.pending_wrap = false;
+        if self.row == self.top {
+            self.scroll_down(self.top, self.bottom, 1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+    }
+
+    /// Lines about to scroll off the top of the normal screen go into the
+    /// scrollback
+    fn save_lines(&mut self, top: usize, lines: usize) {
+        if top != 0 || self.on_alternate {
+            return;
+        }
+        for row in 0..lines.min(self.bottom + 1) {
+            let Some(line) = self.scrollback.push() else {
+                return;
+            };
+            row_text(&self.cells[row][..self.columns], line);
+            // Keep showing the same lines while scrolled back
+            if self.view > 0 {
+                self.view = (self.view + 1).min(self.scrollback.len());
+            }
+        }
+    }
+
+    /// Move rows `top..=bottom` up, blank lines coming in at the bottom
+    fn scroll_up(&mut self, top: usize, bottom: usize, lines: usize) {
+        let lines = lines.min(bottom + 1 - top);
+        self.cells[top..=bottom].rotate_left(lines);
+        self.dirty[top..=bottom].fill(true);
+        for row in bottom + 1 - lines..=bottom {
+            self.erase_cells(row, 0, self.columns);
+        }
+    }
+
+    /// Move rows `top..=bottom` down, blank lines coming in at the top
+    fn scroll_down(&mut self, top: usize, bottom: usize, lines: usize) {
+        let lines = lines.min(bottom + 1 - top);
+        self.cells[top..=bottom].rotate_right(lines);
+        self.dirty[top..=bottom].fill(true);
+        for row in top..top + lines {
+            self.erase_cells(row, 0, self.columns);
+        }
+    }
+}
+
+/// The characters of a row without trailing blanks, as much as fits
+fn row_text<const N: usize>(cells: &[Cell], line: &mut FixedString<N>) {
+    let end = cells.iter().rposition(|cell| !cell.is_blank()).map_or(0, |end| end + 1);
+    for cell in &cells[..end] {
+        if cell.ch != Cell::WIDE_TAIL && !line.push(cell.glyph()) {
+            break;
+        }
+    }
+}
+
+/// Cells with their colours; the attributes are reset at the end. A wide
+/// character without room for its right half (the screen got narrower) is
+/// drawn as a blank.
+fn write_cells(out: &mut impl Write, cells: &[Cell]) {
+    let mut attributes = Attributes::DEFAULT;
+    for (column, cell) in cells.iter().enumerate() {
+        if cell.ch == Cell::WIDE_TAIL {
+            continue;
+        }
+        if cell.attributes != attributes {
+            attributes = cell.attributes;
+            let _ = write_sgr(out, &attributes);
+        }
+        let cut = column + 1 == cells.len() && text::char_width(cell.ch) == 2;
+        let _ = out.write_char(if cut { ' ' } else { cell.glyph() });
+    }
+    if attributes != Attributes::DEFAULT {
+        let _ = out.write_str("\x1B[0m");
+    }
+}
+
+/// Text cut to `width` columns; returns the columns written
+fn write_clipped(out: &mut impl Write, s: &str, width: usize) -> usize {
+    let mut used = 0;
+    for c in s.chars() {
+        let c_width = text::char_width(c);
+        if used + c_width > width {
+            break;
+        }
+        let _ = out.write_char(c);
+        used += c_width;
+    }
+    used
+}
+
+/// Select graphic rendition: the escape sequence for `attributes`
+fn write_sgr(out: &mut impl Write, attributes: &Attributes) -> core::fmt::Result {
+    out.write_str("\x1B[0")?;
+    if attributes.bold {
+        out.write_str(";1")?;
+    }
+    if attributes.underline {
+        out.write_str(";4")?;
+    }
+    if attributes.reverse {
+        out.write_str(";7")?;
+    }
+    for (color, base) in [(attributes.foreground, 30), (attributes.background, 40)] {
+        match color {
+            Color::Default => {}
+            Color::Indexed(n @ 0..=7) => write!(out, ";{}", base + n as usize)?,
+            Color::Indexed(n @ 8..=15) => write!(out, ";{}", base + 60 + (n - 8) as usize)?,
+            Color::Indexed(n) => write!(out, ";{};5;{}", base + 8, n)?,
+            Color::Rgb(r, g, b) => write!(out, ";{};2;{};{};{}", base + 8, r, g, b)?,
+        }
+    }
+    out.write_str("m")
+}
+
+impl core::fmt::Write for Screen {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.puts(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    fn screen() -> Box<Screen> {
+        let mut screen = Box::new(Screen::empty());
+        screen.init(CaptureSink::leak());
+        screen
+    }
+
+    /// Text of a row, right halves of wide characters left out
+    fn row_text(screen: &Screen, row: usize) -> String {
+        let cells = (0..screen.columns()).map(|column| screen.cell(row, column).glyph());
+        cells.filter(|&c| c != Cell::WIDE_TAIL).collect::<String>().trim_end().to_string()
+    }
+
+    fn rows(screen: &Screen) -> Vec<String> {
+        (0..screen.rows()).map(|row| row_text(screen, row)).collect()
+    }
+
+    #[test]
+    fn text_and_line_breaks_fill_the_grid() {
+        let mut screen = screen();
+        screen.puts("[Desktop 1]$ ls\nFiles:\n  a.txt\tb\n");
+        assert_eq!(row_text(&screen, 0), "[Desktop 1]$ ls");
+        assert_eq!(row_text(&screen, 1), "Files:");
+        assert_eq!(row_text(&screen, 2), "  a.txt b");
+        assert_eq!(screen.cursor(), (3, 0));
+    }
+
+    #[test]
+    fn backspace_and_carriage_return_move_the_cursor() {
+        let mut screen = screen();
+        screen.puts("abc\x08 \x08d\rX");
+        assert_eq!(row_text(&screen, 0), "Xbd");
+        assert_eq!(screen.cursor(), (0, 1));
+    }
+
+    #[test]
+    fn long_lines_wrap_and_the_bottom_scrolls() {
+        let mut screen = screen();
+        screen.set_size(10, 3);
+        screen.puts("0123456789");
+        // A full line leaves the cursor on it until the next character
+        assert_eq!(screen.cursor(), (0, 9));
+        screen.puts("ab\ncd\nef");
+        assert_eq!(row_text(&screen, 0), "ab");
+        assert_eq!(row_text(&screen, 1), "cd");
+        assert_eq!(row_text(&screen, 2), "ef");
+    }
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        let mut screen = screen();
+        screen.set_size(4, 3);
+        screen.puts("a日本");
+        assert_eq!(screen.cell(0, 1).ch, '日');
+        assert_eq!(screen.cell(0, 2).ch, Cell::WIDE_TAIL);
+        // No room for the second half: the character moves to the next line
+        assert_eq!(row_text(&screen, 0), "a日");
+        assert_eq!(row_text(&screen, 1), "本");
+        screen.putc(0xC3);
+        screen.putc(0xA5);
+        assert_eq!(row_text(&screen, 1), "本å");
+        // Writing over half of a wide character removes all of it
+        screen.puts("\x1B[1;3Hx");
+        assert_eq!(row_text(&screen, 0), "a x");
+    }
+
+    #[test]
+    fn unknown_sequences_are_not_text() {
+        let mut screen = screen();
+        screen.puts("\x1B[1;31mred\x1B[0m\x1B]0;title\x07!\x1B[?2004h\x1B(B\x1B[>c\x1B[!p");
+        assert_eq!(row_text(&screen, 0), "red!");
+    }
+
+    #[test]
+    fn cursor_movement_and_erasing() {
+        let mut screen = screen();
+        screen.set_size(10, 4);
+        screen.puts("aaaaaaaaaa\nbbbbbbbbbb\ncccccccccc\x1B[2;4H");
+        assert_eq!(screen.cursor(), (1, 3));
+        screen.puts("\x1B[K\x1B[A\x1B[2D\x1B[1K\x1B[3;6H\x1B[2X");
+        assert_eq!(rows(&screen), ["  aaaaaaaa", "bbb", "ccccc  ccc", ""]);
+        screen.puts("\x1B[3;3H\x1B[J");
+        assert_eq!(rows(&screen), ["  aaaaaaaa", "bbb", "cc", ""]);
+        screen.puts("\x1B[1;1H\x1B[2P\x1B[2@\x1B[G\x1B[5Cz\x1B[99;99Hy");
+        assert_eq!(rows(&screen), ["  aaazaaaa", "bbb", "cc", "         y"]);
+        screen.puts("\x1B[2J");
+        assert_eq!(rows(&screen), ["", "", "", ""]);
+        assert_eq!(screen.cursor(), (3, 9));
+    }
+
+    #[test]
+    fn graphic_rendition() {
+        let mut screen = screen();
+        screen.puts("\x1B[1;4;7;32;41ma\x1B[22;24;27;39;49mb\x1B[38;5;200;48;2;1;2;3mc\x1B[93;104md\x1B[0me");
+        let attributes = |column| screen.cell(0, column).attributes;
+        assert_eq!(
+            attributes(0),
+            Attributes {
+                foreground: Color::Indexed(2),
+                background: Color::Indexed(1),
+                bold: true,
+                underline: true,
+                reverse: true,
+            }
+        );
+        assert_eq!(attributes(1), Attributes::DEFAULT);
+        assert_eq!(attributes(2).foreground, Color::Indexed(200));
+        assert_eq!(attributes(2).background, Color::Rgb(1, 2, 3));
+        assert_eq!((attributes(3).foreground, attributes(3).background), (Color::Indexed(11), Color::Indexed(12)));
+        assert_eq!(attributes(4), Attributes::DEFAULT);
+
+        // Erasing uses the background colour
+        screen.puts("\x1B[44m\x1B[2K");
+        assert_eq!(screen.cell(0, 0).attributes.background, Color::Indexed(4));
+    }
+
+    #[test]
+    fn scroll_region_keeps_the_lines_outside() {
+        let mut screen = screen();
+        screen.set_size(10, 5);
+        screen.puts("title\none\ntwo\nthree\nstatus");
+        screen.puts("\x1B[2;4r");
+        assert_eq!(screen.cursor(), (0, 0));
+        screen.puts("\x1B[4;1H\nfour");
+        assert_eq!(rows(&screen), ["title", "two", "three", "four", "status"]);
+        screen.puts("\x1B[2;1H\x1BM\x1BMzero");
+        assert_eq!(rows(&screen), ["title", "zero", "", "two", "status"]);
+        screen.puts("\x1B[3;1H\x1B[L");
+        assert_eq!(rows(&screen), ["title", "zero", "", "", "status"]);
+        screen.puts("\x1B[2;1H\x1B[2M");
+        assert_eq!(rows(&screen), ["title", "", "", "", "status"]);
+        // Only the normal screen scrolling from the top feeds the scrollback
+        assert!(screen.scrollback().is_empty());
+    }
+
+    #[test]
+    fn save_and_restore_cursor() {
+        let mut screen = screen();
+        screen.puts("\x1B[3;5H\x1B[1m\x1B7\x1B[H\x1B[0mx\x1B8y");
+        assert_eq!(screen.cursor(), (2, 5));
+        assert!(screen.cell(2, 4).attributes.bold);
+        assert!(!screen.cell(0, 0).attributes.bold);
+        screen.puts("\x1B[s\x1B[10;10H\x1B[u");
+        assert_eq!(screen.cursor(), (2, 5));
+    }
+
+    #[test]
+    fn alternate_screen_keeps_the_normal_one() {
+        let sink = CaptureSink::leak();
+        let mut screen = Box::new(Screen::empty());
+        screen.init(sink);
+        screen.puts("[Desktop 1]$ top\n");
+        screen.puts("\x1B[?1049h\x1B[HPID 1");
+        assert!(screen.is_alternate());
+        assert_eq!(row_text(&screen, 0), "PID 1");
+        assert_eq!(row_text(&screen, 1), "");
+        // Switching redraws the terminal from the grid
+        assert!(sink.text().ends_with("\x1B[1;1HPID 1\x1B[1;6H"));
+
+        screen.puts("\x1B[?1049l");
+        assert!(!screen.is_alternate());
+        assert_eq!(row_text(&screen, 0), "[Desktop 1]$ top");
+        assert_eq!(screen.cursor(), (1, 0));
+    }
+
+    #[test]
+    fn modes_and_reset() {
+        let mut screen = screen();
+        screen.set_size(4, 2);
+        screen.puts("\x1B[?7labcdef");
+        assert_eq!(rows(&screen), ["abcf", ""]);
+        screen.puts("\x1B[?25l\x1B[31m\x1Bc");
+        assert_eq!(rows(&screen), ["", ""]);
+        screen.puts("abcdef");
+        assert_eq!(rows(&screen), ["abcd", "ef"]);
+        assert_eq!(screen.cell(0, 0).attributes, Attributes::DEFAULT);
+    }
+
+    #[test]
+    fn hidden_screens_only_update_the_grid() {
+        let sink = CaptureSink::leak();
+        let mut screen = Box::new(Screen::empty());
+        screen.init(sink);
+        screen.set_visible(false);
+        screen.puts("quiet");
+        screen.clear();
+        screen.puts("hello");
+        assert_eq!(sink.text(), "");
+        assert_eq!(row_text(&screen, 0), "hello");
+    }
+
+    #[test]
+    fn render_redraws_the_grid() {
+        let sink = CaptureSink::leak();
+        let mut screen = Box::new(Screen::empty());
+        screen.init(sink);
+        screen.puts("one\n\nthree");
+        screen.cells[1][2] = Cell {
+            ch: 'x',
+            attributes: Attributes { bold: true, foreground: Color::Indexed(9), ..Attributes::DEFAULT },
+        };
+        sink.clear();
+        screen.render();
+        assert_eq!(
+            sink.text(),
+            "\x1B[?1049l\x1B[0m\x1B[r\x1B[?7h\x1B[?25h\x1B[2J\
+             \x1B[1;1Hone\x1B[2;1H  \x1B[0;1;91mx\x1B[0m\x1B[3;1Hthree\x1B[3;6H"
+        );
+
+        // Modes that differ from a fresh terminal are set again
+        screen.puts("\x1B[2;3r\x1B[?25l\x1B[44m");
+        sink.clear();
+        screen.render();
+        assert!(sink.text().ends_with("\x1B[2;3r\x1B[?25l\x1B[1;1H\x1B[0;44m"));
+    }
+}
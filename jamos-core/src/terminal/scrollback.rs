@@ -0,0 +1,317 @@
+// Scrollback - lines that scrolled off the top of a screen
+// Each screen keeps a ring of the most recent lines as plain text (colours
+// are dropped). ScrollbackMode is the pager for browsing it: Shift+PageUp
+// enters it from the shell, `/` searches back and `q` returns to the live
+// output.
+use super::screen::{Screen, MAX_COLUMNS};
+use crate::keyboard::{Key, KeyEvent};
+use crate::keymap::{Action, Keymap, Scope};
+use crate::text::FixedString;
+
+pub const MAX_SCROLLBACK_LINES: usize = 200;
+pub const DEFAULT_SCROLLBACK_LINES: usize = 100;
+
+// UTF-8 bytes kept of a line; enough for a full row of two-byte characters
+pub const LINE_BYTES: usize = MAX_COLUMNS * 2;
+
+const MAX_QUERY_LEN: usize = 32;
+
+pub struct Scrollback {
+    lines: [FixedString<LINE_BYTES>; MAX_SCROLLBACK_LINES],
+    // Slot of the oldest line
+    start: usize,
+    len: usize,
+    limit: usize,
+}
+
+impl Scrollback {
+    /// All zeros like the screen holding it; keeps nothing until `init`
+    pub const fn empty() -> Self {
+        Scrollback {
+            lines: [FixedString::new(); MAX_SCROLLBACK_LINES],
+            start: 0,
+            len: 0,
+            limit: 0,
+        }
+    }
+
+    /// Forget the lines and go back to the default limit
+    pub fn init(&mut self) {
+        self.clear();
+        self.limit = DEFAULT_SCROLLBACK_LINES;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Keep at most `lines` (up to MAX_SCROLLBACK_LINES; 0 turns the
+    /// scrollback off), dropping the oldest ones over the new limit
+    pub fn set_limit(&mut self, lines: usize) {
+        self.limit = lines.min(MAX_SCROLLBACK_LINES);
+        if self.len > self.limit {
+            self.start = (self.start + self.len - self.limit) % MAX_SCROLLBACK_LINES;
+            self.len = self.limit;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Line `index`, oldest first
+    pub fn line(&self, index: usize) -> &FixedString<LINE_BYTES> {
+        &self.lines[(self.start + index) % MAX_SCROLLBACK_LINES]
+    }
+
+    /// An empty line added after the newest, for the caller to fill in; the
+    /// oldest line makes room when the ring is full. None when turned off.
+    pub fn push(&mut self) -> Option<&mut FixedString<LINE_BYTES>> {
+        if self.limit == 0 {
+            return None;
+        }
+        if self.len == self.limit {
+            self.start = (self.start + 1) % MAX_SCROLLBACK_LINES;
+        } else {
+            self.len += 1;
+        }
+        let line = &mut self.lines[(self.start + self.len - 1) % MAX_SCROLLBACK_LINES];
+        line.clear();
+        Some(line)
+    }
+}
+
+/// Browsing the scrollback of a screen
+pub struct ScrollbackMode {
+    query: FixedString<MAX_QUERY_LEN>,
+    // Typing a search after `/`
+    searching: bool,
+}
+
+impl ScrollbackMode {
+    pub const fn empty() -> Self {
+        ScrollbackMode {
+            query: FixedString::new(),
+            searching: false,
+        }
+    }
+
+    /// Scroll back a page; false if there is nothing to scroll back to
+    pub fn enter(&mut self, screen: &mut Screen) -> bool {
+        if screen.scrollback().is_empty() {
+            return false;
+        }
+        self.searching = false;
+        screen.set_view(page(screen));
+        true
+    }
+
+    /// Handle a key; false once back at the live output
+    pub fn handle_key(&mut self, event: &KeyEvent, keymap: &Keymap, screen: &mut Screen) -> bool {
+        if self.searching {
+            self.search_key(event, screen);
+            return true;
+        }
+        let view = screen.view();
+        match keymap.action(event, Scope::Desktop) {
+            Some(Action::ScrollbackUp) => screen.set_view(view + page(screen)),
+            Some(Action::ScrollbackDown) => screen.set_view(view.saturating_sub(page(screen))),
+            _ => match event.key {
+                _ if event.has_command_modifier() => {}
+                Key::PageUp => screen.set_view(view + page(screen)),
+                Key::PageDown => screen.set_view(view.saturating_sub(page(screen))),
+                Key::Up => screen.set_view(view + 1),
+                Key::Down => screen.set_view(view.saturating_sub(1)),
+                Key::Home => screen.set_view(usize::MAX),
+                Key::End | Key::Escape | Key::Char('q') => screen.set_view(0),
+                Key::Char('/') => {
+                    self.searching = true;
+                    self.query.clear();
+                    screen.draw_status("/");
+                }
+                Key::Char('n') if !self.query.is_empty() => {
+                    let top = screen.scrollback().len() - view;
+                    let found = (0..top).rev().find(|&i| self.matches(screen, i));
+                    self.show(found, screen);
+                }
+                Key::Char('N') if !self.query.is_empty() => {
+                    let top = screen.scrollback().len() - view;
+                    let found = (top + 1..screen.history_len()).find(|&i| self.matches(screen, i));
+                    self.show(found, screen);
+                }
+                _ => {}
+            },
+        }
+        screen.view() > 0
+    }
+
+    fn search_key(&mut self, event: &KeyEvent, screen: &mut Screen) {
+        match event.key {
+            Key::Char(c) if !event.has_command_modifier() => {
+                self.query.push(c);
+            }
+            Key::Backspace => {
+                self.query.pop_grapheme();
+            }
+            Key::Enter => {
+                self.searching = false;
+                if !self.query.is_empty() {
+                    // Start from the bottom of the display, so a match
+                    // already in view counts
+                    let bottom = screen.scrollback().len() - screen.view() + screen.rows();
+                    let found = (0..bottom.min(screen.history_len())).rev().find(|&i| self.matches(screen, i));
+                    self.show(found, screen);
+                } else {
+                    screen.render();
+                }
+                return;
+            }
+            Key::Escape => {
+                self.searching = false;
+                screen.render();
+                return;
+            }
+            _ => {}
+        }
+        let mut prompt = FixedString::<{ MAX_QUERY_LEN + 1 }>::new();
+        prompt.push('/');
+        let _ = core::fmt::Write::write_str(&mut prompt, self.query.as_str());
+        screen.draw_status(prompt.as_str());
+    }
+
+    fn matches(&self, screen: &Screen, line: usize) -> bool {
+        screen.history_line(line).as_str().contains(self.query.as_str())
+    }
+
+    /// Scroll so the line found (of the history, see Screen::history_line)
+    /// is at the top, or as near as the scrollback allows
+    fn show(&self, found: Option<usize>, screen: &mut Screen) {
+        match found {
+            Some(index) => {
+                let view = screen.scrollback().len().saturating_sub(index).max(1);
+                if view == screen.view() {
+                    screen.render();
+                } else {
+                    screen.set_view(view);
+                }
+            }
+            None => screen.draw_status("Pattern not found"),
+        }
+    }
+}
+
+/// Lines to move for PageUp/PageDown, keeping one line of context
+fn page(screen: &Screen) -> usize {
+    screen.rows().saturating_sub(1).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    fn lines(scrollback: &Scrollback) -> Vec<&str> {
+        (0..scrollback.len()).map(|i| scrollback.line(i).as_str()).collect()
+    }
+
+    fn push(scrollback: &mut Scrollback, text: &str) {
+        if let Some(line) = scrollback.push() {
+            *line = FixedString::from_str_truncated(text);
+        }
+    }
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(Key::Char(c))
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_lines() {
+        let mut scrollback = Box::new(Scrollback::empty());
+        scrollback.set_limit(3);
+        for text in ["one", "two", "three", "four", "five"] {
+            push(&mut scrollback, text);
+        }
+        assert_eq!(lines(&scrollback), ["three", "four", "five"]);
+
+        scrollback.set_limit(2);
+        assert_eq!(lines(&scrollback), ["four", "five"]);
+        scrollback.set_limit(MAX_SCROLLBACK_LINES + 1);
+        assert_eq!(scrollback.limit(), MAX_SCROLLBACK_LINES);
+
+        scrollback.set_limit(0);
+        assert!(scrollback.push().is_none());
+        assert!(scrollback.is_empty());
+    }
+
+    #[test]
+    fn lines_scrolled_off_the_screen_are_kept() {
+        let mut screen = Box::new(Screen::empty());
+        screen.init(CaptureSink::leak());
+        screen.set_size(20, 3);
+        screen.puts("\x1B[1mbold\x1B[0m\n日本\nthree\nfour\n");
+        assert_eq!(lines(screen.scrollback()), ["bold", "日本"]);
+        assert_eq!(screen.history_len(), 4);
+        assert_eq!(screen.history_line(3).as_str(), "four");
+    }
+
+    #[test]
+    fn write_history_keeps_the_newest_lines_that_fit() {
+        let mut screen = Box::new(Screen::empty());
+        screen.init(CaptureSink::leak());
+        screen.set_size(20, 2);
+        screen.puts("first\nsecond\nthird\n");
+        let mut buf = [0u8; 13];
+        assert_eq!(screen.write_history(&mut buf), (13, 2));
+        assert_eq!(&buf, b"second\nthird\n");
+    }
+
+    #[test]
+    fn scrollback_mode_pages_and_searches() {
+        let sink = CaptureSink::leak();
+        let mut screen = Box::new(Screen::empty());
+        screen.init(sink);
+        screen.set_size(20, 3);
+        for n in 0..10 {
+            screen.puts(if n == 2 { "needle\n" } else { "hay\n" });
+        }
+        let mut keymap = Keymap::empty();
+        keymap.init();
+        let mut mode = ScrollbackMode::empty();
+        assert!(mode.enter(&mut screen));
+        assert_eq!(screen.view(), 2);
+
+        // Output while scrolled back only updates the grid
+        sink.clear();
+        screen.puts("more\n");
+        assert_eq!(sink.text(), "");
+        assert_eq!(screen.view(), 3);
+
+        for c in "/needle".chars() {
+            assert!(mode.handle_key(&key(c), &keymap, &mut screen));
+        }
+        assert!(mode.handle_key(&KeyEvent::new(Key::Enter), &keymap, &mut screen));
+        assert_eq!(screen.scrollback().len() - screen.view(), 2);
+        assert!(sink.text().contains("needle"));
+
+        sink.clear();
+        assert!(mode.handle_key(&key('n'), &keymap, &mut screen));
+        assert!(sink.text().contains("Pattern not found"));
+
+        // Searching forward finds lines still on the screen
+        screen.puts("needle again");
+        assert!(mode.handle_key(&key('N'), &keymap, &mut screen));
+        assert_eq!(screen.view(), 1);
+
+        assert!(!mode.handle_key(&key('q'), &keymap, &mut screen));
+        assert_eq!(screen.view(), 0);
+    }
+}
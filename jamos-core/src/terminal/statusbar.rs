@@ -0,0 +1,286 @@
+// Status bar - one line of chrome on the bottom row of the console
+// Lists the desktops with the current one highlighted, then the active pane,
+// the compositor, the time and free memory. The desktops keep that row out
+// of their scroll region and the bar only writes to the terminal when the
+// line changes, so it can be updated as often as the caller likes.
+use core::fmt::Write;
+
+use super::screen::{Raw, DEFAULT_COLUMNS, DEFAULT_ROWS, MAX_COLUMNS};
+use super::vdesktop::MAX_NAME_LEN;
+use crate::console::{CharSink, Disconnected};
+use crate::text::{self, FixedString};
+
+// Room for a full row of three-byte characters and the colour changes
+const LINE_BYTES: usize = MAX_COLUMNS * 4;
+
+const BAR: &str = "\x1B[0;7m";
+const CURRENT: &str = "\x1B[0;1;30;42m";
+
+/// Everything the bar shows, gathered by the caller
+pub struct Status<'a> {
+    /// Desktop names in the order they are arranged
+    pub desktops: &'a [FixedString<MAX_NAME_LEN>],
+    pub current: usize,
+    /// Active pane, counting from 0, and how many panes the desktop has
+    pub pane: usize,
+    pub panes: usize,
+    pub compositor_running: bool,
+    /// Seconds since the Unix epoch; shown as UTC
+    pub time: u64,
+    pub free_memory: usize,
+}
+
+pub struct StatusBar {
+    sink: &'static dyn CharSink,
+    enabled: bool,
+    columns: usize,
+    rows: usize,
+    // What the terminal shows, empty when the bar has to be drawn again
+    shown: FixedString<LINE_BYTES>,
+}
+
+impl StatusBar {
+    pub const fn empty() -> Self {
+        StatusBar {
+            sink: &Disconnected,
+            enabled: false,
+            columns: DEFAULT_COLUMNS,
+            rows: DEFAULT_ROWS,
+            shown: FixedString::new(),
+        }
+    }
+
+    /// Shown from the next update on
+    pub fn init(&mut self, sink: &'static dyn CharSink) {
+        self.sink = sink;
+        self.set_enabled(true);
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.shown.clear();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Rows the bar takes at the bottom of the terminal
+    pub fn rows(&self) -> usize {
+        usize::from(self.enabled)
+    }
+
+    pub fn set_size(&mut self, columns: usize, rows: usize) {
+        if columns > 0 && rows > 0 {
+            self.columns = columns.min(MAX_COLUMNS);
+            self.rows = rows;
+            self.shown.clear();
+        }
+    }
+
+    /// Draw the bar if it is enabled and differs from what is shown; the
+    /// cursor is left where it was
+    pub fn update(&mut self, status: &Status) {
+        if !self.enabled {
+            return;
+        }
+        let line = self.compose(status);
+        if line.as_str() == self.shown.as_str() {
+            return;
+        }
+        let mut out = Raw(self.sink);
+        let _ = write!(out, "\x1B7\x1B[{};1H{}\x1B8", self.rows, line.as_str());
+        self.shown = line;
+    }
+
+    /// The desktops on the left, cut short with '…' when they do not all
+    /// fit but always up to the current one, and the rest on the right
+    fn compose(&self, status: &Status) -> FixedString<LINE_BYTES> {
+        let mut right = FixedString::<MAX_COLUMNS>::new();
+        let minutes = status.time / 60;
+        let _ = write!(
+            right,
+            " pane {}/{} │ wayland {} │ {:02}:{:02} │ ",
+            status.pane + 1,
+            status.panes,
+            if status.compositor_running { "on" } else { "off" },
+            minutes / 60 % 24,
+            minutes % 60
+        );
+        if status.free_memory >= 1 << 20 {
+            let _ = write!(right, "{}M free ", status.free_memory >> 20);
+        } else {
+            let _ = write!(right, "{}K free ", status.free_memory >> 10);
+        }
+        let mut right_width = text::display_width(right.as_str().as_bytes());
+        if right_width > self.columns {
+            right.clear();
+            right_width = 0;
+        }
+        let room = self.columns - right_width;
+
+        let mut line = FixedString::new();
+        let _ = line.write_str(BAR);
+        let mut used = 0;
+        let first = first_shown(status, room);
+        if first > 0 {
+            line.push('…');
+            used += 1;
+        }
+        for (position, name) in status.desktops.iter().enumerate().skip(first) {
+            let label = label(position, name);
+            let width = text::display_width(label.as_str().as_bytes());
+            // Keep a column for the '…' unless this is the last desktop
+            let needed = width + usize::from(position + 1 < status.desktops.len());
+            if used + needed > room {
+                if used < room {
+                    line.push('…');
+                    used += 1;
+                }
+                break;
+            }
+            if position == status.current {
+                let _ = write!(line, "{}{}{}", CURRENT, label.as_str(), BAR);
+            } else {
+                let _ = line.write_str(label.as_str());
+            }
+            used += width;
+        }
+        for _ in used..room {
+            line.push(' ');
+        }
+        let _ = write!(line, "{}\x1B[0m", right.as_str());
+        line
+    }
+}
+
+fn label(position: usize, name: &FixedString<MAX_NAME_LEN>) -> FixedString<{ MAX_NAME_LEN + 8 }> {
+    let mut label = FixedString::new();
+    let _ = write!(label, " {}:{} ", position + 1, name.as_str());
+    label
+}
+
+/// The first desktop to list so that the current one fits in `room`
+/// columns, with a '…' for the desktops left out on either side
+fn first_shown(status: &Status, room: usize) -> usize {
+    let count = status.desktops.len();
+    if status.current >= count {
+        return 0;
+    }
+    let width = |position: usize| text::display_width(label(position, &status.desktops[position]).as_str().as_bytes());
+    let after = usize::from(status.current + 1 < count);
+    let mut needed = (0..=status.current).map(width).sum::<usize>() + after;
+    let mut first = 0;
+    while first < status.current && needed > room {
+        needed -= width(first);
+        // The '…' for the desktops before
+        if first == 0 {
+            needed += 1;
+        }
+        first += 1;
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    fn names(names: &[&str]) -> Vec<FixedString<MAX_NAME_LEN>> {
+        names.iter().map(|name| FixedString::from_str_truncated(name)).collect()
+    }
+
+    fn status(desktops: &[FixedString<MAX_NAME_LEN>]) -> Status<'_> {
+        Status {
+            desktops,
+            current: 1,
+            pane: 0,
+            panes: 2,
+            compositor_running: true,
+            // 1970-01-02 01:01:05
+            time: 25 * 3600 + 65,
+            free_memory: 96 << 20,
+        }
+    }
+
+    #[test]
+    fn draws_on_the_bottom_row() {
+        let sink = CaptureSink::leak();
+        let mut bar = StatusBar::empty();
+        bar.init(sink);
+        bar.set_size(70, 24);
+        let desktops = names(&["Desktop 1", "Notes"]);
+        bar.update(&status(&desktops));
+        let right = " pane 1/2 │ wayland on │ 01:01 │ 96M free ";
+        let expected = format!(
+            "\x1B7\x1B[24;1H\x1B[0;7m 1:Desktop 1 \x1B[0;1;30;42m 2:Notes \x1B[0;7m{}{}\x1B[0m\x1B8",
+            " ".repeat(70 - 22 - right.chars().count()),
+            right
+        );
+        assert_eq!(sink.text(), expected);
+    }
+
+    #[test]
+    fn redraws_only_what_changed() {
+        let sink = CaptureSink::leak();
+        let mut bar = StatusBar::empty();
+        bar.init(sink);
+        let desktops = names(&["Desktop 1", "Notes"]);
+        let mut status = status(&desktops);
+        bar.update(&status);
+        sink.clear();
+        status.time += 30;
+        bar.update(&status);
+        assert_eq!(sink.text(), "");
+
+        status.time += 30;
+        status.compositor_running = false;
+        bar.update(&status);
+        assert!(sink.text().contains("wayland off │ 01:02"));
+
+        sink.clear();
+        bar.set_enabled(false);
+        assert_eq!(bar.rows(), 0);
+        bar.update(&status);
+        assert_eq!(sink.text(), "");
+    }
+
+    #[test]
+    fn desktops_that_do_not_fit_are_cut_short() {
+        let sink = CaptureSink::leak();
+        let mut bar = StatusBar::empty();
+        bar.init(sink);
+        bar.set_size(70, 24);
+        let desktops = names(&["One", "Two", "Three", "Four", "Five", "Six"]);
+        let mut status = status(&desktops);
+        status.free_memory = 512 << 10;
+        bar.update(&status);
+        let text = sink.text();
+        assert!(text.contains(" 2:Two \x1B[0;7m 3:Three …    pane"));
+        assert!(text.ends_with("│ 512K free \x1B[0m\x1B8"));
+        assert!(!text.contains("4:Four"));
+    }
+
+    #[test]
+    fn the_current_desktop_is_always_shown() {
+        let sink = CaptureSink::leak();
+        let mut bar = StatusBar::empty();
+        bar.init(sink);
+        let desktops = names(&["Desktop 1", "Desktop 2", "Desktop 3", "Desktop 4"]);
+        let mut status = status(&desktops);
+        status.current = 2;
+        bar.update(&status);
+        let text = sink.text();
+        assert!(text.contains("\x1B[0;7m… 2:Desktop 2 \x1B[0;1;30;42m 3:Desktop 3 \x1B[0;7m…"));
+        assert!(!text.contains("1:Desktop 1"));
+
+        // The last one leaves no '…' after it
+        sink.clear();
+        status.current = 3;
+        bar.update(&status);
+        let text = sink.text();
+        assert!(text.contains("\x1B[0;7m… 3:Desktop 3 \x1B[0;1;30;42m 4:Desktop 4 \x1B[0;7m "));
+        assert!(!text.contains("2:Desktop 2"));
+    }
+}
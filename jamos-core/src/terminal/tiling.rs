@@ -0,0 +1,1189 @@
+// Tiling window manager for virtual desktops
+// The panes of a desktop are the leaves of a binary tree of splits: each
+// split divides its part of the terminal side by side or stacked at a ratio
+// of its own, and either half can be split again. Each pane has its own
+// screen, drawn in its rectangle, and its own shell input line. Borders go
+// between the panes, highlighted around the active one, which is the pane
+// that gets the keys. A zoomed pane covers the whole terminal until the
+// layout changes again. The panes come from a pool all desktops share.
+use core::fmt::Write;
+
+use super::lineedit::LineEditor;
+use super::pool::{PanePool, POOL_PANES};
+use super::screen::{Raw, Screen, MAX_COLUMNS, MAX_ROWS};
+use crate::console::{CharSink, Disconnected};
+use crate::keyboard::KeyEvent;
+use crate::keymap::Keymap;
+
+/// Panes a desktop can be split into: as many as the pool has, so that only
+/// the pool running out (or the terminal's size) stops a split
+pub const MAX_PANES: usize = POOL_PANES;
+
+// A split has two children, so MAX_PANES leaves need this many nodes
+const MAX_NODES: usize = 2 * MAX_PANES - 1;
+
+// Smallest pane a split leaves, and how close a border can be moved to the
+// edge of its split
+const MIN_PANE_COLUMNS: usize = 10;
+const MIN_PANE_ROWS: usize = 3;
+
+// Border position in percent of the split, and how far a resize moves it
+const DEFAULT_SPLIT: usize = 50;
+const RESIZE_STEP: usize = 5;
+
+const ACTIVE_BORDER: &str = "\x1B[0;32m";
+
+// Which neighbours a border cell joins, to pick the box drawing character
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+const HIGHLIGHTED: u8 = 16;
+
+/// How a split divides its part of the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Vertical,    // Side by side, with a vertical border
+    Horizontal,  // Stacked
+}
+
+/// Which way to move the focus or a border
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// A rectangle of the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub row: usize,
+    pub column: usize,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Area {
+    const fn whole(columns: usize, rows: usize) -> Self {
+        Area { row: 0, column: 0, columns, rows }
+    }
+
+    fn contains(&self, row: usize, column: usize) -> bool {
+        (self.row..self.row + self.rows).contains(&row) && (self.column..self.column + self.columns).contains(&column)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum NodeKind {
+    Free,
+    // Slot of the pane in `panes`
+    Pane(usize),
+    // `ratio` is the percent of the area the first child gets
+    Split { orientation: Orientation, ratio: usize, first: usize, second: usize },
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    kind: NodeKind,
+    parent: Option<usize>,
+    // Where the node was laid out, borders between its children included
+    area: Area,
+}
+
+impl Node {
+    const FREE: Node = Node {
+        kind: NodeKind::Free,
+        parent: None,
+        area: Area::whole(0, 0),
+    };
+}
+
+/// A tree of splits read from text, for `TilingManager::apply`. A split is
+/// `v` (side by side) or `h` (stacked) with the percent its first half gets,
+/// followed by its two halves; a pane is `.`, or `*` for the active one. So
+/// `v50 . h30 * .` is a pane on the left and two stacked on the right.
+#[derive(Clone, Copy)]
+pub struct Layout {
+    // The root is node 0; the panes are numbered left to right
+    nodes: [Node; MAX_NODES],
+    panes: usize,
+    active: usize,
+}
+
+impl Layout {
+    /// One pane covering the terminal
+    pub const fn single() -> Self {
+        let mut nodes = [Node::FREE; MAX_NODES];
+        nodes[0].kind = NodeKind::Pane(0);
+        Layout { nodes, panes: 1, active: 0 }
+    }
+
+    /// How many panes the layout has
+    pub fn panes(&self) -> usize {
+        self.panes
+    }
+
+    pub fn parse(text: &str) -> Result<Layout, &'static str> {
+        let mut layout = Layout { nodes: [Node::FREE; MAX_NODES], panes: 0, active: 0 };
+        let mut used = 0;
+        let mut words = text.split_whitespace();
+        layout.parse_node(&mut words, None, &mut used)?;
+        if words.next().is_some() {
+            return Err("Layout has words left over");
+        }
+        Ok(layout)
+    }
+
+    fn parse_node<'a>(
+        &mut self,
+        words: &mut impl Iterator<Item = &'a str>,
+        parent: Option<usize>,
+        used: &mut usize,
+    ) -> Result<usize, &'static str> {
+        let word = words.next().ok_or("Layout ends too soon")?;
+        if *used == MAX_NODES {
+            return Err("Too many panes");
+        }
+        let node = *used;
+        *used += 1;
+        self.nodes[node].parent = parent;
+        if word == "." || word == "*" {
+            if word == "*" {
+                self.active = self.panes;
+            }
+            self.nodes[node].kind = NodeKind::Pane(self.panes);
+            self.panes += 1;
+            return Ok(node);
+        }
+        let (orientation, ratio) = if let Some(ratio) = word.strip_prefix('v') {
+            (Orientation::Vertical, ratio)
+        } else if let Some(ratio) = word.strip_prefix('h') {
+            (Orientation::Horizontal, ratio)
+        } else {
+            return Err("Unknown word in layout");
+        };
+        let ratio = ratio.parse().ok().filter(|ratio| (1..100).contains(ratio)).ok_or("Split ratio must be 1 to 99")?;
+        // Taken before the halves so they get nodes of their own
+        self.nodes[node].kind = NodeKind::Pane(0);
+        let first = self.parse_node(words, Some(node), used)?;
+        let second = self.parse_node(words, Some(node), used)?;
+        self.nodes[node].kind = NodeKind::Split { orientation, ratio, first, second };
+        Ok(node)
+    }
+}
+
+pub struct Pane {
+    screen: Screen,
+    input: LineEditor,
+    // Leaf of the tree the pane is in
+    node: usize,
+    pane_id: usize,
+}
+
+impl Pane {
+    pub const fn empty() -> Self {
+        Pane {
+            screen: Screen::empty(),
+            input: LineEditor::new(),
+            node: 0,
+            pane_id: 0,
+        }
+    }
+
+    /// A fresh shell; in place, a screen is too big to move around
+    fn init(&mut self, sink: &'static dyn CharSink, pane_id: usize, node: usize) {
+        self.screen.init(sink);
+        self.input.clear();
+        self.node = node;
+        self.pane_id = pane_id;
+    }
+
+    pub fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    /// The shell input line
+    pub fn input(&self) -> &LineEditor {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut LineEditor {
+        &mut self.input
+    }
+
+    /// Apply an editing key to the input line; false if it is not one
+    pub fn edit_input(&mut self, event: &KeyEvent, keymap: &Keymap) -> bool {
+        self.input.handle_key(event, keymap, &mut self.screen)
+    }
+
+    /// Type text into the input line; false if it does not fit
+    pub fn insert_input(&mut self, text: &str) -> bool {
+        self.input.insert(&mut self.screen, text)
+    }
+
+    /// Put `text` in place of the input line
+    pub fn set_input(&mut self, text: &str) {
+        self.input.set(&mut self.screen, text);
+    }
+
+    /// Wipe the prompt and input line from the screen, keeping the line
+    pub fn erase_input(&mut self) {
+        self.input.erase(&mut self.screen);
+    }
+
+    /// Draw the input line again after a fresh prompt
+    pub fn redraw_input(&mut self) {
+        self.input.redraw(&mut self.screen);
+    }
+
+    /// Move the cursor past the input line, ready for a line break
+    pub fn finish_input(&mut self) {
+        self.input.move_to_end(&mut self.screen);
+    }
+
+    pub fn id(&self) -> usize {
+        self.pane_id
+    }
+}
+
+pub struct TilingManager {
+    // None until `init`
+    sink: Option<&'static dyn CharSink>,
+    pool: Option<&'static PanePool>,
+    // The panes taken from the pool, by slot
+    panes: [Option<&'static mut Pane>; MAX_PANES],
+    nodes: [Node; MAX_NODES],
+    root: usize,
+    active_pane: usize,
+    pane_count: usize,
+    // Size of the whole terminal
+    columns: usize,
+    rows: usize,
+    // Rows at the bottom kept for a status bar
+    reserved: usize,
+    zoomed: bool,
+    // Whether the desktop is the one shown
+    visible: bool,
+}
+
+impl TilingManager {
+    /// All zeros, holding no panes; `init` before use
+    pub const fn empty() -> Self {
+        TilingManager {
+            sink: None,
+            pool: None,
+            panes: [const { None }; MAX_PANES],
+            nodes: [Node::FREE; MAX_NODES],
+            root: 0,
+            active_pane: 0,
+            pane_count: 0,
+            columns: 0,
+            rows: 0,
+            reserved: 0,
+            zoomed: false,
+            visible: false,
+        }
+    }
+
+    /// One pane from `pool` covering the terminal, which keeps its size
+    /// once it has one. The panes held before go back to the pool first;
+    /// if it has none left the manager holds none either.
+    pub fn init(&mut self, sink: &'static dyn CharSink, pool: &'static PanePool) -> Result<(), &'static str> {
+        self.release();
+        self.sink = Some(sink);
+        self.pool = Some(pool);
+        let pane = pool.take().ok_or("No free panes")?;
+        if self.columns == 0 {
+            self.columns = super::screen::DEFAULT_COLUMNS;
+            self.rows = super::screen::DEFAULT_ROWS;
+        }
+        self.nodes = [Node::FREE; MAX_NODES];
+        self.nodes[0].kind = NodeKind::Pane(0);
+        pane.init(sink, 0, 0);
+        self.panes[0] = Some(pane);
+        self.root = 0;
+        self.pane_count = 1;
+        self.active_pane = 0;
+        self.zoomed = false;
+        self.visible = true;
+        self.relayout();
+        Ok(())
+    }
+
+    /// Give every pane back to the pool, as when the desktop closes
+    pub fn release(&mut self) {
+        for slot in 0..MAX_PANES {
+            self.give_back(slot);
+        }
+        self.pane_count = 0;
+    }
+
+    fn give_back(&mut self, slot: usize) {
+        if let (Some(pane), Some(pool)) = (self.panes[slot].take(), self.pool) {
+            pool.give_back(pane);
+        }
+    }
+
+    /// Panes this manager could have at once: those it has and those left
+    /// in the pool
+    pub fn available_panes(&self) -> usize {
+        self.pane_count + self.pool.map_or(0, |pool| pool.free())
+    }
+
+    // The tree only refers to slots that hold a pane
+    fn pane(&self, slot: usize) -> &Pane {
+        self.panes[slot].as_deref().expect("pane slot in use")
+    }
+
+    fn pane_mut(&mut self, slot: usize) -> &mut Pane {
+        self.panes[slot].as_deref_mut().expect("pane slot in use")
+    }
+
+    fn sink(&self) -> &'static dyn CharSink {
+        self.sink.unwrap_or(&Disconnected)
+    }
+
+    /// Terminal dimensions as reported by the client; the panes are laid
+    /// out again (and drawn, when split)
+    pub fn set_size(&mut self, columns: usize, rows: usize) {
+        if columns > 0 && rows > 0 {
+            self.columns = columns.min(MAX_COLUMNS);
+            self.rows = rows.min(MAX_ROWS).max(self.reserved + 1);
+            self.relayout();
+            if !self.is_direct() {
+                self.render();
+            }
+        }
+    }
+
+    /// Keep `rows` at the bottom of the terminal out of the panes' reach.
+    /// The panes are then always drawn row by row, even a single one, so
+    /// that nothing they print can scroll or clear those rows.
+    pub fn set_reserved_rows(&mut self, rows: usize) {
+        if rows != self.reserved && rows < self.rows {
+            self.reserved = rows;
+            self.relayout();
+            if self.visible {
+                self.render();
+            }
+        }
+    }
+
+    /// Split the active pane in two side by side; the new pane on the right
+    /// becomes the active one
+    pub fn split_vertical(&mut self) -> Result<(), &'static str> {
+        if self.area(self.active_pane).columns < 2 * MIN_PANE_COLUMNS + 1 {
+            return Err("Pane too narrow to split");
+        }
+        self.split(Orientation::Vertical)
+    }
+
+    /// Split the active pane in two stacked; the new pane below becomes the
+    /// active one
+    pub fn split_horizontal(&mut self) -> Result<(), &'static str> {
+        if self.area(self.active_pane).rows < 2 * MIN_PANE_ROWS + 1 {
+            return Err("Pane too short to split");
+        }
+        self.split(Orientation::Horizontal)
+    }
+
+    /// The active pane's leaf becomes a split with the pane as its first
+    /// child and a new pane as the second
+    fn split(&mut self, orientation: Orientation) -> Result<(), &'static str> {
+        let slot = self.panes.iter().position(Option::is_none).ok_or("Too many panes")?;
+        let mut free = (0..MAX_NODES).filter(|&node| matches!(self.nodes[node].kind, NodeKind::Free));
+        let (Some(first), Some(second)) = (free.next(), free.next()) else {
+            return Err("Too many panes");
+        };
+        let pane = self.pool.and_then(|pool| pool.take()).ok_or("No free panes")?;
+        let leaf = self.pane(self.active_pane).node;
+        self.nodes[first] = Node { kind: NodeKind::Pane(self.active_pane), parent: Some(leaf), ..self.nodes[leaf] };
+        self.nodes[second] = Node { kind: NodeKind::Pane(slot), parent: Some(leaf), ..self.nodes[leaf] };
+        self.nodes[leaf].kind = NodeKind::Split { orientation, ratio: DEFAULT_SPLIT, first, second };
+        self.pane_mut(self.active_pane).node = first;
+        pane.init(self.sink(), slot, second);
+        self.panes[slot] = Some(pane);
+        self.pane_count += 1;
+        self.active_pane = slot;
+        self.zoomed = false;
+        self.relayout();
+        self.render();
+        Ok(())
+    }
+
+    /// Close the active pane; the other half of its split takes the whole
+    /// split, and the pane of it nearest the closed one becomes active
+    pub fn close(&mut self) -> Result<(), &'static str> {
+        let leaf = self.pane(self.active_pane).node;
+        let Some(parent) = self.nodes[leaf].parent else {
+            return Err("Cannot close the only pane");
+        };
+        let NodeKind::Split { first, second, .. } = self.nodes[parent].kind else {
+            return Err("Cannot close the only pane");
+        };
+        let sibling = if first == leaf { second } else { first };
+
+        // The sibling moves up into its parent's node
+        self.nodes[parent].kind = self.nodes[sibling].kind;
+        match self.nodes[parent].kind {
+            NodeKind::Pane(slot) => self.pane_mut(slot).node = parent,
+            NodeKind::Split { first, second, .. } => {
+                self.nodes[first].parent = Some(parent);
+                self.nodes[second].parent = Some(parent);
+            }
+            NodeKind::Free => {}
+        }
+        self.nodes[sibling] = Node::FREE;
+        self.nodes[leaf] = Node::FREE;
+        self.give_back(self.active_pane);
+        self.pane_count -= 1;
+        self.active_pane = self.outer_pane(parent, second == leaf);
+        self.zoomed = false;
+        self.relayout();
+        self.render();
+        Ok(())
+    }
+
+    pub fn next_pane(&mut self) -> bool {
+        self.pane_count > 1 && self.focus(self.neighbour(self.active_pane, true))
+    }
+
+    pub fn prev_pane(&mut self) -> bool {
+        self.pane_count > 1 && self.focus(self.neighbour(self.active_pane, false))
+    }
+
+    /// Make the pane next to the active one in `direction` active; the one
+    /// level with the cursor when several are
+    pub fn focus_direction(&mut self, direction: Direction) -> bool {
+        let area = self.area(self.active_pane);
+        let (cursor_row, cursor_column) = self.pane(self.active_pane).screen.cursor();
+        let row = area.row + cursor_row.min(area.rows - 1);
+        let column = area.column + cursor_column.min(area.columns - 1);
+        // A cell just past the border on that side
+        let target = match direction {
+            Direction::Left => area.column.checked_sub(2).map(|column| (row, column)),
+            Direction::Right => Some((row, area.column + area.columns + 1)),
+            Direction::Up => area.row.checked_sub(2).map(|row| (row, column)),
+            Direction::Down => Some((area.row + area.rows + 1, column)),
+        };
+        let Some((row, column)) = target else {
+            return false;
+        };
+        match (0..MAX_PANES).find(|&slot| self.panes[slot].is_some() && self.area(slot).contains(row, column)) {
+            Some(slot) => self.focus(slot),
+            None => false,
+        }
+    }
+
+    fn focus(&mut self, slot: usize) -> bool {
+        self.active_pane = slot;
+        if self.zoomed {
+            self.zoomed = false;
+            self.relayout();
+        }
+        self.render();
+        true
+    }
+
+    /// Swap the active pane with the next (or previous) one; it stays
+    /// active in its new place
+    pub fn swap(&mut self, forward: bool) -> bool {
+        if self.pane_count <= 1 {
+            return false;
+        }
+        let other = self.neighbour(self.active_pane, forward);
+        let (a, b) = (self.pane(self.active_pane).node, self.pane(other).node);
+        self.nodes[a].kind = NodeKind::Pane(other);
+        self.nodes[b].kind = NodeKind::Pane(self.active_pane);
+        self.pane_mut(self.active_pane).node = b;
+        self.pane_mut(other).node = a;
+        self.zoomed = false;
+        self.relayout();
+        self.render();
+        true
+    }
+
+    /// Let the active pane cover the whole terminal, or put it back
+    pub fn toggle_zoom(&mut self) -> bool {
+        if self.pane_count <= 1 {
+            return false;
+        }
+        self.zoomed = !self.zoomed;
+        self.relayout();
+        self.render();
+        true
+    }
+
+    pub fn is_zoomed(&self) -> bool {
+        self.zoomed
+    }
+
+    /// Move the nearest border of the active pane that runs the right way:
+    /// one between columns for Left/Right, rows for Up/Down. False if there
+    /// is none or it cannot go further.
+    pub fn resize(&mut self, direction: Direction) -> bool {
+        let wanted = match direction {
+            Direction::Left | Direction::Right => Orientation::Vertical,
+            Direction::Up | Direction::Down => Orientation::Horizontal,
+        };
+        let mut node = self.pane(self.active_pane).node;
+        let (split, second) = loop {
+            let Some(parent) = self.nodes[node].parent else {
+                return false;
+            };
+            if let NodeKind::Split { orientation, second, .. } = self.nodes[parent].kind {
+                if orientation == wanted {
+                    break (parent, second);
+                }
+            }
+            node = parent;
+        };
+        let NodeKind::Split { ratio, .. } = &mut self.nodes[split].kind else {
+            return false;
+        };
+        let before = *ratio;
+        *ratio = match direction {
+            Direction::Right | Direction::Down => before + RESIZE_STEP,
+            Direction::Left | Direction::Up => before.saturating_sub(RESIZE_STEP),
+        }
+        .clamp(RESIZE_STEP, 100 - RESIZE_STEP);
+
+        let border = self.nodes[second].area;
+        self.zoomed = false;
+        self.relayout();
+        if self.nodes[second].area == border {
+            // Already as close to the edge as the panes may get
+            if let NodeKind::Split { ratio, .. } = &mut self.nodes[split].kind {
+                *ratio = before;
+            }
+            self.relayout();
+            return false;
+        }
+        self.render();
+        true
+    }
+
+    /// The layout in the words `Layout::parse` reads
+    pub fn save(&self, out: &mut impl Write) -> core::fmt::Result {
+        self.save_node(self.root, out)
+    }
+
+    fn save_node(&self, node: usize, out: &mut impl Write) -> core::fmt::Result {
+        match self.nodes[node].kind {
+            NodeKind::Split { orientation, ratio, first, second } => {
+                let letter = match orientation {
+                    Orientation::Vertical => 'v',
+                    Orientation::Horizontal => 'h',
+                };
+                write!(out, "{}{} ", letter, ratio)?;
+                self.save_node(first, out)?;
+                out.write_char(' ')?;
+                self.save_node(second, out)
+            }
+            NodeKind::Pane(slot) => out.write_char(if slot == self.active_pane { '*' } else { '.' }),
+            NodeKind::Free => Ok(()),
+        }
+    }
+
+    /// Whether the panes of `layout` can all have their smallest size
+    pub fn fits(&self, layout: &Layout) -> bool {
+        let (columns, rows) = min_size(&layout.nodes, 0);
+        columns <= self.columns && rows <= self.rows - self.reserved
+    }
+
+    /// Replace the panes with fresh shells laid out as `layout` says
+    pub fn apply(&mut self, layout: &Layout) -> Result<(), &'static str> {
+        if !self.fits(layout) {
+            return Err("Layout does not fit the terminal");
+        }
+        if layout.panes > self.available_panes() {
+            return Err("No free panes");
+        }
+        self.release();
+        self.nodes = layout.nodes;
+        for node in 0..MAX_NODES {
+            if let NodeKind::Pane(slot) = self.nodes[node].kind {
+                // Counted above
+                if let Some(pane) = self.pool.and_then(|pool| pool.take()) {
+                    pane.init(self.sink(), slot, node);
+                    self.panes[slot] = Some(pane);
+                }
+            }
+        }
+        self.root = 0;
+        self.pane_count = layout.panes;
+        self.active_pane = layout.active;
+        self.zoomed = false;
+        self.relayout();
+        if self.visible {
+            self.render();
+        }
+        Ok(())
+    }
+
+    /// The pane after (or before) `slot`, left to right and top to bottom
+    /// through the tree, wrapping around
+    fn neighbour(&self, slot: usize, forward: bool) -> usize {
+        let mut node = self.pane(slot).node;
+        while let Some(parent) = self.nodes[node].parent {
+            if let NodeKind::Split { first, second, .. } = self.nodes[parent].kind {
+                let (from, to) = if forward { (first, second) } else { (second, first) };
+                if node == from {
+                    return self.outer_pane(to, !forward);
+                }
+            }
+            node = parent;
+        }
+        self.outer_pane(self.root, !forward)
+    }
+
+    /// The first (or last) pane under `node`
+    fn outer_pane(&self, mut node: usize, last: bool) -> usize {
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Split { first, second, .. } => node = if last { second } else { first },
+                NodeKind::Pane(slot) => return slot,
+                NodeKind::Free => return self.active_pane,
+            }
+        }
+    }
+
+    /// The part of the terminal a pane has in the tree, zoomed or not
+    fn area(&self, slot: usize) -> Area {
+        self.nodes[self.pane(slot).node].area
+    }
+
+    /// Give `node` its area, and its children their parts of it
+    fn lay_out(&mut self, node: usize, area: Area) {
+        self.nodes[node].area = area;
+        let NodeKind::Split { orientation, ratio, first, second } = self.nodes[node].kind else {
+            return;
+        };
+        let (a, b) = (min_size(&self.nodes, first), min_size(&self.nodes, second));
+        let (first_area, second_area) = match orientation {
+            Orientation::Vertical => {
+                let border = border_position(area.columns, ratio, a.0, b.0);
+                (
+                    Area { columns: border.max(1), ..area },
+                    Area { column: area.column + border + 1, columns: area.columns.saturating_sub(border + 1).max(1), ..area },
+                )
+            }
+            Orientation::Horizontal => {
+                let border = border_position(area.rows, ratio, a.1, b.1);
+                (
+                    Area { rows: border.max(1), ..area },
+                    Area { row: area.row + border + 1, rows: area.rows.saturating_sub(border + 1).max(1), ..area },
+                )
+            }
+        };
+        self.lay_out(first, first_area);
+        self.lay_out(second, second_area);
+    }
+
+    /// Give each pane its size and place on the terminal; a single or
+    /// zoomed pane is the whole terminal and, unless rows are reserved,
+    /// writes to it directly
+    fn relayout(&mut self) {
+        let whole = Area::whole(self.columns, self.rows - self.reserved);
+        self.lay_out(self.root, whole);
+        let direct = self.is_direct();
+        let (zoomed, active, visible) = (self.zoomed, self.active_pane, self.visible);
+        for slot in 0..MAX_PANES {
+            if self.panes[slot].is_none() {
+                continue;
+            }
+            let shown = !zoomed || slot == active;
+            let area = if zoomed { whole } else { self.area(slot) };
+            let screen = &mut self.pane_mut(slot).screen;
+            // Setting the size resets the scroll region, so only on a change
+            if (screen.columns(), screen.rows()) != (area.columns, area.rows) {
+                screen.set_size(area.columns, area.rows);
+            }
+            screen.set_origin(if direct { None } else { Some((area.row, area.column)) });
+            screen.set_visible(visible && shown);
+        }
+    }
+
+    /// Whether the active pane writes straight to the terminal
+    fn is_direct(&self) -> bool {
+        self.reserved == 0 && (self.pane_count <= 1 || self.zoomed)
+    }
+
+    /// Draw every pane and the borders, leaving the cursor in the active
+    /// pane
+    pub fn render(&self) {
+        let active = self.pane(self.active_pane);
+        if self.is_direct() {
+            active.screen.render();
+            return;
+        }
+        if !self.visible {
+            return;
+        }
+        let mut out = Raw(self.sink());
+        let _ = out.write_str("\x1B[?1049l\x1B[0m");
+        if self.reserved == 0 {
+            let _ = out.write_str("\x1B[r\x1B[?7h\x1B[?25h\x1B[2J");
+        } else {
+            // Every cell above the reserved rows is drawn, so there is no
+            // need to clear them too
+            let _ = write!(out, "\x1B[1;{}r\x1B[?7h\x1B[?25h", self.rows - self.reserved);
+        }
+        if !self.zoomed {
+            self.draw_borders(&mut out);
+        }
+        for (slot, pane) in self.panes.iter().enumerate() {
+            if let Some(pane) = pane {
+                if slot != self.active_pane && !self.zoomed {
+                    pane.screen.render();
+                }
+            }
+        }
+        active.screen.render();
+    }
+
+    fn draw_borders(&self, out: &mut Raw) {
+        let mut cells = [[0u8; MAX_COLUMNS]; MAX_ROWS];
+        let (columns, rows) = (self.columns, self.rows - self.reserved);
+        let mut mark = |row: usize, column: usize, bits: u8| {
+            if row < rows && column < columns {
+                cells[row][column] |= bits;
+            }
+        };
+
+        // Each split's border, joined to the one it ends on at either end
+        for node in self.nodes.iter() {
+            let NodeKind::Split { orientation, second, .. } = node.kind else {
+                continue;
+            };
+            let (area, next) = (node.area, self.nodes[second].area);
+            match orientation {
+                Orientation::Vertical => {
+                    let column = next.column - 1;
+                    for row in area.row..area.row + area.rows {
+                        mark(row, column, UP | DOWN);
+                    }
+                    if area.row > 0 {
+                        mark(area.row - 1, column, DOWN);
+                    }
+                    mark(area.row + area.rows, column, UP);
+                }
+                Orientation::Horizontal => {
+                    let row = next.row - 1;
+                    for column in area.column..area.column + area.columns {
+                        mark(row, column, LEFT | RIGHT);
+                    }
+                    if area.column > 0 {
+                        mark(row, area.column - 1, RIGHT);
+                    }
+                    mark(row, area.column + area.columns, LEFT);
+                }
+            }
+        }
+
+        // The ring of border around the active pane
+        let active = self.area(self.active_pane);
+        let around = |start: usize, size: usize, end: usize| start.saturating_sub(1)..(start + size + 1).min(end);
+        for line in &mut cells[around(active.row, active.rows, rows)] {
+            for bits in &mut line[around(active.column, active.columns, columns)] {
+                if *bits != 0 {
+                    *bits |= HIGHLIGHTED;
+                }
+            }
+        }
+
+        for (row, line) in cells.iter().enumerate().take(rows) {
+            // Column the terminal's cursor is at, if on this row
+            let mut at = None;
+            let mut highlighted = false;
+            for (column, &bits) in line.iter().enumerate().take(columns) {
+                let Some(c) = border_char(bits) else {
+                    continue;
+                };
+                if at != Some(column) {
+                    let _ = write!(out, "\x1B[{};{}H", row + 1, column + 1);
+                }
+                if (bits & HIGHLIGHTED != 0) != highlighted {
+                    highlighted = !highlighted;
+                    let _ = out.write_str(if highlighted { ACTIVE_BORDER } else { "\x1B[0m" });
+                }
+                let _ = out.write_char(c);
+                at = Some(column + 1);
+            }
+            if highlighted {
+                let _ = out.write_str("\x1B[0m");
+            }
+        }
+    }
+
+    /// Show or hide the desktop; hidden panes keep their screens up to date
+    /// but write nothing
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        self.relayout();
+    }
+
+    pub fn current_pane(&self) -> &Pane {
+        self.pane(self.active_pane)
+    }
+
+    pub fn current_pane_mut(&mut self) -> &mut Pane {
+        self.pane_mut(self.active_pane)
+    }
+
+    /// Every pane of the desktop, in no particular order
+    pub fn panes_mut(&mut self) -> impl Iterator<Item = &mut Pane> {
+        self.panes.iter_mut().flatten().map(|pane| &mut **pane)
+    }
+
+    pub fn get_active_pane(&self) -> usize {
+        self.active_pane
+    }
+
+    /// Where the active pane comes left to right and top to bottom,
+    /// counting from 0
+    pub fn get_active_position(&self) -> usize {
+        let mut slot = self.outer_pane(self.root, false);
+        for position in 0..self.pane_count {
+            if slot == self.active_pane {
+                return position;
+            }
+            slot = self.neighbour(slot, true);
+        }
+        0
+    }
+
+    pub fn get_pane_count(&self) -> usize {
+        self.pane_count
+    }
+}
+
+/// Columns and rows the panes under `node` need at least
+fn min_size(nodes: &[Node], node: usize) -> (usize, usize) {
+    match nodes[node].kind {
+        NodeKind::Split { orientation, first, second, .. } => {
+            let (a, b) = (min_size(nodes, first), min_size(nodes, second));
+            match orientation {
+                Orientation::Vertical => (a.0 + b.0 + 1, a.1.max(b.1)),
+                Orientation::Horizontal => (a.0.max(b.0), a.1 + b.1 + 1),
+            }
+        }
+        _ => (MIN_PANE_COLUMNS, MIN_PANE_ROWS),
+    }
+}
+
+/// Where a border goes across `size` cells at `percent`, leaving at least
+/// `before` and `after` cells on either side when there is room
+fn border_position(size: usize, percent: usize, before: usize, after: usize) -> usize {
+    let low = before.min(size.saturating_sub(1) / 2);
+    let high = size.saturating_sub(after + 1).max(low);
+    (size * percent / 100).clamp(low, high)
+}
+
+fn border_char(bits: u8) -> Option<char> {
+    Some(match bits & !HIGHLIGHTED {
+        b if b == UP | DOWN => '│',
+        b if b == LEFT | RIGHT => '─',
+        b if b == UP | DOWN | LEFT | RIGHT => '┼',
+        b if b == UP | DOWN | RIGHT => '├',
+        b if b == UP | DOWN | LEFT => '┤',
+        b if b == DOWN | LEFT | RIGHT => '┬',
+        b if b == UP | LEFT | RIGHT => '┴',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    /// Run `test` on a new tiling manager; its screens do not fit on a test
+    /// thread's stack, so it gets a thread of its own
+    fn with_tiling(test: impl FnOnce(&mut TilingManager, &'static CaptureSink) + Send + 'static) {
+        let thread = std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            let sink = CaptureSink::leak();
+            let mut tiling = Box::new(TilingManager::empty());
+            tiling.init(sink, Box::leak(Box::new(PanePool::empty()))).unwrap();
+            test(&mut tiling, sink);
+        });
+        thread.unwrap().join().unwrap();
+    }
+
+    /// (row, column, columns, rows) of each pane in use, by slot
+    fn regions(tiling: &TilingManager) -> Vec<(usize, usize, usize, usize)> {
+        (0..MAX_PANES)
+            .filter(|&slot| tiling.panes[slot].is_some())
+            .map(|slot| tiling.area(slot))
+            .map(|area| (area.row, area.column, area.columns, area.rows))
+            .collect()
+    }
+
+    #[test]
+    fn splits_nest() {
+        with_tiling(|tiling, _| {
+            assert!(tiling.current_pane_mut().screen_mut().origin().is_none());
+
+            tiling.split_vertical().unwrap();
+            assert_eq!(tiling.get_active_pane(), 1);
+            assert_eq!(regions(tiling), [(0, 0, 40, 24), (0, 41, 39, 24)]);
+            assert_eq!(tiling.current_pane_mut().screen_mut().origin(), Some((0, 41)));
+
+            // The right half again, then its right half stacked
+            tiling.split_vertical().unwrap();
+            tiling.split_horizontal().unwrap();
+            assert_eq!(regions(tiling), [(0, 0, 40, 24), (0, 41, 19, 24), (0, 61, 19, 12), (13, 61, 19, 11)]);
+            assert_eq!(tiling.current_pane_mut().screen_mut().columns(), 19);
+            assert_eq!(tiling.split_vertical(), Err("Pane too narrow to split"));
+            for _ in 0..3 {
+                tiling.split_horizontal().unwrap_or(());
+            }
+            assert_eq!(tiling.split_horizontal(), Err("Pane too short to split"));
+
+            // Back on the left
+            tiling.next_pane();
+            assert_eq!(tiling.get_active_pane(), 0);
+            tiling.split_vertical().unwrap();
+            tiling.split_horizontal().unwrap();
+            tiling.split_horizontal().unwrap();
+            assert_eq!(tiling.get_pane_count(), 8);
+        });
+    }
+
+    #[test]
+    fn splits_go_on_until_the_pool_runs_out() {
+        with_tiling(|tiling, _| {
+            // Every pane split while the terminal has room
+            fn split_all(tiling: &mut TilingManager) -> usize {
+                let mut count = 0;
+                while tiling.get_pane_count() > count {
+                    count = tiling.get_pane_count();
+                    for _ in 0..count {
+                        while tiling.split_vertical().is_ok() || tiling.split_horizontal().is_ok() {}
+                        tiling.next_pane();
+                    }
+                }
+                count
+            }
+            let count = split_all(tiling);
+            assert!(count > 8);
+            let pool = tiling.pool.unwrap();
+            assert_eq!(pool.free(), POOL_PANES - count);
+
+            // On a bigger terminal, until the pool has none left
+            tiling.set_size(MAX_COLUMNS, MAX_ROWS);
+            assert_eq!(split_all(tiling), POOL_PANES);
+            assert_eq!(pool.free(), 0);
+        });
+    }
+
+    #[test]
+    fn panes_come_from_the_pool() {
+        with_tiling(|tiling, _| {
+            // Other desktops hold all but one of the rest
+            let pool = tiling.pool.unwrap();
+            while pool.free() > 1 {
+                pool.take();
+            }
+            assert_eq!(tiling.available_panes(), 2);
+            tiling.split_vertical().unwrap();
+            assert_eq!(tiling.split_horizontal(), Err("No free panes"));
+            assert_eq!(tiling.apply(&Layout::parse("v50 . h50 . *").unwrap()), Err("No free panes"));
+            assert_eq!(tiling.get_pane_count(), 2);
+
+            tiling.close().unwrap();
+            assert_eq!(pool.free(), 1);
+            tiling.split_horizontal().unwrap();
+            tiling.release();
+            assert_eq!(pool.free(), 2);
+        });
+    }
+
+    #[test]
+    fn closing_gives_the_space_to_the_other_half() {
+        with_tiling(|tiling, _| {
+            assert_eq!(tiling.close(), Err("Cannot close the only pane"));
+
+            // Left pane, two stacked on the right
+            tiling.split_vertical().unwrap();
+            tiling.split_horizontal().unwrap();
+            tiling.next_pane();
+            assert_eq!(tiling.get_active_pane(), 0);
+            tiling.close().unwrap();
+            assert_eq!(regions(tiling), [(0, 0, 80, 12), (13, 0, 80, 11)]);
+            // The top one was next to the closed pane
+            assert_eq!(tiling.get_active_pane(), 1);
+
+            tiling.close().unwrap();
+            assert_eq!(tiling.get_active_pane(), 2);
+            assert_eq!(regions(tiling), [(0, 0, 80, 24)]);
+            assert!(tiling.current_pane_mut().screen_mut().origin().is_none());
+
+            // Slots are reused
+            tiling.split_horizontal().unwrap();
+            assert_eq!(tiling.get_active_pane(), 0);
+        });
+    }
+
+    #[test]
+    fn panes_keep_their_own_input_and_output() {
+        with_tiling(|tiling, sink| {
+            tiling.current_pane_mut().screen_mut().puts("left");
+            assert!(tiling.current_pane_mut().insert_input("a"));
+            tiling.split_vertical().unwrap();
+            assert!(tiling.current_pane_mut().input().is_empty());
+
+            sink.clear();
+            tiling.current_pane_mut().screen_mut().puts("right");
+            // Drawn inside the pane instead of at the terminal's cursor
+            assert!(sink.text().starts_with("\x1B[1;42Hright\x1B[34X"));
+            assert!(sink.text().ends_with("\x1B[1;47H"));
+
+            tiling.next_pane();
+            assert_eq!(tiling.current_pane_mut().input().as_str(), "a");
+            assert_eq!(tiling.current_pane_mut().screen_mut().cell(0, 0).ch, 'l');
+        });
+    }
+
+    #[test]
+    fn borders_highlight_the_active_pane() {
+        with_tiling(|tiling, sink| {
+            tiling.split_vertical().unwrap();
+            let text = sink.text();
+            assert!(text.contains("\x1B[1;41H\x1B[0;32m│\x1B[0m"));
+            assert!(text.contains("\x1B[24;41H\x1B[0;32m│\x1B[0m"));
+
+            tiling.split_horizontal().unwrap();
+            sink.clear();
+            tiling.prev_pane();
+            tiling.prev_pane();
+            let text = sink.text();
+            // The left pane is active: the border next to it, not the one
+            // between the right panes
+            assert!(text.contains("\x1B[13;41H\x1B[0;32m├\x1B[0m─"));
+        });
+    }
+
+    #[test]
+    fn resizing_moves_the_nearest_border() {
+        with_tiling(|tiling, _| {
+            assert!(!tiling.resize(Direction::Right));
+            tiling.split_vertical().unwrap();
+            tiling.split_vertical().unwrap();
+            assert!(!tiling.resize(Direction::Up));
+            // Between the two right panes
+            assert!(tiling.resize(Direction::Right));
+            assert_eq!(regions(tiling), [(0, 0, 40, 24), (0, 41, 21, 24), (0, 63, 17, 24)]);
+            for _ in 0..20 {
+                tiling.resize(Direction::Right);
+            }
+            assert!(!tiling.resize(Direction::Right));
+            assert_eq!(regions(tiling)[2].2, MIN_PANE_COLUMNS);
+
+            // The outer border keeps room for both right panes
+            tiling.focus(0);
+            for _ in 0..20 {
+                tiling.resize(Direction::Right);
+            }
+            assert_eq!(regions(tiling)[1].1, 80 - 2 * MIN_PANE_COLUMNS - 1);
+
+            tiling.set_size(15, 5);
+            assert_eq!(regions(tiling)[0], (0, 0, 7, 5));
+        });
+    }
+
+    #[test]
+    fn focus_moves_towards_a_direction() {
+        with_tiling(|tiling, _| {
+            // Left pane, two stacked on the right
+            tiling.split_vertical().unwrap();
+            tiling.split_horizontal().unwrap();
+            assert!(tiling.focus_direction(Direction::Up));
+            assert_eq!(tiling.get_active_pane(), 1);
+            assert!(!tiling.focus_direction(Direction::Up));
+            assert!(!tiling.focus_direction(Direction::Right));
+
+            assert!(tiling.focus_direction(Direction::Left));
+            assert_eq!(tiling.get_active_pane(), 0);
+            // Level with the cursor, on the bottom half
+            tiling.current_pane_mut().screen_mut().puts("\x1B[20;1H");
+            assert!(tiling.focus_direction(Direction::Right));
+            assert_eq!(tiling.get_active_pane(), 2);
+        });
+    }
+
+    #[test]
+    fn swapping_and_zooming() {
+        with_tiling(|tiling, sink| {
+            assert!(!tiling.toggle_zoom());
+            tiling.split_vertical().unwrap();
+            assert!(tiling.swap(true));
+            assert_eq!(tiling.get_active_pane(), 1);
+            assert_eq!(regions(tiling), [(0, 41, 39, 24), (0, 0, 40, 24)]);
+            assert_eq!(tiling.current_pane_mut().screen_mut().origin(), Some((0, 0)));
+
+            assert!(tiling.toggle_zoom());
+            assert!(tiling.is_zoomed());
+            assert!(tiling.current_pane_mut().screen_mut().origin().is_none());
+            assert_eq!(tiling.current_pane_mut().screen_mut().columns(), 80);
+            // The other pane keeps its output to itself meanwhile
+            assert!(!tiling.pane(0).screen.is_visible());
+
+            sink.clear();
+            assert!(tiling.next_pane());
+            assert!(!tiling.is_zoomed());
+            assert!(tiling.pane(1).screen.is_visible());
+            assert!(sink.text().contains('│'));
+        });
+    }
+
+    #[test]
+    fn reserved_rows_are_never_drawn_on() {
+        with_tiling(|tiling, sink| {
+            sink.clear();
+            tiling.set_reserved_rows(1);
+            let screen = tiling.current_pane_mut().screen_mut();
+            assert_eq!(screen.origin(), Some((0, 0)));
+            assert_eq!(screen.rows(), 23);
+            assert!(sink.text().contains("\x1B[1;23r"));
+            assert!(!sink.text().contains("\x1B[2J"));
+
+            for _ in 0..30 {
+                tiling.current_pane_mut().screen_mut().puts("line\n");
+            }
+            tiling.split_vertical().unwrap();
+            assert_eq!(tiling.get_active_position(), 1);
+            assert_eq!(regions(tiling), [(0, 0, 40, 23), (0, 41, 39, 23)]);
+            tiling.toggle_zoom();
+            assert_eq!(tiling.current_pane_mut().screen_mut().origin(), Some((0, 0)));
+            assert!(!sink.text().contains("\x1B[24;"));
+        });
+    }
+
+    #[test]
+    fn layouts_are_saved_as_words() {
+        with_tiling(|tiling, _| {
+            tiling.split_horizontal().unwrap();
+            tiling.resize(Direction::Up);
+            tiling.split_vertical().unwrap();
+            let mut layout = String::new();
+            tiling.save(&mut layout).unwrap();
+            assert_eq!(layout, "h45 . v50 . *");
+            let before = regions(tiling);
+
+            tiling.apply(&Layout::single()).unwrap();
+            assert_eq!(tiling.get_pane_count(), 1);
+            tiling.apply(&Layout::parse(&layout).unwrap()).unwrap();
+            assert_eq!(regions(tiling), before);
+            assert_eq!(tiling.get_active_pane(), 2);
+
+            assert_eq!(Layout::parse("v50 . . .").err(), Some("Layout has words left over"));
+            assert_eq!(Layout::parse("x50 . .").err(), Some("Unknown word in layout"));
+            assert_eq!(Layout::parse("h0 . .").err(), Some("Split ratio must be 1 to 99"));
+            let crowded = Layout::parse("v50 v50 v50 . . v50 . . v50 v50 . . v50 . .").unwrap();
+            assert_eq!(tiling.apply(&crowded), Err("Layout does not fit the terminal"));
+        });
+    }
+}
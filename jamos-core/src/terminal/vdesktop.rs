@@ -0,0 +1,726 @@
+// Virtual desktop management with tiling terminal support
+use core::fmt::Write;
+
+use super::history::{History, HISTORY_BYTES};
+use super::pool::{PanePool, POOL_PANES};
+use super::screen::Screen;
+use super::tiling::{Layout, TilingManager};
+use crate::console::{CharSink, Disconnected};
+use crate::keyboard::KeyEvent;
+use crate::keymap::{Action, Keymap, Scope};
+use crate::text::FixedString;
+
+pub const MAX_NAME_LEN: usize = 16;
+// Bytes of UTF-8, so fewer characters for non-ASCII text; a line may wrap
+// over several rows
+pub const MAX_INPUT_LEN: usize = 1024;
+
+pub struct VirtualDesktop {
+    name: FixedString<MAX_NAME_LEN>,
+    // The panes, each with its screen and input line
+    tiling: TilingManager,
+    // Commands run in any of the panes
+    history: History,
+    is_active: bool,
+}
+
+impl VirtualDesktop {
+    pub const fn empty() -> Self {
+        VirtualDesktop {
+            name: FixedString::new(),
+            tiling: TilingManager::empty(),
+            history: History::empty(),
+            is_active: false,
+        }
+    }
+    
+    /// One fresh pane from `pool`, and the name; the command history stays.
+    /// An error if the pool has no pane left.
+    pub fn init(&mut self, sink: &'static dyn CharSink, pool: &'static PanePool, name: &str) -> Result<(), &'static str> {
+        self.tiling.init(sink, pool)?;
+        self.set_name(name);
+        self.is_active = true;
+        Ok(())
+    }
+
+    /// Close the desktop, giving its panes back to the pool and forgetting
+    /// its history
+    pub fn release(&mut self) {
+        self.tiling.release();
+        self.history.clear();
+        self.is_active = false;
+    }
+
+    /// Names longer than MAX_NAME_LEN bytes are cut at a character boundary
+    pub fn set_name(&mut self, name: &str) {
+        self.name = FixedString::from_str_truncated(name);
+    }
+
+    /// A copy, so it can be printed while the screen is borrowed
+    pub fn name(&self) -> FixedString<MAX_NAME_LEN> {
+        self.name
+    }
+
+    /// Screen of the active pane
+    pub fn screen_mut(&mut self) -> &mut Screen {
+        self.tiling.current_pane_mut().screen_mut()
+    }
+
+    pub fn tiling(&self) -> &TilingManager {
+        &self.tiling
+    }
+
+    pub fn tiling_mut(&mut self) -> &mut TilingManager {
+        &mut self.tiling
+    }
+
+    /// Terminal dimensions as reported by the client (e.g. telnet NAWS)
+    pub fn set_size(&mut self, columns: usize, rows: usize) {
+        self.tiling.set_size(columns, rows);
+    }
+
+    /// A hidden desktop keeps its panes up to date but writes nothing
+    pub fn set_visible(&mut self, visible: bool) {
+        self.tiling.set_visible(visible);
+    }
+
+    /// Draw the desktop again as it was left
+    pub fn render(&self) {
+        self.tiling.render();
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// The history numbered as `history` lists it, on the active pane
+    pub fn print_history(&mut self) {
+        let screen = self.tiling.current_pane_mut().screen_mut();
+        for (number, line) in self.history.iter() {
+            let _ = writeln!(screen, "{:>5}  {}", number, line);
+        }
+    }
+
+    /// Apply an editing key to the active pane's input line; false if it
+    /// is not one. history.previous/next (Up/Down) bring back earlier lines.
+    pub fn edit_input(&mut self, event: &KeyEvent, keymap: &Keymap) -> bool {
+        let pane = self.tiling.current_pane_mut();
+        let recalled = match keymap.action(event, Scope::Line) {
+            Some(Action::HistoryPrevious) => self.history.older(pane.input().as_str()),
+            Some(Action::HistoryNext) => self.history.newer(),
+            _ => return pane.edit_input(event, keymap),
+        };
+        if let Some(line) = recalled {
+            pane.set_input(line);
+        }
+        true
+    }
+
+    /// Type text at the input cursor; false if it does not fit
+    pub fn insert_input(&mut self, text: &str) -> bool {
+        self.tiling.current_pane_mut().insert_input(text)
+    }
+
+    /// Replace the input line without drawing it; `redraw_input` shows it
+    pub fn set_input_text(&mut self, text: &str) {
+        self.tiling.current_pane_mut().input_mut().set_text(text);
+    }
+
+    /// Wipe the prompt and input line from the screen, keeping the line
+    pub fn erase_input(&mut self) {
+        self.tiling.current_pane_mut().erase_input();
+    }
+
+    /// Draw the input line again after a fresh prompt
+    pub fn redraw_input(&mut self) {
+        self.tiling.current_pane_mut().redraw_input();
+    }
+
+    /// Move the cursor past the input line, ready for a line break
+    pub fn finish_input(&mut self) {
+        self.tiling.current_pane_mut().finish_input();
+    }
+
+    /// Byte offset of the cursor in the input line
+    pub fn input_cursor(&self) -> usize {
+        self.tiling.current_pane().input().cursor()
+    }
+
+    pub fn input(&self) -> FixedString<MAX_INPUT_LEN> {
+        *self.tiling.current_pane().input().line()
+    }
+
+    pub fn clear_input(&mut self) {
+        self.tiling.current_pane_mut().input_mut().clear();
+    }
+}
+
+/// Desktops the console can have open at once, each with at least one pane;
+/// a desktop without its screens is small, so the pool runs out first.
+/// Alt+1..9 reach the first nine.
+pub const MAX_DESKTOPS: usize = POOL_PANES;
+
+/// Room for every desktop's whole history in one file from
+/// `write_histories`
+pub const HISTORIES_BYTES: usize = MAX_DESKTOPS * (HISTORY_BYTES + 1);
+
+pub struct VirtualDesktopManager {
+    // None until `init`
+    sink: Option<&'static dyn CharSink>,
+    pool: Option<&'static PanePool>,
+    // A desktop keeps its slot, being too big to move; `order` lists the
+    // slots in use as the desktops are arranged
+    desktops: [VirtualDesktop; MAX_DESKTOPS],
+    order: [usize; MAX_DESKTOPS],
+    count: usize,
+    // Position of the current desktop in `order`
+    current_index: usize,
+    // Rows at the bottom of the terminal no desktop draws on
+    reserved: usize,
+    // Whether desktops closed or moved since `take_histories_changed`
+    reordered: bool,
+}
+
+impl VirtualDesktopManager {
+    /// All zeros, so that the static holding the desktops goes in .bss;
+    /// `init` before use
+    pub const fn empty() -> Self {
+        VirtualDesktopManager {
+            sink: None,
+            pool: None,
+            desktops: [const { VirtualDesktop::empty() }; MAX_DESKTOPS],
+            order: [0; MAX_DESKTOPS],
+            count: 0,
+            current_index: 0,
+            reserved: 0,
+            reordered: false,
+        }
+    }
+    
+    fn sink(&self) -> &'static dyn CharSink {
+        self.sink.unwrap_or(&Disconnected)
+    }
+
+    /// One desktop, with a pane from `pool`, which must have one left
+    pub fn init(&mut self, sink: &'static dyn CharSink, pool: &'static PanePool) {
+        self.sink = Some(sink);
+        self.pool = Some(pool);
+        for desktop in self.desktops.iter_mut() {
+            desktop.release();
+        }
+        let _ = self.desktops[0].init(sink, pool, "Desktop 1");
+        self.order[0] = 0;
+        self.count = 1;
+        self.current_index = 0;
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut VirtualDesktop> {
+        self.get_mut(self.current_index)
+    }
+
+    /// The desktop at `position`, counting from 0
+    pub fn get(&self, position: usize) -> Option<&VirtualDesktop> {
+        (position < self.count).then(|| &self.desktops[self.order[position]])
+    }
+
+    pub fn get_mut(&mut self, position: usize) -> Option<&mut VirtualDesktop> {
+        (position < self.count).then(|| &mut self.desktops[self.order[position]])
+    }
+
+    /// Hide the current desktop and show the one at `position`, drawn
+    /// again as it was left; false if there is none
+    pub fn switch_to(&mut self, position: usize) -> bool {
+        if position >= self.count {
+            return false;
+        }
+        if position != self.current_index {
+            if let Some(desktop) = self.current_mut() {
+                desktop.set_visible(false);
+            }
+            self.current_index = position;
+            self.show_current();
+        }
+        true
+    }
+
+    fn show_current(&mut self) {
+        if let Some(desktop) = self.current_mut() {
+            desktop.set_visible(true);
+            desktop.render();
+        }
+    }
+
+    /// Switch to the next desktop, from the last back to the first; false
+    /// if there is only one
+    pub fn next_desktop(&mut self) -> bool {
+        self.count > 1 && self.switch_to((self.current_index + 1) % self.count)
+    }
+
+    /// Switch to the previous desktop, from the first round to the last
+    pub fn prev_desktop(&mut self) -> bool {
+        self.count > 1 && self.switch_to((self.current_index + self.count - 1) % self.count)
+    }
+
+    /// Add a blank desktop after the others and make it the current one;
+    /// it is named "Desktop N" with the lowest N no other desktop has. The
+    /// console still shows the previous desktop until something is drawn.
+    pub fn create(&mut self) -> Result<(), &'static str> {
+        let slot = self.desktops.iter().position(|desktop| !desktop.is_active).ok_or("Too many desktops")?;
+        let pool = self.pool.ok_or("No free panes")?;
+        let name = self.unused_name();
+        self.desktops[slot].init(self.sink(), pool, name.as_str())?;
+        if let Some(desktop) = self.current_mut() {
+            desktop.set_visible(false);
+        }
+        self.desktops[slot].tiling.set_reserved_rows(self.reserved);
+        self.order[self.count] = slot;
+        self.current_index = self.count;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn unused_name(&self) -> FixedString<MAX_NAME_LEN> {
+        let mut name = FixedString::new();
+        for number in 1..=MAX_DESKTOPS {
+            name.clear();
+            let _ = write!(name, "Desktop {}", number);
+            if !(0..self.count).any(|position| self.desktops[self.order[position]].name.as_str() == name.as_str()) {
+                break;
+            }
+        }
+        name
+    }
+
+    /// Close the desktop at `position`; closing the current one shows the
+    /// desktop before it (or the new first one)
+    pub fn close(&mut self, position: usize) -> Result<(), &'static str> {
+        if position >= self.count {
+            return Err("No such desktop");
+        }
+        if self.count == 1 {
+            return Err("Cannot close the only desktop");
+        }
+        let closed = &mut self.desktops[self.order[position]];
+        closed.set_visible(false);
+        closed.release();
+        self.order.copy_within(position + 1..self.count, position);
+        self.count -= 1;
+        self.reordered = true;
+        if position < self.current_index {
+            self.current_index -= 1;
+        } else if position == self.current_index {
+            self.current_index = position.saturating_sub(1);
+            self.show_current();
+        }
+        Ok(())
+    }
+
+    /// Move the current desktop to `position`, shifting the ones between
+    pub fn move_to(&mut self, position: usize) -> Result<(), &'static str> {
+        if position >= self.count {
+            return Err("No such desktop");
+        }
+        let from = self.current_index;
+        if from < position {
+            self.order[from..=position].rotate_left(1);
+        } else {
+            self.order[position..=from].rotate_right(1);
+        }
+        self.current_index = position;
+        self.reordered = true;
+        Ok(())
+    }
+
+    /// Keep `rows` at the bottom of the terminal free on every desktop, for
+    /// a status bar; the current desktop is drawn again to fit
+    pub fn set_reserved_rows(&mut self, rows: usize) {
+        self.reserved = rows;
+        for position in 0..self.count {
+            self.desktops[self.order[position]].tiling.set_reserved_rows(rows);
+        }
+    }
+
+    /// The desktops as a session file for `load`: each one's name and
+    /// layout in order, then which one is current. Panes have no working
+    /// directory to save, the VFS being a single flat directory.
+    pub fn save(&self, out: &mut impl Write) -> core::fmt::Result {
+        for position in 0..self.count {
+            let desktop = &self.desktops[self.order[position]];
+            writeln!(out, "desktop {}", desktop.name.as_str())?;
+            out.write_str("layout ")?;
+            desktop.tiling.save(out)?;
+            out.write_char('\n')?;
+        }
+        writeln!(out, "current {}", self.current_index + 1)
+    }
+
+    /// Replace the desktops with those of a session file from `save`, each
+    /// pane a fresh shell, and show the current one; returns how many
+    /// desktops there are. The desktop in each place keeps its command
+    /// history. Nothing changes if a line is wrong: the error
+    /// comes with its line number.
+    pub fn load(&mut self, session: &str) -> Result<usize, (usize, &'static str)> {
+        let mut names = [FixedString::<MAX_NAME_LEN>::new(); MAX_DESKTOPS];
+        // Layouts are big, so they are kept as text and parsed again to be
+        // applied
+        let mut layouts = [None; MAX_DESKTOPS];
+        let mut panes = [1; MAX_DESKTOPS];
+        let mut count = 0;
+        let mut current = 1;
+        let mut lines = 0;
+        for (number, line) in session.lines().enumerate() {
+            lines = number + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let rest = rest.trim();
+            match word {
+                "desktop" if count == MAX_DESKTOPS => return Err((lines, "Too many desktops")),
+                "desktop" if rest.is_empty() => return Err((lines, "Desktop needs a name")),
+                "desktop" => {
+                    names[count] = FixedString::from_str_truncated(rest);
+                    count += 1;
+                }
+                "layout" if count == 0 => return Err((lines, "Layout before any desktop")),
+                "layout" => {
+                    let layout = Layout::parse(rest).map_err(|e| (lines, e))?;
+                    if !self.desktops[self.order[0]].tiling.fits(&layout) {
+                        return Err((lines, "Layout does not fit the terminal"));
+                    }
+                    layouts[count - 1] = Some(rest);
+                    panes[count - 1] = layout.panes();
+                }
+                "current" => current = rest.parse().map_err(|_| (lines, "Current needs a desktop number"))?,
+                _ => return Err((lines, "Unknown setting")),
+            }
+        }
+        if count == 0 {
+            return Err((lines.max(1), "No desktops in session"));
+        }
+        if current == 0 || current > count {
+            return Err((lines, "No such current desktop"));
+        }
+        let Some(pool) = self.pool else {
+            return Err((lines, "No free panes"));
+        };
+        // The desktops' panes go back to the pool before the new ones are taken
+        let held: usize = (0..self.count).map(|position| self.desktops[self.order[position]].tiling.get_pane_count()).sum();
+        if panes[..count].iter().sum::<usize>() > pool.free() + held {
+            return Err((lines, "No free panes"));
+        }
+
+        if let Some(desktop) = self.current_mut() {
+            desktop.set_visible(false);
+        }
+        // Every pane goes back first, so that the layouts can take them in
+        // any order
+        for position in 0..self.count {
+            self.desktops[self.order[position]].tiling.release();
+        }
+        for position in count..self.count {
+            self.desktops[self.order[position]].release();
+        }
+        let sink = self.sink();
+        for (position, (name, layout)) in names.iter().zip(layouts.iter()).enumerate().take(count) {
+            let slot = if position < self.count {
+                self.order[position]
+            } else {
+                // There are MAX_DESKTOPS slots for at most as many desktops
+                self.desktops.iter().position(|desktop| !desktop.is_active).unwrap_or(position)
+            };
+            let desktop = &mut self.desktops[slot];
+            // Counted above
+            let _ = desktop.init(sink, pool, name.as_str());
+            desktop.set_visible(false);
+            desktop.tiling.set_reserved_rows(self.reserved);
+            // Checked above
+            let layout = layout.and_then(|text| Layout::parse(text).ok()).unwrap_or(Layout::single());
+            let _ = desktop.tiling.apply(&layout);
+            self.order[position] = slot;
+        }
+        self.count = count;
+        self.current_index = current - 1;
+        self.reordered = true;
+        self.show_current();
+        Ok(count)
+    }
+
+    /// Every desktop's command history in order, each ended by a blank
+    /// line, for `load_histories`; returns the bytes written. What does not
+    /// fit in `buf` is left out, the oldest commands of a desktop first.
+    pub fn write_histories(&self, buf: &mut [u8]) -> usize {
+        let mut size = 0;
+        for position in 0..self.count {
+            if size == buf.len() {
+                break;
+            }
+            let end = buf.len() - 1;
+            let (written, _) = self.desktops[self.order[position]].history.write_to(&mut buf[size..end]);
+            size += written;
+            buf[size] = b'\n';
+            size += 1;
+        }
+        size
+    }
+
+    /// Whether `write_histories` would write something different from the
+    /// last call: a history changed, or the desktops closed or moved
+    pub fn take_histories_changed(&mut self) -> bool {
+        let mut changed = core::mem::replace(&mut self.reordered, false);
+        for desktop in self.desktops.iter_mut() {
+            changed |= desktop.history.take_changed();
+        }
+        changed
+    }
+
+    /// Add the commands of a file from `write_histories` to the desktops'
+    /// histories, the first part to the first desktop and so on; returns
+    /// how many were added
+    pub fn load_histories(&mut self, text: &str) -> usize {
+        let mut position = 0;
+        let mut added = 0;
+        for line in text.lines() {
+            if line.is_empty() {
+                position += 1;
+                continue;
+            }
+            let Some(desktop) = self.get_mut(position) else {
+                break;
+            };
+            let history = desktop.history_mut();
+            let before = history.next_number();
+            history.push(line);
+            added += history.next_number() - before;
+        }
+        added
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.current_index
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CaptureSink;
+
+    /// Run `test` on a new manager with one desktop; the pool's screens
+    /// need a thread with a big stack
+    fn with_manager(test: impl FnOnce(&mut VirtualDesktopManager) + Send + 'static) {
+        let thread = std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            let mut vdm = Box::new(VirtualDesktopManager::empty());
+            vdm.init(CaptureSink::leak(), Box::leak(Box::new(PanePool::empty())));
+            test(&mut vdm);
+        });
+        thread.unwrap().join().unwrap();
+    }
+
+    fn names(vdm: &VirtualDesktopManager) -> Vec<String> {
+        (0..vdm.get_count()).map(|position| vdm.get(position).unwrap().name().as_str().to_string()).collect()
+    }
+
+    #[test]
+    fn switching_wraps_around() {
+        with_manager(|vdm| {
+            assert!(!vdm.next_desktop());
+            vdm.create().unwrap();
+            vdm.create().unwrap();
+            assert_eq!(names(vdm), ["Desktop 1", "Desktop 2", "Desktop 3"]);
+            assert_eq!(vdm.get_index(), 2);
+
+            assert!(vdm.next_desktop());
+            assert_eq!(vdm.get_index(), 0);
+            assert!(vdm.prev_desktop());
+            assert_eq!(vdm.get_index(), 2);
+            assert!(vdm.switch_to(1));
+            assert!(!vdm.switch_to(3));
+            assert_eq!(vdm.get_index(), 1);
+
+            while vdm.get_count() < MAX_DESKTOPS {
+                vdm.create().unwrap();
+            }
+            assert_eq!(vdm.create(), Err("Too many desktops"));
+        });
+    }
+
+    #[test]
+    fn desktops_share_the_pane_pool() {
+        with_manager(|vdm| {
+            // Far more desktops than each could have of its own screens
+            for _ in 0..20 {
+                vdm.create().unwrap();
+            }
+            let pool = vdm.pool.unwrap();
+            assert_eq!(pool.free(), POOL_PANES - 21);
+
+            // The panes open are what runs out: split every desktop as far
+            // as it goes and open another
+            loop {
+                let tiling = vdm.current_mut().unwrap().tiling_mut();
+                if tiling.split_vertical().is_err() && tiling.split_horizontal().is_err() && vdm.create().is_err() {
+                    break;
+                }
+            }
+            assert_eq!(pool.free(), 0);
+            assert_eq!(vdm.create(), Err("No free panes"));
+            let count = vdm.get_count();
+            assert_eq!(vdm.load("desktop A\nlayout v50 . *\n"), Ok(1));
+            assert_eq!(pool.free(), POOL_PANES - 2);
+
+            // Closing a desktop gives its panes back
+            for _ in 1..count {
+                vdm.create().unwrap();
+            }
+            vdm.current_mut().unwrap().tiling_mut().split_vertical().unwrap();
+            let free = pool.free();
+            vdm.close(vdm.get_index()).unwrap();
+            assert_eq!(pool.free(), free + 2);
+        });
+    }
+
+    #[test]
+    fn closing_and_moving_keep_the_order() {
+        with_manager(|vdm| {
+            assert_eq!(vdm.close(0), Err("Cannot close the only desktop"));
+            vdm.create().unwrap();
+            vdm.create().unwrap();
+            vdm.current_mut().unwrap().set_name("Notes");
+
+            // Closing another desktop keeps the current one
+            vdm.close(0).unwrap();
+            assert_eq!(names(vdm), ["Desktop 2", "Notes"]);
+            assert_eq!(vdm.get_index(), 1);
+            assert_eq!(vdm.close(2), Err("No such desktop"));
+
+            // New desktops take the first free number and slot
+            vdm.create().unwrap();
+            assert_eq!(names(vdm), ["Desktop 2", "Notes", "Desktop 1"]);
+
+            vdm.move_to(0).unwrap();
+            assert_eq!(names(vdm), ["Desktop 1", "Desktop 2", "Notes"]);
+            assert_eq!(vdm.get_index(), 0);
+            vdm.move_to(1).unwrap();
+            assert_eq!(names(vdm), ["Desktop 2", "Desktop 1", "Notes"]);
+
+            // Closing the current desktop shows the one before it
+            vdm.close(1).unwrap();
+            assert_eq!(vdm.get_index(), 0);
+            assert_eq!(vdm.current_mut().unwrap().name().as_str(), "Desktop 2");
+        });
+    }
+
+    #[test]
+    fn sessions_restore_names_order_and_layouts() {
+        with_manager(|vdm| {
+            vdm.create().unwrap();
+            vdm.current_mut().unwrap().set_name("Notes");
+            let tiling = vdm.current_mut().unwrap().tiling_mut();
+            tiling.split_vertical().unwrap();
+            tiling.split_horizontal().unwrap();
+            tiling.prev_pane();
+            vdm.move_to(0).unwrap();
+
+            let mut session = String::new();
+            vdm.save(&mut session).unwrap();
+            assert_eq!(session, "desktop Notes\nlayout v50 . h50 * .\ndesktop Desktop 1\nlayout *\ncurrent 1\n");
+
+            vdm.close(0).unwrap();
+            vdm.create().unwrap();
+            assert_eq!(vdm.load(&session), Ok(2));
+            assert_eq!(names(vdm), ["Notes", "Desktop 1"]);
+            assert_eq!(vdm.get_index(), 0);
+            let tiling = vdm.current_mut().unwrap().tiling();
+            assert_eq!(tiling.get_pane_count(), 3);
+            assert_eq!(tiling.get_active_position(), 1);
+
+            // A bad line leaves the desktops alone
+            assert_eq!(vdm.load("desktop A\nlayout v50 .\n"), Err((2, "Layout ends too soon")));
+            assert_eq!(vdm.load("layout *"), Err((1, "Layout before any desktop")));
+            assert_eq!(vdm.load("desktop A\ncurrent 2"), Err((2, "No such current desktop")));
+            assert_eq!(vdm.load("desktop A\nlayout v50 v50 v50 . . v50 . . v50 v50 . . v50 . .\n"), Err((2, "Layout does not fit the terminal")));
+            assert_eq!(names(vdm), ["Notes", "Desktop 1"]);
+        });
+    }
+
+    #[test]
+    fn history_survives_a_load() {
+        with_manager(|vdm| {
+            vdm.create().unwrap();
+            vdm.get_mut(0).unwrap().history_mut().push("ls");
+            vdm.get_mut(1).unwrap().history_mut().push("cat notes");
+            let history = |vdm: &VirtualDesktopManager, position| -> Vec<String> {
+                vdm.get(position).unwrap().history().iter().map(|(_, line)| line.to_string()).collect()
+            };
+
+            // Each place keeps its history; a desktop the session adds starts
+            // with none
+            assert_eq!(vdm.load("desktop A\nlayout v50 . *\ndesktop B\ndesktop C\n"), Ok(3));
+            assert_eq!(history(vdm, 0), ["ls"]);
+            assert_eq!(history(vdm, 1), ["cat notes"]);
+            assert!(history(vdm, 2).is_empty());
+
+            // A desktop the session leaves out is closed with its history
+            assert_eq!(vdm.load("desktop A\n"), Ok(1));
+            assert_eq!(history(vdm, 0), ["ls"]);
+            vdm.create().unwrap();
+            vdm.create().unwrap();
+            assert!(history(vdm, 1).is_empty() && history(vdm, 2).is_empty());
+        });
+    }
+
+    #[test]
+    fn histories_are_saved_per_desktop() {
+        with_manager(|vdm| {
+            vdm.create().unwrap();
+            vdm.create().unwrap();
+            vdm.get_mut(0).unwrap().history_mut().push("ls");
+            vdm.get_mut(0).unwrap().history_mut().push("cat a");
+            vdm.get_mut(2).unwrap().history_mut().push("help");
+            let mut buf = [0u8; 64];
+            let size = vdm.write_histories(&mut buf);
+            assert_eq!(&buf[..size], b"ls\ncat a\n\n\nhelp\n\n");
+
+            // Only new commands or moved desktops make it worth writing again
+            assert!(vdm.take_histories_changed());
+            assert!(!vdm.take_histories_changed());
+            vdm.get_mut(2).unwrap().history_mut().push("help");
+            assert!(!vdm.take_histories_changed());
+            vdm.move_to(0).unwrap();
+            assert!(vdm.take_histories_changed());
+            vdm.move_to(2).unwrap();
+            vdm.take_histories_changed();
+
+            // Each part goes back to its own desktop
+            for position in 0..3 {
+                vdm.get_mut(position).unwrap().history_mut().clear();
+            }
+            let text = core::str::from_utf8(&buf[..size]).unwrap();
+            assert_eq!(vdm.load_histories(text), 3);
+            let history = |vdm: &VirtualDesktopManager, position| -> Vec<String> {
+                vdm.get(position).unwrap().history().iter().map(|(_, line)| line.to_string()).collect()
+            };
+            assert_eq!(history(vdm, 0), ["ls", "cat a"]);
+            assert!(history(vdm, 1).is_empty());
+            assert_eq!(history(vdm, 2), ["help"]);
+
+            // Parts for desktops there are not are left out, and a short
+            // buffer keeps the newest commands
+            vdm.close(2).unwrap();
+            vdm.get_mut(0).unwrap().history_mut().clear();
+            assert_eq!(vdm.load_histories(text), 2);
+            let size = vdm.write_histories(&mut buf[..7]);
+            assert_eq!(&buf[..size], b"cat a\n\n");
+        });
+    }
+}
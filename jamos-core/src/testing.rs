@@ -0,0 +1,79 @@
+// Helpers for host unit tests: console endpoints backed by memory.
+// Terminals keep `&'static` endpoints, so tests leak them; each test gets its own.
+
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::clock::Clock;
+use crate::console::{CharSink, CharSource};
+
+/// Input source that replays a fixed byte script
+pub struct ScriptedInput {
+    bytes: &'static [u8],
+    position: AtomicUsize,
+}
+
+impl ScriptedInput {
+    pub fn leak(bytes: &'static [u8]) -> &'static ScriptedInput {
+        Box::leak(Box::new(ScriptedInput {
+            bytes,
+            position: AtomicUsize::new(0),
+        }))
+    }
+}
+
+impl CharSource for ScriptedInput {
+    fn getc(&self) -> Option<u8> {
+        let position = self.position.fetch_add(1, Ordering::Relaxed);
+        self.bytes.get(position).copied()
+    }
+}
+
+/// Output sink that records everything written to it
+pub struct CaptureSink {
+    bytes: RefCell<Vec<u8>>,
+}
+
+impl CaptureSink {
+    pub fn leak() -> &'static CaptureSink {
+        Box::leak(Box::new(CaptureSink {
+            bytes: RefCell::new(Vec::new()),
+        }))
+    }
+
+    /// Everything written so far, as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.bytes.borrow_mut().clear();
+    }
+}
+
+impl CharSink for CaptureSink {
+    fn putc(&self, c: u8) {
+        self.bytes.borrow_mut().push(c);
+    }
+}
+
+/// Clock that only moves when the test says so
+pub struct ManualClock {
+    now: Cell<u64>,
+}
+
+impl ManualClock {
+    pub fn leak() -> &'static ManualClock {
+        Box::leak(Box::new(ManualClock { now: Cell::new(0) }))
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now.set(self.now.get() + ms);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.now.get()
+    }
+}
@@ -0,0 +1,352 @@
+// UTF-8 text helpers for fixed-size buffers
+// Text is stored as UTF-8 bytes everywhere. Editing works on "grapheme-ish"
+// units: a character together with the combining marks, variation selectors
+// and zero-width-joined characters that follow it. That covers accented
+// letters and most emoji without the full Unicode segmentation tables.
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+/// Characters that attach to the one before them
+fn is_extender(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'       // combining diacritical marks
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{FE00}'..='\u{FE0F}'     // variation selectors
+        | '\u{1F3FB}'..='\u{1F3FF}'   // emoji skin tones
+        | '\u{E0020}'..='\u{E007F}'   // emoji tag sequences
+        | ZERO_WIDTH_JOINER)
+}
+
+/// Columns a character takes up on a terminal
+pub fn char_width(c: char) -> usize {
+    if is_extender(c) || c.is_control() {
+        return 0;
+    }
+    let wide = matches!(c,
+        '\u{1100}'..='\u{115F}'       // Hangul Jamo
+        | '\u{2E80}'..='\u{A4CF}'     // CJK, Kana, Yi
+        | '\u{AC00}'..='\u{D7A3}'     // Hangul syllables
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FE30}'..='\u{FE4F}'
+        | '\u{FF00}'..='\u{FF60}'     // fullwidth forms
+        | '\u{FFE0}'..='\u{FFE6}'
+        | '\u{1F300}'..='\u{1F64F}'   // emoji
+        | '\u{1F900}'..='\u{1F9FF}'
+        | '\u{20000}'..='\u{3FFFD}');
+    if wide { 2 } else { 1 }
+}
+
+/// Terminal columns taken by UTF-8 text; invalid bytes count as one each.
+/// A grapheme is as wide as its widest character (a joined emoji sequence
+/// is drawn as one glyph).
+pub fn display_width(bytes: &[u8]) -> usize {
+    let mut width = 0;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let end = next_grapheme(bytes, pos);
+        let mut grapheme_width = 0;
+        while let Some((char_end, c)) = char_at(&bytes[..end], pos) {
+            grapheme_width = grapheme_width.max(c.map_or(1, char_width));
+            pos = char_end;
+        }
+        width += grapheme_width;
+    }
+    width
+}
+
+/// The character starting at `pos`: its end and the character, or None for
+/// a byte that does not start valid UTF-8
+fn char_at(bytes: &[u8], pos: usize) -> Option<(usize, Option<char>)> {
+    let first = *bytes.get(pos)?;
+    let len = match first {
+        0x00..=0x7F => 1,
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return Some((pos + 1, None)),
+    };
+    match bytes.get(pos..pos + len).and_then(|b| core::str::from_utf8(b).ok()) {
+        Some(s) => Some((pos + len, s.chars().next())),
+        None => Some((pos + 1, None)),
+    }
+}
+
+/// The character ending at `pos`: its start and the character
+fn char_before(bytes: &[u8], pos: usize) -> Option<(usize, Option<char>)> {
+    if pos == 0 {
+        return None;
+    }
+    // A character is at most four bytes; find the one that ends exactly here
+    for start in (pos.saturating_sub(4)..pos).rev() {
+        if let Some((end, Some(c))) = char_at(bytes, start) {
+            if end == pos {
+                return Some((start, Some(c)));
+            }
+        }
+    }
+    Some((pos - 1, None))
+}
+
+/// Start of the grapheme that ends at `pos`
+pub fn previous_grapheme(bytes: &[u8], pos: usize) -> usize {
+    let Some((mut start, mut c)) = char_before(bytes, pos) else {
+        return pos;
+    };
+    loop {
+        let joined = matches!(char_before(bytes, start), Some((_, Some(ZERO_WIDTH_JOINER))));
+        if !(c.is_some_and(is_extender) || joined) {
+            return start;
+        }
+        match char_before(bytes, start) {
+            Some((previous, previous_char)) => {
+                start = previous;
+                c = previous_char;
+            }
+            None => return start,
+        }
+    }
+}
+
+/// End of the grapheme that starts at `pos`
+pub fn next_grapheme(bytes: &[u8], pos: usize) -> usize {
+    let Some((mut end, mut c)) = char_at(bytes, pos) else {
+        return pos;
+    };
+    while let Some((next_end, next)) = char_at(bytes, end) {
+        let joined = c == Some(ZERO_WIDTH_JOINER);
+        if !(next.is_some_and(is_extender) || joined) {
+            break;
+        }
+        end = next_end;
+        c = next;
+    }
+    end
+}
+
+/// Longest prefix of `s` within `max` bytes that does not split a
+/// character
+pub fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// `pos`, moved back to the start of the UTF-8 character it falls inside
+pub fn floor_char_boundary(bytes: &[u8], pos: usize) -> usize {
+    if pos >= bytes.len() {
+        return bytes.len();
+    }
+    for start in (pos.saturating_sub(3)..=pos).rev() {
+        if let Some((end, Some(_))) = char_at(bytes, start) {
+            if end > pos {
+                return start;
+            }
+        }
+    }
+    pos
+}
+
+/// String stored inline in a fixed number of bytes, always valid UTF-8
+#[derive(Clone, Copy)]
+pub struct FixedString<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedString<N> {
+    pub const fn new() -> Self {
+        FixedString { bytes: [0; N], len: 0 }
+    }
+
+    /// Copy of `s`, cut at a character boundary if it does not fit
+    pub fn from_str_truncated(s: &str) -> Self {
+        let s = truncate(s, N);
+        let mut string = FixedString::new();
+        string.bytes[..s.len()].copy_from_slice(s.as_bytes());
+        string.len = s.len();
+        string
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole characters from a &str or a char are ever stored
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a character; false if it does not fit
+    pub fn push(&mut self, c: char) -> bool {
+        let len = c.len_utf8();
+        if self.len + len > N {
+            return false;
+        }
+        c.encode_utf8(&mut self.bytes[self.len..self.len + len]);
+        self.len += len;
+        true
+    }
+
+    /// Remove the last grapheme; returns the columns it took up on screen
+    pub fn pop_grapheme(&mut self) -> usize {
+        let start = previous_grapheme(&self.bytes[..self.len], self.len);
+        let width = display_width(&self.bytes[start..self.len]);
+        self.len = start;
+        width
+    }
+
+    /// Insert `s` at byte `pos`; false and unchanged if `pos` is not a
+    /// character boundary or `s` does not fit
+    pub fn insert_str(&mut self, pos: usize, s: &str) -> bool {
+        if !self.as_str().is_char_boundary(pos) || self.len + s.len() > N {
+            return false;
+        }
+        self.bytes.copy_within(pos..self.len, pos + s.len());
+        self.bytes[pos..pos + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        true
+    }
+
+    /// Remove the bytes from `start` to `end`; nothing is removed unless both
+    /// are character boundaries
+    pub fn remove(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len);
+        let text = self.as_str();
+        if start < end && text.is_char_boundary(start) && text.is_char_boundary(end) {
+            self.bytes.copy_within(end..self.len, start);
+            self.len -= end - start;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> core::fmt::Write for FixedString<N> {
+    /// Fails once the text no longer fits
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if !self.push(c) {
+                return Err(core::fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for FixedString<N> {
+    fn default() -> Self {
+        FixedString::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_whole_characters() {
+        assert_eq!(truncate("abc", 8), "abc");
+        assert_eq!(truncate("åäö", 3), "å");
+        assert_eq!(truncate("åäö", 4), "åä");
+        assert_eq!(truncate("日本", 5), "日");
+        assert_eq!(truncate("日本", 2), "");
+    }
+
+    #[test]
+    fn graphemes_include_combining_marks_and_joiners() {
+        let text = "ae\u{301}👩\u{200D}💻!";
+        let bytes = text.as_bytes();
+        let mut starts = Vec::new();
+        let mut pos = bytes.len();
+        while pos > 0 {
+            pos = previous_grapheme(bytes, pos);
+            starts.push(pos);
+        }
+        assert_eq!(starts, [15, 4, 1, 0]);
+
+        let mut ends = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            pos = next_grapheme(bytes, pos);
+            ends.push(pos);
+        }
+        assert_eq!(ends, [1, 4, 15, 16]);
+    }
+
+    #[test]
+    fn invalid_bytes_are_single_units() {
+        let bytes = b"a\xff\xc3";
+        assert_eq!(previous_grapheme(bytes, 3), 2);
+        assert_eq!(previous_grapheme(bytes, 2), 1);
+        assert_eq!(next_grapheme(bytes, 1), 2);
+        assert_eq!(display_width(bytes), 3);
+        assert_eq!(floor_char_boundary(bytes, 2), 2);
+    }
+
+    #[test]
+    fn floor_char_boundary_backs_out_of_characters() {
+        let bytes = "aö日".as_bytes();
+        let floors: Vec<usize> = (0..=bytes.len()).map(|pos| floor_char_boundary(bytes, pos)).collect();
+        assert_eq!(floors, [0, 1, 1, 3, 3, 3, 6]);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(display_width("abc".as_bytes()), 3);
+        assert_eq!(display_width("e\u{301}".as_bytes()), 1);
+        assert_eq!(display_width("日本".as_bytes()), 4);
+        assert_eq!(display_width("👩\u{200D}💻".as_bytes()), 2);
+    }
+
+    #[test]
+    fn fixed_string_push_and_pop() {
+        let mut s = FixedString::<5>::new();
+        assert!(s.push('a'));
+        assert!(s.push('日'));
+        assert!(!s.push('本'));
+        assert!(s.push('!'));
+        assert_eq!(s.as_str(), "a日!");
+        assert_eq!(s.pop_grapheme(), 1);
+        assert_eq!(s.pop_grapheme(), 2);
+        assert_eq!(s.as_str(), "a");
+        assert_eq!(FixedString::<4>::from_str_truncated("åäö").as_str(), "åä");
+    }
+
+    #[test]
+    fn fixed_string_insert_and_remove() {
+        let mut s = FixedString::<8>::from_str_truncated("ad");
+        assert!(s.insert_str(1, "bc"));
+        assert!(s.insert_str(0, "日"));
+        assert_eq!(s.as_str(), "日abcd");
+        assert!(!s.insert_str(0, "xyz"));
+        s.remove(0, 4);
+        s.remove(2, 9);
+        assert_eq!(s.as_str(), "bc");
+    }
+
+    #[test]
+    fn fixed_string_keeps_characters_whole() {
+        let mut s = FixedString::<16>::from_str_truncated("a日b");
+        assert!(!s.insert_str(2, "x"));
+        assert!(!s.insert_str(9, "x"));
+        s.remove(0, 2);
+        s.remove(3, 5);
+        assert_eq!(s.as_str(), "a日b");
+        s.remove(1, 4);
+        assert_eq!(s.as_str(), "ab");
+    }
+}
@@ -0,0 +1,63 @@
+// Common utility functions
+
+use crate::terminal::Screen;
+
+/// Print a number to the screen by converting to decimal digits
+pub fn print_number(screen: &mut Screen, n: usize) {
+    let mut buf = [0u8; 20];
+    let mut num = n;
+    let mut len = 0;
+    
+    if num == 0 {
+        buf[0] = b'0';
+        len = 1;
+    } else {
+        while num > 0 {
+            buf[len] = b'0' + (num % 10) as u8;
+            num /= 10;
+            len += 1;
+        }
+    }
+    
+    // Print in reverse
+    for i in (0..len).rev() {
+        screen.putc(buf[i]);
+    }
+}
+
+/// Parse an unsigned decimal number from ASCII bytes
+pub fn parse_number(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let mut value: usize = 0;
+    for &b in bytes {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add((b - b'0') as usize)?;
+    }
+    Some(value)
+}
+
+/// Write `n` as decimal digits into `buf`; returns the number of bytes written
+pub fn format_number(n: usize, buf: &mut [u8]) -> usize {
+    let mut digits = [0u8; 20];
+    let mut num = n;
+    let mut len = 0;
+    loop {
+        digits[len] = b'0' + (num % 10) as u8;
+        num /= 10;
+        len += 1;
+        if num == 0 {
+            break;
+        }
+    }
+    if len > buf.len() {
+        return 0;
+    }
+    for (i, slot) in buf[..len].iter_mut().enumerate() {
+        *slot = digits[len - 1 - i];
+    }
+    len
+}
@@ -3,6 +3,7 @@
 use super::protocol::{GlobalEntry, Interface, Message, MessageType};
 use super::surface::SurfaceManager;
 use super::CompositorState;
+use crate::console::CharSink;
 use crate::terminal::Screen;
 use crate::utils::print_number;
 
@@ -44,9 +45,8 @@ impl WaylandCompositor {
         }
     }
 
-    pub fn init(&mut self) {
-        use crate::drivers::uart::Uart;
-        let uart = Uart::new();
+    /// `uart` is the console the workaround below writes to
+    pub fn init(&mut self, uart: &dyn CharSink) {
         
         // NOTE: These uart.putc calls output "\r" (carriage return) to work around
         // a compiler optimization bug in rustc release builds for aarch64-unknown-none
@@ -93,14 +93,12 @@ impl WaylandCompositor {
         screen.puts("Listening for client connections...\n");
         screen.puts("\nGlobal interfaces registered:\n");
         
-        for i in 0..self.global_count {
-            if let Some(global) = self.globals[i] {
-                screen.puts("  - ");
-                screen.puts(global.interface.name());
-                screen.puts(" (version ");
-                print_number(screen, global.version as usize);
-                screen.puts(")\n");
-            }
+        for global in self.globals[..self.global_count].iter().flatten() {
+            screen.puts("  - ");
+            screen.puts(global.interface.name());
+            screen.puts(" (version ");
+            print_number(screen, global.version as usize);
+            screen.puts(")\n");
         }
         
         screen.puts("\nUse 'wayland status' to check compositor status\n");
@@ -114,10 +112,8 @@ impl WaylandCompositor {
         }
 
         // Disconnect all clients
-        for client in &mut self.clients {
-            if let Some(c) = client {
-                c.connected = false;
-            }
+        for c in self.clients.iter_mut().flatten() {
+            c.connected = false;
         }
 
         self.state = CompositorState::Stopped;
@@ -231,3 +227,59 @@ impl WaylandCompositor {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::Disconnected;
+    use crate::testing::CaptureSink;
+
+    fn new_compositor() -> Box<WaylandCompositor> {
+        let mut compositor = Box::new(WaylandCompositor::empty());
+        compositor.init(&Disconnected);
+        compositor
+    }
+
+    #[test]
+    fn start_lists_globals_and_stop_stops() {
+        let sink = CaptureSink::leak();
+        let mut screen = Screen::new(sink);
+        let mut compositor = new_compositor();
+        assert!(!compositor.is_running());
+
+        compositor.start(&mut screen);
+        assert!(compositor.is_running());
+        let text = sink.text();
+        assert!(text.contains("wl_compositor (version 4)"));
+        assert!(text.contains("wl_seat (version 7)"));
+        assert!(text.contains("wl_output (version 3)"));
+
+        compositor.stop(&mut screen);
+        assert!(!compositor.is_running());
+        sink.clear();
+        compositor.stop(&mut screen);
+        assert_eq!(sink.text(), "Wayland compositor is not running.\n");
+    }
+
+    #[test]
+    fn status_counts_clients_and_surfaces() {
+        let sink = CaptureSink::leak();
+        let mut screen = Screen::new(sink);
+        let mut compositor = new_compositor();
+        let client = compositor.connect_client().unwrap();
+        compositor.connect_client().unwrap();
+        compositor.disconnect_client(client);
+        compositor.handle_message(
+            Message::new(1, 0, MessageType::CompositorCreateSurface),
+            &mut screen,
+        );
+
+        sink.clear();
+        compositor.status(&mut screen);
+        let text = sink.text();
+        assert!(text.contains("State: Stopped\n"));
+        assert!(text.contains("Connected clients: 1\n"));
+        assert!(text.contains("Active surfaces: 1\n"));
+        assert!(text.contains("Registered globals: 3\n"));
+    }
+}
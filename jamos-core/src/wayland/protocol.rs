@@ -37,6 +37,9 @@ impl Message {
 /// Wayland global registry entry
 #[derive(Debug, Clone, Copy)]
 pub struct GlobalEntry {
+    // What a client names the global by in wl_registry.bind, which is not
+    // handled yet
+    #[allow(dead_code)]
     pub name: u32,
     pub interface: Interface,
     pub version: u32,
@@ -45,9 +48,13 @@ pub struct GlobalEntry {
 /// Wayland interface types
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Interface {
+    // Core interfaces that are not advertised as globals
+    #[allow(dead_code)]
     Display,
+    #[allow(dead_code)]
     Registry,
     Compositor,
+    #[allow(dead_code)]
     Surface,
     Seat,
     Output,
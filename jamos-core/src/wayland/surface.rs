@@ -97,6 +97,7 @@ impl SurfaceManager {
         None
     }
 
+    #[cfg(test)]
     pub fn get_surface(&self, id: ObjectId) -> Option<&Surface> {
         self.surfaces.iter()
             .filter_map(|s| s.as_ref())
@@ -125,3 +126,92 @@ impl SurfaceManager {
         self.surfaces.iter().filter(|s| s.is_some()).count()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_manager() -> SurfaceManager {
+        let mut manager = SurfaceManager::empty();
+        manager.init();
+        manager
+    }
+
+    #[test]
+    fn surfaces_get_increasing_ids() {
+        let mut manager = new_manager();
+        assert_eq!(manager.create_surface(), Some(SURFACE_ID_START));
+        assert_eq!(manager.create_surface(), Some(SURFACE_ID_START + 1));
+        assert_eq!(manager.count_surfaces(), 2);
+    }
+
+    #[test]
+    fn new_surfaces_have_defaults() {
+        let mut manager = new_manager();
+        let id = manager.create_surface().unwrap();
+        let surface = manager.get_surface(id).unwrap();
+        assert_eq!((surface.x, surface.y), (0, 0));
+        assert_eq!((surface.width, surface.height), (DEFAULT_SURFACE_WIDTH, DEFAULT_SURFACE_HEIGHT));
+        assert!(!surface.visible);
+        assert!(!surface.buffer_attached);
+    }
+
+    #[test]
+    fn surfaces_can_be_changed_in_place() {
+        let mut manager = new_manager();
+        let id = manager.create_surface().unwrap();
+        let surface = manager.get_surface_mut(id).unwrap();
+        surface.set_position(10, -5);
+        surface.set_size(320, 200);
+        surface.attach_buffer();
+        surface.commit();
+
+        let surface = manager.get_surface(id).unwrap();
+        assert_eq!((surface.x, surface.y, surface.width, surface.height), (10, -5, 320, 200));
+        assert!(surface.visible && surface.buffer_attached);
+
+        manager.get_surface_mut(id).unwrap().destroy();
+        let surface = manager.get_surface(id).unwrap();
+        assert!(!surface.visible && !surface.buffer_attached);
+    }
+
+    #[test]
+    fn unknown_ids_are_not_found() {
+        let mut manager = new_manager();
+        manager.create_surface().unwrap();
+        assert!(manager.get_surface(1).is_none());
+        assert!(manager.get_surface_mut(SURFACE_ID_START + 1).is_none());
+        assert!(!manager.destroy_surface(1));
+    }
+
+    #[test]
+    fn destroy_frees_the_slot_but_not_the_id() {
+        let mut manager = new_manager();
+        let first = manager.create_surface().unwrap();
+        assert!(manager.destroy_surface(first));
+        assert!(manager.get_surface(first).is_none());
+        assert_eq!(manager.count_surfaces(), 0);
+        assert_eq!(manager.create_surface(), Some(first + 1));
+    }
+
+    #[test]
+    fn capacity_is_limited() {
+        let mut manager = new_manager();
+        for _ in 0..32 {
+            assert!(manager.create_surface().is_some());
+        }
+        assert_eq!(manager.create_surface(), None);
+
+        assert!(manager.destroy_surface(SURFACE_ID_START + 3));
+        assert!(manager.create_surface().is_some());
+    }
+
+    #[test]
+    fn init_clears_everything() {
+        let mut manager = new_manager();
+        manager.create_surface().unwrap();
+        manager.init();
+        assert_eq!(manager.count_surfaces(), 0);
+        assert_eq!(manager.create_surface(), Some(SURFACE_ID_START));
+    }
+}
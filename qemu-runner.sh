@@ -0,0 +1,28 @@
+#!/bin/bash
+
+# Cargo runner for Jamos: boots the kernel ELF that cargo built in QEMU.
+# `cargo qemu` gets the interactive console; `cargo qemu-test` binaries (built
+# under deps/) run headless and report through the semihosting exit status.
+
+KERNEL="$1"
+shift
+
+QEMU_ARGS=(
+    -machine virt
+    -cpu cortex-a57
+    -semihosting
+    -kernel "$KERNEL"
+)
+
+case "$KERNEL" in
+    */deps/*)
+        # A test that hangs instead of exiting fails after a minute.
+        # Test name filters are not supported: the whole suite always runs.
+        exec timeout 60 qemu-system-aarch64 "${QEMU_ARGS[@]}" \
+            -display none -serial stdio -monitor none
+        ;;
+    *)
+        echo "Press Ctrl-A then X to exit QEMU"
+        exec qemu-system-aarch64 "${QEMU_ARGS[@]}" -nographic -serial mon:stdio "$@"
+        ;;
+esac
@@ -0,0 +1,123 @@
+// Hosted backend - the "hardware" when Jamos runs as a Linux process
+// (`--features hosted`). The host terminal plays the serial console: it is put
+// into raw mode like QEMU's stdio chardev, and a reader thread collects stdin
+// so the UART can be polled without blocking.
+// Ctrl-A X quits, as in QEMU; so does the end of piped input.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+const CTRL_A: u8 = 0x01;
+
+struct Input {
+    bytes: VecDeque<u8>,
+    escape: bool,
+    eof: bool,
+}
+
+static INPUT: Mutex<Input> = Mutex::new(Input {
+    bytes: VecDeque::new(),
+    escape: false,
+    eof: false,
+});
+
+// `stty -g` settings to restore on exit; None when stdin is not a terminal
+static SAVED_TERMINAL: Mutex<Option<String>> = Mutex::new(None);
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Enter raw mode and start reading stdin; call once before the kernel starts
+pub fn init() {
+    if let Some(saved) = stty(&["-g"]) {
+        // Raw input, but keep LF -> CRLF on output like QEMU's console does
+        stty(&["raw", "-echo", "opost", "onlcr"]);
+        *SAVED_TERMINAL.lock().unwrap() = Some(saved);
+    }
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+    }));
+
+    std::thread::spawn(|| {
+        let mut buf = [0u8; 256];
+        loop {
+            let n = std::io::stdin().read(&mut buf).unwrap_or(0);
+            let mut input = INPUT.lock().unwrap();
+            if n == 0 {
+                input.eof = true;
+                return;
+            }
+            for &b in &buf[..n] {
+                if input.escape {
+                    input.escape = false;
+                    match b {
+                        b'x' | b'X' => {
+                            drop(input);
+                            exit(0);
+                        }
+                        CTRL_A => input.bytes.push_back(CTRL_A),
+                        _ => {
+                            input.bytes.push_back(CTRL_A);
+                            input.bytes.push_back(b);
+                        }
+                    }
+                } else if b == CTRL_A {
+                    input.escape = true;
+                } else {
+                    input.bytes.push_back(b);
+                }
+            }
+        }
+    });
+}
+
+fn restore_terminal() {
+    if let Some(saved) = SAVED_TERMINAL.lock().unwrap().take() {
+        // Jamos turned on bracketed paste; the shell we return to may not want it
+        let _ = std::io::stdout().write_all(b"\x1B[?2004l");
+        let _ = std::io::stdout().flush();
+        stty(&[&saved]);
+    }
+}
+
+/// Restore the terminal and leave the process
+pub fn exit(status: i32) -> ! {
+    let _ = std::io::stdout().flush();
+    restore_terminal();
+    std::process::exit(status)
+}
+
+pub fn write_byte(c: u8) {
+    let _ = std::io::stdout().write_all(&[c]);
+}
+
+/// Next input byte, if any. Output is flushed whenever the kernel polls, so
+/// everything written before waiting for input is on screen.
+pub fn read_byte() -> Option<u8> {
+    let _ = std::io::stdout().flush();
+    let mut input = INPUT.lock().unwrap();
+    match input.bytes.pop_front() {
+        Some(b) => Some(b),
+        None if input.eof => {
+            // Piped input is used up and everything it caused has run
+            drop(input);
+            exit(0);
+        }
+        None => None,
+    }
+}
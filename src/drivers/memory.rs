@@ -0,0 +1,32 @@
+// Physical memory accounting
+// There is no allocator: the kernel image (code, statics and the boot stack)
+// is all that is in use, and RAM from its end on is free.
+
+#[cfg(not(feature = "hosted"))]
+const RAM_BASE: usize = 0x4000_0000;
+// QEMU's default for the virt machine; qemu-runner.sh does not pass -m
+#[cfg(not(feature = "hosted"))]
+const RAM_SIZE: usize = 128 << 20;
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    static __stack_end: u8;
+}
+
+/// Bytes of RAM nothing uses
+#[cfg(not(feature = "hosted"))]
+pub fn free_bytes() -> usize {
+    let image_end = core::ptr::addr_of!(__stack_end) as usize;
+    (RAM_BASE + RAM_SIZE).saturating_sub(image_end)
+}
+
+/// The host's available memory, from /proc/meminfo
+#[cfg(feature = "hosted")]
+pub fn free_bytes() -> usize {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse::<usize>().ok())
+        .map_or(0, |kilobytes| kilobytes << 10)
+}
@@ -1,2 +1,10 @@
+pub use jamos_core::{console, keyboard};
 pub mod uart;
-pub mod keyboard;
+pub mod timer;
+pub mod rtc;
+pub mod memory;
+pub mod virtio_net;
+#[cfg_attr(feature = "hosted", path = "semihosting_hosted.rs")]
+pub mod semihosting;
+#[cfg(feature = "hosted")]
+pub mod host;
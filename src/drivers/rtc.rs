@@ -0,0 +1,20 @@
+// PL031 real-time clock on the QEMU virt machine
+// QEMU starts it at the host's time of day; the hosted build asks the host
+
+#[cfg(not(feature = "hosted"))]
+const RTC_BASE: usize = 0x0901_0000;
+#[cfg(not(feature = "hosted"))]
+const RTC_DR: *const u32 = RTC_BASE as *const u32; // Data register
+
+/// Seconds since the Unix epoch (UTC)
+#[cfg(not(feature = "hosted"))]
+pub fn seconds() -> u64 {
+    unsafe { core::ptr::read_volatile(RTC_DR) as u64 }
+}
+
+#[cfg(feature = "hosted")]
+pub fn seconds() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
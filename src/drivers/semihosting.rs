@@ -0,0 +1,212 @@
+// ARM semihosting (AArch64 `hlt #0xf000` ABI)
+// Lets the kernel talk to the host through QEMU when it runs with
+// `-semihosting`: debug output, exit status and host file access.
+// Without semihosting the HLT instruction is undefined; the exception vector
+// below turns it into a failed call so probing is always safe.
+
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const SYS_OPEN: usize = 0x01;
+const SYS_CLOSE: usize = 0x02;
+const SYS_WRITE0: usize = 0x04;
+const SYS_WRITE: usize = 0x05;
+const SYS_READ: usize = 0x06;
+const SYS_FLEN: usize = 0x0C;
+const SYS_ERRNO: usize = 0x13;
+const SYS_EXIT: usize = 0x18;
+const SYS_EXIT_EXTENDED: usize = 0x20;
+
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+
+// Returned by the exception vector when semihosting is not enabled
+const CALL_FAILED: usize = usize::MAX;
+
+const UNKNOWN: u8 = 0;
+const AVAILABLE: u8 = 1;
+const UNAVAILABLE: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+// Exception vector table: 16 entries of 128 bytes. Only synchronous
+// exceptions at the current EL are handled, and only to skip a HLT #0xf000
+// that trapped because semihosting is off (x0 = -1). Anything else parks
+// the CPU as before.
+global_asm!(
+    r#"
+    .section .text
+    .balign 2048
+    .global exception_vectors
+exception_vectors:
+    .rept 4
+    .balign 128
+    b unhandled_exception
+    .endr
+
+    // Current EL with SP_ELx, synchronous
+    .balign 128
+    stp x1, x2, [sp, #-16]!
+    mrs x1, esr_el1
+    lsr x1, x1, #26
+    cbnz x1, 1f                 // exception class 0: undefined instruction
+    mrs x1, elr_el1
+    ldr w2, [x1]
+    movz w1, #0x0000
+    movk w1, #0xd45e, lsl #16   // encoding of hlt #0xf000
+    cmp w1, w2
+    b.ne 1f
+    mrs x1, elr_el1
+    add x1, x1, #4
+    msr elr_el1, x1
+    mov x0, #-1
+    ldp x1, x2, [sp], #16
+    eret
+1:
+    ldp x1, x2, [sp], #16
+    b unhandled_exception
+
+    .rept 11
+    .balign 128
+    b unhandled_exception
+    .endr
+
+unhandled_exception:
+    wfe
+    b unhandled_exception
+    "#
+);
+
+extern "C" {
+    static exception_vectors: u8;
+}
+
+/// Install the exception vectors; must run before the first semihosting call
+pub fn init() {
+    unsafe {
+        core::arch::asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) core::ptr::addr_of!(exception_vectors) as usize,
+        );
+    }
+}
+
+unsafe fn call(operation: usize, parameter: usize) -> usize {
+    let result: usize;
+    core::arch::asm!(
+        "hlt #0xf000",
+        inout("x0") operation => result,
+        in("x1") parameter,
+        options(nostack),
+    );
+    result
+}
+
+/// True if QEMU was started with `-semihosting`
+pub fn is_available() -> bool {
+    match STATE.load(Ordering::Relaxed) {
+        AVAILABLE => true,
+        UNAVAILABLE => false,
+        _ => {
+            // SYS_ERRNO has no side effects and never returns -1 on a real host
+            let available = unsafe { call(SYS_ERRNO, 0) } != CALL_FAILED;
+            STATE.store(if available { AVAILABLE } else { UNAVAILABLE }, Ordering::Relaxed);
+            available
+        }
+    }
+}
+
+/// Write a string to the host's debug console (QEMU's stderr)
+pub fn write0(s: &str) {
+    if !is_available() {
+        return;
+    }
+    // SYS_WRITE0 wants a NUL-terminated string
+    let mut buf = [0u8; 128];
+    for chunk in s.as_bytes().chunks(buf.len() - 1) {
+        buf[..chunk.len()].copy_from_slice(chunk);
+        buf[chunk.len()] = 0;
+        unsafe {
+            call(SYS_WRITE0, buf.as_ptr() as usize);
+        }
+    }
+}
+
+/// Stop QEMU with the given exit status. Returns only if semihosting is off.
+pub fn exit(status: u32) {
+    if !is_available() {
+        return;
+    }
+    let block = [ADP_STOPPED_APPLICATION_EXIT, status as usize];
+    unsafe {
+        call(SYS_EXIT_EXTENDED, block.as_ptr() as usize);
+        // Hosts without SYS_EXIT_EXTENDED still take the 64-bit SYS_EXIT form
+        call(SYS_EXIT, block.as_ptr() as usize);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    Write,
+}
+
+/// A file on the host, opened through semihosting
+pub struct HostFile {
+    handle: usize,
+}
+
+impl HostFile {
+    pub fn open(path: &str, mode: OpenMode) -> Result<HostFile, &'static str> {
+        if !is_available() {
+            return Err("Semihosting is not enabled (run QEMU with -semihosting)");
+        }
+        let mut name = [0u8; 256];
+        if path.is_empty() || path.len() >= name.len() {
+            return Err("Invalid host path");
+        }
+        name[..path.len()].copy_from_slice(path.as_bytes());
+
+        // fopen() modes: 1 = "rb", 5 = "wb"
+        let mode = match mode {
+            OpenMode::Read => 1,
+            OpenMode::Write => 5,
+        };
+        let block = [name.as_ptr() as usize, mode, path.len()];
+        let handle = unsafe { call(SYS_OPEN, block.as_ptr() as usize) };
+        if handle == CALL_FAILED {
+            return Err("Cannot open host file");
+        }
+        Ok(HostFile { handle })
+    }
+
+    /// File length in bytes
+    pub fn len(&self) -> Result<usize, &'static str> {
+        let block = [self.handle];
+        let length = unsafe { call(SYS_FLEN, block.as_ptr() as usize) };
+        if length == CALL_FAILED { Err("Cannot get host file length") } else { Ok(length) }
+    }
+
+    /// Read up to `buf.len()` bytes; returns 0 at end of file
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let block = [self.handle, buf.as_mut_ptr() as usize, buf.len()];
+        // The result is the number of bytes *not* read
+        let remaining = unsafe { call(SYS_READ, block.as_ptr() as usize) };
+        if remaining > buf.len() { Err("Host read failed") } else { Ok(buf.len() - remaining) }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, &'static str> {
+        let block = [self.handle, data.as_ptr() as usize, data.len()];
+        let remaining = unsafe { call(SYS_WRITE, block.as_ptr() as usize) };
+        if remaining > data.len() { Err("Host write failed") } else { Ok(data.len() - remaining) }
+    }
+}
+
+impl Drop for HostFile {
+    fn drop(&mut self) {
+        let block = [self.handle];
+        unsafe {
+            call(SYS_CLOSE, block.as_ptr() as usize);
+        }
+    }
+}
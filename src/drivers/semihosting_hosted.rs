@@ -0,0 +1,56 @@
+// Semihosting API for the hosted build
+// A Linux process already is the host, so host files and the exit status map
+// straight onto std; `hostcat`/`hostcp` work on the local filesystem.
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+pub fn init() {}
+
+// Only the test runner reports through these in the hosted build
+
+/// Debug output goes to stderr, as QEMU does with SYS_WRITE0
+#[cfg(test)]
+pub fn write0(s: &str) {
+    eprint!("{}", s);
+}
+
+#[cfg(test)]
+pub fn exit(status: u32) {
+    super::host::exit(status as i32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    Write,
+}
+
+pub struct HostFile {
+    file: File,
+}
+
+impl HostFile {
+    pub fn open(path: &str, mode: OpenMode) -> Result<HostFile, &'static str> {
+        let file = match mode {
+            OpenMode::Read => File::open(path),
+            OpenMode::Write => File::create(path),
+        };
+        file.map(|file| HostFile { file }).map_err(|_| "Cannot open host file")
+    }
+
+    pub fn len(&self) -> Result<usize, &'static str> {
+        self.file
+            .metadata()
+            .map(|m| m.len() as usize)
+            .map_err(|_| "Cannot get host file length")
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        self.file.read(buf).map_err(|_| "Host read failed")
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, &'static str> {
+        self.file.write(data).map_err(|_| "Host write failed")
+    }
+}
@@ -0,0 +1,56 @@
+// ARM generic timer - monotonic time source for timeouts and delays
+// Reads the virtual counter directly; no interrupts are configured
+
+use jamos_core::clock::Clock;
+
+/// Raw counter value (CNTVCT_EL0)
+#[cfg(not(feature = "hosted"))]
+pub fn ticks() -> u64 {
+    let value: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, cntvct_el0", out(reg) value);
+    }
+    value
+}
+
+/// Counter frequency in Hz (CNTFRQ_EL0, set up by firmware/QEMU)
+#[cfg(not(feature = "hosted"))]
+pub fn frequency() -> u64 {
+    let value: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, cntfrq_el0", out(reg) value);
+    }
+    value
+}
+
+// Hosted build: nanoseconds since the first reading
+#[cfg(feature = "hosted")]
+pub fn ticks() -> u64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed().as_nanos() as u64
+}
+
+#[cfg(feature = "hosted")]
+pub fn frequency() -> u64 {
+    1_000_000_000
+}
+
+/// Milliseconds since the counter started
+pub fn uptime_ms() -> u64 {
+    let freq = frequency();
+    if freq == 0 {
+        return 0;
+    }
+    // Split the division to avoid overflowing ticks * 1000
+    let t = ticks();
+    (t / freq) * 1000 + ((t % freq) * 1000) / freq
+}
+
+/// The generic timer as the clock for input timeouts
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        uptime_ms()
+    }
+}
@@ -1,11 +1,19 @@
 // PL011 UART driver for ARM64 QEMU virt machine
+// The hosted build routes the same interface to the host terminal
 
+use super::console::{CharSink, CharSource};
+
+#[cfg(not(feature = "hosted"))]
 const UART0_BASE: usize = 0x0900_0000;
+#[cfg(not(feature = "hosted"))]
 const UART0_DR: *mut u32 = UART0_BASE as *mut u32;           // Data register
+#[cfg(not(feature = "hosted"))]
 const UART0_FR: *mut u32 = (UART0_BASE + 0x18) as *mut u32; // Flag register
 
 // UART Flag Register bits
+#[cfg(not(feature = "hosted"))]
 const UART_FR_TXFF: u32 = 1 << 5; // Transmit FIFO full
+#[cfg(not(feature = "hosted"))]
 const UART_FR_RXFE: u32 = 1 << 4; // Receive FIFO empty
 
 #[derive(Clone, Copy)]
@@ -20,6 +28,12 @@ impl Uart {
         Uart
     }
 
+    #[cfg(feature = "hosted")]
+    pub fn putc(&self, c: u8) {
+        super::host::write_byte(c);
+    }
+
+    #[cfg(not(feature = "hosted"))]
     pub fn putc(&self, c: u8) {
         unsafe {
             // Wait for TX FIFO to have space
@@ -38,6 +52,12 @@ impl Uart {
         }
     }
 
+    #[cfg(feature = "hosted")]
+    pub fn getc(&self) -> Option<u8> {
+        super::host::read_byte()
+    }
+
+    #[cfg(not(feature = "hosted"))]
     pub fn getc(&self) -> Option<u8> {
         unsafe {
             // Check if RX FIFO has data
@@ -50,3 +70,15 @@ impl Uart {
         }
     }
 }
+
+impl CharSink for Uart {
+    fn putc(&self, c: u8) {
+        Uart::putc(self, c);
+    }
+}
+
+impl CharSource for Uart {
+    fn getc(&self) -> Option<u8> {
+        Uart::getc(self)
+    }
+}
@@ -0,0 +1,412 @@
+// virtio-net driver over the virtio-mmio transport (QEMU virt machine)
+// Supports both the legacy (version 1) and modern (version 2) register layouts.
+// The driver polls the used rings; no interrupts are configured.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+// QEMU virt places 32 virtio-mmio slots starting at this address
+const VIRTIO_MMIO_BASE: usize = 0x0a00_0000;
+const VIRTIO_MMIO_STRIDE: usize = 0x200;
+const VIRTIO_MMIO_SLOTS: usize = 32;
+
+// virtio-mmio register offsets
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028; // legacy only
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c; // legacy only
+const REG_QUEUE_PFN: usize = 0x040; // legacy only
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const REG_CONFIG: usize = 0x100;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976; // "virt"
+const VIRTIO_DEVICE_NET: u32 = 1;
+
+// Device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+// Feature bits
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+const VIRTIO_F_VERSION_1: u32 = 1 << 0; // bit 32, i.e. bit 0 of feature word 1
+
+// Descriptor flags
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+
+const QUEUE_SIZE: usize = 16;
+const PAGE_SIZE: usize = 4096;
+
+/// Largest Ethernet frame we handle (without FCS)
+pub const MAX_FRAME_SIZE: usize = 1514;
+const BUFFER_SIZE: usize = 1536;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+// Split virtqueue laid out as the legacy interface requires: descriptor table and
+// available ring in the first page, used ring on the next page boundary.
+#[repr(C, align(4096))]
+struct VirtqMemory {
+    desc: [VirtqDesc; QUEUE_SIZE],
+    avail_flags: u16,
+    avail_idx: u16,
+    avail_ring: [u16; QUEUE_SIZE],
+    avail_used_event: u16,
+    _padding: [u8; PAGE_SIZE - 16 * QUEUE_SIZE - 2 * QUEUE_SIZE - 6],
+    used_flags: u16,
+    used_idx: u16,
+    used_ring: [VirtqUsedElem; QUEUE_SIZE],
+    used_avail_event: u16,
+}
+
+impl VirtqMemory {
+    const fn empty() -> Self {
+        VirtqMemory {
+            desc: [VirtqDesc { addr: 0, len: 0, flags: 0, next: 0 }; QUEUE_SIZE],
+            avail_flags: 0,
+            avail_idx: 0,
+            avail_ring: [0; QUEUE_SIZE],
+            avail_used_event: 0,
+            _padding: [0; PAGE_SIZE - 16 * QUEUE_SIZE - 2 * QUEUE_SIZE - 6],
+            used_flags: 0,
+            used_idx: 0,
+            used_ring: [VirtqUsedElem { id: 0, len: 0 }; QUEUE_SIZE],
+            used_avail_event: 0,
+        }
+    }
+}
+
+#[repr(C, align(16))]
+struct PacketBuffers {
+    data: [[u8; BUFFER_SIZE]; QUEUE_SIZE],
+}
+
+// DMA memory must live at a fixed physical address, so it is kept in statics
+// rather than inside the driver struct (the MMU is off, so virtual == physical).
+static mut RX_QUEUE_MEM: VirtqMemory = VirtqMemory::empty();
+static mut TX_QUEUE_MEM: VirtqMemory = VirtqMemory::empty();
+static mut RX_BUFFERS: PacketBuffers = PacketBuffers { data: [[0; BUFFER_SIZE]; QUEUE_SIZE] };
+static mut TX_BUFFERS: PacketBuffers = PacketBuffers { data: [[0; BUFFER_SIZE]; QUEUE_SIZE] };
+
+/// Driver-side bookkeeping for one virtqueue
+#[derive(Clone, Copy)]
+struct Virtqueue {
+    mem: *mut VirtqMemory,
+    size: u16,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    const fn empty() -> Self {
+        Virtqueue {
+            mem: core::ptr::null_mut(),
+            size: 0,
+            last_used: 0,
+        }
+    }
+
+    fn set_desc(&mut self, index: usize, addr: u64, len: u32, flags: u16) {
+        unsafe {
+            let desc = &raw mut (*self.mem).desc[index];
+            write_volatile(desc, VirtqDesc { addr, len, flags, next: 0 });
+        }
+    }
+
+    /// Publish descriptor `index` in the available ring
+    fn push_avail(&mut self, index: u16) {
+        unsafe {
+            let idx = read_volatile(&raw const (*self.mem).avail_idx);
+            let slot = &raw mut (*self.mem).avail_ring[(idx % self.size) as usize];
+            write_volatile(slot, index);
+            fence(Ordering::SeqCst);
+            write_volatile(&raw mut (*self.mem).avail_idx, idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+    }
+
+    /// Next completed (descriptor id, length) pair from the used ring
+    fn pop_used(&mut self) -> Option<(u16, u32)> {
+        unsafe {
+            fence(Ordering::SeqCst);
+            let used_idx = read_volatile(&raw const (*self.mem).used_idx);
+            if used_idx == self.last_used {
+                return None;
+            }
+            let elem = read_volatile(&raw const (*self.mem).used_ring[(self.last_used % self.size) as usize]);
+            self.last_used = self.last_used.wrapping_add(1);
+            Some((elem.id as u16, elem.len))
+        }
+    }
+}
+
+pub struct VirtioNet {
+    base: usize,
+    legacy: bool,
+    header_len: usize,
+    mac: [u8; 6],
+    rx: Virtqueue,
+    tx: Virtqueue,
+    tx_free: [bool; QUEUE_SIZE],
+    present: bool,
+}
+
+impl VirtioNet {
+    pub const fn empty() -> Self {
+        VirtioNet {
+            base: 0,
+            legacy: false,
+            header_len: 0,
+            mac: [0; 6],
+            rx: Virtqueue::empty(),
+            tx: Virtqueue::empty(),
+            tx_free: [true; QUEUE_SIZE],
+            present: false,
+        }
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Probe the virtio-mmio slots for a network device and bring it up
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        // A Linux process has no virtio-mmio bus to probe
+        if cfg!(feature = "hosted") {
+            return Err("No virtio-net device in hosted mode");
+        }
+        let mut slot = 0;
+        while slot < VIRTIO_MMIO_SLOTS {
+            let base = VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_STRIDE;
+            self.base = base;
+            if self.read_reg(REG_MAGIC) == VIRTIO_MAGIC && self.read_reg(REG_DEVICE_ID) == VIRTIO_DEVICE_NET {
+                return self.setup();
+            }
+            slot += 1;
+        }
+        self.base = 0;
+        Err("No virtio-net device found")
+    }
+
+    fn setup(&mut self) -> Result<(), &'static str> {
+        let version = self.read_reg(REG_VERSION);
+        if version != 1 && version != 2 {
+            return Err("Unsupported virtio-mmio version");
+        }
+        self.legacy = version == 1;
+
+        // Reset, then acknowledge the device
+        self.write_reg(REG_STATUS, 0);
+        self.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE);
+        self.write_reg(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        // Feature negotiation: we only need the MAC address (and VERSION_1 on modern devices)
+        self.write_reg(REG_DEVICE_FEATURES_SEL, 0);
+        let features_lo = self.read_reg(REG_DEVICE_FEATURES);
+        self.write_reg(REG_DRIVER_FEATURES_SEL, 0);
+        self.write_reg(REG_DRIVER_FEATURES, features_lo & VIRTIO_NET_F_MAC);
+
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        if self.legacy {
+            self.header_len = 10;
+            self.write_reg(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            self.write_reg(REG_DEVICE_FEATURES_SEL, 1);
+            let features_hi = self.read_reg(REG_DEVICE_FEATURES);
+            if features_hi & VIRTIO_F_VERSION_1 == 0 {
+                return Err("Device lacks VIRTIO_F_VERSION_1");
+            }
+            self.write_reg(REG_DRIVER_FEATURES_SEL, 1);
+            self.write_reg(REG_DRIVER_FEATURES, VIRTIO_F_VERSION_1);
+            self.header_len = 12;
+
+            status |= STATUS_FEATURES_OK;
+            self.write_reg(REG_STATUS, status);
+            if self.read_reg(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+                return Err("Device rejected features");
+            }
+        }
+
+        self.rx = self.setup_queue(RX_QUEUE, &raw mut RX_QUEUE_MEM)?;
+        self.tx = self.setup_queue(TX_QUEUE, &raw mut TX_QUEUE_MEM)?;
+
+        // Read the MAC address from config space, or fall back to QEMU's default
+        if features_lo & VIRTIO_NET_F_MAC != 0 {
+            let mut i = 0;
+            while i < 6 {
+                self.mac[i] = unsafe { read_volatile((self.base + REG_CONFIG + i) as *const u8) };
+                i += 1;
+            }
+        } else {
+            self.mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        }
+
+        // Hand every receive buffer to the device
+        let mut i = 0;
+        while i < self.rx.size as usize {
+            let addr = unsafe { (&raw const RX_BUFFERS.data[i]) as u64 };
+            self.rx.set_desc(i, addr, BUFFER_SIZE as u32, VIRTQ_DESC_F_WRITE);
+            self.rx.push_avail(i as u16);
+            i += 1;
+        }
+        self.tx_free = [true; QUEUE_SIZE];
+
+        status |= STATUS_DRIVER_OK;
+        self.write_reg(REG_STATUS, status);
+        self.write_reg(REG_QUEUE_NOTIFY, RX_QUEUE);
+
+        self.present = true;
+        Ok(())
+    }
+
+    fn setup_queue(&self, index: u32, mem: *mut VirtqMemory) -> Result<Virtqueue, &'static str> {
+        self.write_reg(REG_QUEUE_SEL, index);
+        let max = self.read_reg(REG_QUEUE_NUM_MAX) as usize;
+        if max == 0 {
+            return Err("Virtqueue not available");
+        }
+        let size = max.min(QUEUE_SIZE) as u16;
+        self.write_reg(REG_QUEUE_NUM, size as u32);
+
+        let addr = mem as u64;
+        if self.legacy {
+            self.write_reg(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write_reg(REG_QUEUE_PFN, (addr / PAGE_SIZE as u64) as u32);
+        } else {
+            let avail = unsafe { (&raw const (*mem).avail_flags) as u64 };
+            let used = unsafe { (&raw const (*mem).used_flags) as u64 };
+            self.write_reg(REG_QUEUE_DESC_LOW, addr as u32);
+            self.write_reg(REG_QUEUE_DESC_HIGH, (addr >> 32) as u32);
+            self.write_reg(REG_QUEUE_DRIVER_LOW, avail as u32);
+            self.write_reg(REG_QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            self.write_reg(REG_QUEUE_DEVICE_LOW, used as u32);
+            self.write_reg(REG_QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            self.write_reg(REG_QUEUE_READY, 1);
+        }
+
+        Ok(Virtqueue {
+            mem,
+            size,
+            last_used: 0,
+        })
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn ack_interrupts(&self) {
+        let pending = self.read_reg(REG_INTERRUPT_STATUS);
+        if pending != 0 {
+            self.write_reg(REG_INTERRUPT_ACK, pending);
+        }
+    }
+
+    fn reclaim_tx(&mut self) {
+        while let Some((id, _)) = self.tx.pop_used() {
+            if (id as usize) < QUEUE_SIZE {
+                self.tx_free[id as usize] = true;
+            }
+        }
+    }
+
+    /// Queue an Ethernet frame for transmission
+    pub fn transmit(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+        if !self.present {
+            return Err("Network device not present");
+        }
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err("Frame too large");
+        }
+
+        self.ack_interrupts();
+        self.reclaim_tx();
+        let slot = self.tx_free[..self.tx.size as usize].iter().position(|&free| free)
+            .ok_or("Transmit queue full")?;
+        self.tx_free[slot] = false;
+
+        // virtio-net header (all zero: no offloads) followed by the frame
+        let total = self.header_len + frame.len();
+        unsafe {
+            let buf = (&raw mut TX_BUFFERS.data[slot]) as *mut u8;
+            core::ptr::write_bytes(buf, 0, self.header_len);
+            core::ptr::copy_nonoverlapping(frame.as_ptr(), buf.add(self.header_len), frame.len());
+            self.tx.set_desc(slot, buf as u64, total as u32, 0);
+        }
+        self.tx.push_avail(slot as u16);
+        self.write_reg(REG_QUEUE_NOTIFY, TX_QUEUE);
+        Ok(())
+    }
+
+    /// Copy the next received Ethernet frame into `buf`, returning its length
+    pub fn receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.present {
+            return None;
+        }
+        self.ack_interrupts();
+        let (id, len) = self.rx.pop_used()?;
+        let id = id as usize;
+        let len = len as usize;
+
+        let mut copied = 0;
+        if id < QUEUE_SIZE && len > self.header_len {
+            let frame_len = (len - self.header_len).min(buf.len()).min(BUFFER_SIZE - self.header_len);
+            unsafe {
+                let data = (&raw const RX_BUFFERS.data[id]) as *const u8;
+                core::ptr::copy_nonoverlapping(data.add(self.header_len), buf.as_mut_ptr(), frame_len);
+            }
+            copied = frame_len;
+        }
+
+        // Recycle the buffer back to the device
+        if id < QUEUE_SIZE {
+            self.rx.push_avail(id as u16);
+            self.write_reg(REG_QUEUE_NOTIFY, RX_QUEUE);
+        }
+
+        Some(copied)
+    }
+}
@@ -1,28 +1,68 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "hosted"), no_std)]
+#![cfg_attr(not(feature = "hosted"), no_main)]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::testing::run_tests))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
+use core::fmt::Write;
+#[cfg(not(feature = "hosted"))]
 use core::arch::global_asm;
+#[cfg(not(feature = "hosted"))]
 use core::panic::PanicInfo;
 
+// Cargo builds for the host unless told otherwise, and only the hosted build
+// runs there
+#[cfg(not(any(feature = "hosted", target_os = "none")))]
+compile_error!("the kernel builds with --target aarch64-unknown-none (`cargo qemu`); use --features hosted to run it on this machine");
+
 mod drivers;
-mod terminal;
-mod filesystem;
-mod editor;
-mod wayland;
+mod net;
+mod shell;
+mod transfer;
 mod utils;
+#[cfg(test)]
+mod testing;
 
+use jamos_core::{terminal, filesystem, editor, wayland, text::{self, FixedString}};
+use jamos_core::keymap::{Action, Keymap, Scope};
 use drivers::{uart::Uart, keyboard::{Keyboard, Key, KeyEvent}};
-use terminal::{VirtualDesktopManager, Screen};
+use terminal::{VirtualDesktopManager, Screen, StatusBar, vdesktop::{VirtualDesktop, HISTORIES_BYTES, MAX_DESKTOPS, MAX_NAME_LEN, MAX_INPUT_LEN}};
+use terminal::statusbar::Status;
+use terminal::pool::PanePool;
+use terminal::history::{HistorySearch, SearchOutcome};
+use terminal::completion::{Candidates, CompletionContext, TabCompletion, TabOutcome};
+use terminal::scrollback::ScrollbackMode;
+use terminal::tiling::Direction;
 use filesystem::VirtualFileSystem;
 use editor::{TextEditor, buffer::EditorAction};
 use wayland::WaylandCompositor;
-use utils::print_number;
+use net::{NetInterface, Ipv4Addr, netcat::Netcat};
+use net::telnet::{self, TelnetServer, TelnetEvent, TELNET_PORT, MAX_TELNET_SESSIONS};
+use transfer::{Port, Protocol, Report};
+use drivers::timer::{self, SystemClock};
+use drivers::{memory, rtc};
+use drivers::semihosting::{self, HostFile, OpenMode};
+use utils::{print_number, parse_number};
+use shell::{ArgError, Builtin, Context, Registry};
+
+// The serial console; remote sessions get their sinks from the telnet server
+static CONSOLE: Uart = Uart::empty();
 
 // Global static storage for the virtual desktop manager
 static mut VDM_STORAGE: VirtualDesktopManager = VirtualDesktopManager::empty();
+// The screens of every pane, the console's and the remote sessions'
+static mut PANE_POOL: PanePool = PanePool::empty();
 static mut VFS_STORAGE: VirtualFileSystem = VirtualFileSystem::empty();
-static mut EDITOR_STORAGE: TextEditor = TextEditor::empty();
+static mut CONSOLE_SESSION: Session = Session::empty();
 static mut WAYLAND_STORAGE: WaylandCompositor = WaylandCompositor::empty();
+static mut NET_STORAGE: NetInterface = NetInterface::empty();
+static mut TELNETD_STORAGE: TelnetServer = TelnetServer::empty();
+static mut KEYMAP_STORAGE: Keymap = Keymap::empty();
+static mut STATUS_BAR_STORAGE: StatusBar = StatusBar::empty();
+static mut COMMANDS_STORAGE: Registry = Registry::empty();
+static mut REMOTE_STORAGE: [RemoteTerminal; MAX_TELNET_SESSIONS] = [const { RemoteTerminal::empty() }; MAX_TELNET_SESSIONS];
+// The history host file as read or written; too big for the stack
+static mut HISTORIES_BUFFER: [u8; HISTORIES_BYTES] = [0; HISTORIES_BYTES];
 
 fn get_vdm() -> &'static mut VirtualDesktopManager {
     unsafe {
@@ -30,15 +70,21 @@ fn get_vdm() -> &'static mut VirtualDesktopManager {
     }
 }
 
+fn get_pane_pool() -> &'static PanePool {
+    unsafe {
+        &*core::ptr::addr_of!(PANE_POOL)
+    }
+}
+
 fn get_vfs() -> &'static mut VirtualFileSystem {
     unsafe {
         &mut VFS_STORAGE
     }
 }
 
-fn get_editor() -> &'static mut TextEditor {
+fn get_console_session() -> &'static mut Session {
     unsafe {
-        &mut EDITOR_STORAGE
+        &mut *core::ptr::addr_of_mut!(CONSOLE_SESSION)
     }
 }
 
@@ -48,7 +94,50 @@ fn get_wayland() -> &'static mut WaylandCompositor {
     }
 }
 
+fn get_net() -> &'static mut NetInterface {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(NET_STORAGE)
+    }
+}
+
+fn get_telnetd() -> &'static mut TelnetServer {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(TELNETD_STORAGE)
+    }
+}
+
+fn get_keymap() -> &'static mut Keymap {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(KEYMAP_STORAGE)
+    }
+}
+
+fn get_status_bar() -> &'static mut StatusBar {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(STATUS_BAR_STORAGE)
+    }
+}
+
+fn get_commands() -> &'static mut Registry {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(COMMANDS_STORAGE)
+    }
+}
+
+fn get_remote(slot: usize) -> &'static mut RemoteTerminal {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(REMOTE_STORAGE[slot])
+    }
+}
+
+fn get_histories_buffer() -> &'static mut [u8; HISTORIES_BYTES] {
+    unsafe {
+        &mut *core::ptr::addr_of_mut!(HISTORIES_BUFFER)
+    }
+}
+
 // Assembly boot stub to initialize stack pointer before calling Rust
+#[cfg(not(feature = "hosted"))]
 global_asm!(
     r#"
     .section .text.boot
@@ -83,56 +172,178 @@ enum TerminalMode {
     Normal,
     NamingDesktop,
     Editor,
+    Netcat,
+    Scrollback,
+    HistorySearch,
+}
+
+/// Shell state of one terminal; the console and every remote session have their own
+struct Session {
+    mode: TerminalMode,
+    editor: TextEditor,
+    netcat: Netcat,
+    scrollback: ScrollbackMode,
+    search: HistorySearch,
+    completion: TabCompletion,
+}
+
+impl Session {
+    const fn empty() -> Self {
+        Session {
+            mode: TerminalMode::Normal,
+            editor: TextEditor::empty(),
+            netcat: Netcat::empty(),
+            scrollback: ScrollbackMode::empty(),
+            search: HistorySearch::empty(),
+            completion: TabCompletion::empty(),
+        }
+    }
+}
+
+/// Which terminal a key press came from
+#[derive(Clone, Copy)]
+enum Origin {
+    Console { index: usize, count: usize },
+    Remote(usize),
+}
+
+/// Desktop, input decoder and shell state of a telnet client
+struct RemoteTerminal {
+    desktop: VirtualDesktop,
+    keyboard: Keyboard,
+    session: Session,
+    active: bool,
+}
+
+impl RemoteTerminal {
+    const fn empty() -> Self {
+        RemoteTerminal {
+            desktop: VirtualDesktop::empty(),
+            keyboard: Keyboard::empty(),
+            session: Session::empty(),
+            active: false,
+        }
+    }
+}
+
+// Hosted build: a Linux process standing in for the boot stub
+#[cfg(feature = "hosted")]
+fn main() {
+    drivers::host::init();
+    Uart::new().puts("Jamos hosted on Linux (Ctrl-A X to quit)\n\n");
+    rust_main();
 }
 
 // Assembly boot stub calls this function after setting up the stack
 #[no_mangle]
 pub extern "C" fn rust_main() -> ! {
-    let uart = Uart::new();
-    let mut keyboard = Keyboard::new(Uart::new());
+    let mut keyboard = Keyboard::new(&CONSOLE, &SystemClock);
     
-    // Print initial message
-    uart.puts("Hello lovely Anna!\n\n");
-    uart.puts("=== Jamos Experimental Terminal ===\n");
-    uart.puts("Ctrl+Right: New desktop | Ctrl+Left: Prev desktop | Ctrl+N: Name\n\n");
+    // Exception vectors first, so semihosting calls are safe without -semihosting
+    semihosting::init();
     
-    // Initialize virtual desktop manager in global storage
-    get_vdm().init(Uart::new());
+    // `cargo qemu-test`: run the test cases; they stop QEMU when done.
+    // Hosted test binaries start in the test harness instead.
+    #[cfg(all(test, not(feature = "hosted")))]
+    test_main();
     
     // Initialize virtual filesystem
     get_vfs().init();
     
+    // Initialize virtual desktop manager in global storage; boot messages
+    // go through the first desktop so they are redrawn on switching back
+    get_vdm().init(&CONSOLE, get_pane_pool());
+    // The status bar takes the console's bottom row
+    get_status_bar().init(&CONSOLE);
+    get_vdm().set_reserved_rows(get_status_bar().rows());
+    // The desktops and layout of the last session written to the host
+    let restored = restore_session();
+    // And the commands run before, each desktop's its own
+    let history = restore_history();
+    let screen = get_vdm().current_mut().unwrap().screen_mut();
+    
+    // Print initial message
+    screen.puts("Hello lovely Anna!\n\n");
+    screen.puts("=== Jamos Experimental Terminal ===\n");
+    screen.puts("Ctrl+Right: New desktop | Ctrl+Left: Prev desktop | Ctrl+N: Name\n\n");
+    
+    // Default key bindings
+    get_keymap().init();
+    // The shell's commands
+    register_commands(get_commands());
+    
     // Initialize Wayland compositor
-    get_wayland().init();
+    get_wayland().init(&CONSOLE);
     
-    let mut mode = TerminalMode::Normal;
+    // Initialize networking (optional: QEMU needs -device virtio-net-device)
+    match get_net().init() {
+        Ok(()) => {
+            screen.puts("Network: virtio-net up, requesting address via DHCP\n");
+            match get_telnetd().start(get_net()) {
+                Ok(()) => screen.puts("Telnet: remote shell listening on port 23\n\n"),
+                Err(e) => {
+                    screen.puts("Telnet: ");
+                    screen.puts(e);
+                    screen.puts("\n\n");
+                }
+            }
+        }
+        Err(e) => {
+            screen.puts("Network: ");
+            screen.puts(e);
+            screen.puts("\n\n");
+        }
+    }
+    
+    match restored {
+        Ok(count) => {
+            let _ = writeln!(screen, "Session: {} desktops restored from {}\n", count, SESSION_HOST_FILE);
+        }
+        Err(Some(e)) => {
+            let _ = writeln!(screen, "Session: {}: {}\n", SESSION_HOST_FILE, e);
+        }
+        Err(None) => {}
+    }
+    if let Some(count) = history {
+        let _ = writeln!(screen, "History: {} commands read from {}\n", count, HISTORY_HOST_FILE);
+    }
     
-    // Show prompt
+    // Show prompt, in every pane of a restored session
     {
         let vdm = get_vdm();
         if let Some(desktop) = vdm.current_mut() {
-            desktop.screen_mut().puts("[Desktop 1]$ ");
+            desktop.screen_mut().set_bracketed_paste(true);
         }
+        show_prompts(vdm);
     }
     
     // Main terminal loop
+    const STATUS_REFRESH_MS: u64 = 1000;
+    let mut status_due = 0;
     loop {
         if let Some(event) = keyboard.poll() {
-            let vdm = get_vdm();
-            match mode {
-                TerminalMode::Normal => {
-                    handle_normal_mode(vdm, &event, &mut mode);
-                }
-                TerminalMode::NamingDesktop => {
-                    handle_naming_mode(vdm, &event, &mut mode);
-                }
-                TerminalMode::Editor => {
-                    handle_editor_mode(vdm, &event, &mut mode);
-                }
-            }
+            handle_console_key(&event, keyboard.pasted());
+            status_due = 0;
+        }
+        
+        // Service the network stack, remote sessions and any interactive nc session
+        get_net().poll();
+        poll_remote_terminals();
+        if let Some(desktop) = get_vdm().current_mut() {
+            poll_session(desktop, get_console_session());
+        }
+        
+        // Refresh the status bar after each key and on timer ticks; it is
+        // only drawn when its line changes
+        let now = timer::uptime_ms();
+        if now >= status_due {
+            update_status_bar();
+            status_due = now + STATUS_REFRESH_MS;
         }
         
         // Small delay to avoid busy-waiting
+        #[cfg(feature = "hosted")]
+        std::thread::sleep(std::time::Duration::from_millis(1));
         for _ in 0..1000 {
             unsafe {
                 core::arch::asm!("nop");
@@ -141,206 +352,891 @@ pub extern "C" fn rust_main() -> ! {
     }
 }
 
-fn handle_normal_mode(
-    vdm: &mut VirtualDesktopManager,
-    event: &KeyEvent,
-    mode: &mut TerminalMode,
-) {
-    // Handle Ctrl+Right: Create new virtual desktop
-    if event.ctrl && event.key == Key::Right {
-        let uart = Uart::new();
-        if vdm.next(uart) {
-            if let Some(desktop) = vdm.current_mut() {
-                let mut name_buf = [0u8; 32];
-                let name_len = desktop.copy_name_to(&mut name_buf);
-                let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                desktop.screen_mut().clear();
-                desktop.screen_mut().puts(">>> Switched to ");
-                desktop.screen_mut().puts(name);
-                desktop.screen_mut().puts(" <<<\n\n");
-                show_prompt(desktop.screen_mut(), name);
+/// `pasted` is the text of a Key::Paste event
+fn handle_console_key(event: &KeyEvent, pasted: &str) {
+    let vdm = get_vdm();
+    let session = get_console_session();
+    let mut event = *event;
+
+    // A line found by Ctrl+R runs as if typed, so it can be a desktops command
+    if matches!(session.mode, TerminalMode::HistorySearch) && event.key != Key::Paste {
+        let Some(desktop) = vdm.current_mut() else {
+            return;
+        };
+        if !handle_history_search(desktop, session, &event) {
+            return;
+        }
+        event = KeyEvent::new(Key::Enter);
+    }
+
+    // Desktop switching only applies to the console's own desktops
+    if let TerminalMode::Normal = session.mode {
+        if let Some(
+            action @ (Action::DesktopNext
+            | Action::DesktopPrevious
+            | Action::DesktopMoveLeft
+            | Action::DesktopMoveRight
+            | Action::DesktopJump(_)),
+        ) = get_keymap().action(&event, Scope::Desktop)
+        {
+            handle_desktop_action(vdm, action);
+            save_history(vdm);
+            return;
+        }
+        // And commands get them all, for those that manage them
+        if event.key == Key::Enter {
+            let Some(line) = vdm.current_mut().and_then(submit_input) else {
+                return;
+            };
+            get_commands().run(line.as_str(), &mut Context::console(vdm, session));
+            save_history(vdm);
+            return;
+        }
+    }
+
+    let origin = Origin::Console { index: vdm.get_index(), count: vdm.get_count() };
+    if let Some(desktop) = vdm.current_mut() {
+        handle_key(desktop, session, &event, pasted, origin);
+    }
+}
+
+fn handle_key(desktop: &mut VirtualDesktop, session: &mut Session, event: &KeyEvent, pasted: &str, origin: Origin) {
+    if event.key == Key::Paste {
+        handle_paste(desktop, session, pasted);
+        return;
+    }
+    match session.mode {
+        TerminalMode::Normal => {
+            handle_normal_mode(desktop, session, event, origin);
+        }
+        TerminalMode::NamingDesktop => {
+            handle_naming_mode(desktop, session, event);
+        }
+        TerminalMode::Editor => {
+            handle_editor_mode(desktop, session, event);
+        }
+        TerminalMode::Netcat => {
+            handle_netcat_mode(desktop, session, event);
+        }
+        TerminalMode::Scrollback => {
+            if !session.scrollback.handle_key(event, get_keymap(), desktop.screen_mut()) {
+                session.mode = TerminalMode::Normal;
+            }
+        }
+        TerminalMode::HistorySearch => {
+            if handle_history_search(desktop, session, event) {
+                handle_normal_mode(desktop, session, &KeyEvent::new(Key::Enter), origin);
+            }
+        }
+    }
+}
+
+/// A key during Ctrl+R; true when the line found should run. When the
+/// search ends the prompt is drawn again with the line to edit or run.
+fn handle_history_search(desktop: &mut VirtualDesktop, session: &mut Session, event: &KeyEvent) -> bool {
+    let outcome = session.search.handle_key(event, get_keymap(), desktop);
+    if outcome == SearchOutcome::Searching {
+        return false;
+    }
+    session.mode = TerminalMode::Normal;
+    show_current_prompt(desktop);
+    desktop.redraw_input();
+    outcome == SearchOutcome::Run
+}
+
+/// Pasted text is inserted, never run: the shell puts it on the input line
+/// (line breaks become spaces) for the user to check and press Enter
+fn handle_paste(desktop: &mut VirtualDesktop, session: &mut Session, pasted: &str) {
+    match session.mode {
+        TerminalMode::Normal | TerminalMode::NamingDesktop => {
+            for c in pasted.chars() {
+                let c = if c == '\n' || c == '\t' { ' ' } else { c };
+                if !desktop.insert_input(c.encode_utf8(&mut [0; 4])) {
+                    break;
+                }
             }
         }
+        TerminalMode::Editor => {
+            session.editor.paste(pasted);
+            session.editor.render(desktop.screen_mut());
+        }
+        TerminalMode::Netcat => {
+            session.netcat.paste(pasted, get_net(), desktop.screen_mut());
+        }
+        TerminalMode::HistorySearch => {
+            session.search.paste(pasted, desktop);
+        }
+        TerminalMode::Scrollback => {}
+    }
+}
+
+/// Work a terminal does between key presses
+fn poll_session(desktop: &mut VirtualDesktop, session: &mut Session) {
+    if let TerminalMode::Netcat = session.mode {
+        if !session.netcat.poll(get_net(), desktop.screen_mut()) {
+            session.mode = TerminalMode::Normal;
+            show_current_prompt(desktop);
+        }
+    }
+}
+
+fn poll_remote_terminals() {
+    let telnetd = get_telnetd();
+    telnetd.poll(get_net());
+    while let Some(event) = telnetd.next_event() {
+        match event {
+            TelnetEvent::Connected(slot) => open_remote_terminal(slot),
+            TelnetEvent::Resized(slot, columns, rows) => {
+                get_remote(slot).desktop.set_size(columns, rows);
+            }
+            TelnetEvent::Disconnected(slot) => close_remote_terminal(slot),
+        }
+    }
+
+    for slot in 0..MAX_TELNET_SESSIONS {
+        let remote = get_remote(slot);
+        if !remote.active {
+            continue;
+        }
+        while let Some(event) = remote.keyboard.poll() {
+            let pasted = remote.keyboard.pasted();
+            handle_key(&mut remote.desktop, &mut remote.session, &event, pasted, Origin::Remote(slot));
+        }
+        poll_session(&mut remote.desktop, &mut remote.session);
+    }
+}
+
+fn open_remote_terminal(slot: usize) {
+    let remote = get_remote(slot);
+    let mut name = FixedString::<MAX_NAME_LEN>::from_str_truncated("Remote ");
+    name.push((b'1' + slot as u8) as char);
+    if let Err(e) = remote.desktop.init(telnet::sink(slot), get_pane_pool(), name.as_str()) {
+        let sink = telnet::sink(slot);
+        sink.puts(e);
+        sink.puts("\r\n");
+        get_telnetd().disconnect(slot);
         return;
     }
+    remote.desktop.clear_input();
+    remote.keyboard = Keyboard::new(telnet::source(slot), &SystemClock);
+    remote.session.mode = TerminalMode::Normal;
+    remote.active = true;
 
-    // Handle Ctrl+Left: Switch to previous desktop
-    if event.ctrl && event.key == Key::Left {
-        if vdm.previous() {
-            if let Some(desktop) = vdm.current_mut() {
-                let mut name_buf = [0u8; 32];
-                let name_len = desktop.copy_name_to(&mut name_buf);
-                let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                // Show which desktop we switched to
-                desktop.screen_mut().puts("\n>>> Switched to ");
-                desktop.screen_mut().puts(name);
-                desktop.screen_mut().puts(" <<<\n");
-                show_prompt(desktop.screen_mut(), name);
+    let screen = remote.desktop.screen_mut();
+    screen.set_bracketed_paste(true);
+    screen.puts("=== Jamos Experimental Terminal ===\n");
+    screen.puts("Remote session ");
+    print_number(screen, slot + 1);
+    screen.puts(". Type 'help' for commands, 'exit' to disconnect.\n\n");
+    show_current_prompt(&mut remote.desktop);
+}
+
+fn close_remote_terminal(slot: usize) {
+    let remote = get_remote(slot);
+    if let TerminalMode::Netcat = remote.session.mode {
+        remote.session.netcat.close(get_net(), remote.desktop.screen_mut(), "");
+    }
+    remote.session.mode = TerminalMode::Normal;
+    remote.desktop.release();
+    remote.active = false;
+}
+
+fn handle_desktop_action(vdm: &mut VirtualDesktopManager, action: Action) {
+    match action {
+        // desktop.next (Ctrl+Right): the next desktop, or a new one after
+        // the last while there is room
+        Action::DesktopNext => {
+            if vdm.get_index() + 1 < vdm.get_count() || create_desktop(vdm, None).is_err() {
+                vdm.next_desktop();
+            }
+        }
+        // desktop.previous (Ctrl+Left): Switch to previous desktop; it is
+        // redrawn as it was left
+        Action::DesktopPrevious => {
+            vdm.prev_desktop();
+        }
+        // desktop.move-left/right (Ctrl+Shift+Left/Right): Reorder
+        Action::DesktopMoveLeft => {
+            if let Some(position) = vdm.get_index().checked_sub(1) {
+                let _ = vdm.move_to(position);
+            }
+        }
+        Action::DesktopMoveRight => {
+            let _ = vdm.move_to(vdm.get_index() + 1);
+        }
+        // desktop.jump-N (Alt+N): Straight to desktop N
+        Action::DesktopJump(number) => {
+            vdm.switch_to(number - 1);
+        }
+        _ => {}
+    }
+}
+
+/// Open a new desktop and greet it; an error if there are too many or no
+/// pane is free for it
+fn create_desktop(vdm: &mut VirtualDesktopManager, name: Option<&str>) -> Result<(), &'static str> {
+    vdm.create()?;
+    if let Some(desktop) = vdm.current_mut() {
+        if let Some(name) = name {
+            desktop.set_name(name);
+        }
+        let name = desktop.name();
+        let name = name.as_str();
+        desktop.screen_mut().clear();
+        desktop.screen_mut().puts(">>> Switched to ");
+        desktop.screen_mut().puts(name);
+        desktop.screen_mut().puts(" <<<\n\n");
+        show_prompt(desktop.screen_mut(), name);
+    }
+    Ok(())
+}
+
+/// `desktops`, on the console's desktop manager
+fn handle_desktops_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    match context.desktops() {
+        Some(vdm) => list_desktops(vdm),
+        None => context.screen().puts("desktop: a remote session has a single desktop\n"),
+    }
+    Ok(())
+}
+
+/// `desktop ...`, which works on the console's desktop manager instead of
+/// the current desktop
+fn handle_desktop_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let Some(vdm) = context.desktops() else {
+        context.screen().puts("desktop: a remote session has a single desktop\n");
+        return Ok(());
+    };
+
+    // Desktops are numbered from 1
+    let parse_position = |word: &str| parse_number(word.as_bytes()).and_then(|n| n.checked_sub(1));
+    let mut words = args.split_whitespace();
+    // Ok(true) when the desktop now shown is drawn with its prompt
+    let result = match (words.next(), words.next(), words.next()) {
+        (Some("new"), name, None) => create_desktop(vdm, name).map(|()| true),
+        (Some("close"), word, None) => {
+            let current = vdm.get_index();
+            let position = word.map_or(Some(current), parse_position);
+            // The desktop shown instead is drawn as it was left
+            position
+                .map_or(Err("No such desktop"), |position| vdm.close(position))
+                .map(|()| position == Some(current))
+        }
+        (Some("move"), Some(word), None) => parse_position(word)
+            .map_or(Err("No such desktop"), |position| vdm.move_to(position))
+            .map(|()| false),
+        (Some(word), None, None) if parse_position(word).is_some() => match parse_position(word) {
+            Some(position) if position == vdm.get_index() => Ok(false),
+            Some(position) if vdm.switch_to(position) => Ok(true),
+            _ => Err("No such desktop"),
+        },
+        _ => return Err(ArgError::Usage),
+    };
+    match result {
+        Ok(drawn) => context.prompt = !drawn,
+        Err(e) => {
+            let _ = writeln!(context.screen(), "{}", e);
+        }
+    }
+    Ok(())
+}
+
+/// One line per desktop, the current one marked with '*'
+fn list_desktops(vdm: &mut VirtualDesktopManager) {
+    let (index, count) = (vdm.get_index(), vdm.get_count());
+    let mut names = [FixedString::<MAX_NAME_LEN>::new(); MAX_DESKTOPS];
+    let mut panes = [0; MAX_DESKTOPS];
+    for position in 0..count {
+        if let Some(desktop) = vdm.get(position) {
+            names[position] = desktop.name();
+            panes[position] = desktop.tiling().get_pane_count();
+        }
+    }
+    let Some(desktop) = vdm.current_mut() else {
+        return;
+    };
+    let screen = desktop.screen_mut();
+    for (position, (name, panes)) in names.iter().zip(panes).enumerate().take(count) {
+        let marker = if position == index { '*' } else { ' ' };
+        let _ = write!(screen, "{} {}  {}", marker, position + 1, name.as_str());
+        if panes > 1 {
+            let _ = write!(screen, " ({} panes)", panes);
+        }
+        screen.puts("\n");
+    }
+}
+
+// VFS file `session save` and `session load` use without a name
+const DEFAULT_SESSION: &str = "session";
+// Host file restored at boot; `hostcp -w session jamos.session` writes it
+const SESSION_HOST_FILE: &str = "jamos.session";
+
+/// `session save|load [<name>]`: the console's desktops, their names and
+/// pane layouts, to or from a VFS file
+fn handle_session_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let mut words = args.split_whitespace();
+    let (command, name) = match (words.next(), words.next(), words.next()) {
+        (Some(command @ ("save" | "load")), name, None) => (command, name.unwrap_or(DEFAULT_SESSION)),
+        _ => return Err(ArgError::Usage),
+    };
+    let Some(vdm) = context.desktops() else {
+        context.screen().puts("session: a remote session has a single desktop\n");
+        return Ok(());
+    };
+    if command == "load" {
+        match load_session(vdm, name) {
+            Ok(count) => {
+                if let Some(desktop) = vdm.current_mut() {
+                    let _ = writeln!(desktop.screen_mut(), "Session loaded from {} ({} desktops)", name, count);
+                }
+                show_prompts(vdm);
+                context.prompt = false;
+            }
+            Err(e) => {
+                let _ = writeln!(context.screen(), "session: {}", e);
+            }
+        }
+    } else {
+        let mut session = FixedString::<{ filesystem::MAX_FILE_SIZE }>::new();
+        let result = match vdm.save(&mut session) {
+            Ok(()) => get_vfs().save_file(name, session.as_str().as_bytes()),
+            Err(_) => Err("too many desktops and panes for one file"),
+        };
+        match result {
+            Ok(_) => {
+                let _ = writeln!(context.screen(), "Session saved to {}", name);
+            }
+            Err(e) => {
+                let _ = writeln!(context.screen(), "session: {}", e);
             }
         }
+    }
+    Ok(())
+}
+
+/// Replace the desktops with the session in VFS file `name`
+fn load_session(vdm: &mut VirtualDesktopManager, name: &str) -> Result<usize, SessionError> {
+    let vfs = get_vfs();
+    let inode_id = vfs.find_inode_by_name(name).ok_or(SessionError::Plain("file not found"))?;
+    let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+    let size = vfs.read_file(inode_id, &mut buf).map_err(SessionError::Plain)?;
+    let session = core::str::from_utf8(&buf[..size]).map_err(|_| SessionError::Plain("not a session file"))?;
+    vdm.load(session).map_err(|(line, e)| SessionError::Line(line, e))
+}
+
+enum SessionError {
+    Plain(&'static str),
+    Line(usize, &'static str),
+}
+
+impl core::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            SessionError::Plain(e) => f.write_str(e),
+            SessionError::Line(line, e) => write!(f, "line {}: {}", line, e),
+        }
+    }
+}
+
+/// Copy the session file from the host (with -semihosting, or the hosted
+/// build) into the VFS and load it. Err(None) when there is none.
+fn restore_session() -> Result<usize, Option<SessionError>> {
+    copy_from_host(SESSION_HOST_FILE, DEFAULT_SESSION).map_err(|_| None)?;
+    load_session(get_vdm(), DEFAULT_SESSION).map_err(Some)
+}
+
+/// A prompt in every pane of every desktop, as after loading a session
+fn show_prompts(vdm: &mut VirtualDesktopManager) {
+    for position in 0..vdm.get_count() {
+        let Some(desktop) = vdm.get_mut(position) else {
+            continue;
+        };
+        let name = desktop.name();
+        for pane in desktop.tiling_mut().panes_mut() {
+            show_prompt(pane.screen_mut(), name.as_str());
+        }
+    }
+}
+
+/// `statusbar [on|off]`; the desktops give up the bottom row to the bar or
+/// take it back
+fn handle_statusbar_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    if context.desktops().is_none() {
+        context.screen().puts("statusbar: only the serial console has a status bar\n");
+        return Ok(());
+    }
+    let bar = get_status_bar();
+    match args {
+        "" => context.screen().puts(if bar.is_enabled() { "Status bar: on\n" } else { "Status bar: off\n" }),
+        "on" => bar.set_enabled(true),
+        "off" => bar.set_enabled(false),
+        _ => return Err(ArgError::Usage),
+    }
+    if let Some(vdm) = context.desktops() {
+        vdm.set_reserved_rows(bar.rows());
+    }
+    Ok(())
+}
+
+/// Give the status bar the console's current state
+fn update_status_bar() {
+    let vdm = get_vdm();
+    let count = vdm.get_count();
+    let mut names = [FixedString::<MAX_NAME_LEN>::new(); MAX_DESKTOPS];
+    for (position, name) in names.iter_mut().enumerate().take(count) {
+        if let Some(desktop) = vdm.get(position) {
+            *name = desktop.name();
+        }
+    }
+    let (pane, panes) = vdm
+        .get(vdm.get_index())
+        .map_or((0, 1), |desktop| (desktop.tiling().get_active_position(), desktop.tiling().get_pane_count()));
+    get_status_bar().update(&Status {
+        desktops: &names[..count],
+        current: vdm.get_index(),
+        pane,
+        panes,
+        compositor_running: get_wayland().is_running(),
+        time: rtc::seconds(),
+        free_memory: memory::free_bytes(),
+    });
+}
+
+fn handle_normal_mode(
+    desktop: &mut VirtualDesktop,
+    session: &mut Session,
+    event: &KeyEvent,
+    origin: Origin,
+) {
+    // line.complete (Tab): Complete the word before the cursor
+    if get_keymap().action(event, Scope::Line) == Some(Action::LineComplete) {
+        if session.completion.complete(get_commands(), desktop) == TabOutcome::Listed {
+            show_current_prompt(desktop);
+            desktop.redraw_input();
+        }
         return;
     }
+    session.completion.reset();
 
-    // Handle Ctrl+N: Name current desktop
-    if event.ctrl && event.key == Key::Char(b'n') || event.key == Key::Char(b'N') {
-        *mode = TerminalMode::NamingDesktop;
-        if let Some(desktop) = vdm.current_mut() {
+    match get_keymap().action(event, Scope::Desktop) {
+        // desktop.rename (Ctrl+N): Name current desktop
+        Some(Action::DesktopRename) => {
+            session.mode = TerminalMode::NamingDesktop;
+            desktop.finish_input();
             desktop.clear_input();
             desktop.screen_mut().puts("\n[Enter new name for desktop]: ");
+            return;
         }
+        // scrollback.up (Shift+PageUp): Browse the lines scrolled off the top
+        Some(Action::ScrollbackUp) => {
+            if session.scrollback.enter(desktop.screen_mut()) {
+                session.mode = TerminalMode::Scrollback;
+            }
+            return;
+        }
+        Some(
+            action @ (Action::PaneSplitVertical
+            | Action::PaneSplitHorizontal
+            | Action::PaneClose
+            | Action::PaneNext
+            | Action::PanePrevious
+            | Action::PaneResizeLeft
+            | Action::PaneResizeRight
+            | Action::PaneResizeUp
+            | Action::PaneResizeDown
+            | Action::PaneFocusLeft
+            | Action::PaneFocusRight
+            | Action::PaneFocusUp
+            | Action::PaneFocusDown
+            | Action::PaneSwapNext
+            | Action::PaneSwapPrevious
+            | Action::PaneZoom),
+        ) => {
+            handle_pane_action(desktop, action);
+            return;
+        }
+        _ => {}
+    }
+    // history.search (Ctrl+R): Find an earlier command as it is typed
+    if get_keymap().action(event, Scope::Line) == Some(Action::HistorySearch) {
+        session.search.enter(desktop);
+        session.mode = TerminalMode::HistorySearch;
         return;
     }
 
     // Handle normal input
     match event.key {
-        Key::Char(c) => {
-            if let Some(desktop) = vdm.current_mut() {
-                desktop.screen_mut().putc(c);
-                desktop.add_input(c);
-            }
-        }
         Key::Enter => {
-            let index = vdm.get_index();
-            let count = vdm.get_count();
-            
-            if let Some(desktop) = vdm.current_mut() {
-                // Copy input to local buffer before processing
-                let mut input_buf = [0u8; 256];
-                let input_slice = desktop.get_input();
-                let input_len = input_slice.len();
-                input_buf[..input_len].copy_from_slice(input_slice);
-                let input = &input_buf[..input_len];
-                
-                desktop.screen_mut().puts("\n");
-                
-                // Simple command handling
-                if input == b"help" {
-                    desktop.screen_mut().puts("Available commands:\n");
-                    desktop.screen_mut().puts("  help    - Show this help\n");
-                    desktop.screen_mut().puts("  clear   - Clear screen\n");
-                    desktop.screen_mut().puts("  info    - Show desktop info\n");
-                    desktop.screen_mut().puts("  ls      - List files\n");
-                    desktop.screen_mut().puts("  touch   - Create file (usage: touch <name>)\n");
-                    desktop.screen_mut().puts("  rm      - Delete file (usage: rm <name>)\n");
-                    desktop.screen_mut().puts("  edit    - Edit file (usage: edit <name>)\n");
-                    desktop.screen_mut().puts("  cat     - Display file (usage: cat <name>)\n");
-                    desktop.screen_mut().puts("  wayland - Wayland compositor (usage: wayland [start|stop|status])\n");
-                } else if input == b"clear" {
-                    desktop.screen_mut().clear();
-                } else if input == b"info" {
-                    let mut name_buf = [0u8; 32];
-                    let name_len = desktop.copy_name_to(&mut name_buf);
-                    let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                    desktop.screen_mut().puts("Desktop: ");
-                    desktop.screen_mut().puts(name);
-                    desktop.screen_mut().puts("\n");
-                    desktop.screen_mut().puts("Index: ");
-                    print_number(desktop.screen_mut(), index + 1);
-                    desktop.screen_mut().puts(" of ");
-                    print_number(desktop.screen_mut(), count);
-                    desktop.screen_mut().puts("\n");
-                } else if input == b"ls" {
-                    handle_ls_command(desktop.screen_mut());
-                } else if input.starts_with(b"touch ") {
-                    let filename = &input[6..];
-                    handle_touch_command(desktop.screen_mut(), filename);
-                } else if input.starts_with(b"rm ") {
-                    let filename = &input[3..];
-                    handle_rm_command(desktop.screen_mut(), filename);
-                } else if input.starts_with(b"edit ") {
-                    let filename = &input[5..];
-                    handle_edit_command(desktop.screen_mut(), filename, mode);
-                    return;
-                } else if input.starts_with(b"cat ") {
-                    let filename = &input[4..];
-                    handle_cat_command(desktop.screen_mut(), filename);
-                } else if input == b"wayland" || input.starts_with(b"wayland ") {
-                    handle_wayland_command(desktop.screen_mut(), input);
-                } else if input.len() > 0 {
-                    desktop.screen_mut().puts("Unknown command: ");
-                    for &b in input {
-                        desktop.screen_mut().putc(b);
-                    }
-                    desktop.screen_mut().puts("\n");
-                }
-                
-                desktop.clear_input();
-                let mut name_buf = [0u8; 32];
-                let name_len = desktop.copy_name_to(&mut name_buf);
-                let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                show_prompt(desktop.screen_mut(), name);
+            if let Some(line) = submit_input(desktop) {
+                get_commands().run(line.as_str(), &mut Context::new(desktop, session, origin));
             }
         }
-        Key::Backspace => {
-            if let Some(desktop) = vdm.current_mut() {
-                desktop.remove_input();
-                // Send backspace sequence: BS + space + BS
-                desktop.screen_mut().puts("\x08 \x08");
-            }
+        // Typing and moving about the line
+        _ => {
+            desktop.edit_input(event, get_keymap());
+        }
+    }
+}
+
+/// Take the input line to run, with `!!` and `!n` expanded, and add it to
+/// the desktop's history. An event not in the history is reported and
+/// nothing runs (None), as in bash.
+fn submit_input(desktop: &mut VirtualDesktop) -> Option<FixedString<MAX_INPUT_LEN>> {
+    desktop.finish_input();
+    // Copy the line so the desktop can be borrowed while it runs
+    let typed = desktop.input();
+    let mut line = FixedString::new();
+    match desktop.history().expand(typed.as_str(), &mut line) {
+        // Show what runs instead of what was typed
+        Ok(true) => {
+            desktop.screen_mut().puts("\n");
+            desktop.screen_mut().puts(line.as_str());
+        }
+        Ok(false) => {}
+        Err(e) => {
+            let _ = writeln!(desktop.screen_mut(), "\n{}", e);
+            desktop.clear_input();
+            show_current_prompt(desktop);
+            return None;
+        }
+    }
+    desktop.history_mut().push(line.as_str());
+    // Completion leaves a space after the last word
+    Some(FixedString::from_str_truncated(line.as_str().trim_end()))
+}
+
+/// The shell's own commands, in the order `help` lists them
+static BUILTINS: [Builtin; 29] = [
+    Builtin { name: "help", usage: "[<command>]", help: "Show this help", completer: Some(complete_command), run: handle_help_command },
+    Builtin { name: "clear", usage: "", help: "Clear screen", completer: None, run: handle_clear_command },
+    Builtin { name: "info", usage: "", help: "Show desktop info", completer: None, run: handle_info_command },
+    Builtin { name: "desktops", usage: "", help: "List desktops", completer: None, run: handle_desktops_command },
+    Builtin {
+        name: "desktop",
+        usage: "<n> | new [<name>] | close [<n>] | move <n>",
+        help: "Manage desktops",
+        completer: Some(complete_desktop),
+        run: handle_desktop_command,
+    },
+    Builtin {
+        name: "session",
+        usage: "save|load [<name>]",
+        help: "Save or load the desktops and panes",
+        completer: Some(complete_session),
+        run: handle_session_command,
+    },
+    Builtin {
+        name: "statusbar",
+        usage: "[on|off]",
+        help: "Show or hide the status bar",
+        completer: Some(complete_statusbar),
+        run: handle_statusbar_command,
+    },
+    Builtin { name: "ls", usage: "", help: "List files", completer: None, run: handle_ls_command },
+    Builtin { name: "touch", usage: "<name>", help: "Create file", completer: None, run: handle_touch_command },
+    Builtin { name: "rm", usage: "<name>", help: "Delete file", completer: Some(complete_file), run: handle_rm_command },
+    Builtin { name: "edit", usage: "<name>", help: "Edit file", completer: Some(complete_file), run: handle_edit_command },
+    Builtin { name: "cat", usage: "<name>", help: "Display file", completer: Some(complete_file), run: handle_cat_command },
+    Builtin {
+        name: "wayland",
+        usage: "[start|stop|status]",
+        help: "Wayland compositor",
+        completer: Some(complete_wayland),
+        run: handle_wayland_command,
+    },
+    Builtin { name: "ifconfig", usage: "", help: "Show network configuration", completer: None, run: handle_ifconfig_command },
+    Builtin { name: "dhcp", usage: "", help: "Request a new address via DHCP", completer: None, run: handle_dhcp_command },
+    Builtin { name: "ping", usage: "<ip>", help: "Send ICMP echo requests", completer: None, run: handle_ping_command },
+    Builtin {
+        name: "nc",
+        usage: "<ip> <port> | -l <port> | -u <ip> <port>",
+        help: "Netcat",
+        completer: None,
+        run: handle_nc_command,
+    },
+    Builtin {
+        name: "telnetd",
+        usage: "[start|stop|status]",
+        help: "Remote shell server",
+        completer: Some(complete_telnetd),
+        run: handle_telnetd_command,
+    },
+    Builtin { name: "exit", usage: "", help: "Close this remote session", completer: None, run: handle_exit_command },
+    Builtin {
+        name: "rz",
+        usage: "[-x <name> | -y | -z]",
+        help: "Receive files over serial",
+        completer: None,
+        run: handle_rz_command,
+    },
+    Builtin {
+        name: "sz",
+        usage: "[-x | -y | -z] <name>...",
+        help: "Send files over serial",
+        completer: Some(complete_files),
+        run: handle_sz_command,
+    },
+    Builtin {
+        name: "hostcat",
+        usage: "<hostpath>",
+        help: "Display a host file, needs -semihosting",
+        completer: None,
+        run: handle_hostcat_command,
+    },
+    Builtin {
+        name: "hostcp",
+        usage: "<hostpath> <name> | -w <name> <hostpath>",
+        help: "Copy host file to VFS, or with -w a VFS file to the host",
+        completer: Some(complete_hostcp),
+        run: handle_hostcp_command,
+    },
+    Builtin {
+        name: "bind",
+        usage: "<keys> <action>",
+        help: "Bind a key to an action",
+        completer: Some(complete_bind),
+        run: handle_bind_command,
+    },
+    Builtin { name: "unbind", usage: "<keys>", help: "Remove a key binding", completer: None, run: handle_unbind_command },
+    Builtin {
+        name: "bindings",
+        usage: "[load <name> | save <name> | reset]",
+        help: "List key bindings",
+        completer: Some(complete_bindings),
+        run: handle_bindings_command,
+    },
+    Builtin {
+        name: "scrollback",
+        usage: "[<lines> | clear]",
+        help: "Show or set the scrollback size",
+        completer: Some(complete_scrollback),
+        run: handle_scrollback_command,
+    },
+    Builtin {
+        name: "history-dump",
+        usage: "<name>",
+        help: "Save the scrollback and screen to a file",
+        completer: Some(complete_file),
+        run: handle_history_dump_command,
+    },
+    Builtin {
+        name: "history",
+        usage: "[-c | -w [<name>] | -r [<name>]]",
+        help: "List, clear, write or read the commands run",
+        completer: Some(complete_history),
+        run: handle_history_command,
+    },
+];
+
+fn register_commands(commands: &mut Registry) {
+    for command in &BUILTINS {
+        // The table fits MAX_COMMANDS
+        let _ = commands.register(command);
+    }
+}
+
+/// `help <command>`: the command names
+fn complete_command(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        for command in get_commands().iter() {
+            out.add(command.name());
+        }
+    }
+}
+
+/// The files in the VFS
+fn add_files(out: &mut Candidates) {
+    let mut file_list = [[0u8; 32]; 32];
+    let count = get_vfs().list_files(&mut file_list);
+    for name in &file_list[..count] {
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        if let Ok(name) = core::str::from_utf8(&name[..len]) {
+            out.add(name);
+        }
+    }
+}
+
+/// A file name as the only argument
+fn complete_file(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        add_files(out);
+    }
+}
+
+/// File names as every argument
+fn complete_files(_: &CompletionContext, out: &mut Candidates) {
+    add_files(out);
+}
+
+fn complete_wayland(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        out.add_all(&["start", "stop", "status"]);
+    }
+}
+
+fn complete_telnetd(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        out.add_all(&["start", "stop", "status"]);
+    }
+}
+
+fn complete_desktop(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        out.add_all(&["new", "close", "move"]);
+    }
+}
+
+fn complete_session(context: &CompletionContext, out: &mut Candidates) {
+    match context.position() {
+        1 => out.add_all(&["save", "load"]),
+        2 => add_files(out),
+        _ => {}
+    }
+}
+
+fn complete_statusbar(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        out.add_all(&["on", "off"]);
+    }
+}
+
+fn complete_scrollback(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 1 {
+        out.add("clear");
+    }
+}
+
+fn complete_bindings(context: &CompletionContext, out: &mut Candidates) {
+    match context.position() {
+        1 => out.add_all(&["load", "save", "reset"]),
+        2 if context.word(1) != Some("reset") => add_files(out),
+        _ => {}
+    }
+}
+
+/// `bind <keys> <action>`: the action names
+fn complete_bind(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 2 {
+        for action in Action::all() {
+            out.add(action.name());
         }
+    }
+}
+
+fn complete_history(context: &CompletionContext, out: &mut Candidates) {
+    match context.position() {
+        1 => out.add_all(&["-c", "-w", "-r"]),
+        2 if context.word(1) != Some("-c") => add_files(out),
         _ => {}
     }
 }
 
+/// `hostcp -w <name> <hostpath>`: the VFS file; host paths are not listed
+fn complete_hostcp(context: &CompletionContext, out: &mut Candidates) {
+    if context.position() == 2 && context.word(1) == Some("-w") {
+        add_files(out);
+    }
+}
+
+/// pane.* actions (Alt+| and Alt+- split, Alt+X closes, Alt+O/Alt+P move
+/// the focus, Alt+Shift+arrows move the borders)
+fn handle_pane_action(desktop: &mut VirtualDesktop, action: Action) {
+    let tiling = desktop.tiling_mut();
+    let result = match action {
+        Action::PaneSplitVertical => tiling.split_vertical(),
+        Action::PaneSplitHorizontal => tiling.split_horizontal(),
+        Action::PaneClose => tiling.close(),
+        Action::PaneNext => {
+            tiling.next_pane();
+            return;
+        }
+        Action::PanePrevious => {
+            tiling.prev_pane();
+            return;
+        }
+        Action::PaneSwapNext | Action::PaneSwapPrevious => {
+            tiling.swap(action == Action::PaneSwapNext);
+            return;
+        }
+        Action::PaneZoom => {
+            tiling.toggle_zoom();
+            return;
+        }
+        Action::PaneFocusLeft | Action::PaneFocusRight | Action::PaneFocusUp | Action::PaneFocusDown => {
+            let direction = match action {
+                Action::PaneFocusLeft => Direction::Left,
+                Action::PaneFocusRight => Direction::Right,
+                Action::PaneFocusUp => Direction::Up,
+                _ => Direction::Down,
+            };
+            tiling.focus_direction(direction);
+            return;
+        }
+        _ => {
+            let direction = match action {
+                Action::PaneResizeLeft => Direction::Left,
+                Action::PaneResizeRight => Direction::Right,
+                Action::PaneResizeUp => Direction::Up,
+                _ => Direction::Down,
+            };
+            tiling.resize(direction);
+            return;
+        }
+    };
+    match result {
+        // A new pane starts with its own prompt
+        Ok(()) if action != Action::PaneClose => show_current_prompt(desktop),
+        Ok(()) => {}
+        Err(e) => {
+            desktop.finish_input();
+            let screen = desktop.screen_mut();
+            screen.puts("\n[");
+            screen.puts(e);
+            screen.puts("]\n");
+            show_current_prompt(desktop);
+            desktop.redraw_input();
+        }
+    }
+}
+
 fn handle_naming_mode(
-    vdm: &mut VirtualDesktopManager,
+    desktop: &mut VirtualDesktop,
+    session: &mut Session,
     event: &KeyEvent,
-    mode: &mut TerminalMode,
 ) {
     match event.key {
-        Key::Char(c) => {
-            if let Some(desktop) = vdm.current_mut() {
-                desktop.screen_mut().putc(c);
-                desktop.add_input(c);
-            }
-        }
         Key::Enter => {
-            if let Some(desktop) = vdm.current_mut() {
-                // Copy input to local buffer before processing
-                let mut input_buf = [0u8; 256];
-                let input_slice = desktop.get_input();
-                let input_len = input_slice.len();
-                input_buf[..input_len].copy_from_slice(input_slice);
-                
-                if input_len > 0 {
-                    let name_str = core::str::from_utf8(&input_buf[..input_len]).unwrap_or("Unnamed");
-                    desktop.set_name(name_str);
-                    let mut name_buf = [0u8; 32];
-                    let name_len = desktop.copy_name_to(&mut name_buf);
-                    let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                    desktop.screen_mut().puts("\n[Desktop renamed to: ");
-                    desktop.screen_mut().puts(name);
-                    desktop.screen_mut().puts("]\n");
-                } else {
-                    desktop.screen_mut().puts("\n[Name unchanged]\n");
-                }
-                desktop.clear_input();
-                let mut name_buf = [0u8; 32];
-                let name_len = desktop.copy_name_to(&mut name_buf);
-                let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                show_prompt(desktop.screen_mut(), name);
-            }
-            *mode = TerminalMode::Normal;
-        }
-        Key::Backspace => {
-            if let Some(desktop) = vdm.current_mut() {
-                desktop.remove_input();
-                // Send backspace sequence: BS + space + BS
-                desktop.screen_mut().puts("\x08 \x08");
+            desktop.finish_input();
+            let line = desktop.input();
+            if !line.is_empty() {
+                desktop.set_name(line.as_str());
+                let name = desktop.name();
+                let name = name.as_str();
+                desktop.screen_mut().puts("\n[Desktop renamed to: ");
+                desktop.screen_mut().puts(name);
+                desktop.screen_mut().puts("]\n");
+            } else {
+                desktop.screen_mut().puts("\n[Name unchanged]\n");
             }
+            desktop.clear_input();
+            show_current_prompt(desktop);
+            session.mode = TerminalMode::Normal;
         }
         Key::Escape => {
-            if let Some(desktop) = vdm.current_mut() {
-                desktop.screen_mut().puts("\n[Cancelled]\n");
-                desktop.clear_input();
-                let mut name_buf = [0u8; 32];
-                let name_len = desktop.copy_name_to(&mut name_buf);
-                let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                show_prompt(desktop.screen_mut(), name);
-            }
-            *mode = TerminalMode::Normal;
+            desktop.finish_input();
+            desktop.screen_mut().puts("\n[Cancelled]\n");
+            desktop.clear_input();
+            show_current_prompt(desktop);
+            session.mode = TerminalMode::Normal;
+        }
+        // The history holds commands, not names
+        _ if matches!(
+            get_keymap().action(event, Scope::Line),
+            Some(Action::HistoryPrevious | Action::HistoryNext | Action::HistorySearch | Action::LineComplete)
+        ) => {}
+        _ => {
+            desktop.edit_input(event, get_keymap());
         }
-        _ => {}
     }
 }
 
@@ -350,9 +1246,76 @@ fn show_prompt(screen: &mut Screen, desktop_name: &str) {
     screen.puts("]$ ");
 }
 
+fn show_current_prompt(desktop: &mut terminal::vdesktop::VirtualDesktop) {
+    let name = desktop.name();
+    let name = name.as_str();
+    show_prompt(desktop.screen_mut(), name);
+}
+
 
 
-fn handle_ls_command(screen: &mut Screen) {
+/// `help` lists every command, `help <command>` tells about one
+fn handle_help_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let commands = get_commands();
+    if args.is_empty() {
+        commands.print_help(context.screen());
+    } else {
+        let command = commands.find(args).ok_or(ArgError::Invalid("no such command"))?;
+        shell::print_command_help(context.screen(), command);
+    }
+    Ok(())
+}
+
+fn handle_clear_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    context.screen().clear();
+    Ok(())
+}
+
+fn handle_info_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    let origin = context.origin;
+    let desktop = context.desktop();
+    let name = desktop.name();
+    let name = name.as_str();
+    desktop.screen_mut().puts("Desktop: ");
+    desktop.screen_mut().puts(name);
+    desktop.screen_mut().puts("\n");
+    match origin {
+        Origin::Console { index, count } => {
+            desktop.screen_mut().puts("Index: ");
+            print_number(desktop.screen_mut(), index + 1);
+            desktop.screen_mut().puts(" of ");
+            print_number(desktop.screen_mut(), count);
+            desktop.screen_mut().puts("\n");
+        }
+        Origin::Remote(slot) => {
+            desktop.screen_mut().puts("Remote session: ");
+            print_number(desktop.screen_mut(), slot + 1);
+            if let Some((ip, port)) = get_telnetd().remote(get_net(), slot) {
+                desktop.screen_mut().puts(" from ");
+                net::print_ipv4(desktop.screen_mut(), ip);
+                desktop.screen_mut().puts(":");
+                print_number(desktop.screen_mut(), port as usize);
+            }
+            desktop.screen_mut().puts("\n");
+        }
+    }
+    let screen = desktop.screen_mut();
+    let (columns, rows) = (screen.columns(), screen.rows());
+    screen.puts("Terminal: ");
+    print_number(screen, columns);
+    screen.puts("x");
+    print_number(screen, rows);
+    screen.puts("\n");
+    Ok(())
+}
+
+fn handle_ifconfig_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    get_net().print_status(context.screen());
+    Ok(())
+}
+
+fn handle_ls_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    let screen = context.screen();
     let vfs = get_vfs();
     let mut file_list = [[0u8; 32]; 32];
     let count = vfs.list_files(&mut file_list);
@@ -371,21 +1334,21 @@ fn handle_ls_command(screen: &mut Screen) {
             screen.puts("\n");
         }
     }
+    Ok(())
 }
 
-fn handle_touch_command(screen: &mut Screen, filename: &[u8]) {
+fn handle_touch_command(filename: &str, context: &mut Context) -> Result<(), ArgError> {
     if filename.is_empty() {
-        screen.puts("Usage: touch <filename>\n");
-        return;
+        return Err(ArgError::Usage);
     }
-    
+    let screen = context.screen();
+
     let vfs = get_vfs();
-    let filename_str = core::str::from_utf8(filename).unwrap_or("");
     
-    match vfs.create_file(filename_str) {
+    match vfs.create_file(filename) {
         Ok(_) => {
             screen.puts("File created: ");
-            screen.puts(filename_str);
+            screen.puts(filename);
             screen.puts("\n");
         }
         Err(e) => {
@@ -394,21 +1357,21 @@ fn handle_touch_command(screen: &mut Screen, filename: &[u8]) {
             screen.puts("\n");
         }
     }
+    Ok(())
 }
 
-fn handle_rm_command(screen: &mut Screen, filename: &[u8]) {
+fn handle_rm_command(filename: &str, context: &mut Context) -> Result<(), ArgError> {
     if filename.is_empty() {
-        screen.puts("Usage: rm <filename>\n");
-        return;
+        return Err(ArgError::Usage);
     }
-    
+    let screen = context.screen();
+
     let vfs = get_vfs();
-    let filename_str = core::str::from_utf8(filename).unwrap_or("");
     
-    match vfs.delete_file(filename_str) {
+    match vfs.delete_file(filename) {
         Ok(_) => {
             screen.puts("File deleted: ");
-            screen.puts(filename_str);
+            screen.puts(filename);
             screen.puts("\n");
         }
         Err(e) => {
@@ -417,18 +1380,18 @@ fn handle_rm_command(screen: &mut Screen, filename: &[u8]) {
             screen.puts("\n");
         }
     }
+    Ok(())
 }
 
-fn handle_cat_command(screen: &mut Screen, filename: &[u8]) {
+fn handle_cat_command(filename: &str, context: &mut Context) -> Result<(), ArgError> {
     if filename.is_empty() {
-        screen.puts("Usage: cat <filename>\n");
-        return;
+        return Err(ArgError::Usage);
     }
-    
+    let screen = context.screen();
+
     let vfs = get_vfs();
-    let filename_str = core::str::from_utf8(filename).unwrap_or("");
     
-    match vfs.find_inode_by_name(filename_str) {
+    match vfs.find_inode_by_name(filename) {
         Some(inode_id) => {
             let mut buf = [0u8; 512];
             match vfs.read_file(inode_id, &mut buf) {
@@ -447,25 +1410,25 @@ fn handle_cat_command(screen: &mut Screen, filename: &[u8]) {
         }
         None => {
             screen.puts("File not found: ");
-            screen.puts(filename_str);
+            screen.puts(filename);
             screen.puts("\n");
         }
     }
+    Ok(())
 }
 
-fn handle_edit_command(screen: &mut Screen, filename: &[u8], mode: &mut TerminalMode) {
+fn handle_edit_command(filename: &str, context: &mut Context) -> Result<(), ArgError> {
     if filename.is_empty() {
-        screen.puts("Usage: edit <filename>\n");
-        return;
+        return Err(ArgError::Usage);
     }
-    
-    let filename_str = core::str::from_utf8(filename).unwrap_or("");
-    let editor = get_editor();
-    editor.set_filename(filename_str);
+
+    let (desktop, session) = context.desktop_and_session();
+    let editor = &mut session.editor;
+    editor.set_filename(filename);
     
     // Try to load existing file
     let vfs = get_vfs();
-    if let Some(inode_id) = vfs.find_inode_by_name(filename_str) {
+    if let Some(inode_id) = vfs.find_inode_by_name(filename) {
         let mut buf = [0u8; 2048];
         match vfs.read_file(inode_id, &mut buf) {
             Ok(size) => {
@@ -478,103 +1441,717 @@ fn handle_edit_command(screen: &mut Screen, filename: &[u8], mode: &mut Terminal
     }
     
     // Render editor
-    editor.render(screen);
-    *mode = TerminalMode::Editor;
+    editor.render(desktop.screen_mut());
+    session.mode = TerminalMode::Editor;
+    context.prompt = false;
+    Ok(())
 }
 
-fn handle_wayland_command(screen: &mut Screen, input: &[u8]) {
+fn handle_wayland_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
     let wayland = get_wayland();
-    
-    if input == b"wayland" || input == b"wayland status" {
-        wayland.status(screen);
-    } else if input == b"wayland start" {
-        wayland.start(screen);
-    } else if input == b"wayland stop" {
-        wayland.stop(screen);
+    let screen = context.screen();
+    match args {
+        "" | "status" => wayland.status(screen),
+        "start" => wayland.start(screen),
+        "stop" => wayland.stop(screen),
+        _ => return Err(ArgError::Usage),
+    }
+    Ok(())
+}
+
+fn handle_dhcp_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    let screen = context.screen();
+    let net = get_net();
+    if !net.is_up() {
+        screen.puts("No network device.\n");
+        return Ok(());
+    }
+    net.renew();
+    screen.puts("Requesting address");
+    let start = timer::uptime_ms();
+    while !net.is_configured() && timer::uptime_ms() - start < 10_000 {
+        net.poll();
+    }
+    if net.is_configured() {
+        screen.puts(": ");
+        net::print_ipv4(screen, net.config.address);
+        screen.puts("\n");
+    } else {
+        screen.puts(": no reply yet, still trying in the background\n");
+    }
+    Ok(())
+}
+
+fn handle_ping_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let target = Ipv4Addr::parse(args.as_bytes()).ok_or(ArgError::Usage)?;
+    let screen = context.screen();
+    let net = get_net();
+    if !net.is_configured() {
+        screen.puts("Network is not configured (see 'ifconfig').\n");
+        return Ok(());
+    }
+
+    const PING_COUNT: u16 = 4;
+    const PING_TIMEOUT_MS: u64 = 1000;
+    screen.puts("PING ");
+    net::print_ipv4(screen, target);
+    screen.puts(": 56 data bytes\n");
+
+    let mut received = 0;
+    for seq in 1..=PING_COUNT {
+        let sent_at = timer::uptime_ms();
+        if let Err(e) = net.send_ping(target, seq, 56) {
+            screen.puts("Error: ");
+            screen.puts(e);
+            screen.puts("\n");
+            return Ok(());
+        }
+        let mut rtt = None;
+        while timer::uptime_ms() - sent_at < PING_TIMEOUT_MS {
+            net.poll();
+            rtt = net.ping_reply_time(seq);
+            if rtt.is_some() {
+                break;
+            }
+        }
+        match rtt {
+            Some(ms) => {
+                received += 1;
+                screen.puts("Reply from ");
+                net::print_ipv4(screen, target);
+                screen.puts(": seq=");
+                print_number(screen, seq as usize);
+                screen.puts(" time=");
+                print_number(screen, ms as usize);
+                screen.puts("ms\n");
+                // Keep a one second cadence between requests
+                while timer::uptime_ms() - sent_at < PING_TIMEOUT_MS {
+                    net.poll();
+                }
+            }
+            None => {
+                screen.puts("Request timed out: seq=");
+                print_number(screen, seq as usize);
+                screen.puts("\n");
+            }
+        }
+    }
+
+    print_number(screen, PING_COUNT as usize);
+    screen.puts(" packets transmitted, ");
+    print_number(screen, received);
+    screen.puts(" received\n");
+    Ok(())
+}
+
+/// Start an interactive nc session, handing the terminal to netcat mode
+fn handle_nc_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let (desktop, session) = context.desktop_and_session();
+    let screen = desktop.screen_mut();
+    let netcat = &mut session.netcat;
+    let mut words = args.as_bytes().split(|&b| b == b' ').filter(|w| !w.is_empty());
+    let first = words.next();
+    let second = words.next();
+    let third = words.next();
+
+    let net = get_net();
+    let parse_port = |w: Option<&[u8]>| w.and_then(parse_number).filter(|&p| p > 0 && p <= 0xffff).map(|p| p as u16);
+
+    let result = match (first, second, third) {
+        (Some(b"-l"), port, None) => match parse_port(port) {
+            Some(port) => netcat.listen_tcp(net, port, screen),
+            None => Err("Invalid port"),
+        },
+        (Some(b"-u"), Some(ip), port) => match (Ipv4Addr::parse(ip), parse_port(port)) {
+            (Some(ip), Some(port)) => netcat.open_udp(net, ip, port, screen),
+            _ => Err("Invalid address or port"),
+        },
+        (Some(ip), port, None) if ip[0] != b'-' => match (Ipv4Addr::parse(ip), parse_port(port)) {
+            (Some(ip), Some(port)) => netcat.connect_tcp(net, ip, port, screen),
+            _ => Err("Invalid address or port"),
+        },
+        _ => return Err(ArgError::Usage),
+    };
+
+    match result {
+        Ok(()) => {
+            session.mode = TerminalMode::Netcat;
+            context.prompt = false;
+        }
+        Err(e) => {
+            screen.puts("nc: ");
+            screen.puts(e);
+            screen.puts("\n");
+        }
+    }
+    Ok(())
+}
+
+fn handle_bind_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    if args.split_whitespace().count() != 2 {
+        return Err(ArgError::Usage);
+    }
+    apply_binding(context.screen(), "bind", args);
+    Ok(())
+}
+
+fn handle_unbind_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    if args.split_whitespace().count() != 1 {
+        return Err(ArgError::Usage);
+    }
+    apply_binding(context.screen(), "unbind", args);
+    Ok(())
+}
+
+/// Run `bind` or `unbind` on the keymap as a config file line
+fn apply_binding(screen: &mut Screen, command: &str, args: &str) {
+    let mut line = FixedString::<MAX_INPUT_LEN>::new();
+    let _ = write!(line, "{} {}", command, args);
+    if let Err(e) = get_keymap().apply(line.as_str()) {
+        let _ = write!(screen, "{}: {}\nActions:", command, e);
+        for action in Action::all() {
+            screen.puts(" ");
+            screen.puts(action.name());
+        }
+        screen.puts("\n");
+    }
+}
+
+fn handle_bindings_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let screen = context.screen();
+    let keymap = get_keymap();
+    let mut words = args.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (None, _, _) => {
+            for binding in keymap.iter() {
+                let mut keys = FixedString::<32>::new();
+                let _ = write!(keys, "{}", binding.pattern);
+                let _ = writeln!(screen, "  {:<16} {}", keys.as_str(), binding.action.name());
+            }
+        }
+        (Some("reset"), None, _) => {
+            keymap.init();
+            screen.puts("Key bindings reset to the defaults\n");
+        }
+        (Some("load"), Some(name), None) => {
+            let vfs = get_vfs();
+            let Some(inode_id) = vfs.find_inode_by_name(name) else {
+                screen.puts("bindings: file not found\n");
+                return Ok(());
+            };
+            let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+            let config = match vfs.read_file(inode_id, &mut buf) {
+                Ok(size) => core::str::from_utf8(&buf[..size]).unwrap_or(""),
+                Err(e) => {
+                    screen.puts("bindings: ");
+                    screen.puts(e);
+                    screen.puts("\n");
+                    return Ok(());
+                }
+            };
+            match keymap.load(config) {
+                Ok(count) => {
+                    let _ = writeln!(screen, "Key bindings loaded from {} ({} changes to the defaults)", name, count);
+                }
+                Err((line, e)) => {
+                    let _ = writeln!(screen, "bindings: {} line {}: {}", name, line, e);
+                }
+            }
+        }
+        (Some("save"), Some(name), None) => {
+            let mut config = FixedString::<{ filesystem::MAX_FILE_SIZE }>::new();
+            if keymap.save(&mut config).is_err() {
+                screen.puts("bindings: too many bindings for one file\n");
+                return Ok(());
+            }
+            match get_vfs().save_file(name, config.as_str().as_bytes()) {
+                Ok(_) => {
+                    let _ = writeln!(screen, "Key bindings saved to {}", name);
+                }
+                Err(e) => {
+                    screen.puts("bindings: ");
+                    screen.puts(e);
+                    screen.puts("\n");
+                }
+            }
+        }
+        _ => return Err(ArgError::Usage),
+    }
+    Ok(())
+}
+
+fn handle_scrollback_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let screen = context.screen();
+    match args {
+        "" => {
+            let scrollback = screen.scrollback();
+            let (len, limit) = (scrollback.len(), scrollback.limit());
+            let _ = writeln!(screen, "Scrollback: {} of {} lines", len, limit);
+        }
+        "clear" => {
+            screen.scrollback_mut().clear();
+            screen.puts("Scrollback cleared\n");
+        }
+        lines => match parse_number(lines.as_bytes()) {
+            Some(lines) => {
+                screen.scrollback_mut().set_limit(lines);
+                let limit = screen.scrollback().limit();
+                let _ = writeln!(screen, "Scrollback set to {} lines", limit);
+            }
+            None => return Err(ArgError::Usage),
+        },
+    }
+    Ok(())
+}
+
+fn handle_history_dump_command(name: &str, context: &mut Context) -> Result<(), ArgError> {
+    if name.is_empty() {
+        return Err(ArgError::Usage);
+    }
+    let screen = context.screen();
+    let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+    let (size, lines) = screen.write_history(&mut buf);
+    let total = screen.history_len();
+    match get_vfs().save_file(name, &buf[..size]) {
+        Ok(_) => {
+            let _ = write!(screen, "Saved {} lines to {}", lines, name);
+            if lines < total {
+                let _ = write!(screen, " (the oldest {} did not fit)", total - lines);
+            }
+            screen.puts("\n");
+        }
+        Err(e) => {
+            screen.puts("history-dump: ");
+            screen.puts(e);
+            screen.puts("\n");
+        }
+    }
+    Ok(())
+}
+
+// VFS file `history -w` and `history -r` use without a name
+const DEFAULT_HISTORY: &str = "history";
+// Host file holding the histories of the console's desktops, in order: read
+// at boot, written when they change
+const HISTORY_HOST_FILE: &str = "jamos.history";
+
+fn handle_history_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let desktop = context.desktop();
+    let mut words = args.split_whitespace();
+    let (option, name) = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => {
+            desktop.print_history();
+            return Ok(());
+        }
+        (Some("-c"), None, _) => {
+            desktop.history_mut().clear();
+            return Ok(());
+        }
+        (Some(option @ ("-w" | "-r")), name, None) => (option, name.unwrap_or(DEFAULT_HISTORY)),
+        _ => return Err(ArgError::Usage),
+    };
+    if option == "-r" {
+        match read_history(desktop, name) {
+            Ok(count) => {
+                let _ = writeln!(desktop.screen_mut(), "Read {} commands from {}", count, name);
+            }
+            Err(e) => {
+                let _ = writeln!(desktop.screen_mut(), "history: {}", e);
+            }
+        }
+        return Ok(());
+    }
+    // The newest commands that fit in one file
+    let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+    let (size, count) = desktop.history().write_to(&mut buf);
+    let total = desktop.history().len();
+    let screen = desktop.screen_mut();
+    match get_vfs().save_file(name, &buf[..size]) {
+        Ok(_) => {
+            let _ = write!(screen, "Saved {} commands to {}", count, name);
+            if count < total {
+                let _ = write!(screen, " (the oldest {} did not fit)", total - count);
+            }
+            screen.puts("\n");
+        }
+        Err(e) => {
+            let _ = writeln!(screen, "history: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Add the commands in VFS file `name` to the desktop's history
+fn read_history(desktop: &mut VirtualDesktop, name: &str) -> Result<usize, &'static str> {
+    let vfs = get_vfs();
+    let inode_id = vfs.find_inode_by_name(name).ok_or("file not found")?;
+    let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+    let size = vfs.read_file(inode_id, &mut buf)?;
+    let history = core::str::from_utf8(&buf[..size]).map_err(|_| "not a text file")?;
+    Ok(desktop.history_mut().load(history))
+}
+
+/// Read the history file from the host (with -semihosting, or the hosted
+/// build) into the desktops' histories, each part into its own desktop
+fn restore_history() -> Option<usize> {
+    let buf = get_histories_buffer();
+    let size = read_host_file(HISTORY_HOST_FILE, buf).ok()?;
+    let histories = core::str::from_utf8(&buf[..size]).ok()?;
+    Some(get_vdm().load_histories(histories))
+}
+
+/// Write every desktop's whole history to the host file, so that they
+/// survive a reboot without going through the VFS and its small files; only
+/// when a history changed or the desktops moved, the file being up to 256
+/// KiB. Without semihosting there is nowhere to write them.
+fn save_history(vdm: &mut VirtualDesktopManager) {
+    if !vdm.take_histories_changed() {
+        return;
+    }
+    let buf = get_histories_buffer();
+    let size = vdm.write_histories(buf);
+    let _ = write_host_file(HISTORY_HOST_FILE, &buf[..size]);
+}
+
+fn handle_telnetd_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let screen = context.screen();
+    let telnetd = get_telnetd();
+    let net = get_net();
+
+    if args.is_empty() || args == "status" {
+        if telnetd.is_running() {
+            screen.puts("Telnet server: listening on port ");
+            print_number(screen, TELNET_PORT as usize);
+            screen.puts("\n");
+        } else {
+            screen.puts("Telnet server: stopped\n");
+        }
+        screen.puts("Sessions: ");
+        print_number(screen, telnetd.session_count());
+        screen.puts("\n");
+        for slot in 0..MAX_TELNET_SESSIONS {
+            if let Some((ip, port)) = telnetd.remote(net, slot) {
+                screen.puts("  Remote ");
+                print_number(screen, slot + 1);
+                screen.puts(": ");
+                net::print_ipv4(screen, ip);
+                screen.puts(":");
+                print_number(screen, port as usize);
+                screen.puts("\n");
+            }
+        }
+    } else if args == "start" {
+        match telnetd.start(net) {
+            Ok(()) => {
+                screen.puts("Telnet server listening on port ");
+                print_number(screen, TELNET_PORT as usize);
+                screen.puts("\n");
+            }
+            Err(e) => {
+                screen.puts("Error: ");
+                screen.puts(e);
+                screen.puts("\n");
+            }
+        }
+    } else if args == "stop" {
+        if telnetd.is_running() {
+            telnetd.stop(net);
+            screen.puts("Telnet server stopped, remote sessions closed\n");
+        } else {
+            screen.puts("Telnet server is not running\n");
+        }
+    } else {
+        return Err(ArgError::Usage);
+    }
+    Ok(())
+}
+
+fn handle_exit_command(_: &str, context: &mut Context) -> Result<(), ArgError> {
+    match context.origin {
+        Origin::Remote(slot) => {
+            let screen = context.screen();
+            screen.puts("Goodbye.\n");
+            screen.set_bracketed_paste(false);
+            get_telnetd().disconnect(slot);
+            context.prompt = false;
+        }
+        Origin::Console { .. } => {
+            context.screen().puts("exit: the serial console cannot be closed\n");
+        }
+    }
+    Ok(())
+}
+
+fn handle_rz_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let origin = context.origin;
+    let screen = context.screen();
+    if let Origin::Remote(_) = origin {
+        screen.puts("rz: file transfers only work on the serial console\n");
+        return Ok(());
+    }
+    let mut words = args.as_bytes().split(|&b| b == b' ').filter(|w| !w.is_empty());
+    let (protocol, name) = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => (Protocol::Auto, &b""[..]),
+        (Some(b"-y"), None, _) => (Protocol::Ymodem, &b""[..]),
+        (Some(b"-z"), None, _) => (Protocol::Zmodem, &b""[..]),
+        (Some(b"-x"), Some(name), None) => (Protocol::Xmodem, name),
+        _ => return Err(ArgError::Usage),
+    };
+
+    screen.puts("rz: waiting for the sender (start sz/sb/sx on the host, Ctrl+X twice to cancel)\n");
+    let mut report = Report::empty();
+    let result = transfer::receive(&Port::new(&CONSOLE, &CONSOLE), protocol, get_vfs(), name, &mut report);
+    screen.puts("\n");
+    report.print(screen);
+    match result {
+        Ok(()) => {
+            screen.puts("rz: ");
+            print_number(screen, report.completed());
+            screen.puts(" file(s) received\n");
+        }
+        Err(e) => {
+            screen.puts("rz: ");
+            screen.puts(e);
+            screen.puts("\n");
+        }
+    }
+    Ok(())
+}
+
+fn handle_sz_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let origin = context.origin;
+    let screen = context.screen();
+    if let Origin::Remote(_) = origin {
+        screen.puts("sz: file transfers only work on the serial console\n");
+        return Ok(());
+    }
+    let mut protocol = Protocol::Auto;
+    let mut names: [&[u8]; 8] = [b""; 8];
+    let mut count = 0;
+    for word in args.as_bytes().split(|&b| b == b' ').filter(|w| !w.is_empty()) {
+        match word {
+            b"-x" => protocol = Protocol::Xmodem,
+            b"-y" => protocol = Protocol::Ymodem,
+            b"-z" => protocol = Protocol::Zmodem,
+            _ if count < names.len() => {
+                names[count] = word;
+                count += 1;
+            }
+            _ => {}
+        }
+    }
+    if count == 0 || (protocol == Protocol::Xmodem && count > 1) {
+        return Err(ArgError::Usage);
+    }
+
+    screen.puts("sz: waiting for the receiver (start rz/rb/rx on the host, Ctrl+X twice to cancel)\n");
+    let mut report = Report::empty();
+    let result = transfer::send(&Port::new(&CONSOLE, &CONSOLE), protocol, get_vfs(), &names[..count], &mut report);
+    screen.puts("\n");
+    report.print(screen);
+    match result {
+        Ok(()) => {
+            screen.puts("sz: ");
+            print_number(screen, report.completed());
+            screen.puts(" file(s) sent\n");
+        }
+        Err(e) => {
+            screen.puts("sz: ");
+            screen.puts(e);
+            screen.puts("\n");
+        }
+    }
+    Ok(())
+}
+
+fn handle_hostcat_command(path: &str, context: &mut Context) -> Result<(), ArgError> {
+    if path.is_empty() {
+        return Err(ArgError::Usage);
+    }
+    let screen = context.screen();
+    let mut file = match HostFile::open(path, OpenMode::Read) {
+        Ok(file) => file,
+        Err(e) => {
+            screen.puts("hostcat: ");
+            screen.puts(e);
+            screen.puts("\n");
+            return Ok(());
+        }
+    };
+    let mut buf = [0u8; 256];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                for &byte in &buf[..n] {
+                    screen.putc(byte);
+                }
+            }
+            Err(e) => {
+                screen.puts("\nhostcat: ");
+                screen.puts(e);
+                break;
+            }
+        }
+    }
+    screen.puts("\n");
+    Ok(())
+}
+
+fn handle_hostcp_command(args: &str, context: &mut Context) -> Result<(), ArgError> {
+    let mut words = args.split(' ').filter(|w| !w.is_empty());
+    let (to_host, source, target) = match (words.next(), words.next(), words.next(), words.next()) {
+        (Some("-w"), Some(name), Some(path), None) => (true, name, path),
+        (Some(path), Some(name), None, _) if path != "-w" => (false, path, name),
+        _ => return Err(ArgError::Usage),
+    };
+    let screen = context.screen();
+    let result = if to_host {
+        copy_to_host(source, target)
     } else {
-        screen.puts("Usage: wayland [start|stop|status]\n");
-        screen.puts("  start  - Start the Wayland compositor\n");
-        screen.puts("  stop   - Stop the Wayland compositor\n");
-        screen.puts("  status - Show compositor status (default)\n");
+        copy_from_host(source, target)
+    };
+    match result {
+        Ok(size) => {
+            screen.puts("hostcp: ");
+            print_number(screen, size);
+            screen.puts(" bytes copied\n");
+        }
+        Err(e) => {
+            screen.puts("hostcp: ");
+            screen.puts(e);
+            screen.puts("\n");
+        }
+    }
+    Ok(())
+}
+
+/// Read a whole host file into `buf`; returns its size
+fn read_host_file(path: &str, buf: &mut [u8]) -> Result<usize, &'static str> {
+    let mut file = HostFile::open(path, OpenMode::Read)?;
+    if file.len()? > buf.len() {
+        return Err("File too large");
+    }
+    let mut size = 0;
+    while size < buf.len() {
+        match file.read(&mut buf[size..])? {
+            0 => break,
+            n => size += n,
+        }
+    }
+    Ok(size)
+}
+
+/// Create or replace a host file with `data`
+fn write_host_file(path: &str, data: &[u8]) -> Result<usize, &'static str> {
+    let mut file = HostFile::open(path, OpenMode::Write)?;
+    let mut written = 0;
+    while written < data.len() {
+        match file.write(&data[written..])? {
+            0 => return Err("Host write failed"),
+            n => written += n,
+        }
+    }
+    Ok(data.len())
+}
+
+fn copy_from_host(path: &str, name: &str) -> Result<usize, &'static str> {
+    let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+    let size = match read_host_file(path, &mut buf) {
+        Err("File too large") => return Err("File too large (VFS files hold 512 bytes)"),
+        result => result?,
+    };
+    get_vfs().save_file(name, &buf[..size])
+}
+
+fn copy_to_host(name: &str, path: &str) -> Result<usize, &'static str> {
+    let vfs = get_vfs();
+    let inode_id = vfs.find_inode_by_name(name).ok_or("File not found")?;
+    let mut buf = [0u8; filesystem::MAX_FILE_SIZE];
+    let size = vfs.read_file(inode_id, &mut buf)?;
+    write_host_file(path, &buf[..size])
+}
+
+fn handle_netcat_mode(
+    desktop: &mut VirtualDesktop,
+    session: &mut Session,
+    event: &KeyEvent,
+) {
+    if !session.netcat.handle_key(event, get_net(), desktop.screen_mut()) {
+        session.mode = TerminalMode::Normal;
+        show_current_prompt(desktop);
     }
 }
 
 fn handle_editor_mode(
-    vdm: &mut VirtualDesktopManager,
+    desktop: &mut VirtualDesktop,
+    session: &mut Session,
     event: &KeyEvent,
-    mode: &mut TerminalMode,
 ) {
-    let editor = get_editor();
+    let editor = &mut session.editor;
+    let action = editor.handle_key(event, get_keymap(), desktop.screen_mut());
     
-    if let Some(desktop) = vdm.current_mut() {
-        let action = editor.handle_key(event, desktop.screen_mut());
+    match action {
+        EditorAction::Save | EditorAction::SaveAndQuit => {
+            let filename = editor.filename();
+            let filename_str = filename.as_str();
         
-        match action {
-            EditorAction::Save | EditorAction::SaveAndQuit => {
-                let mut filename_buf = [0u8; 32];
-                let filename = editor.get_filename();
-                let len = filename.len().min(32);
-                filename_buf[..len].copy_from_slice(filename.as_bytes());
-                let filename_str = core::str::from_utf8(&filename_buf[..len]).unwrap_or("");
-                
-                let content = editor.get_content();
-                let vfs = get_vfs();
+            let content = editor.get_content();
+            let vfs = get_vfs();
                 
-                // Create file if it doesn't exist
-                let inode_id = match vfs.find_inode_by_name(filename_str) {
-                    Some(id) => id,
-                    None => {
-                        match vfs.create_file(filename_str) {
-                            Ok(id) => id,
-                            Err(e) => {
-                                desktop.screen_mut().puts("\nError creating file: ");
-                                desktop.screen_mut().puts(e);
-                                desktop.screen_mut().puts("\n");
-                                return;
-                            }
+            // Create file if it doesn't exist
+            let inode_id = match vfs.find_inode_by_name(filename_str) {
+                Some(id) => id,
+                None => {
+                    match vfs.create_file(filename_str) {
+                        Ok(id) => id,
+                        Err(e) => {
+                            desktop.screen_mut().puts("\nError creating file: ");
+                            desktop.screen_mut().puts(e);
+                            desktop.screen_mut().puts("\n");
+                            return;
                         }
                     }
-                };
-                
-                // Write content
-                match vfs.write_file(inode_id, content) {
-                    Ok(_) => {
-                        editor.mark_saved();
-                        desktop.screen_mut().puts("\nFile saved: ");
-                        desktop.screen_mut().puts(filename_str);
-                        desktop.screen_mut().puts("\n");
-                    }
-                    Err(e) => {
-                        desktop.screen_mut().puts("\nError saving file: ");
-                        desktop.screen_mut().puts(e);
-                        desktop.screen_mut().puts("\n");
-                    }
                 }
+            };
                 
-                if action == EditorAction::SaveAndQuit {
-                    *mode = TerminalMode::Normal;
-                    let mut name_buf = [0u8; 32];
-                    let name_len = desktop.copy_name_to(&mut name_buf);
-                    let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
-                    show_prompt(desktop.screen_mut(), name);
+            // Write content
+            match vfs.write_file(inode_id, content) {
+                Ok(_) => {
+                    editor.mark_saved();
+                    desktop.screen_mut().puts("\nFile saved: ");
+                    desktop.screen_mut().puts(filename_str);
+                    desktop.screen_mut().puts("\n");
+                }
+                Err(e) => {
+                    desktop.screen_mut().puts("\nError saving file: ");
+                    desktop.screen_mut().puts(e);
+                    desktop.screen_mut().puts("\n");
                 }
             }
-            EditorAction::Quit => {
-                *mode = TerminalMode::Normal;
-                desktop.screen_mut().puts("\nEditor closed.\n");
-                let mut name_buf = [0u8; 32];
-                let name_len = desktop.copy_name_to(&mut name_buf);
-                let name = core::str::from_utf8(&name_buf[..name_len]).unwrap_or("???");
+            
+            if action == EditorAction::SaveAndQuit {
+                session.mode = TerminalMode::Normal;
+                let name = desktop.name();
+                let name = name.as_str();
                 show_prompt(desktop.screen_mut(), name);
             }
-            EditorAction::Continue => {
-                // Continue editing
-            }
+        }
+        EditorAction::Quit => {
+            session.mode = TerminalMode::Normal;
+            desktop.screen_mut().puts("\nEditor closed.\n");
+            let name = desktop.name();
+            let name = name.as_str();
+            show_prompt(desktop.screen_mut(), name);
+        }
+        EditorAction::Continue => {
+            // Continue editing
         }
     }
 }
 
 
+#[cfg(not(any(test, feature = "hosted")))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     let uart = Uart::new();
@@ -599,6 +2176,20 @@ fn panic(info: &PanicInfo) -> ! {
         uart.puts("\n");
     }
     uart.puts("System halted.\n");
+    // Under QEMU -semihosting, stop the emulator with a failure status
+    semihosting::write0("Jamos: kernel panic\n");
+    semihosting::exit(1);
+    loop {
+        unsafe {
+            core::arch::asm!("wfe");
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "hosted")))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panic(info);
     loop {
         unsafe {
             core::arch::asm!("wfe");
@@ -0,0 +1,126 @@
+// Address Resolution Protocol: cache, requests and replies
+
+use super::ethernet::{self, ETHERTYPE_ARP, ETHERNET_HEADER_LEN};
+use super::{Ipv4Addr, MacAddr, NetInterface};
+use crate::drivers::timer;
+
+const ARP_CACHE_SIZE: usize = 16;
+const ARP_PACKET_LEN: usize = 28;
+const ARP_ENTRY_LIFETIME_MS: u64 = 300_000;
+// ARP frames are padded up to the Ethernet minimum frame size
+const ARP_FRAME_LEN: usize = 60;
+
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+
+#[derive(Clone, Copy)]
+struct ArpEntry {
+    ip: Ipv4Addr,
+    mac: MacAddr,
+    updated_at: u64,
+    is_valid: bool,
+}
+
+impl ArpEntry {
+    const fn empty() -> Self {
+        ArpEntry {
+            ip: Ipv4Addr::UNSPECIFIED,
+            mac: MacAddr::ZERO,
+            updated_at: 0,
+            is_valid: false,
+        }
+    }
+}
+
+pub struct ArpCache {
+    entries: [ArpEntry; ARP_CACHE_SIZE],
+}
+
+impl ArpCache {
+    pub const fn empty() -> Self {
+        ArpCache {
+            entries: [ArpEntry::empty(); ARP_CACHE_SIZE],
+        }
+    }
+
+    pub fn lookup(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+        let now = timer::uptime_ms();
+        self.entries.iter()
+            .find(|e| e.is_valid && e.ip == ip && now.wrapping_sub(e.updated_at) < ARP_ENTRY_LIFETIME_MS)
+            .map(|e| e.mac)
+    }
+
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr) {
+        let now = timer::uptime_ms();
+        // Reuse the existing entry, else a free slot, else evict the oldest
+        let index = self.entries.iter().position(|e| e.is_valid && e.ip == ip)
+            .or_else(|| self.entries.iter().position(|e| !e.is_valid))
+            .unwrap_or_else(|| {
+                let mut oldest = 0;
+                for i in 1..ARP_CACHE_SIZE {
+                    if self.entries[i].updated_at < self.entries[oldest].updated_at {
+                        oldest = i;
+                    }
+                }
+                oldest
+            });
+        self.entries[index] = ArpEntry {
+            ip,
+            mac,
+            updated_at: now,
+            is_valid: true,
+        };
+    }
+}
+
+impl NetInterface {
+    pub(super) fn arp_input(&mut self, packet: &[u8]) {
+        if packet.len() < ARP_PACKET_LEN {
+            return;
+        }
+        // Only Ethernet/IPv4 mappings
+        let hardware_type = u16::from_be_bytes([packet[0], packet[1]]);
+        let protocol_type = u16::from_be_bytes([packet[2], packet[3]]);
+        if hardware_type != 1 || protocol_type != 0x0800 || packet[4] != 6 || packet[5] != 4 {
+            return;
+        }
+
+        let op = u16::from_be_bytes([packet[6], packet[7]]);
+        let sender_mac = MacAddr::from_bytes(&packet[8..14]);
+        let sender_ip = Ipv4Addr::from_bytes(&packet[14..18]);
+        let target_ip = Ipv4Addr::from_bytes(&packet[24..28]);
+
+        if !sender_ip.is_unspecified() {
+            self.arp.insert(sender_ip, sender_mac);
+            self.flush_pending(sender_ip, sender_mac);
+        }
+
+        if op == ARP_OP_REQUEST && self.is_configured() && target_ip == self.config.address {
+            self.send_arp(ARP_OP_REPLY, sender_mac, sender_ip);
+        }
+    }
+
+    pub(super) fn arp_request(&mut self, ip: Ipv4Addr) -> Result<(), &'static str> {
+        self.send_arp(ARP_OP_REQUEST, MacAddr::ZERO, ip);
+        Ok(())
+    }
+
+    fn send_arp(&mut self, op: u16, target_mac: MacAddr, target_ip: Ipv4Addr) {
+        let mut frame = [0u8; ARP_FRAME_LEN];
+        let dst = if op == ARP_OP_REQUEST { MacAddr::BROADCAST } else { target_mac };
+        ethernet::write_header(&mut frame, dst, self.mac, ETHERTYPE_ARP);
+
+        let arp = &mut frame[ETHERNET_HEADER_LEN..];
+        arp[0..2].copy_from_slice(&1u16.to_be_bytes());
+        arp[2..4].copy_from_slice(&0x0800u16.to_be_bytes());
+        arp[4] = 6;
+        arp[5] = 4;
+        arp[6..8].copy_from_slice(&op.to_be_bytes());
+        arp[8..14].copy_from_slice(&self.mac.0);
+        arp[14..18].copy_from_slice(&self.config.address.0);
+        arp[18..24].copy_from_slice(&target_mac.0);
+        arp[24..28].copy_from_slice(&target_ip.0);
+
+        let _ = self.send_frame(&frame);
+    }
+}
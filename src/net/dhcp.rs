@@ -0,0 +1,378 @@
+// DHCP client (RFC 2131): DISCOVER -> OFFER -> REQUEST -> ACK, with lease renewal
+
+use super::{IpConfig, Ipv4Addr, MacAddr, NetInterface};
+
+pub const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_SERVER_PORT: u16 = 67;
+
+const BOOTP_HEADER_LEN: usize = 236;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_PACKET_LEN: usize = 300;
+
+const MSG_DISCOVER: u8 = 1;
+const MSG_OFFER: u8 = 2;
+const MSG_REQUEST: u8 = 3;
+const MSG_ACK: u8 = 5;
+const MSG_NAK: u8 = 6;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+
+const RETRY_INTERVAL_MS: u64 = 4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DhcpState {
+    Idle,
+    Selecting,
+    Requesting,
+    Bound,
+    // Past T1: asking the server that granted the lease to extend it
+    Renewing,
+}
+
+pub struct DhcpClient {
+    state: DhcpState,
+    xid: u32,
+    offered: IpConfig,
+    server: Ipv4Addr,
+    lease_secs: u32,
+    bound_at: u64,
+    last_sent: u64,
+}
+
+// Fields of an incoming DHCP message that the client cares about
+struct DhcpMessage {
+    xid: u32,
+    message_type: u8,
+    your_ip: Ipv4Addr,
+    config: IpConfig,
+    server: Ipv4Addr,
+    lease_secs: u32,
+}
+
+impl DhcpClient {
+    pub const fn empty() -> Self {
+        DhcpClient {
+            state: DhcpState::Idle,
+            xid: 0,
+            offered: IpConfig::empty(),
+            server: Ipv4Addr::UNSPECIFIED,
+            lease_secs: 0,
+            bound_at: 0,
+            last_sent: 0,
+        }
+    }
+
+    /// Begin a fresh exchange; the DISCOVER goes out on the next poll
+    pub fn start(&mut self, now: u64) {
+        self.state = DhcpState::Selecting;
+        self.xid = 0x4a41_0000 ^ (now as u32);
+        self.offered = IpConfig::empty();
+        // Back-date the last transmission so the first poll sends immediately
+        self.last_sent = now.wrapping_sub(RETRY_INTERVAL_MS);
+    }
+
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            DhcpState::Idle => "idle",
+            DhcpState::Selecting => "discovering",
+            DhcpState::Requesting => "requesting",
+            DhcpState::Bound => "bound",
+            DhcpState::Renewing => "renewing",
+        }
+    }
+
+    /// Fill in a BOOTREQUEST of `message_type` from `mac`. A REQUEST names
+    /// the offered address and the server chosen in options; while renewing
+    /// the leased address `client_ip` goes in ciaddr instead and the server is
+    /// not named (RFC 2131 4.3.2).
+    fn write_message(&self, message_type: u8, mac: MacAddr, client_ip: Ipv4Addr, packet: &mut [u8; DHCP_PACKET_LEN]) {
+        let renewing = self.state == DhcpState::Renewing;
+        packet[0] = 1; // BOOTREQUEST
+        packet[1] = 1; // Ethernet
+        packet[2] = 6; // hardware address length
+        packet[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        if renewing {
+            // The server answers the leased address directly
+            packet[12..16].copy_from_slice(&client_ip.0);
+        } else {
+            packet[10..12].copy_from_slice(&0x8000u16.to_be_bytes()); // ask for broadcast replies
+        }
+        packet[28..34].copy_from_slice(&mac.0);
+        packet[BOOTP_HEADER_LEN..BOOTP_HEADER_LEN + 4].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+        let mut i = BOOTP_HEADER_LEN + 4;
+        packet[i] = OPT_MESSAGE_TYPE;
+        packet[i + 1] = 1;
+        packet[i + 2] = message_type;
+        i += 3;
+
+        if message_type == MSG_REQUEST && !renewing {
+            packet[i] = OPT_REQUESTED_IP;
+            packet[i + 1] = 4;
+            packet[i + 2..i + 6].copy_from_slice(&self.offered.address.0);
+            i += 6;
+            if !self.server.is_unspecified() {
+                packet[i] = OPT_SERVER_ID;
+                packet[i + 1] = 4;
+                packet[i + 2..i + 6].copy_from_slice(&self.server.0);
+                i += 6;
+            }
+        }
+
+        packet[i] = OPT_PARAMETER_LIST;
+        packet[i + 1] = 3;
+        packet[i + 2] = OPT_SUBNET_MASK;
+        packet[i + 3] = OPT_ROUTER;
+        packet[i + 4] = OPT_DNS;
+        i += 5;
+        packet[i] = OPT_END;
+    }
+}
+
+fn parse_message(data: &[u8]) -> Option<DhcpMessage> {
+    if data.len() < BOOTP_HEADER_LEN + 4 || data[0] != 2 {
+        return None;
+    }
+    if data[BOOTP_HEADER_LEN..BOOTP_HEADER_LEN + 4] != DHCP_MAGIC_COOKIE {
+        return None;
+    }
+
+    let mut message = DhcpMessage {
+        xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        message_type: 0,
+        your_ip: Ipv4Addr::from_bytes(&data[16..20]),
+        config: IpConfig::empty(),
+        server: Ipv4Addr::UNSPECIFIED,
+        lease_secs: 0,
+    };
+
+    let mut i = BOOTP_HEADER_LEN + 4;
+    while i < data.len() {
+        let option = data[i];
+        if option == OPT_END {
+            break;
+        }
+        if option == OPT_PAD {
+            i += 1;
+            continue;
+        }
+        if i + 1 >= data.len() {
+            break;
+        }
+        let len = data[i + 1] as usize;
+        let value_start = i + 2;
+        if value_start + len > data.len() {
+            break;
+        }
+        let value = &data[value_start..value_start + len];
+        match option {
+            OPT_MESSAGE_TYPE if len >= 1 => message.message_type = value[0],
+            OPT_SUBNET_MASK if len >= 4 => message.config.netmask = Ipv4Addr::from_bytes(value),
+            OPT_ROUTER if len >= 4 => message.config.gateway = Ipv4Addr::from_bytes(value),
+            OPT_DNS if len >= 4 => message.config.dns = Ipv4Addr::from_bytes(value),
+            OPT_SERVER_ID if len >= 4 => message.server = Ipv4Addr::from_bytes(value),
+            OPT_LEASE_TIME if len >= 4 => {
+                message.lease_secs = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+            }
+            _ => {}
+        }
+        i = value_start + len;
+    }
+    message.config.address = message.your_ip;
+
+    Some(message)
+}
+
+impl NetInterface {
+    pub(super) fn dhcp_poll(&mut self, now: u64) {
+        match self.dhcp.state {
+            DhcpState::Idle => {}
+            DhcpState::Selecting | DhcpState::Requesting | DhcpState::Renewing => {
+                let lease_ms = (self.dhcp.lease_secs as u64) * 1000;
+                if self.dhcp.state == DhcpState::Renewing && now.wrapping_sub(self.dhcp.bound_at) >= lease_ms {
+                    // The lease ran out without an answer: the address is no longer ours
+                    self.config = IpConfig::empty();
+                    self.dhcp.start(now);
+                } else if now.wrapping_sub(self.dhcp.last_sent) >= RETRY_INTERVAL_MS {
+                    self.dhcp.last_sent = now;
+                    let message_type = if self.dhcp.state == DhcpState::Selecting { MSG_DISCOVER } else { MSG_REQUEST };
+                    self.send_dhcp(message_type);
+                }
+            }
+            DhcpState::Bound => {
+                // Renew at half the lease (T1) with the server that granted it
+                let renew_after = (self.dhcp.lease_secs as u64) * 500;
+                if self.dhcp.lease_secs != u32::MAX && now.wrapping_sub(self.dhcp.bound_at) >= renew_after {
+                    self.dhcp.state = DhcpState::Renewing;
+                    self.dhcp.last_sent = now;
+                    self.send_dhcp(MSG_REQUEST);
+                }
+            }
+        }
+    }
+
+    pub(super) fn dhcp_input(&mut self, data: &[u8]) {
+        let message = match parse_message(data) {
+            Some(m) => m,
+            None => return,
+        };
+        if message.xid != self.dhcp.xid {
+            return;
+        }
+
+        match (self.dhcp.state, message.message_type) {
+            (DhcpState::Selecting, MSG_OFFER) => {
+                self.dhcp.offered = message.config;
+                self.dhcp.server = message.server;
+                self.dhcp.state = DhcpState::Requesting;
+                self.dhcp.last_sent = crate::drivers::timer::uptime_ms();
+                self.send_dhcp(MSG_REQUEST);
+            }
+            (DhcpState::Requesting | DhcpState::Renewing, MSG_ACK) => {
+                let mut config = message.config;
+                if config.netmask.is_unspecified() {
+                    config.netmask = self.dhcp.offered.netmask;
+                }
+                if config.gateway.is_unspecified() {
+                    config.gateway = self.dhcp.offered.gateway;
+                }
+                if config.dns.is_unspecified() {
+                    config.dns = self.dhcp.offered.dns;
+                }
+                self.config = config;
+                self.dhcp.offered = config;
+                if !message.server.is_unspecified() {
+                    self.dhcp.server = message.server;
+                }
+                self.dhcp.lease_secs = if message.lease_secs == 0 { 3600 } else { message.lease_secs };
+                self.dhcp.bound_at = crate::drivers::timer::uptime_ms();
+                self.dhcp.state = DhcpState::Bound;
+            }
+            (DhcpState::Requesting | DhcpState::Renewing, MSG_NAK) => {
+                self.config = IpConfig::empty();
+                self.dhcp.start(crate::drivers::timer::uptime_ms());
+            }
+            _ => {}
+        }
+    }
+
+    fn send_dhcp(&mut self, message_type: u8) {
+        let mut packet = [0u8; DHCP_PACKET_LEN];
+        self.dhcp.write_message(message_type, self.mac, self.config.address, &mut packet);
+
+        if self.dhcp.state == DhcpState::Renewing && !self.dhcp.server.is_unspecified() {
+            // RENEWING: unicast from the leased address
+            let _ = self.send_udp(DHCP_CLIENT_PORT, self.dhcp.server, DHCP_SERVER_PORT, &packet);
+            return;
+        }
+        // Everything else is broadcast from 0.0.0.0
+        let saved = self.config.address;
+        self.config.address = Ipv4Addr::UNSPECIFIED;
+        let _ = self.send_udp(DHCP_CLIENT_PORT, Ipv4Addr::BROADCAST, DHCP_SERVER_PORT, &packet);
+        self.config.address = saved;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    const LEASED: Ipv4Addr = Ipv4Addr([10, 0, 2, 15]);
+    const SERVER: Ipv4Addr = Ipv4Addr([10, 0, 2, 2]);
+
+    fn client(state: DhcpState) -> DhcpClient {
+        let mut client = DhcpClient::empty();
+        client.start(0);
+        client.state = state;
+        client.offered.address = LEASED;
+        client.server = SERVER;
+        client
+    }
+
+    fn option(packet: &[u8], code: u8) -> Option<&[u8]> {
+        let mut i = BOOTP_HEADER_LEN + 4;
+        while i + 1 < packet.len() && packet[i] != OPT_END {
+            let len = packet[i + 1] as usize;
+            if packet[i] == code {
+                return Some(&packet[i + 2..i + 2 + len]);
+            }
+            i += 2 + len;
+        }
+        None
+    }
+
+    #[test_case]
+    fn request_names_offer_and_server() {
+        let mut packet = [0u8; DHCP_PACKET_LEN];
+        client(DhcpState::Requesting).write_message(MSG_REQUEST, MAC, Ipv4Addr::UNSPECIFIED, &mut packet);
+        assert_eq!(packet[0], 1);
+        assert_eq!(&packet[10..12], &[0x80, 0]);
+        assert_eq!(&packet[12..16], &[0; 4]);
+        assert_eq!(&packet[28..34], &MAC.0);
+        assert_eq!(option(&packet, OPT_MESSAGE_TYPE), Some(&[MSG_REQUEST][..]));
+        assert_eq!(option(&packet, OPT_REQUESTED_IP), Some(&LEASED.0[..]));
+        assert_eq!(option(&packet, OPT_SERVER_ID), Some(&SERVER.0[..]));
+    }
+
+    #[test_case]
+    fn discover_names_no_address() {
+        let mut packet = [0u8; DHCP_PACKET_LEN];
+        client(DhcpState::Selecting).write_message(MSG_DISCOVER, MAC, Ipv4Addr::UNSPECIFIED, &mut packet);
+        assert_eq!(option(&packet, OPT_MESSAGE_TYPE), Some(&[MSG_DISCOVER][..]));
+        assert_eq!(option(&packet, OPT_REQUESTED_IP), None);
+        assert_eq!(option(&packet, OPT_SERVER_ID), None);
+    }
+
+    #[test_case]
+    fn renewal_sets_ciaddr_without_server_options() {
+        let mut packet = [0u8; DHCP_PACKET_LEN];
+        client(DhcpState::Renewing).write_message(MSG_REQUEST, MAC, LEASED, &mut packet);
+        assert_eq!(&packet[12..16], &LEASED.0);
+        assert_eq!(&packet[10..12], &[0, 0]);
+        assert_eq!(option(&packet, OPT_MESSAGE_TYPE), Some(&[MSG_REQUEST][..]));
+        assert_eq!(option(&packet, OPT_REQUESTED_IP), None);
+        assert_eq!(option(&packet, OPT_SERVER_ID), None);
+    }
+
+    #[test_case]
+    fn parses_an_ack() {
+        let mut reply = [0u8; DHCP_PACKET_LEN];
+        reply[0] = 2; // BOOTREPLY
+        reply[4..8].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        reply[16..20].copy_from_slice(&LEASED.0);
+        reply[BOOTP_HEADER_LEN..BOOTP_HEADER_LEN + 4].copy_from_slice(&DHCP_MAGIC_COOKIE);
+        let options = [
+            OPT_MESSAGE_TYPE, 1, MSG_ACK,
+            OPT_PAD,
+            OPT_SUBNET_MASK, 4, 255, 255, 255, 0,
+            OPT_ROUTER, 4, 10, 0, 2, 2,
+            OPT_SERVER_ID, 4, 10, 0, 2, 2,
+            OPT_LEASE_TIME, 4, 0, 0, 0x0e, 0x10,
+            OPT_END,
+        ];
+        reply[BOOTP_HEADER_LEN + 4..BOOTP_HEADER_LEN + 4 + options.len()].copy_from_slice(&options);
+
+        let message = parse_message(&reply).unwrap();
+        assert_eq!(message.xid, 0x1234_5678);
+        assert_eq!(message.message_type, MSG_ACK);
+        assert_eq!(message.config.address, LEASED);
+        assert_eq!(message.config.netmask, Ipv4Addr([255, 255, 255, 0]));
+        assert_eq!(message.config.gateway, SERVER);
+        assert_eq!(message.server, SERVER);
+        assert_eq!(message.lease_secs, 3600);
+
+        reply[BOOTP_HEADER_LEN] = 0;
+        assert!(parse_message(&reply).is_none());
+        assert!(parse_message(&reply[..BOOTP_HEADER_LEN]).is_none());
+    }
+}
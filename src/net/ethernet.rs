@@ -0,0 +1,28 @@
+// Ethernet II framing
+
+use super::MacAddr;
+
+pub const ETHERNET_HEADER_LEN: usize = 14;
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub struct EthernetHeader {
+    pub dst: MacAddr,
+    pub ethertype: u16,
+}
+
+pub fn parse(frame: &[u8]) -> Option<EthernetHeader> {
+    if frame.len() < ETHERNET_HEADER_LEN {
+        return None;
+    }
+    Some(EthernetHeader {
+        dst: MacAddr::from_bytes(&frame[0..6]),
+        ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+    })
+}
+
+pub fn write_header(frame: &mut [u8], dst: MacAddr, src: MacAddr, ethertype: u16) {
+    frame[0..6].copy_from_slice(&dst.0);
+    frame[6..12].copy_from_slice(&src.0);
+    frame[12..14].copy_from_slice(&ethertype.to_be_bytes());
+}
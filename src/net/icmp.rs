@@ -0,0 +1,134 @@
+// ICMP: answers echo requests and tracks replies to our own pings
+
+use super::ipv4::{Ipv4Header, PROTOCOL_ICMP};
+use super::{checksum, Ipv4Addr, NetInterface};
+use crate::drivers::timer;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_HEADER_LEN: usize = 8;
+const MAX_ICMP_PAYLOAD: usize = 1472;
+
+/// Identifier placed in every echo request sent by the `ping` command
+pub const PING_IDENTIFIER: u16 = 0x4a4d; // "JM"
+
+const MAX_OUTSTANDING_PINGS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct PingRecord {
+    target: Ipv4Addr,
+    sequence: u16,
+    sent_at: u64,
+    rtt_ms: Option<u64>,
+    is_valid: bool,
+}
+
+impl PingRecord {
+    const fn empty() -> Self {
+        PingRecord {
+            target: Ipv4Addr::UNSPECIFIED,
+            sequence: 0,
+            sent_at: 0,
+            rtt_ms: None,
+            is_valid: false,
+        }
+    }
+}
+
+/// Outstanding echo requests keyed by sequence number
+pub struct PingTracker {
+    records: [PingRecord; MAX_OUTSTANDING_PINGS],
+}
+
+impl PingTracker {
+    pub const fn empty() -> Self {
+        PingTracker {
+            records: [PingRecord::empty(); MAX_OUTSTANDING_PINGS],
+        }
+    }
+
+    fn record_sent(&mut self, target: Ipv4Addr, sequence: u16, now: u64) {
+        let index = (sequence as usize) % MAX_OUTSTANDING_PINGS;
+        self.records[index] = PingRecord {
+            target,
+            sequence,
+            sent_at: now,
+            rtt_ms: None,
+            is_valid: true,
+        };
+    }
+
+    fn record_reply(&mut self, from: Ipv4Addr, sequence: u16, now: u64) {
+        let index = (sequence as usize) % MAX_OUTSTANDING_PINGS;
+        let record = &mut self.records[index];
+        if record.is_valid && record.sequence == sequence && record.target == from && record.rtt_ms.is_none() {
+            record.rtt_ms = Some(now.wrapping_sub(record.sent_at));
+        }
+    }
+
+    fn reply_time(&self, sequence: u16) -> Option<u64> {
+        let record = &self.records[(sequence as usize) % MAX_OUTSTANDING_PINGS];
+        if record.is_valid && record.sequence == sequence {
+            record.rtt_ms
+        } else {
+            None
+        }
+    }
+}
+
+impl NetInterface {
+    /// Round-trip time of the reply to ping `sequence`, once it has arrived
+    pub fn ping_reply_time(&self, sequence: u16) -> Option<u64> {
+        self.pings.reply_time(sequence)
+    }
+
+    pub(super) fn icmp_input(&mut self, header: &Ipv4Header, payload: &[u8]) {
+        if payload.len() < ICMP_HEADER_LEN || payload.len() > ICMP_HEADER_LEN + MAX_ICMP_PAYLOAD {
+            return;
+        }
+        if checksum(payload, 0) != 0 {
+            return;
+        }
+
+        match payload[0] {
+            ICMP_ECHO_REQUEST => {
+                // Echo the request back with the type changed
+                let mut reply = [0u8; ICMP_HEADER_LEN + MAX_ICMP_PAYLOAD];
+                let len = payload.len();
+                reply[..len].copy_from_slice(payload);
+                reply[0] = ICMP_ECHO_REPLY;
+                reply[2] = 0;
+                reply[3] = 0;
+                let sum = checksum(&reply[..len], 0);
+                reply[2..4].copy_from_slice(&sum.to_be_bytes());
+                let _ = self.send_ipv4(header.src, PROTOCOL_ICMP, &reply[..len]);
+            }
+            ICMP_ECHO_REPLY => {
+                let identifier = u16::from_be_bytes([payload[4], payload[5]]);
+                let sequence = u16::from_be_bytes([payload[6], payload[7]]);
+                if identifier == PING_IDENTIFIER {
+                    self.pings.record_reply(header.src, sequence, timer::uptime_ms());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Send an echo request carrying `data_len` bytes of pattern data
+    pub fn send_ping(&mut self, target: Ipv4Addr, sequence: u16, data_len: usize) -> Result<(), &'static str> {
+        let data_len = data_len.min(MAX_ICMP_PAYLOAD);
+        let mut packet = [0u8; ICMP_HEADER_LEN + MAX_ICMP_PAYLOAD];
+        let len = ICMP_HEADER_LEN + data_len;
+        packet[0] = ICMP_ECHO_REQUEST;
+        packet[4..6].copy_from_slice(&PING_IDENTIFIER.to_be_bytes());
+        packet[6..8].copy_from_slice(&sequence.to_be_bytes());
+        for i in 0..data_len {
+            packet[ICMP_HEADER_LEN + i] = (i & 0xff) as u8;
+        }
+        let sum = checksum(&packet[..len], 0);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+
+        self.pings.record_sent(target, sequence, timer::uptime_ms());
+        self.send_ipv4(target, PROTOCOL_ICMP, &packet[..len])
+    }
+}
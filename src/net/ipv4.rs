@@ -0,0 +1,117 @@
+// IPv4 header parsing and construction (no fragmentation or options)
+
+use super::{checksum, Ipv4Addr};
+
+pub const IPV4_HEADER_LEN: usize = 20;
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+const DEFAULT_TTL: u8 = 64;
+
+pub struct Ipv4Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub header_len: usize,
+    pub total_len: usize,
+}
+
+pub fn parse(packet: &[u8]) -> Option<Ipv4Header> {
+    if packet.len() < IPV4_HEADER_LEN || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = ((packet[0] & 0x0f) as usize) * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < IPV4_HEADER_LEN || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+    if checksum(&packet[..header_len], 0) != 0 {
+        return None;
+    }
+
+    // Fragments are not reassembled; drop anything with MF set or a non-zero offset
+    let flags_fragment = u16::from_be_bytes([packet[6], packet[7]]);
+    if flags_fragment & 0x3fff != 0 {
+        return None;
+    }
+
+    Some(Ipv4Header {
+        src: Ipv4Addr::from_bytes(&packet[12..16]),
+        dst: Ipv4Addr::from_bytes(&packet[16..20]),
+        protocol: packet[9],
+        header_len,
+        total_len,
+    })
+}
+
+pub fn write_header(header: &mut [u8], src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload_len: usize, id: u16) {
+    let total_len = (IPV4_HEADER_LEN + payload_len) as u16;
+    header[0] = 0x45; // version 4, 5 words
+    header[1] = 0;
+    header[2..4].copy_from_slice(&total_len.to_be_bytes());
+    header[4..6].copy_from_slice(&id.to_be_bytes());
+    header[6..8].copy_from_slice(&0x4000u16.to_be_bytes()); // don't fragment
+    header[8] = DEFAULT_TTL;
+    header[9] = protocol;
+    header[10] = 0;
+    header[11] = 0;
+    header[12..16].copy_from_slice(&src.0);
+    header[16..20].copy_from_slice(&dst.0);
+    let sum = checksum(&header[..IPV4_HEADER_LEN], 0);
+    header[10..12].copy_from_slice(&sum.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: Ipv4Addr = Ipv4Addr([10, 0, 2, 15]);
+    const DST: Ipv4Addr = Ipv4Addr([10, 0, 2, 2]);
+
+    fn packet(payload: &[u8]) -> [u8; 64] {
+        let mut packet = [0u8; 64];
+        write_header(&mut packet[..IPV4_HEADER_LEN], SRC, DST, PROTOCOL_UDP, payload.len(), 7);
+        packet[IPV4_HEADER_LEN..IPV4_HEADER_LEN + payload.len()].copy_from_slice(payload);
+        packet
+    }
+
+    #[test_case]
+    fn written_header_parses_back() {
+        let packet = packet(b"hello");
+        let header = parse(&packet).unwrap();
+        assert_eq!(header.src, SRC);
+        assert_eq!(header.dst, DST);
+        assert_eq!(header.protocol, PROTOCOL_UDP);
+        assert_eq!(header.header_len, IPV4_HEADER_LEN);
+        assert_eq!(header.total_len, IPV4_HEADER_LEN + 5);
+        assert_eq!(&packet[4..6], &7u16.to_be_bytes());
+    }
+
+    #[test_case]
+    fn bad_headers_are_dropped() {
+        let mut corrupt = packet(b"hello");
+        corrupt[8] ^= 1;
+        assert!(parse(&corrupt).is_none());
+
+        let good = packet(b"hello");
+        assert!(parse(&good[..IPV4_HEADER_LEN + 4]).is_none());
+        assert!(parse(&good[..IPV4_HEADER_LEN - 1]).is_none());
+
+        let mut ipv6 = packet(b"hello");
+        ipv6[0] = 0x65;
+        assert!(parse(&ipv6).is_none());
+    }
+
+    #[test_case]
+    fn fragments_are_dropped() {
+        for flags_fragment in [0x2000u16, 0x0001] {
+            let mut packet = packet(b"hello");
+            packet[6..8].copy_from_slice(&flags_fragment.to_be_bytes());
+            packet[10..12].copy_from_slice(&[0, 0]);
+            let sum = checksum(&packet[..IPV4_HEADER_LEN], 0);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+            assert!(parse(&packet).is_none());
+        }
+    }
+}
@@ -0,0 +1,436 @@
+// Minimal IPv4 network stack running on top of the virtio-net driver
+// Everything is polled from the main loop: frames are pulled from the device,
+// dispatched by protocol, and protocol timers (TCP retransmission, DHCP) are
+// driven by the generic timer.
+
+pub mod ethernet;
+pub mod arp;
+pub mod ipv4;
+pub mod icmp;
+pub mod udp;
+pub mod tcp;
+pub mod dhcp;
+pub mod netcat;
+pub mod telnet;
+
+use crate::drivers::timer;
+use crate::drivers::virtio_net::{VirtioNet, MAX_FRAME_SIZE};
+use crate::terminal::Screen;
+use crate::utils::{parse_number, print_number};
+use arp::ArpCache;
+use dhcp::DhcpClient;
+use ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERNET_HEADER_LEN};
+use icmp::PingTracker;
+use tcp::TcpSockets;
+use udp::UdpSockets;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0, 0, 0, 0]);
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255, 255, 255, 255]);
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Ipv4Addr([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn is_unspecified(self) -> bool {
+        self == Ipv4Addr::UNSPECIFIED
+    }
+
+    /// Parse dotted-quad notation such as `10.0.2.2`
+    pub fn parse(text: &[u8]) -> Option<Ipv4Addr> {
+        let mut octets = [0u8; 4];
+        let mut count = 0;
+        for part in text.split(|&b| b == b'.') {
+            if count == 4 {
+                return None;
+            }
+            let value = parse_number(part)?;
+            if value > 255 {
+                return None;
+            }
+            octets[count] = value as u8;
+            count += 1;
+        }
+        if count == 4 { Some(Ipv4Addr(octets)) } else { None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&bytes[..6]);
+        MacAddr(mac)
+    }
+}
+
+/// Addresses assigned to the interface (by DHCP)
+#[derive(Debug, Clone, Copy)]
+pub struct IpConfig {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+impl IpConfig {
+    pub const fn empty() -> Self {
+        IpConfig {
+            address: Ipv4Addr::UNSPECIFIED,
+            netmask: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            dns: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+
+    fn is_local(&self, addr: Ipv4Addr) -> bool {
+        let mask = self.netmask.to_u32();
+        (addr.to_u32() & mask) == (self.address.to_u32() & mask)
+    }
+
+    fn subnet_broadcast(&self) -> Ipv4Addr {
+        let mask = self.netmask.to_u32();
+        Ipv4Addr(((self.address.to_u32() & mask) | !mask).to_be_bytes())
+    }
+}
+
+// A single IPv4 packet parked while ARP resolves its next hop
+struct PendingPacket {
+    next_hop: Ipv4Addr,
+    frame: [u8; MAX_FRAME_SIZE],
+    len: usize,
+    queued_at: u64,
+}
+
+const PENDING_TIMEOUT_MS: u64 = 3000;
+
+pub struct NetInterface {
+    device: VirtioNet,
+    mac: MacAddr,
+    pub config: IpConfig,
+    arp: ArpCache,
+    pending: PendingPacket,
+    pings: PingTracker,
+    pub udp: UdpSockets,
+    pub tcp: TcpSockets,
+    dhcp: DhcpClient,
+    ip_id: u16,
+    rx_packets: usize,
+    tx_packets: usize,
+}
+
+impl NetInterface {
+    pub const fn empty() -> Self {
+        NetInterface {
+            device: VirtioNet::empty(),
+            mac: MacAddr::ZERO,
+            config: IpConfig::empty(),
+            arp: ArpCache::empty(),
+            pending: PendingPacket {
+                next_hop: Ipv4Addr::UNSPECIFIED,
+                frame: [0; MAX_FRAME_SIZE],
+                len: 0,
+                queued_at: 0,
+            },
+            pings: PingTracker::empty(),
+            udp: UdpSockets::empty(),
+            tcp: TcpSockets::empty(),
+            dhcp: DhcpClient::empty(),
+            ip_id: 1,
+            rx_packets: 0,
+            tx_packets: 0,
+        }
+    }
+
+    /// Probe for a virtio-net device and start DHCP
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        self.device.init()?;
+        self.mac = MacAddr(self.device.mac());
+        self.dhcp.start(timer::uptime_ms());
+        Ok(())
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.device.is_present()
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.config.address.is_unspecified()
+    }
+
+    /// Process received frames and run protocol timers. Called from the main loop
+    /// and from any command that blocks waiting for network traffic.
+    pub fn poll(&mut self) {
+        if !self.device.is_present() {
+            return;
+        }
+
+        let mut budget = 32;
+        while budget > 0 {
+            let mut frame = [0u8; MAX_FRAME_SIZE];
+            let len = match self.device.receive(&mut frame) {
+                Some(len) => len,
+                None => break,
+            };
+            self.rx_packets += 1;
+            self.handle_frame(&frame[..len]);
+            budget -= 1;
+        }
+
+        let now = timer::uptime_ms();
+        if self.pending.len > 0 && now.wrapping_sub(self.pending.queued_at) > PENDING_TIMEOUT_MS {
+            self.pending.len = 0;
+        }
+        self.dhcp_poll(now);
+        self.tcp_poll(now);
+    }
+
+    /// Restart address acquisition
+    pub fn renew(&mut self) {
+        self.config = IpConfig::empty();
+        self.dhcp.start(timer::uptime_ms());
+    }
+
+    fn handle_frame(&mut self, frame: &[u8]) {
+        let header = match ethernet::parse(frame) {
+            Some(h) => h,
+            None => return,
+        };
+        if header.dst != self.mac && header.dst != MacAddr::BROADCAST {
+            return;
+        }
+        let payload = &frame[ETHERNET_HEADER_LEN..];
+        match header.ethertype {
+            ETHERTYPE_ARP => self.arp_input(payload),
+            ETHERTYPE_IPV4 => self.ipv4_input(payload),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn send_frame(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+        self.device.transmit(frame)?;
+        self.tx_packets += 1;
+        Ok(())
+    }
+
+    /// Build and send an IPv4 packet, resolving the next hop through ARP
+    pub(crate) fn send_ipv4(&mut self, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), &'static str> {
+        if payload.len() + ipv4::IPV4_HEADER_LEN + ETHERNET_HEADER_LEN > MAX_FRAME_SIZE {
+            return Err("Packet too large");
+        }
+
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let ip_start = ETHERNET_HEADER_LEN;
+        let payload_start = ip_start + ipv4::IPV4_HEADER_LEN;
+        let total = payload_start + payload.len();
+
+        let id = self.ip_id;
+        self.ip_id = self.ip_id.wrapping_add(1);
+        ipv4::write_header(&mut frame[ip_start..payload_start], self.config.address, dst, protocol, payload.len(), id);
+        frame[payload_start..total].copy_from_slice(payload);
+
+        // Broadcasts never need address resolution
+        if dst == Ipv4Addr::BROADCAST || (self.is_configured() && dst == self.config.subnet_broadcast()) {
+            ethernet::write_header(&mut frame, MacAddr::BROADCAST, self.mac, ETHERTYPE_IPV4);
+            return self.send_frame(&frame[..total]);
+        }
+
+        let next_hop = if self.config.is_local(dst) || self.config.gateway.is_unspecified() {
+            dst
+        } else {
+            self.config.gateway
+        };
+
+        match self.arp.lookup(next_hop) {
+            Some(mac) => {
+                ethernet::write_header(&mut frame, mac, self.mac, ETHERTYPE_IPV4);
+                self.send_frame(&frame[..total])
+            }
+            None => {
+                // Park the packet until the ARP reply arrives
+                self.pending.frame[..total].copy_from_slice(&frame[..total]);
+                self.pending.len = total;
+                self.pending.next_hop = next_hop;
+                self.pending.queued_at = timer::uptime_ms();
+                self.arp_request(next_hop)
+            }
+        }
+    }
+
+    /// Send the parked packet once its next hop has been resolved
+    fn flush_pending(&mut self, resolved: Ipv4Addr, mac: MacAddr) {
+        if self.pending.len == 0 || self.pending.next_hop != resolved {
+            return;
+        }
+        let len = self.pending.len;
+        self.pending.len = 0;
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        frame[..len].copy_from_slice(&self.pending.frame[..len]);
+        ethernet::write_header(&mut frame, mac, self.mac, ETHERTYPE_IPV4);
+        let _ = self.send_frame(&frame[..len]);
+    }
+
+    fn ipv4_input(&mut self, packet: &[u8]) {
+        let header = match ipv4::parse(packet) {
+            Some(h) => h,
+            None => return,
+        };
+
+        let for_us = header.dst == self.config.address
+            || header.dst == Ipv4Addr::BROADCAST
+            || (self.is_configured() && header.dst == self.config.subnet_broadcast())
+            // Before DHCP completes, accept anything so the offer can reach us
+            || !self.is_configured();
+        if !for_us {
+            return;
+        }
+
+        let payload = &packet[header.header_len..header.total_len];
+        match header.protocol {
+            ipv4::PROTOCOL_ICMP => self.icmp_input(&header, payload),
+            ipv4::PROTOCOL_UDP => self.udp_input(&header, payload),
+            ipv4::PROTOCOL_TCP => self.tcp_input(&header, payload),
+            _ => {}
+        }
+    }
+
+    /// Print interface configuration and counters (used by `ifconfig`)
+    pub fn print_status(&self, screen: &mut Screen) {
+        if !self.device.is_present() {
+            screen.puts("No network device (start QEMU with -device virtio-net-device)\n");
+            return;
+        }
+        screen.puts("eth0: virtio-net\n");
+        screen.puts("  MAC:     ");
+        print_mac(screen, self.mac);
+        screen.puts("\n  Address: ");
+        print_ipv4(screen, self.config.address);
+        screen.puts("\n  Netmask: ");
+        print_ipv4(screen, self.config.netmask);
+        screen.puts("\n  Gateway: ");
+        print_ipv4(screen, self.config.gateway);
+        screen.puts("\n  DNS:     ");
+        print_ipv4(screen, self.config.dns);
+        screen.puts("\n  DHCP:    ");
+        screen.puts(self.dhcp.state_name());
+        screen.puts("\n  RX packets: ");
+        print_number(screen, self.rx_packets);
+        screen.puts("  TX packets: ");
+        print_number(screen, self.tx_packets);
+        screen.puts("\n");
+    }
+}
+
+/// Internet checksum (RFC 1071) over `data`, continuing from `initial`
+pub fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    let mut i = 0;
+    while i + 1 < data.len() {
+        sum += u16::from_be_bytes([data[i], data[i + 1]]) as u32;
+        i += 2;
+    }
+    if i < data.len() {
+        sum += (data[i] as u32) << 8;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Partial checksum of the TCP/UDP pseudo-header
+pub fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let mut sum = 0u32;
+    sum += u16::from_be_bytes([src.0[0], src.0[1]]) as u32;
+    sum += u16::from_be_bytes([src.0[2], src.0[3]]) as u32;
+    sum += u16::from_be_bytes([dst.0[0], dst.0[1]]) as u32;
+    sum += u16::from_be_bytes([dst.0[2], dst.0[3]]) as u32;
+    sum += protocol as u32;
+    sum += len as u32;
+    sum
+}
+
+pub fn print_ipv4(screen: &mut Screen, addr: Ipv4Addr) {
+    for (i, &octet) in addr.0.iter().enumerate() {
+        if i > 0 {
+            screen.putc(b'.');
+        }
+        print_number(screen, octet as usize);
+    }
+}
+
+pub fn print_mac(screen: &mut Screen, mac: MacAddr) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    for i in 0..6 {
+        if i > 0 {
+            screen.putc(b':');
+        }
+        screen.putc(HEX[(mac.0[i] >> 4) as usize]);
+        screen.putc(HEX[(mac.0[i] & 0xf) as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn checksum_of_an_ipv4_header() {
+        let mut header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+            0x00, 0x00, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(checksum(&header, 0), 0xb861);
+        header[10..12].copy_from_slice(&0xb861u16.to_be_bytes());
+        assert_eq!(checksum(&header, 0), 0);
+    }
+
+    #[test_case]
+    fn checksum_pads_odd_lengths_and_folds_carries() {
+        assert_eq!(checksum(&[0x12], 0), !0x1200);
+        assert_eq!(checksum(&[0xff, 0xff, 0x00, 0x01], 0), !0x0001);
+        assert_eq!(checksum(&[], 0x1_fffe), !0xffff);
+    }
+
+    #[test_case]
+    fn pseudo_header_counts_addresses_protocol_and_length() {
+        let src = Ipv4Addr([10, 0, 2, 15]);
+        let dst = Ipv4Addr([10, 0, 2, 2]);
+        assert_eq!(pseudo_header_sum(src, dst, 6, 20), 0x0a00 + 0x020f + 0x0a00 + 0x0202 + 6 + 20);
+    }
+
+    #[test_case]
+    fn parse_dotted_quads() {
+        assert_eq!(Ipv4Addr::parse(b"10.0.2.2"), Some(Ipv4Addr([10, 0, 2, 2])));
+        assert_eq!(Ipv4Addr::parse(b"255.255.255.255"), Some(Ipv4Addr::BROADCAST));
+        assert_eq!(Ipv4Addr::parse(b"10.0.2"), None);
+        assert_eq!(Ipv4Addr::parse(b"10.0.2.2.1"), None);
+        assert_eq!(Ipv4Addr::parse(b"10.0.2.256"), None);
+        assert_eq!(Ipv4Addr::parse(b"10..2.2"), None);
+    }
+
+    #[test_case]
+    fn local_addresses_and_subnet_broadcast() {
+        let config = IpConfig {
+            address: Ipv4Addr([10, 0, 2, 15]),
+            netmask: Ipv4Addr([255, 255, 255, 0]),
+            gateway: Ipv4Addr([10, 0, 2, 2]),
+            dns: Ipv4Addr([10, 0, 2, 3]),
+        };
+        assert!(config.is_local(Ipv4Addr([10, 0, 2, 200])));
+        assert!(!config.is_local(Ipv4Addr([10, 0, 3, 1])));
+        assert_eq!(config.subnet_broadcast(), Ipv4Addr([10, 0, 2, 255]));
+    }
+}
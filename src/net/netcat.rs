@@ -0,0 +1,220 @@
+// Interactive netcat-style session used by the `nc` shell command.
+// Typed lines are sent on Enter; received data is printed as it arrives.
+// Ctrl+C or Ctrl+D ends the session.
+
+use super::tcp::{TcpHandle, TcpState};
+use super::udp::{UdpHandle, MAX_UDP_PAYLOAD};
+use super::{print_ipv4, Ipv4Addr, NetInterface};
+use crate::drivers::keyboard::{Key, KeyEvent};
+use crate::terminal::Screen;
+use crate::text;
+use crate::utils::print_number;
+
+const LINE_BUFFER_SIZE: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Connection {
+    None,
+    Connecting(TcpHandle),
+    Listening(TcpHandle),
+    Tcp(TcpHandle),
+    Udp(UdpHandle, Ipv4Addr, u16),
+}
+
+pub struct Netcat {
+    connection: Connection,
+    line: [u8; LINE_BUFFER_SIZE],
+    line_len: usize,
+}
+
+impl Netcat {
+    pub const fn empty() -> Self {
+        Netcat {
+            connection: Connection::None,
+            line: [0; LINE_BUFFER_SIZE],
+            line_len: 0,
+        }
+    }
+
+    pub fn connect_tcp(&mut self, net: &mut NetInterface, ip: Ipv4Addr, port: u16, screen: &mut Screen) -> Result<(), &'static str> {
+        let handle = net.tcp_connect(ip, port)?;
+        self.connection = Connection::Connecting(handle);
+        self.line_len = 0;
+        screen.puts("Connecting to ");
+        print_ipv4(screen, ip);
+        screen.puts(":");
+        print_number(screen, port as usize);
+        screen.puts("... (Ctrl+C to quit)\n");
+        Ok(())
+    }
+
+    pub fn listen_tcp(&mut self, net: &mut NetInterface, port: u16, screen: &mut Screen) -> Result<(), &'static str> {
+        let handle = net.tcp_listen(port)?;
+        self.connection = Connection::Listening(handle);
+        self.line_len = 0;
+        screen.puts("Listening on port ");
+        print_number(screen, port as usize);
+        screen.puts("... (Ctrl+C to quit)\n");
+        Ok(())
+    }
+
+    pub fn open_udp(&mut self, net: &mut NetInterface, ip: Ipv4Addr, port: u16, screen: &mut Screen) -> Result<(), &'static str> {
+        let handle = net.udp.bind(0)?;
+        self.connection = Connection::Udp(handle, ip, port);
+        self.line_len = 0;
+        screen.puts("Sending UDP to ");
+        print_ipv4(screen, ip);
+        screen.puts(":");
+        print_number(screen, port as usize);
+        screen.puts(" from port ");
+        print_number(screen, net.udp.local_port(handle) as usize);
+        screen.puts(" (Ctrl+C to quit)\n");
+        Ok(())
+    }
+
+    /// Handle a key press; returns false once the session has ended
+    pub fn handle_key(&mut self, event: &KeyEvent, net: &mut NetInterface, screen: &mut Screen) -> bool {
+        if event.ctrl && (event.key == Key::Char('c') || event.key == Key::Char('d')) {
+            self.close(net, screen, "\n[Connection closed]\n");
+            return false;
+        }
+
+        match event.key {
+            Key::Char(c) if !event.has_command_modifier() => {
+                let len = c.len_utf8();
+                if self.line_len + len <= LINE_BUFFER_SIZE {
+                    c.encode_utf8(&mut self.line[self.line_len..self.line_len + len]);
+                    self.line_len += len;
+                    screen.put_char(c);
+                }
+            }
+            Key::Backspace => {
+                let start = text::previous_grapheme(&self.line[..self.line_len], self.line_len);
+                for _ in 0..text::display_width(&self.line[start..self.line_len]) {
+                    screen.puts("\x08 \x08");
+                }
+                self.line_len = start;
+            }
+            Key::Enter => {
+                screen.puts("\n");
+                if self.line_len < LINE_BUFFER_SIZE {
+                    self.line[self.line_len] = b'\n';
+                    self.line_len += 1;
+                }
+                self.send_line(net, screen);
+                self.line_len = 0;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Pasted text goes out line by line, as if it had been typed
+    pub fn paste(&mut self, text: &str, net: &mut NetInterface, screen: &mut Screen) {
+        for c in text.chars() {
+            let key = if c == '\n' { Key::Enter } else { Key::Char(c) };
+            self.handle_key(&KeyEvent::new(key), net, screen);
+        }
+    }
+
+    fn send_line(&mut self, net: &mut NetInterface, screen: &mut Screen) {
+        let data = &self.line[..self.line_len];
+        match self.connection {
+            Connection::Tcp(handle) => {
+                match net.tcp_send(handle, data) {
+                    Ok(sent) if sent < data.len() => screen.puts("[Send buffer full, line truncated]\n"),
+                    Ok(_) => {}
+                    Err(e) => {
+                        screen.puts("[Error: ");
+                        screen.puts(e);
+                        screen.puts("]\n");
+                    }
+                }
+            }
+            Connection::Udp(handle, ip, port) => {
+                if let Err(e) = net.udp_send_to(handle, ip, port, data) {
+                    screen.puts("[Error: ");
+                    screen.puts(e);
+                    screen.puts("]\n");
+                }
+            }
+            _ => screen.puts("[Not connected yet]\n"),
+        }
+    }
+
+    /// Drive the connection from the main loop; returns false once it has ended
+    pub fn poll(&mut self, net: &mut NetInterface, screen: &mut Screen) -> bool {
+        match self.connection {
+            Connection::None => false,
+            Connection::Connecting(handle) => {
+                match net.tcp.state(handle) {
+                    TcpState::Established => {
+                        screen.puts("[Connected]\n");
+                        self.connection = Connection::Tcp(handle);
+                    }
+                    TcpState::Closed => {
+                        self.close(net, screen, "[Connection refused or timed out]\n");
+                        return false;
+                    }
+                    _ => {}
+                }
+                true
+            }
+            Connection::Listening(listener) => {
+                if let Some(handle) = net.tcp_accept(listener) {
+                    // Single connection, like `nc -l`: stop listening once a peer arrives
+                    net.tcp_close(listener);
+                    let (ip, port) = net.tcp.remote(handle);
+                    screen.puts("[Connection from ");
+                    print_ipv4(screen, ip);
+                    screen.puts(":");
+                    print_number(screen, port as usize);
+                    screen.puts("]\n");
+                    self.connection = Connection::Tcp(handle);
+                }
+                true
+            }
+            Connection::Tcp(handle) => {
+                let mut buf = [0u8; 256];
+                loop {
+                    let count = net.tcp_recv(handle, &mut buf);
+                    if count == 0 {
+                        break;
+                    }
+                    for &b in &buf[..count] {
+                        screen.putc(b);
+                    }
+                }
+                if net.tcp.peer_closed(handle) || net.tcp.is_closed(handle) {
+                    let message = if net.tcp.was_reset(handle) { "[Connection reset]\n" } else { "[Connection closed by peer]\n" };
+                    self.close(net, screen, message);
+                    return false;
+                }
+                true
+            }
+            Connection::Udp(handle, _, _) => {
+                let mut buf = [0u8; MAX_UDP_PAYLOAD];
+                while let Some((count, _, _)) = net.udp.recv_from(handle, &mut buf) {
+                    for &b in &buf[..count] {
+                        screen.putc(b);
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /// End the session, closing its socket
+    pub fn close(&mut self, net: &mut NetInterface, screen: &mut Screen, message: &str) {
+        match self.connection {
+            Connection::Connecting(handle) | Connection::Listening(handle) | Connection::Tcp(handle) => {
+                net.tcp_close(handle);
+            }
+            Connection::Udp(handle, _, _) => net.udp.close(handle),
+            Connection::None => {}
+        }
+        self.connection = Connection::None;
+        self.line_len = 0;
+        screen.puts(message);
+    }
+}
@@ -0,0 +1,1149 @@
+// TCP (RFC 793 subset): active and passive open, in-order receive with an
+// advertised window, sliding-window transmit limited by the peer's window and
+// MSS, go-back-N retransmission with an RFC 6298 style RTO, zero-window
+// probing and orderly close. Out-of-order segments are dropped and re-acked.
+
+use super::ipv4::{Ipv4Header, PROTOCOL_TCP};
+use super::{checksum, pseudo_header_sum, Ipv4Addr, NetInterface};
+use crate::drivers::timer;
+use crate::utils::ring_buffer::RingBuffer;
+
+const MAX_TCP_SOCKETS: usize = 8;
+const TCP_BUFFER_SIZE: usize = 4096;
+const TCP_HEADER_LEN: usize = 20;
+const TCP_MSS: usize = 1460;
+const DEFAULT_PEER_MSS: usize = 536;
+
+const INITIAL_RTO_MS: u64 = 1000;
+const MIN_RTO_MS: u64 = 200;
+const MAX_RTO_MS: u64 = 60_000;
+const MAX_RETRANSMISSIONS: u8 = 8;
+const TIME_WAIT_MS: u64 = 4000;
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Handle to a TCP socket (listener or connection)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpHandle(usize);
+
+// Sequence number comparisons modulo 2^32
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+struct TcpSocket {
+    state: TcpState,
+    local_port: u16,
+    remote_ip: Ipv4Addr,
+    remote_port: u16,
+
+    // Send sequence space; `tx` holds every byte from snd_una onwards
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: usize,
+    peer_mss: usize,
+    tx: RingBuffer<TCP_BUFFER_SIZE>,
+
+    // Receive sequence space
+    rcv_nxt: u32,
+    rx: RingBuffer<TCP_BUFFER_SIZE>,
+    advertised_window: usize,
+    ack_pending: bool,
+
+    // Retransmission state
+    rto_ms: u64,
+    srtt_ms: u64,
+    rttvar_ms: u64,
+    rtt_seq: Option<u32>,
+    rtt_started_at: u64,
+    retransmit_at: Option<u64>,
+    retransmissions: u8,
+    time_wait_until: u64,
+
+    close_requested: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    was_reset: bool,
+    // Listener that spawned this connection and has not accepted it yet
+    parent: Option<usize>,
+    // The application gave up its handle; free the slot once CLOSED
+    released: bool,
+    in_use: bool,
+}
+
+impl TcpSocket {
+    const fn empty() -> Self {
+        TcpSocket {
+            state: TcpState::Closed,
+            local_port: 0,
+            remote_ip: Ipv4Addr::UNSPECIFIED,
+            remote_port: 0,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            peer_mss: DEFAULT_PEER_MSS,
+            tx: RingBuffer::empty(),
+            rcv_nxt: 0,
+            rx: RingBuffer::empty(),
+            advertised_window: 0,
+            ack_pending: false,
+            rto_ms: INITIAL_RTO_MS,
+            srtt_ms: 0,
+            rttvar_ms: 0,
+            rtt_seq: None,
+            rtt_started_at: 0,
+            retransmit_at: None,
+            retransmissions: 0,
+            time_wait_until: 0,
+            close_requested: false,
+            fin_sent: false,
+            fin_received: false,
+            was_reset: false,
+            parent: None,
+            released: false,
+            in_use: false,
+        }
+    }
+
+    // Reinitialise a slot in place (the buffers are too large to rebuild on the stack)
+    fn reset(&mut self, local_port: u16, iss: u32) {
+        self.state = TcpState::Closed;
+        self.local_port = local_port;
+        self.remote_ip = Ipv4Addr::UNSPECIFIED;
+        self.remote_port = 0;
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+        self.snd_wnd = 0;
+        self.peer_mss = DEFAULT_PEER_MSS;
+        self.tx.clear();
+        self.rcv_nxt = 0;
+        self.rx.clear();
+        self.advertised_window = TCP_BUFFER_SIZE;
+        self.ack_pending = false;
+        self.rto_ms = INITIAL_RTO_MS;
+        self.srtt_ms = 0;
+        self.rttvar_ms = 0;
+        self.rtt_seq = None;
+        self.retransmit_at = None;
+        self.retransmissions = 0;
+        self.close_requested = false;
+        self.fin_sent = false;
+        self.fin_received = false;
+        self.was_reset = false;
+        self.parent = None;
+        self.released = false;
+        self.in_use = true;
+    }
+
+    fn receive_window(&self) -> usize {
+        self.rx.free().min(u16::MAX as usize)
+    }
+
+    /// Bytes sent but not yet acknowledged, excluding SYN/FIN
+    fn data_in_flight(&self) -> usize {
+        let mut in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.fin_sent && in_flight > 0 {
+            in_flight -= 1;
+        }
+        in_flight.min(self.tx.len())
+    }
+
+    fn update_rtt(&mut self, sample: u64) {
+        // RFC 6298: SRTT/RTTVAR smoothing, RTO = SRTT + 4 * RTTVAR
+        if self.srtt_ms == 0 {
+            self.srtt_ms = sample.max(1);
+            self.rttvar_ms = sample / 2;
+        } else {
+            let delta = self.srtt_ms.abs_diff(sample);
+            self.rttvar_ms = (3 * self.rttvar_ms + delta) / 4;
+            self.srtt_ms = (7 * self.srtt_ms + sample) / 8;
+        }
+        self.rto_ms = (self.srtt_ms + 4 * self.rttvar_ms).clamp(MIN_RTO_MS, MAX_RTO_MS);
+    }
+
+    fn is_synchronized(&self) -> bool {
+        !matches!(self.state, TcpState::Closed | TcpState::Listen | TcpState::SynSent)
+    }
+}
+
+pub struct TcpSockets {
+    sockets: [TcpSocket; MAX_TCP_SOCKETS],
+    next_ephemeral: u16,
+    iss_counter: u32,
+}
+
+impl TcpSockets {
+    pub const fn empty() -> Self {
+        TcpSockets {
+            sockets: [const { TcpSocket::empty() }; MAX_TCP_SOCKETS],
+            next_ephemeral: EPHEMERAL_PORT_START,
+            iss_counter: 0,
+        }
+    }
+
+    fn allocate(&mut self, local_port: u16) -> Option<usize> {
+        let index = self.sockets.iter().position(|s| !s.in_use)?;
+        // ISN from the timer plus a per-connection stride, as in RFC 6528 without the hash
+        self.iss_counter = self.iss_counter.wrapping_add(64_000);
+        let iss = (timer::ticks() as u32).wrapping_add(self.iss_counter);
+        self.sockets[index].reset(local_port, iss);
+        Some(index)
+    }
+
+    fn ephemeral_port(&mut self) -> u16 {
+        loop {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+            if !self.sockets.iter().any(|s| s.in_use && s.local_port == port) {
+                return port;
+            }
+        }
+    }
+
+    fn find_connection(&self, local_port: u16, remote_ip: Ipv4Addr, remote_port: u16) -> Option<usize> {
+        self.sockets.iter().position(|s| {
+            s.in_use
+                && s.state != TcpState::Listen
+                && s.state != TcpState::Closed
+                && s.local_port == local_port
+                && s.remote_ip == remote_ip
+                && s.remote_port == remote_port
+        })
+    }
+
+    fn find_listener(&self, local_port: u16) -> Option<usize> {
+        self.sockets.iter().position(|s| s.in_use && s.state == TcpState::Listen && s.local_port == local_port)
+    }
+
+    pub fn state(&self, handle: TcpHandle) -> TcpState {
+        self.sockets[handle.0].state
+    }
+
+    /// True once the connection can carry no more data in either direction
+    pub fn is_closed(&self, handle: TcpHandle) -> bool {
+        self.sockets[handle.0].state == TcpState::Closed
+    }
+
+    /// True if the connection was reset or timed out rather than closed cleanly
+    pub fn was_reset(&self, handle: TcpHandle) -> bool {
+        self.sockets[handle.0].was_reset
+    }
+
+    /// True once the peer has sent FIN and all its data has been read
+    pub fn peer_closed(&self, handle: TcpHandle) -> bool {
+        let socket = &self.sockets[handle.0];
+        socket.fin_received && socket.rx.is_empty()
+    }
+
+    /// Free space in the send buffer
+    pub fn send_capacity(&self, handle: TcpHandle) -> usize {
+        self.sockets[handle.0].tx.free()
+    }
+
+    pub fn remote(&self, handle: TcpHandle) -> (Ipv4Addr, u16) {
+        let socket = &self.sockets[handle.0];
+        (socket.remote_ip, socket.remote_port)
+    }
+}
+
+// Fields of a parsed TCP segment
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: usize,
+    mss: Option<usize>,
+    payload: &'a [u8],
+}
+
+fn parse_segment<'a>(header: &Ipv4Header, data: &'a [u8]) -> Option<Segment<'a>> {
+    if data.len() < TCP_HEADER_LEN {
+        return None;
+    }
+    let initial = pseudo_header_sum(header.src, header.dst, PROTOCOL_TCP, data.len());
+    if checksum(data, initial) != 0 {
+        return None;
+    }
+    let data_offset = ((data[12] >> 4) as usize) * 4;
+    if data_offset < TCP_HEADER_LEN || data_offset > data.len() {
+        return None;
+    }
+
+    // Only the MSS option is interpreted
+    let mut mss = None;
+    let mut i = TCP_HEADER_LEN;
+    while i < data_offset {
+        match data[i] {
+            OPT_END => break,
+            OPT_NOP => i += 1,
+            kind => {
+                if i + 1 >= data_offset {
+                    break;
+                }
+                let len = data[i + 1] as usize;
+                if len < 2 || i + len > data_offset {
+                    break;
+                }
+                if kind == OPT_MSS && len == 4 {
+                    mss = Some(u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize);
+                }
+                i += len;
+            }
+        }
+    }
+
+    Some(Segment {
+        src_port: u16::from_be_bytes([data[0], data[1]]),
+        dst_port: u16::from_be_bytes([data[2], data[3]]),
+        seq: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ack: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        flags: data[13],
+        window: u16::from_be_bytes([data[14], data[15]]) as usize,
+        mss,
+        payload: &data[data_offset..],
+    })
+}
+
+impl NetInterface {
+    /// Open a connection to `ip:port`; poll `tcp_state` until ESTABLISHED
+    pub fn tcp_connect(&mut self, ip: Ipv4Addr, port: u16) -> Result<TcpHandle, &'static str> {
+        if !self.is_configured() {
+            return Err("Network is not configured");
+        }
+        let local_port = self.tcp.ephemeral_port();
+        let index = self.tcp.allocate(local_port).ok_or("No free TCP sockets")?;
+        {
+            let socket = &mut self.tcp.sockets[index];
+            socket.remote_ip = ip;
+            socket.remote_port = port;
+            socket.state = TcpState::SynSent;
+            socket.snd_nxt = socket.iss.wrapping_add(1);
+        }
+        self.tcp_send_syn(index);
+        Ok(TcpHandle(index))
+    }
+
+    /// Start listening on `port`; connections are picked up with `tcp_accept`
+    pub fn tcp_listen(&mut self, port: u16) -> Result<TcpHandle, &'static str> {
+        if self.tcp.find_listener(port).is_some() {
+            return Err("Port already in use");
+        }
+        let index = self.tcp.allocate(port).ok_or("No free TCP sockets")?;
+        self.tcp.sockets[index].state = TcpState::Listen;
+        Ok(TcpHandle(index))
+    }
+
+    /// Take the next fully established connection from a listener's backlog
+    pub fn tcp_accept(&mut self, listener: TcpHandle) -> Option<TcpHandle> {
+        let index = self.tcp.sockets.iter().position(|s| {
+            s.in_use
+                && s.parent == Some(listener.0)
+                && matches!(s.state, TcpState::Established | TcpState::CloseWait)
+        })?;
+        self.tcp.sockets[index].parent = None;
+        Some(TcpHandle(index))
+    }
+
+    /// Queue data for sending; returns how many bytes fit in the send buffer
+    pub fn tcp_send(&mut self, handle: TcpHandle, data: &[u8]) -> Result<usize, &'static str> {
+        let socket = &mut self.tcp.sockets[handle.0];
+        match socket.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            TcpState::SynSent | TcpState::SynReceived => return Ok(0),
+            _ => return Err("Connection closed"),
+        }
+        if socket.close_requested {
+            return Err("Connection closing");
+        }
+        let queued = socket.tx.push(data);
+        self.tcp_output(handle.0, false);
+        Ok(queued)
+    }
+
+    /// Read received data; returns 0 when nothing is buffered
+    pub fn tcp_recv(&mut self, handle: TcpHandle, buf: &mut [u8]) -> usize {
+        let socket = &mut self.tcp.sockets[handle.0];
+        let count = socket.rx.pop(buf);
+        // Send a window update once a closed/small window has opened up again
+        if count > 0 && socket.is_synchronized() && !socket.fin_received
+            && socket.advertised_window < TCP_MSS && socket.receive_window() >= TCP_MSS
+        {
+            socket.ack_pending = true;
+            self.tcp_output(handle.0, false);
+        }
+        count
+    }
+
+    /// Close gracefully: pending data is sent, followed by FIN. The handle must
+    /// not be used afterwards; the slot is recycled once the close completes.
+    pub fn tcp_close(&mut self, handle: TcpHandle) {
+        let index = handle.0;
+        match self.tcp.sockets[index].state {
+            TcpState::Listen => {
+                // Drop connections the application never accepted
+                for i in 0..MAX_TCP_SOCKETS {
+                    if self.tcp.sockets[i].in_use && self.tcp.sockets[i].parent == Some(index) {
+                        self.tcp_abort(TcpHandle(i));
+                    }
+                }
+                self.tcp.sockets[index].in_use = false;
+            }
+            TcpState::Closed | TcpState::SynSent => {
+                self.tcp.sockets[index].in_use = false;
+            }
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                let socket = &mut self.tcp.sockets[index];
+                socket.close_requested = true;
+                socket.released = true;
+                self.tcp_output(index, false);
+            }
+            _ => {
+                self.tcp.sockets[index].released = true;
+            }
+        }
+    }
+
+    /// Drop the connection immediately, telling the peer with RST
+    pub fn tcp_abort(&mut self, handle: TcpHandle) {
+        let index = handle.0;
+        let socket = &self.tcp.sockets[index];
+        if socket.is_synchronized() {
+            let seq = socket.snd_nxt;
+            self.tcp_send_segment(index, seq, FLAG_RST | FLAG_ACK, &[]);
+        }
+        self.tcp.sockets[index].state = TcpState::Closed;
+        self.tcp.sockets[index].in_use = false;
+    }
+
+    pub(super) fn tcp_input(&mut self, header: &Ipv4Header, data: &[u8]) {
+        let segment = match parse_segment(header, data) {
+            Some(s) => s,
+            None => return,
+        };
+
+        if let Some(index) = self.tcp.find_connection(segment.dst_port, header.src, segment.src_port) {
+            match self.tcp.sockets[index].state {
+                TcpState::SynSent => self.tcp_input_syn_sent(index, &segment),
+                _ => self.tcp_input_synchronized(index, &segment),
+            }
+            return;
+        }
+
+        if let Some(listener) = self.tcp.find_listener(segment.dst_port) {
+            self.tcp_input_listen(listener, header.src, &segment);
+            return;
+        }
+
+        // No socket: refuse with RST (never in response to a RST)
+        if segment.flags & FLAG_RST == 0 {
+            self.tcp_send_reset_for(header.src, &segment);
+        }
+    }
+
+    fn tcp_input_listen(&mut self, listener: usize, src: Ipv4Addr, segment: &Segment) {
+        if segment.flags & FLAG_RST != 0 {
+            return;
+        }
+        if segment.flags & FLAG_ACK != 0 {
+            self.tcp_send_reset_for(src, segment);
+            return;
+        }
+        if segment.flags & FLAG_SYN == 0 {
+            return;
+        }
+
+        let index = match self.tcp.allocate(segment.dst_port) {
+            Some(i) => i,
+            None => return, // Backlog full: let the peer retransmit its SYN
+        };
+        let socket = &mut self.tcp.sockets[index];
+        socket.remote_ip = src;
+        socket.remote_port = segment.src_port;
+        socket.rcv_nxt = segment.seq.wrapping_add(1);
+        socket.snd_nxt = socket.iss.wrapping_add(1);
+        socket.snd_wnd = segment.window;
+        socket.peer_mss = segment.mss.unwrap_or(DEFAULT_PEER_MSS).min(TCP_MSS);
+        socket.parent = Some(listener);
+        socket.state = TcpState::SynReceived;
+        self.tcp_send_syn(index);
+    }
+
+    fn tcp_input_syn_sent(&mut self, index: usize, segment: &Segment) {
+        let (iss, snd_nxt) = {
+            let socket = &self.tcp.sockets[index];
+            (socket.iss, socket.snd_nxt)
+        };
+        let has_ack = segment.flags & FLAG_ACK != 0;
+        if has_ack && (seq_le(segment.ack, iss) || seq_lt(snd_nxt, segment.ack)) {
+            if segment.flags & FLAG_RST == 0 {
+                let remote = self.tcp.sockets[index].remote_ip;
+                self.tcp_send_reset_for(remote, segment);
+            }
+            return;
+        }
+        if segment.flags & FLAG_RST != 0 {
+            if has_ack {
+                let socket = &mut self.tcp.sockets[index];
+                socket.state = TcpState::Closed;
+                socket.was_reset = true;
+            }
+            return;
+        }
+        if segment.flags & FLAG_SYN == 0 {
+            return;
+        }
+
+        let socket = &mut self.tcp.sockets[index];
+        socket.rcv_nxt = segment.seq.wrapping_add(1);
+        socket.snd_wnd = segment.window;
+        socket.peer_mss = segment.mss.unwrap_or(DEFAULT_PEER_MSS).min(TCP_MSS);
+        if has_ack {
+            socket.snd_una = segment.ack;
+            socket.state = TcpState::Established;
+            socket.retransmit_at = None;
+            socket.retransmissions = 0;
+            if socket.rtt_seq.is_some() {
+                let sample = timer::uptime_ms().wrapping_sub(socket.rtt_started_at);
+                socket.rtt_seq = None;
+                socket.update_rtt(sample);
+            }
+            socket.ack_pending = true;
+            self.tcp_output(index, false);
+        } else {
+            // Simultaneous open
+            socket.state = TcpState::SynReceived;
+            self.tcp_send_syn(index);
+        }
+    }
+
+    fn tcp_input_synchronized(&mut self, index: usize, segment: &Segment) {
+        let now = timer::uptime_ms();
+        let socket = &mut self.tcp.sockets[index];
+
+        // Acceptability: the segment must overlap the receive window
+        let window = socket.receive_window().max(1) as u32;
+        let seg_len = segment.payload.len() as u32;
+        let seg_end = segment.seq.wrapping_add(seg_len.max(1) - 1);
+        let in_window = |seq: u32| seq_le(socket.rcv_nxt, seq) && seq_lt(seq, socket.rcv_nxt.wrapping_add(window));
+        let acceptable = in_window(segment.seq) || (seg_len > 0 && in_window(seg_end));
+        if !(acceptable || (seg_len == 0 && segment.seq == socket.rcv_nxt)) {
+            // Old duplicate or out-of-window: re-ack what we have
+            if segment.flags & FLAG_RST == 0 {
+                socket.ack_pending = true;
+                self.tcp_output(index, false);
+            }
+            return;
+        }
+
+        if segment.flags & FLAG_RST != 0 {
+            socket.state = TcpState::Closed;
+            socket.was_reset = true;
+            if socket.parent.is_some() {
+                // Never accepted, so nobody owns the slot
+                socket.in_use = false;
+            }
+            return;
+        }
+
+        if segment.flags & FLAG_SYN != 0 {
+            if socket.state == TcpState::SynReceived && segment.seq.wrapping_add(1) == socket.rcv_nxt {
+                // Our SYN-ACK was lost; the peer retransmitted its SYN
+                self.tcp_send_syn(index);
+            } else {
+                let seq = socket.snd_nxt;
+                self.tcp_send_segment(index, seq, FLAG_RST, &[]);
+                let socket = &mut self.tcp.sockets[index];
+                socket.state = TcpState::Closed;
+                socket.was_reset = true;
+            }
+            return;
+        }
+
+        if segment.flags & FLAG_ACK == 0 {
+            return;
+        }
+
+        // ACK processing
+        if socket.state == TcpState::SynReceived {
+            if segment.ack != socket.snd_nxt {
+                let remote = socket.remote_ip;
+                self.tcp_send_reset_for(remote, segment);
+                return;
+            }
+            socket.snd_una = segment.ack;
+            socket.state = TcpState::Established;
+            socket.retransmit_at = None;
+            socket.retransmissions = 0;
+            if socket.rtt_seq.is_some() {
+                socket.rtt_seq = None;
+                let sample = now.wrapping_sub(socket.rtt_started_at);
+                socket.update_rtt(sample);
+            }
+        } else if seq_lt(socket.snd_una, segment.ack) && seq_le(segment.ack, socket.snd_nxt) {
+            let acked = segment.ack.wrapping_sub(socket.snd_una) as usize;
+            let fin_acked = socket.fin_sent && segment.ack == socket.snd_nxt;
+            let data_acked = if fin_acked { acked - 1 } else { acked };
+            socket.tx.consume(data_acked);
+            socket.snd_una = segment.ack;
+            socket.retransmissions = 0;
+
+            if let Some(rtt_seq) = socket.rtt_seq {
+                if seq_le(rtt_seq, segment.ack) {
+                    socket.rtt_seq = None;
+                    let sample = now.wrapping_sub(socket.rtt_started_at);
+                    socket.update_rtt(sample);
+                }
+            }
+
+            socket.retransmit_at = if socket.snd_una == socket.snd_nxt { None } else { Some(now + socket.rto_ms) };
+
+            if fin_acked {
+                match socket.state {
+                    TcpState::FinWait1 => socket.state = TcpState::FinWait2,
+                    TcpState::Closing => {
+                        socket.state = TcpState::TimeWait;
+                        socket.time_wait_until = now + TIME_WAIT_MS;
+                    }
+                    TcpState::LastAck => {
+                        socket.state = TcpState::Closed;
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        } else if seq_lt(socket.snd_nxt, segment.ack) {
+            // Acknowledges data we never sent
+            socket.ack_pending = true;
+            self.tcp_output(index, false);
+            return;
+        }
+        socket.snd_wnd = segment.window;
+
+        // Data: accept the in-order part that fits in the receive buffer
+        let can_receive = matches!(socket.state, TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2);
+        if seg_len > 0 && can_receive {
+            let skip = socket.rcv_nxt.wrapping_sub(segment.seq) as usize;
+            if skip < segment.payload.len() {
+                let stored = socket.rx.push(&segment.payload[skip..]);
+                socket.rcv_nxt = socket.rcv_nxt.wrapping_add(stored as u32);
+            }
+            socket.ack_pending = true;
+        }
+
+        // FIN is only honoured once every byte before it has been accepted
+        let fin_seq = segment.seq.wrapping_add(seg_len);
+        if segment.flags & FLAG_FIN != 0 && fin_seq == socket.rcv_nxt && !socket.fin_received {
+            socket.rcv_nxt = socket.rcv_nxt.wrapping_add(1);
+            socket.fin_received = true;
+            socket.ack_pending = true;
+            match socket.state {
+                TcpState::SynReceived | TcpState::Established => socket.state = TcpState::CloseWait,
+                TcpState::FinWait1 => {
+                    // Simultaneous close: our FIN is still unacknowledged
+                    socket.state = TcpState::Closing;
+                }
+                TcpState::FinWait2 => {
+                    socket.state = TcpState::TimeWait;
+                    socket.time_wait_until = now + TIME_WAIT_MS;
+                }
+                _ => {}
+            }
+        } else if segment.flags & FLAG_FIN != 0 && socket.state == TcpState::TimeWait {
+            // Retransmitted FIN: our ACK was lost, so restart the wait
+            socket.time_wait_until = now + TIME_WAIT_MS;
+            socket.ack_pending = true;
+        }
+
+        self.tcp_output(index, false);
+    }
+
+    /// Send whatever the windows allow, then FIN if closing, then a bare ACK if
+    /// one is still owed. `probe` forces one byte out into a zero window.
+    fn tcp_output(&mut self, index: usize, probe: bool) {
+        let now = timer::uptime_ms();
+        let can_send_data = matches!(
+            self.tcp.sockets[index].state,
+            TcpState::Established | TcpState::CloseWait | TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck
+        );
+
+        if can_send_data {
+            let mut forced = probe;
+            loop {
+                let socket = &self.tcp.sockets[index];
+                if socket.fin_sent {
+                    break;
+                }
+                let in_flight = socket.data_in_flight();
+                let unsent = socket.tx.len() - in_flight;
+                if unsent == 0 {
+                    break;
+                }
+                let mut usable = socket.snd_wnd.saturating_sub(in_flight);
+                if usable == 0 && forced {
+                    usable = 1;
+                    forced = false;
+                }
+                if usable == 0 {
+                    // Zero window with nothing outstanding: arm the persist timer
+                    if in_flight == 0 && socket.retransmit_at.is_none() {
+                        let rto = socket.rto_ms;
+                        self.tcp.sockets[index].retransmit_at = Some(now + rto);
+                    }
+                    break;
+                }
+
+                let count = unsent.min(usable).min(socket.peer_mss);
+                let mut data = [0u8; TCP_MSS];
+                let count = socket.tx.peek_at(in_flight, &mut data[..count]);
+                let seq = socket.snd_nxt;
+                self.tcp_send_segment(index, seq, FLAG_ACK | FLAG_PSH, &data[..count]);
+
+                let socket = &mut self.tcp.sockets[index];
+                socket.snd_nxt = seq.wrapping_add(count as u32);
+                if socket.retransmit_at.is_none() {
+                    socket.retransmit_at = Some(now + socket.rto_ms);
+                }
+                if socket.rtt_seq.is_none() && socket.retransmissions == 0 {
+                    socket.rtt_seq = Some(socket.snd_nxt);
+                    socket.rtt_started_at = now;
+                }
+            }
+
+            let socket = &self.tcp.sockets[index];
+            if socket.close_requested && !socket.fin_sent && socket.data_in_flight() == socket.tx.len() {
+                let seq = socket.snd_nxt;
+                self.tcp_send_segment(index, seq, FLAG_FIN | FLAG_ACK, &[]);
+                let socket = &mut self.tcp.sockets[index];
+                socket.snd_nxt = seq.wrapping_add(1);
+                socket.fin_sent = true;
+                socket.state = match socket.state {
+                    TcpState::Established => TcpState::FinWait1,
+                    TcpState::CloseWait => TcpState::LastAck,
+                    other => other,
+                };
+                if socket.retransmit_at.is_none() {
+                    socket.retransmit_at = Some(now + socket.rto_ms);
+                }
+            }
+        }
+
+        if self.tcp.sockets[index].ack_pending && self.tcp.sockets[index].is_synchronized() {
+            let seq = self.tcp.sockets[index].snd_nxt;
+            self.tcp_send_segment(index, seq, FLAG_ACK, &[]);
+        }
+    }
+
+    /// Retransmission, persist and TIME-WAIT timers
+    pub(super) fn tcp_poll(&mut self, now: u64) {
+        for index in 0..MAX_TCP_SOCKETS {
+            if !self.tcp.sockets[index].in_use {
+                continue;
+            }
+
+            if self.tcp.sockets[index].state == TcpState::TimeWait && now >= self.tcp.sockets[index].time_wait_until {
+                self.tcp.sockets[index].state = TcpState::Closed;
+            }
+
+            let deadline = self.tcp.sockets[index].retransmit_at;
+            if let Some(deadline) = deadline {
+                if now >= deadline && self.tcp.sockets[index].state != TcpState::Closed {
+                    self.tcp_retransmit(index, now);
+                }
+            }
+
+            let socket = &mut self.tcp.sockets[index];
+            // Orphaned or released connections give their slot back once closed
+            if socket.state == TcpState::Closed && (socket.released || socket.parent.is_some()) {
+                socket.in_use = false;
+            }
+        }
+    }
+
+    fn tcp_retransmit(&mut self, index: usize, now: u64) {
+        let socket = &mut self.tcp.sockets[index];
+        socket.retransmissions += 1;
+        if socket.retransmissions > MAX_RETRANSMISSIONS {
+            // Give up on the peer
+            let seq = socket.snd_nxt;
+            let synchronized = socket.is_synchronized();
+            socket.state = TcpState::Closed;
+            socket.was_reset = true;
+            socket.retransmit_at = None;
+            if synchronized {
+                self.tcp_send_segment(index, seq, FLAG_RST, &[]);
+            }
+            return;
+        }
+
+        socket.rto_ms = (socket.rto_ms * 2).min(MAX_RTO_MS);
+        socket.retransmit_at = Some(now + socket.rto_ms);
+        // Karn's algorithm: never sample RTT from retransmitted data
+        socket.rtt_seq = None;
+
+        match socket.state {
+            TcpState::SynSent | TcpState::SynReceived => self.tcp_send_syn(index),
+            _ => {
+                let zero_window_probe = socket.snd_wnd == 0 && socket.snd_una == socket.snd_nxt;
+                // Go-back-N: resend everything from the oldest unacknowledged byte
+                socket.snd_nxt = socket.snd_una;
+                socket.fin_sent = false;
+                self.tcp_output(index, zero_window_probe);
+            }
+        }
+    }
+
+    fn tcp_send_syn(&mut self, index: usize) {
+        let now = timer::uptime_ms();
+        let socket = &mut self.tcp.sockets[index];
+        let flags = if socket.state == TcpState::SynReceived { FLAG_SYN | FLAG_ACK } else { FLAG_SYN };
+        let iss = socket.iss;
+        if socket.retransmit_at.is_none() {
+            socket.retransmit_at = Some(now + socket.rto_ms);
+        }
+        if socket.retransmissions == 0 {
+            socket.rtt_seq = Some(iss.wrapping_add(1));
+            socket.rtt_started_at = now;
+        }
+        self.tcp_send_segment(index, iss, flags, &[]);
+    }
+
+    fn tcp_send_segment(&mut self, index: usize, seq: u32, flags: u8, data: &[u8]) {
+        let socket = &mut self.tcp.sockets[index];
+        let ack = if flags & FLAG_ACK != 0 { socket.rcv_nxt } else { 0 };
+        let window = socket.receive_window();
+        socket.advertised_window = window;
+        if flags & FLAG_ACK != 0 {
+            socket.ack_pending = false;
+        }
+        let (local_port, remote_ip, remote_port) = (socket.local_port, socket.remote_ip, socket.remote_port);
+        let _ = self.send_tcp(local_port, remote_ip, remote_port, seq, ack, flags, window as u16, data);
+    }
+
+    /// RST for a segment that matched no connection (RFC 793 "reset generation")
+    fn tcp_send_reset_for(&mut self, dst: Ipv4Addr, segment: &Segment) {
+        if segment.flags & FLAG_ACK != 0 {
+            let _ = self.send_tcp(segment.dst_port, dst, segment.src_port, segment.ack, 0, FLAG_RST, 0, &[]);
+        } else {
+            let mut seg_len = segment.payload.len() as u32;
+            if segment.flags & FLAG_SYN != 0 {
+                seg_len += 1;
+            }
+            if segment.flags & FLAG_FIN != 0 {
+                seg_len += 1;
+            }
+            let ack = segment.seq.wrapping_add(seg_len);
+            let _ = self.send_tcp(segment.dst_port, dst, segment.src_port, 0, ack, FLAG_RST | FLAG_ACK, 0, &[]);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_tcp(
+        &mut self,
+        src_port: u16,
+        dst: Ipv4Addr,
+        dst_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        window: u16,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        // SYNs advertise our MSS
+        let options_len = if flags & FLAG_SYN != 0 { 4 } else { 0 };
+        let header_len = TCP_HEADER_LEN + options_len;
+        let len = header_len + data.len();
+        let mut segment = [0u8; TCP_HEADER_LEN + 4 + TCP_MSS];
+        if len > segment.len() {
+            return Err("Segment too large");
+        }
+
+        segment[0..2].copy_from_slice(&src_port.to_be_bytes());
+        segment[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        segment[4..8].copy_from_slice(&seq.to_be_bytes());
+        segment[8..12].copy_from_slice(&ack.to_be_bytes());
+        segment[12] = ((header_len / 4) as u8) << 4;
+        segment[13] = flags;
+        segment[14..16].copy_from_slice(&window.to_be_bytes());
+        if options_len > 0 {
+            segment[20] = OPT_MSS;
+            segment[21] = 4;
+            segment[22..24].copy_from_slice(&(TCP_MSS as u16).to_be_bytes());
+        }
+        segment[header_len..len].copy_from_slice(data);
+
+        let initial = pseudo_header_sum(self.config.address, dst, PROTOCOL_TCP, len);
+        let sum = checksum(&segment[..len], initial);
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+
+        self.send_ipv4(dst, PROTOCOL_TCP, &segment[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv4::IPV4_HEADER_LEN;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr([10, 0, 2, 15]);
+    const PEER: Ipv4Addr = Ipv4Addr([10, 0, 2, 2]);
+    const PEER_PORT: u16 = 40000;
+    const MSS_OPTION: [u8; 4] = [OPT_MSS, 4, 0x02, 0x18];
+
+    // An interface without a device: what it sends goes nowhere, and the
+    // tests look at the sockets instead
+    static mut NET: NetInterface = NetInterface::empty();
+
+    fn net() -> &'static mut NetInterface {
+        let net = unsafe { &mut *core::ptr::addr_of_mut!(NET) };
+        net.config.address = LOCAL;
+        for socket in net.tcp.sockets.iter_mut() {
+            socket.in_use = false;
+        }
+        net
+    }
+
+    fn ip_header(len: usize) -> Ipv4Header {
+        Ipv4Header { src: PEER, dst: LOCAL, protocol: PROTOCOL_TCP, header_len: IPV4_HEADER_LEN, total_len: IPV4_HEADER_LEN + len }
+    }
+
+    /// A checksummed segment from the peer to `port`; returns its length
+    fn build(data: &mut [u8], port: u16, seq: u32, ack: u32, flags: u8, options: &[u8], payload: &[u8]) -> usize {
+        let header_len = TCP_HEADER_LEN + options.len();
+        let len = header_len + payload.len();
+        data[..len].fill(0);
+        data[0..2].copy_from_slice(&PEER_PORT.to_be_bytes());
+        data[2..4].copy_from_slice(&port.to_be_bytes());
+        data[4..8].copy_from_slice(&seq.to_be_bytes());
+        data[8..12].copy_from_slice(&ack.to_be_bytes());
+        data[12] = ((header_len / 4) as u8) << 4;
+        data[13] = flags;
+        data[14..16].copy_from_slice(&4096u16.to_be_bytes());
+        data[TCP_HEADER_LEN..header_len].copy_from_slice(options);
+        data[header_len..len].copy_from_slice(payload);
+        let sum = checksum(&data[..len], pseudo_header_sum(PEER, LOCAL, PROTOCOL_TCP, len));
+        data[16..18].copy_from_slice(&sum.to_be_bytes());
+        len
+    }
+
+    fn deliver(net: &mut NetInterface, port: u16, seq: u32, ack: u32, flags: u8, payload: &[u8]) {
+        let mut data = [0u8; TCP_HEADER_LEN + 64];
+        let len = build(&mut data, port, seq, ack, flags, &[], payload);
+        net.tcp_input(&ip_header(len), &data[..len]);
+    }
+
+    /// A connection to the peer, which starts its sequence numbers at `peer_iss`
+    fn connect(net: &mut NetInterface, peer_iss: u32) -> (TcpHandle, u16) {
+        let handle = net.tcp_connect(PEER, PEER_PORT).unwrap();
+        let socket = &net.tcp.sockets[handle.0];
+        let (port, iss) = (socket.local_port, socket.iss);
+        deliver(net, port, peer_iss, iss.wrapping_add(1), FLAG_SYN | FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::Established);
+        (handle, port)
+    }
+
+    #[test_case]
+    fn sequence_numbers_compare_across_the_wrap() {
+        assert!(seq_lt(1, 2));
+        assert!(!seq_lt(2, 2));
+        assert!(seq_le(2, 2));
+        assert!(seq_lt(u32::MAX, 0));
+        assert!(seq_lt(0xffff_fff0, 0x10));
+        assert!(!seq_lt(0x10, 0xffff_fff0));
+        assert!(seq_le(0xffff_fff0, 0x10));
+        assert!(!seq_le(0x10, 0xffff_fff0));
+    }
+
+    #[test_case]
+    fn rto_follows_the_rtt_estimate() {
+        let mut socket = TcpSocket::empty();
+        socket.update_rtt(100);
+        assert_eq!((socket.srtt_ms, socket.rttvar_ms, socket.rto_ms), (100, 50, 300));
+        socket.update_rtt(100);
+        assert_eq!((socket.srtt_ms, socket.rttvar_ms, socket.rto_ms), (100, 37, 248));
+
+        let mut fast = TcpSocket::empty();
+        fast.update_rtt(10);
+        assert_eq!(fast.rto_ms, MIN_RTO_MS);
+        let mut slow = TcpSocket::empty();
+        slow.update_rtt(30_000);
+        assert_eq!(slow.rto_ms, MAX_RTO_MS);
+    }
+
+    #[test_case]
+    fn segments_are_checked_and_their_mss_read() {
+        let mut data = [0u8; TCP_HEADER_LEN + 64];
+        let len = build(&mut data, 23, 1000, 0, FLAG_SYN, &MSS_OPTION, b"");
+        let segment = parse_segment(&ip_header(len), &data[..len]).unwrap();
+        assert_eq!((segment.src_port, segment.dst_port), (PEER_PORT, 23));
+        assert_eq!((segment.seq, segment.flags, segment.window), (1000, FLAG_SYN, 4096));
+        assert_eq!(segment.mss, Some(536));
+
+        let len = build(&mut data, 23, 1000, 0, FLAG_ACK, &[OPT_NOP, OPT_NOP, OPT_NOP, OPT_END], b"hi");
+        let segment = parse_segment(&ip_header(len), &data[..len]).unwrap();
+        assert_eq!(segment.mss, None);
+        assert_eq!(segment.payload, b"hi");
+
+        data[len - 1] ^= 1;
+        assert!(parse_segment(&ip_header(len), &data[..len]).is_none());
+    }
+
+    #[test_case]
+    fn passive_open_receive_and_close() {
+        let net = net();
+        let listener = net.tcp_listen(23).unwrap();
+        deliver(net, 23, 1000, 0, FLAG_SYN, &[]);
+        let child = net.tcp.sockets.iter().position(|s| s.in_use && s.parent == Some(listener.0)).unwrap();
+        let child = TcpHandle(child);
+        assert_eq!(net.tcp.state(child), TcpState::SynReceived);
+        assert!(net.tcp_accept(listener).is_none());
+
+        let iss = net.tcp.sockets[child.0].iss;
+        deliver(net, 23, 1001, iss.wrapping_add(1), FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(child), TcpState::Established);
+        assert_eq!(net.tcp_accept(listener), Some(child));
+
+        deliver(net, 23, 1001, iss.wrapping_add(1), FLAG_ACK | FLAG_PSH, b"hi");
+        let mut buf = [0u8; 8];
+        assert_eq!(net.tcp_recv(child, &mut buf), 2);
+        assert_eq!(&buf[..2], b"hi");
+
+        deliver(net, 23, 1003, iss.wrapping_add(1), FLAG_ACK | FLAG_FIN, &[]);
+        assert_eq!(net.tcp.state(child), TcpState::CloseWait);
+        assert!(net.tcp.peer_closed(child));
+
+        net.tcp_close(child);
+        assert_eq!(net.tcp.state(child), TcpState::LastAck);
+        deliver(net, 23, 1004, iss.wrapping_add(2), FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(child), TcpState::Closed);
+    }
+
+    #[test_case]
+    fn active_close_waits_in_time_wait() {
+        let net = net();
+        let (handle, port) = connect(net, 5000);
+        let iss = net.tcp.sockets[handle.0].iss;
+        net.tcp_close(handle);
+        assert_eq!(net.tcp.state(handle), TcpState::FinWait1);
+        deliver(net, port, 5001, iss.wrapping_add(2), FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::FinWait2);
+        deliver(net, port, 5001, iss.wrapping_add(2), FLAG_ACK | FLAG_FIN, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::TimeWait);
+
+        let until = net.tcp.sockets[handle.0].time_wait_until;
+        net.tcp_poll(until);
+        assert_eq!(net.tcp.state(handle), TcpState::Closed);
+        assert!(!net.tcp.sockets[handle.0].in_use);
+    }
+
+    #[test_case]
+    fn syn_ack_for_another_syn_is_refused() {
+        let net = net();
+        let handle = net.tcp_connect(PEER, PEER_PORT).unwrap();
+        let socket = &net.tcp.sockets[handle.0];
+        let (port, iss) = (socket.local_port, socket.iss);
+        deliver(net, port, 5000, iss.wrapping_add(5), FLAG_SYN | FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::SynSent);
+        deliver(net, port, 5000, iss, FLAG_SYN | FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::SynSent);
+    }
+
+    #[test_case]
+    fn only_a_reset_in_the_window_closes() {
+        let net = net();
+        let (handle, port) = connect(net, 5000);
+        deliver(net, port, 5001 + 100_000, 0, FLAG_RST, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::Established);
+        deliver(net, port, 5001, 0, FLAG_RST, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::Closed);
+        assert!(net.tcp.was_reset(handle));
+    }
+
+    #[test_case]
+    fn retransmissions_back_off_and_are_not_timed() {
+        let net = net();
+        let (handle, _) = connect(net, 5000);
+        assert_eq!(net.tcp_send(handle, b"data"), Ok(4));
+        let socket = &net.tcp.sockets[handle.0];
+        assert!(socket.rtt_seq.is_some());
+        let rto = socket.rto_ms;
+
+        // Karn: the retransmitted data gives no RTT sample
+        net.tcp_poll(socket.retransmit_at.unwrap());
+        let socket = &net.tcp.sockets[handle.0];
+        assert_eq!(socket.retransmissions, 1);
+        assert_eq!(socket.rto_ms, rto * 2);
+        assert!(socket.rtt_seq.is_none());
+        assert_eq!(socket.snd_nxt, socket.snd_una.wrapping_add(4));
+
+        for _ in 0..MAX_RETRANSMISSIONS {
+            let deadline = net.tcp.sockets[handle.0].retransmit_at.unwrap();
+            net.tcp_poll(deadline);
+        }
+        assert_eq!(net.tcp.state(handle), TcpState::Closed);
+        assert!(net.tcp.was_reset(handle));
+    }
+
+    #[test_case]
+    fn data_flows_across_the_sequence_wrap() {
+        let net = net();
+        let handle = net.tcp_connect(PEER, PEER_PORT).unwrap();
+        let socket = &mut net.tcp.sockets[handle.0];
+        socket.iss = u32::MAX - 1;
+        socket.snd_una = u32::MAX - 1;
+        socket.snd_nxt = u32::MAX;
+        let port = socket.local_port;
+        deliver(net, port, u32::MAX - 1, u32::MAX, FLAG_SYN | FLAG_ACK, &[]);
+        assert_eq!(net.tcp.state(handle), TcpState::Established);
+
+        // Received data wraps rcv_nxt
+        deliver(net, port, u32::MAX, u32::MAX, FLAG_ACK, b"abcd");
+        assert_eq!(net.tcp.sockets[handle.0].rcv_nxt, 3);
+        deliver(net, port, u32::MAX, u32::MAX, FLAG_ACK, b"abcd");
+        deliver(net, port, 3, u32::MAX, FLAG_ACK, b"ef");
+        let mut buf = [0u8; 8];
+        assert_eq!(net.tcp_recv(handle, &mut buf), 6);
+        assert_eq!(&buf[..6], b"abcdef");
+
+        // So does sent data, acknowledged past the wrap
+        assert_eq!(net.tcp_send(handle, b"xyz"), Ok(3));
+        assert_eq!(net.tcp.sockets[handle.0].snd_nxt, 2);
+        deliver(net, port, 5, 2, FLAG_ACK, &[]);
+        let socket = &net.tcp.sockets[handle.0];
+        assert_eq!(socket.snd_una, 2);
+        assert!(socket.tx.is_empty());
+        assert!(socket.retransmit_at.is_none());
+    }
+}
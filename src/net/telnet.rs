@@ -0,0 +1,525 @@
+// Telnet remote shell service (RFC 854)
+// Each accepted connection becomes a terminal session with its own output sink
+// and input source. Option negotiation covers ECHO and SUPPRESS-GO-AHEAD (we do
+// both, so the client runs in character mode) and NAWS for the window size.
+// Everything else is refused.
+
+use super::tcp::{TcpHandle, TcpState};
+use super::{Ipv4Addr, NetInterface};
+use crate::drivers::console::{CharSink, CharSource};
+use crate::utils::ring_buffer::RingBuffer;
+
+pub const TELNET_PORT: u16 = 23;
+pub const MAX_TELNET_SESSIONS: usize = 4;
+
+const OUTPUT_BUFFER_SIZE: usize = 8192;
+const INPUT_BUFFER_SIZE: usize = 256;
+const SUBNEGOTIATION_SIZE: usize = 16;
+
+// Commands
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// Options
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+const OPT_NAWS: u8 = 31;
+
+// Per-session byte streams. They live outside the server so that terminals can
+// hold `&'static` sinks and sources pointing at them.
+static mut OUTPUT_BUFFERS: [RingBuffer<OUTPUT_BUFFER_SIZE>; MAX_TELNET_SESSIONS] =
+    [const { RingBuffer::empty() }; MAX_TELNET_SESSIONS];
+static mut INPUT_BUFFERS: [RingBuffer<INPUT_BUFFER_SIZE>; MAX_TELNET_SESSIONS] =
+    [const { RingBuffer::empty() }; MAX_TELNET_SESSIONS];
+
+static SINKS: [TelnetSink; MAX_TELNET_SESSIONS] = [TelnetSink(0), TelnetSink(1), TelnetSink(2), TelnetSink(3)];
+static SOURCES: [TelnetSource; MAX_TELNET_SESSIONS] = [TelnetSource(0), TelnetSource(1), TelnetSource(2), TelnetSource(3)];
+
+fn output_buffer(slot: usize) -> &'static mut RingBuffer<OUTPUT_BUFFER_SIZE> {
+    unsafe { &mut *core::ptr::addr_of_mut!(OUTPUT_BUFFERS[slot]) }
+}
+
+fn input_buffer(slot: usize) -> &'static mut RingBuffer<INPUT_BUFFER_SIZE> {
+    unsafe { &mut *core::ptr::addr_of_mut!(INPUT_BUFFERS[slot]) }
+}
+
+/// Terminal output for a session: LF becomes CR LF and IAC is escaped.
+/// Output is dropped while the buffer is full (a stalled client).
+pub struct TelnetSink(usize);
+
+impl CharSink for TelnetSink {
+    fn putc(&self, c: u8) {
+        let output = output_buffer(self.0);
+        match c {
+            b'\n' => output.push(b"\r\n"),
+            IAC => output.push(&[IAC, IAC]),
+            _ => output.push(&[c]),
+        };
+    }
+}
+
+/// Terminal input for a session, with telnet commands already stripped
+pub struct TelnetSource(usize);
+
+impl CharSource for TelnetSource {
+    fn getc(&self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        if input_buffer(self.0).pop(&mut byte) == 1 { Some(byte[0]) } else { None }
+    }
+}
+
+pub fn sink(slot: usize) -> &'static dyn CharSink {
+    &SINKS[slot]
+}
+
+pub fn source(slot: usize) -> &'static dyn CharSource {
+    &SOURCES[slot]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelnetEvent {
+    Connected(usize),
+    Resized(usize, usize, usize),
+    Disconnected(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ParseState {
+    Data,
+    Cr,
+    Iac,
+    Option(u8),
+    Sub,
+    SubIac,
+}
+
+#[derive(Clone, Copy)]
+struct Connection {
+    handle: Option<TcpHandle>,
+    state: ParseState,
+    // Options currently enabled on our side (WILL) and the client's side (DO)
+    local_options: u32,
+    remote_options: u32,
+    sub: [u8; SUBNEGOTIATION_SIZE],
+    sub_len: usize,
+    columns: usize,
+    rows: usize,
+    connected: bool,
+    resized: bool,
+    closing: bool,
+}
+
+impl Connection {
+    const fn empty() -> Self {
+        Connection {
+            handle: None,
+            state: ParseState::Data,
+            local_options: 0,
+            remote_options: 0,
+            sub: [0; SUBNEGOTIATION_SIZE],
+            sub_len: 0,
+            columns: 0,
+            rows: 0,
+            connected: false,
+            resized: false,
+            closing: false,
+        }
+    }
+}
+
+fn option_bit(option: u8) -> u32 {
+    match option {
+        OPT_ECHO => 1 << 0,
+        OPT_SGA => 1 << 1,
+        OPT_NAWS => 1 << 2,
+        _ => 0,
+    }
+}
+
+pub struct TelnetServer {
+    listener: Option<TcpHandle>,
+    connections: [Connection; MAX_TELNET_SESSIONS],
+}
+
+impl TelnetServer {
+    pub const fn empty() -> Self {
+        TelnetServer {
+            listener: None,
+            connections: [const { Connection::empty() }; MAX_TELNET_SESSIONS],
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    pub fn start(&mut self, net: &mut NetInterface) -> Result<(), &'static str> {
+        if self.listener.is_some() {
+            return Err("Already running");
+        }
+        self.listener = Some(net.tcp_listen(TELNET_PORT)?);
+        Ok(())
+    }
+
+    /// Stop listening and end every open session
+    pub fn stop(&mut self, net: &mut NetInterface) {
+        if let Some(listener) = self.listener.take() {
+            net.tcp_close(listener);
+        }
+        for slot in 0..MAX_TELNET_SESSIONS {
+            self.disconnect(slot);
+        }
+    }
+
+    /// Close a session once its pending output has been sent
+    pub fn disconnect(&mut self, slot: usize) {
+        if self.connections[slot].handle.is_some() {
+            self.connections[slot].closing = true;
+        }
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.connections.iter().filter(|c| c.handle.is_some()).count()
+    }
+
+    pub fn remote(&self, net: &NetInterface, slot: usize) -> Option<(Ipv4Addr, u16)> {
+        self.connections[slot].handle.map(|handle| net.tcp.remote(handle))
+    }
+
+    /// Accept new clients and move data between the sessions and their sockets
+    pub fn poll(&mut self, net: &mut NetInterface) {
+        if let Some(listener) = self.listener {
+            while let Some(handle) = net.tcp_accept(listener) {
+                self.accept(net, handle);
+            }
+        }
+
+        for slot in 0..MAX_TELNET_SESSIONS {
+            let Some(handle) = self.connections[slot].handle else { continue };
+
+            let mut buf = [0u8; 256];
+            loop {
+                let count = net.tcp_recv(handle, &mut buf);
+                if count == 0 {
+                    break;
+                }
+                for &b in &buf[..count] {
+                    self.input_byte(slot, b);
+                }
+            }
+
+            self.flush(net, slot, handle);
+
+            let connection = &mut self.connections[slot];
+            let gone = net.tcp.peer_closed(handle) || net.tcp.is_closed(handle);
+            if gone || (connection.closing && output_buffer(slot).is_empty()) {
+                net.tcp_close(handle);
+                connection.handle = None;
+                connection.closing = false;
+            }
+        }
+    }
+
+    /// Next session event for the shell to act on
+    pub fn next_event(&mut self) -> Option<TelnetEvent> {
+        for (slot, connection) in self.connections.iter_mut().enumerate() {
+            if connection.connected && connection.handle.is_none() {
+                connection.connected = false;
+                return Some(TelnetEvent::Disconnected(slot));
+            }
+            if !connection.connected && connection.handle.is_some() {
+                connection.connected = true;
+                return Some(TelnetEvent::Connected(slot));
+            }
+            if connection.resized && connection.connected {
+                connection.resized = false;
+                return Some(TelnetEvent::Resized(slot, connection.columns, connection.rows));
+            }
+        }
+        None
+    }
+
+    fn accept(&mut self, net: &mut NetInterface, handle: TcpHandle) {
+        // A slot is only reused after the shell has seen its Disconnected event
+        let free = self.connections.iter().position(|c| c.handle.is_none() && !c.connected);
+        let Some(slot) = free else {
+            let _ = net.tcp_send(handle, b"Too many sessions, try again later.\r\n");
+            net.tcp_close(handle);
+            return;
+        };
+
+        output_buffer(slot).clear();
+        input_buffer(slot).clear();
+        let connection = &mut self.connections[slot];
+        *connection = Connection::empty();
+        connection.handle = Some(handle);
+
+        // Ask for character mode with server-side echo, and for the window size.
+        // The options count as enabled now so the client's confirmations are not answered again.
+        self.send_command(slot, WILL, OPT_ECHO);
+        self.send_command(slot, WILL, OPT_SGA);
+        self.send_command(slot, DO, OPT_SGA);
+        self.send_command(slot, DO, OPT_NAWS);
+        let connection = &mut self.connections[slot];
+        connection.local_options = option_bit(OPT_ECHO) | option_bit(OPT_SGA);
+        connection.remote_options = option_bit(OPT_SGA) | option_bit(OPT_NAWS);
+    }
+
+    fn flush(&mut self, net: &mut NetInterface, slot: usize, handle: TcpHandle) {
+        if !matches!(net.tcp.state(handle), TcpState::Established | TcpState::CloseWait) {
+            return;
+        }
+        let output = output_buffer(slot);
+        let mut buf = [0u8; 512];
+        while !output.is_empty() {
+            let count = output.peek_at(0, &mut buf[..net.tcp.send_capacity(handle).min(512)]);
+            if count == 0 {
+                break;
+            }
+            match net.tcp_send(handle, &buf[..count]) {
+                Ok(sent) if sent > 0 => output.consume(sent),
+                _ => break,
+            }
+        }
+    }
+
+    fn send_command(&mut self, slot: usize, command: u8, option: u8) {
+        output_buffer(slot).push(&[IAC, command, option]);
+    }
+
+    fn input_byte(&mut self, slot: usize, b: u8) {
+        let state = self.connections[slot].state;
+        self.connections[slot].state = match state {
+            ParseState::Data => self.data_byte(slot, b),
+            // A client in NVT mode ends lines with CR LF or CR NUL; pass on the CR only
+            ParseState::Cr if b == b'\n' || b == 0 => ParseState::Data,
+            ParseState::Cr => self.data_byte(slot, b),
+            ParseState::Iac => match b {
+                IAC => {
+                    input_buffer(slot).push(&[IAC]);
+                    ParseState::Data
+                }
+                WILL | WONT | DO | DONT => ParseState::Option(b),
+                SB => {
+                    self.connections[slot].sub_len = 0;
+                    ParseState::Sub
+                }
+                // NOP, GA, AYT and friends carry no data for us
+                _ => ParseState::Data,
+            },
+            ParseState::Option(command) => {
+                self.negotiate(slot, command, b);
+                ParseState::Data
+            }
+            ParseState::Sub => {
+                if b == IAC {
+                    ParseState::SubIac
+                } else {
+                    self.sub_byte(slot, b);
+                    ParseState::Sub
+                }
+            }
+            ParseState::SubIac => match b {
+                SE => {
+                    self.subnegotiation(slot);
+                    ParseState::Data
+                }
+                IAC => {
+                    self.sub_byte(slot, IAC);
+                    ParseState::Sub
+                }
+                _ => ParseState::Data,
+            },
+        };
+    }
+
+    fn data_byte(&mut self, slot: usize, b: u8) -> ParseState {
+        match b {
+            IAC => ParseState::Iac,
+            b'\r' => {
+                input_buffer(slot).push(&[b]);
+                ParseState::Cr
+            }
+            _ => {
+                input_buffer(slot).push(&[b]);
+                ParseState::Data
+            }
+        }
+    }
+
+    fn sub_byte(&mut self, slot: usize, b: u8) {
+        let connection = &mut self.connections[slot];
+        if connection.sub_len < SUBNEGOTIATION_SIZE {
+            connection.sub[connection.sub_len] = b;
+            connection.sub_len += 1;
+        }
+    }
+
+    /// Answer WILL/WONT/DO/DONT, replying only when an option actually changes
+    /// state so that the two sides cannot loop (RFC 854, "Option negotiation")
+    fn negotiate(&mut self, slot: usize, command: u8, option: u8) {
+        let bit = option_bit(option);
+        let connection = &mut self.connections[slot];
+        let reply = match command {
+            DO if option == OPT_ECHO || option == OPT_SGA => {
+                if connection.local_options & bit != 0 {
+                    None
+                } else {
+                    connection.local_options |= bit;
+                    Some(WILL)
+                }
+            }
+            DO => Some(WONT),
+            DONT => {
+                if connection.local_options & bit != 0 {
+                    connection.local_options &= !bit;
+                    Some(WONT)
+                } else {
+                    None
+                }
+            }
+            WILL if option == OPT_SGA || option == OPT_NAWS => {
+                if connection.remote_options & bit != 0 {
+                    None
+                } else {
+                    connection.remote_options |= bit;
+                    Some(DO)
+                }
+            }
+            WILL => Some(DONT),
+            _ => {
+                // WONT
+                if connection.remote_options & bit != 0 {
+                    connection.remote_options &= !bit;
+                    Some(DONT)
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(reply) = reply {
+            self.send_command(slot, reply, option);
+        }
+    }
+
+    fn subnegotiation(&mut self, slot: usize) {
+        let connection = &mut self.connections[slot];
+        let sub = &connection.sub[..connection.sub_len];
+        // NAWS: IAC SB NAWS <width16> <height16> IAC SE
+        if sub.len() >= 5 && sub[0] == OPT_NAWS {
+            let columns = u16::from_be_bytes([sub[1], sub[2]]) as usize;
+            let rows = u16::from_be_bytes([sub[3], sub[4]]) as usize;
+            if columns > 0 && rows > 0 {
+                connection.columns = columns;
+                connection.rows = rows;
+                connection.resized = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPT_TERMINAL_TYPE: u8 = 24;
+
+    fn server() -> TelnetServer {
+        output_buffer(0).clear();
+        input_buffer(0).clear();
+        TelnetServer::empty()
+    }
+
+    fn feed(server: &mut TelnetServer, bytes: &[u8]) {
+        for &b in bytes {
+            server.input_byte(0, b);
+        }
+    }
+
+    fn assert_drained<const N: usize>(buffer: &mut RingBuffer<N>, expected: &[u8]) {
+        let mut buf = [0u8; 64];
+        let count = buffer.pop(&mut buf);
+        assert_eq!(&buf[..count], expected);
+    }
+
+    #[test_case]
+    fn output_escapes_iac_and_ends_lines_with_cr_lf() {
+        let _ = server();
+        for &b in b"a\nb" {
+            sink(0).putc(b);
+        }
+        sink(0).putc(IAC);
+        assert_drained(output_buffer(0), b"a\r\nb\xff\xff");
+    }
+
+    #[test_case]
+    fn input_unescapes_iac() {
+        let mut server = server();
+        feed(&mut server, &[b'x', IAC, IAC, b'y']);
+        assert_drained(input_buffer(0), b"x\xffy");
+        assert_eq!(source(0).getc(), None);
+    }
+
+    #[test_case]
+    fn cr_nul_and_cr_lf_pass_on_the_cr_only() {
+        let mut server = server();
+        feed(&mut server, b"ls\r\0pwd\r\nx\ry");
+        assert_drained(input_buffer(0), b"ls\rpwd\rx\ry");
+    }
+
+    #[test_case]
+    fn commands_carry_no_data() {
+        let mut server = server();
+        // NOP and AYT between data bytes
+        feed(&mut server, &[b'a', IAC, 241, b'b', IAC, 246, b'c']);
+        assert_drained(input_buffer(0), b"abc");
+    }
+
+    #[test_case]
+    fn naws_sets_the_window_size() {
+        let mut server = server();
+        // 80x255: the 255 of the height is doubled as IAC IAC
+        feed(&mut server, &[IAC, SB, OPT_NAWS, 0, 80, 0, IAC, IAC, IAC, SE, b'z']);
+        let connection = &server.connections[0];
+        assert_eq!((connection.columns, connection.rows), (80, 255));
+        assert!(connection.resized);
+        assert_drained(input_buffer(0), b"z");
+
+        // A zero size is ignored
+        feed(&mut server, &[IAC, SB, OPT_NAWS, 0, 0, 0, 24, IAC, SE]);
+        assert_eq!((server.connections[0].columns, server.connections[0].rows), (80, 255));
+    }
+
+    #[test_case]
+    fn repeated_requests_are_not_answered_again() {
+        let mut server = server();
+        feed(&mut server, &[IAC, DO, OPT_ECHO]);
+        assert_drained(output_buffer(0), &[IAC, WILL, OPT_ECHO]);
+        feed(&mut server, &[IAC, DO, OPT_ECHO]);
+        assert_drained(output_buffer(0), &[]);
+
+        feed(&mut server, &[IAC, WILL, OPT_NAWS]);
+        assert_drained(output_buffer(0), &[IAC, DO, OPT_NAWS]);
+        feed(&mut server, &[IAC, WILL, OPT_NAWS]);
+        assert_drained(output_buffer(0), &[]);
+
+        feed(&mut server, &[IAC, DONT, OPT_ECHO, IAC, DONT, OPT_ECHO]);
+        assert_drained(output_buffer(0), &[IAC, WONT, OPT_ECHO]);
+        feed(&mut server, &[IAC, WONT, OPT_NAWS, IAC, WONT, OPT_NAWS]);
+        assert_drained(output_buffer(0), &[IAC, DONT, OPT_NAWS]);
+    }
+
+    #[test_case]
+    fn unsupported_options_are_refused() {
+        let mut server = server();
+        feed(&mut server, &[IAC, DO, OPT_TERMINAL_TYPE, IAC, WILL, OPT_TERMINAL_TYPE]);
+        assert_drained(output_buffer(0), &[IAC, WONT, OPT_TERMINAL_TYPE, IAC, DONT, OPT_TERMINAL_TYPE]);
+        assert_eq!(server.connections[0].local_options, 0);
+        assert_eq!(server.connections[0].remote_options, 0);
+    }
+}
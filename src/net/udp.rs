@@ -0,0 +1,199 @@
+// UDP: datagram sockets bound to local ports
+
+use super::ipv4::{Ipv4Header, PROTOCOL_UDP};
+use super::{checksum, pseudo_header_sum, Ipv4Addr, NetInterface};
+
+pub const UDP_HEADER_LEN: usize = 8;
+pub const MAX_UDP_PAYLOAD: usize = 1472;
+
+const MAX_UDP_SOCKETS: usize = 8;
+const UDP_QUEUE_LEN: usize = 4;
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Handle to a bound UDP socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpHandle(usize);
+
+#[derive(Clone, Copy)]
+struct Datagram {
+    src: Ipv4Addr,
+    src_port: u16,
+    data: [u8; MAX_UDP_PAYLOAD],
+    len: usize,
+}
+
+impl Datagram {
+    const fn empty() -> Self {
+        Datagram {
+            src: Ipv4Addr::UNSPECIFIED,
+            src_port: 0,
+            data: [0; MAX_UDP_PAYLOAD],
+            len: 0,
+        }
+    }
+}
+
+struct UdpSocket {
+    local_port: u16,
+    queue: [Datagram; UDP_QUEUE_LEN],
+    queue_head: usize,
+    queue_len: usize,
+    is_open: bool,
+}
+
+impl UdpSocket {
+    const fn empty() -> Self {
+        UdpSocket {
+            local_port: 0,
+            queue: [Datagram::empty(); UDP_QUEUE_LEN],
+            queue_head: 0,
+            queue_len: 0,
+            is_open: false,
+        }
+    }
+}
+
+pub struct UdpSockets {
+    sockets: [UdpSocket; MAX_UDP_SOCKETS],
+    next_ephemeral: u16,
+}
+
+impl UdpSockets {
+    pub const fn empty() -> Self {
+        UdpSockets {
+            sockets: [const { UdpSocket::empty() }; MAX_UDP_SOCKETS],
+            next_ephemeral: EPHEMERAL_PORT_START,
+        }
+    }
+
+    fn is_port_bound(&self, port: u16) -> bool {
+        self.sockets.iter().any(|s| s.is_open && s.local_port == port)
+    }
+
+    fn ephemeral_port(&mut self) -> u16 {
+        loop {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if self.next_ephemeral == u16::MAX {
+                EPHEMERAL_PORT_START
+            } else {
+                self.next_ephemeral + 1
+            };
+            if !self.is_port_bound(port) {
+                return port;
+            }
+        }
+    }
+
+    /// Bind a socket to `port` (0 picks an ephemeral port)
+    pub fn bind(&mut self, port: u16) -> Result<UdpHandle, &'static str> {
+        let port = if port == 0 { self.ephemeral_port() } else { port };
+        if self.is_port_bound(port) {
+            return Err("Port already in use");
+        }
+        let index = self.sockets.iter().position(|s| !s.is_open)
+            .ok_or("No free UDP sockets")?;
+        let socket = &mut self.sockets[index];
+        socket.local_port = port;
+        socket.queue_head = 0;
+        socket.queue_len = 0;
+        socket.is_open = true;
+        Ok(UdpHandle(index))
+    }
+
+    pub fn close(&mut self, handle: UdpHandle) {
+        self.sockets[handle.0].is_open = false;
+    }
+
+    pub fn local_port(&self, handle: UdpHandle) -> u16 {
+        self.sockets[handle.0].local_port
+    }
+
+    /// Take the oldest queued datagram: returns (length, source address, source port)
+    pub fn recv_from(&mut self, handle: UdpHandle, buf: &mut [u8]) -> Option<(usize, Ipv4Addr, u16)> {
+        let socket = &mut self.sockets[handle.0];
+        if !socket.is_open || socket.queue_len == 0 {
+            return None;
+        }
+        let datagram = &socket.queue[socket.queue_head];
+        let len = datagram.len.min(buf.len());
+        buf[..len].copy_from_slice(&datagram.data[..len]);
+        let result = (len, datagram.src, datagram.src_port);
+        socket.queue_head = (socket.queue_head + 1) % UDP_QUEUE_LEN;
+        socket.queue_len -= 1;
+        Some(result)
+    }
+
+    fn deliver(&mut self, port: u16, src: Ipv4Addr, src_port: u16, data: &[u8]) {
+        let socket = match self.sockets.iter_mut().find(|s| s.is_open && s.local_port == port) {
+            Some(s) => s,
+            None => return,
+        };
+        if socket.queue_len == UDP_QUEUE_LEN {
+            return; // Queue full: drop, as UDP allows
+        }
+        let slot = (socket.queue_head + socket.queue_len) % UDP_QUEUE_LEN;
+        let datagram = &mut socket.queue[slot];
+        datagram.src = src;
+        datagram.src_port = src_port;
+        datagram.len = data.len().min(MAX_UDP_PAYLOAD);
+        datagram.data[..datagram.len].copy_from_slice(&data[..datagram.len]);
+        socket.queue_len += 1;
+    }
+}
+
+impl NetInterface {
+    pub(super) fn udp_input(&mut self, header: &Ipv4Header, segment: &[u8]) {
+        if segment.len() < UDP_HEADER_LEN {
+            return;
+        }
+        let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+        let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+        let length = u16::from_be_bytes([segment[4], segment[5]]) as usize;
+        let stored_sum = u16::from_be_bytes([segment[6], segment[7]]);
+        if length < UDP_HEADER_LEN || length > segment.len() {
+            return;
+        }
+        let segment = &segment[..length];
+        // A zero checksum means the sender did not compute one
+        if stored_sum != 0 {
+            let initial = pseudo_header_sum(header.src, header.dst, PROTOCOL_UDP, length);
+            if checksum(segment, initial) != 0 {
+                return;
+            }
+        }
+
+        let data = &segment[UDP_HEADER_LEN..];
+        if dst_port == super::dhcp::DHCP_CLIENT_PORT {
+            self.dhcp_input(data);
+            return;
+        }
+        self.udp.deliver(dst_port, header.src, src_port, data);
+    }
+
+    /// Send `data` from the socket's port to `dst:dst_port`
+    pub fn udp_send_to(&mut self, handle: UdpHandle, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Result<(), &'static str> {
+        let src_port = self.udp.local_port(handle);
+        self.send_udp(src_port, dst, dst_port, data)
+    }
+
+    pub(super) fn send_udp(&mut self, src_port: u16, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Result<(), &'static str> {
+        if data.len() > MAX_UDP_PAYLOAD {
+            return Err("Datagram too large");
+        }
+        let len = UDP_HEADER_LEN + data.len();
+        let mut segment = [0u8; UDP_HEADER_LEN + MAX_UDP_PAYLOAD];
+        segment[0..2].copy_from_slice(&src_port.to_be_bytes());
+        segment[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        segment[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+        segment[UDP_HEADER_LEN..len].copy_from_slice(data);
+
+        let initial = pseudo_header_sum(self.config.address, dst, PROTOCOL_UDP, len);
+        let mut sum = checksum(&segment[..len], initial);
+        if sum == 0 {
+            sum = 0xffff;
+        }
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+
+        self.send_ipv4(dst, PROTOCOL_UDP, &segment[..len])
+    }
+}
@@ -0,0 +1,304 @@
+// Shell commands
+// Every command the prompt runs is a Command: its name, the arguments it
+// takes, a line of help, how to complete its arguments and what it does.
+// They are kept in a Registry, which `help`, `help <command>`, usage errors
+// and Tab completion all read, so a command shows up everywhere once it is
+// registered. The built-in commands are a table of Builtins in main.rs; a
+// subsystem can register commands of its own at boot the same way, or with
+// a type of its own implementing Command.
+use core::fmt::Write;
+
+use jamos_core::terminal::completion::{CommandSet, Completer};
+use jamos_core::terminal::{Screen, VirtualDesktopManager, vdesktop::VirtualDesktop};
+
+use crate::{Origin, Session};
+
+pub const MAX_COMMANDS: usize = 48;
+
+/// What is wrong with a command's arguments; the shell reports it along
+/// with the command's usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgError {
+    // Missing, extra or unknown arguments
+    Usage,
+    // An argument that makes no sense, and why
+    Invalid(&'static str),
+}
+
+pub trait Command {
+    fn name(&self) -> &'static str;
+
+    /// The arguments as `help` shows them after the name, like
+    /// `[on|off]`; empty for a command without any
+    fn usage(&self) -> &'static str;
+
+    /// One line on what the command does
+    fn help(&self) -> &'static str;
+
+    /// What completes the arguments on Tab
+    fn completer(&self) -> Option<Completer> {
+        None
+    }
+
+    /// Run with the text after the name, blanks around it trimmed. A
+    /// command without a usage is never given arguments.
+    fn run(&self, args: &str, context: &mut Context) -> Result<(), ArgError>;
+}
+
+/// A command made of a function and its description
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub completer: Option<Completer>,
+    pub run: fn(&str, &mut Context) -> Result<(), ArgError>,
+}
+
+impl Command for Builtin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn completer(&self) -> Option<Completer> {
+        self.completer
+    }
+
+    fn run(&self, args: &str, context: &mut Context) -> Result<(), ArgError> {
+        (self.run)(args, context)
+    }
+}
+
+enum Terminal<'a> {
+    // The console's desktops; commands run on the current one
+    Desktops(&'a mut VirtualDesktopManager),
+    // A remote session's only desktop
+    Desktop(&'a mut VirtualDesktop),
+}
+
+/// Where a command runs: the terminal's desktop and shell state
+pub struct Context<'a> {
+    terminal: Terminal<'a>,
+    pub session: &'a mut Session,
+    pub origin: Origin,
+    // Cleared by a command that takes over the terminal (edit, nc) or has
+    // drawn a prompt of its own, as after switching desktops
+    pub prompt: bool,
+}
+
+impl<'a> Context<'a> {
+    /// The serial console, with all its desktops
+    pub fn console(vdm: &'a mut VirtualDesktopManager, session: &'a mut Session) -> Self {
+        let origin = Origin::Console { index: vdm.get_index(), count: vdm.get_count() };
+        Context { terminal: Terminal::Desktops(vdm), session, origin, prompt: true }
+    }
+
+    /// A terminal with a single desktop
+    pub fn new(desktop: &'a mut VirtualDesktop, session: &'a mut Session, origin: Origin) -> Self {
+        Context { terminal: Terminal::Desktop(desktop), session, origin, prompt: true }
+    }
+
+    pub fn desktop(&mut self) -> &mut VirtualDesktop {
+        match &mut self.terminal {
+            Terminal::Desktops(vdm) => vdm.current_mut().expect("the console always has a desktop"),
+            Terminal::Desktop(desktop) => desktop,
+        }
+    }
+
+    /// Screen of the active pane
+    pub fn screen(&mut self) -> &mut Screen {
+        self.desktop().screen_mut()
+    }
+
+    /// The console's desktop manager; None in a remote session
+    pub fn desktops(&mut self) -> Option<&mut VirtualDesktopManager> {
+        match &mut self.terminal {
+            Terminal::Desktops(vdm) => Some(vdm),
+            Terminal::Desktop(_) => None,
+        }
+    }
+
+    /// The desktop and the shell state at once, for commands that hand the
+    /// terminal to the editor or netcat
+    pub fn desktop_and_session(&mut self) -> (&mut VirtualDesktop, &mut Session) {
+        let desktop = match &mut self.terminal {
+            Terminal::Desktops(vdm) => vdm.current_mut().expect("the console always has a desktop"),
+            Terminal::Desktop(desktop) => desktop,
+        };
+        (desktop, self.session)
+    }
+}
+
+/// The commands the shell knows, in the order `help` lists them
+pub struct Registry {
+    commands: [Option<&'static dyn Command>; MAX_COMMANDS],
+}
+
+impl Registry {
+    pub const fn empty() -> Self {
+        Registry { commands: [None; MAX_COMMANDS] }
+    }
+
+    /// Add a command; one with the name of a registered command takes its
+    /// place
+    pub fn register(&mut self, command: &'static dyn Command) -> Result<(), &'static str> {
+        let name = command.name();
+        let slot = match self.commands.iter().position(|c| c.is_some_and(|c| c.name() == name)) {
+            Some(slot) => slot,
+            None => self.commands.iter().position(Option::is_none).ok_or("Too many commands")?,
+        };
+        self.commands[slot] = Some(command);
+        Ok(())
+    }
+
+    pub fn find(&self, name: &str) -> Option<&'static dyn Command> {
+        self.iter().find(|command| command.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static dyn Command> + '_ {
+        self.commands.iter().flatten().copied()
+    }
+
+    /// Run a command line typed at the prompt: the first word names the
+    /// command and the rest are its arguments. Then a prompt follows,
+    /// unless the command has drawn its own or taken over the terminal.
+    pub fn run(&self, line: &str, context: &mut Context) {
+        let line = line.trim();
+        context.desktop().clear_input();
+        context.screen().puts("\n");
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        match self.find(name) {
+            Some(command) => {
+                let args = args.trim();
+                let result = if command.usage().is_empty() && !args.is_empty() {
+                    Err(ArgError::Usage)
+                } else {
+                    command.run(args, context)
+                };
+                if let Err(e) = result {
+                    print_arg_error(context.screen(), command, e);
+                }
+            }
+            None if line.is_empty() => {}
+            None => {
+                let _ = writeln!(context.screen(), "Unknown command: {}", line);
+            }
+        }
+        if context.prompt {
+            crate::show_current_prompt(context.desktop());
+        }
+    }
+
+    /// `help`: every command, with its usage
+    pub fn print_help(&self, screen: &mut Screen) {
+        screen.puts("Available commands:\n");
+        for command in self.iter() {
+            let _ = write!(screen, "  {:<7} - {}", command.name(), command.help());
+            if !command.usage().is_empty() {
+                screen.puts(" (usage: ");
+                for (i, usage) in alternatives(command.usage()).enumerate() {
+                    let _ = write!(screen, "{}{} {}", if i > 0 { " | " } else { "" }, command.name(), usage);
+                }
+                screen.puts(")");
+            }
+            screen.puts("\n");
+        }
+    }
+}
+
+/// Tab completion reads the command names and completers from here
+impl CommandSet for Registry {
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|command| command.name())
+    }
+
+    fn completer(&self, name: &str) -> Option<Completer> {
+        self.find(name).and_then(|command| command.completer())
+    }
+}
+
+/// The ways of calling a command in its usage: `a | b [c | d]` is `a` and
+/// `b [c | d]`
+fn alternatives(usage: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0;
+    usage
+        .split(move |c| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            c == '|' && depth == 0
+        })
+        .map(str::trim)
+}
+
+/// One line for each way of calling the command, each with its name
+fn print_usage(screen: &mut Screen, command: &dyn Command) {
+    if command.usage().is_empty() {
+        let _ = writeln!(screen, "Usage: {}", command.name());
+        return;
+    }
+    for (i, usage) in alternatives(command.usage()).enumerate() {
+        let _ = writeln!(screen, "{} {} {}", if i == 0 { "Usage:" } else { "      " }, command.name(), usage);
+    }
+}
+
+/// `help <command>`
+pub fn print_command_help(screen: &mut Screen, command: &dyn Command) {
+    print_usage(screen, command);
+    let _ = writeln!(screen, "{}", command.help());
+}
+
+fn print_arg_error(screen: &mut Screen, command: &dyn Command, e: ArgError) {
+    if let ArgError::Invalid(why) = e {
+        let _ = writeln!(screen, "{}: {}", command.name(), why);
+    }
+    print_usage(screen, command);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jamos_core::terminal::completion::{complete, Candidates, CompletionContext};
+
+    fn complete_switch(_: &CompletionContext, out: &mut Candidates) {
+        out.add_all(&["on", "off"]);
+    }
+
+    fn run_nothing(_: &str, _: &mut Context) -> Result<(), ArgError> {
+        Ok(())
+    }
+
+    static STATUS: Builtin = Builtin { name: "status", usage: "[on|off]", help: "", completer: Some(complete_switch), run: run_nothing };
+    static PLAIN_STATUS: Builtin = Builtin { name: "status", usage: "", help: "", completer: None, run: run_nothing };
+    static SYNC: Builtin = Builtin { name: "sync", usage: "", help: "", completer: None, run: run_nothing };
+
+    #[test_case]
+    fn usage_alternatives_split_outside_brackets() {
+        assert!(alternatives("<n> | new [<name>] | close [<n>]").eq(["<n>", "new [<name>]", "close [<n>]"]));
+        assert!(alternatives("[-c | -w [<name>] | -r [<name>]]").eq(["[-c | -w [<name>] | -r [<name>]]"]));
+        assert!(alternatives("<ip> <port>").eq(["<ip> <port>"]));
+    }
+
+    #[test_case]
+    fn completion_reads_the_registry() {
+        let mut registry = Registry::empty();
+        registry.register(&STATUS).unwrap();
+        registry.register(&SYNC).unwrap();
+        assert!(complete(&registry, "s").iter().eq(["status", "sync"]));
+        assert!(complete(&registry, "status o").iter().eq(["off", "on"]));
+
+        // Registering again replaces the command and its completer
+        registry.register(&PLAIN_STATUS).unwrap();
+        assert_eq!(registry.iter().count(), 2);
+        assert!(complete(&registry, "status o").is_empty());
+    }
+}
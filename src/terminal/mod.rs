@@ -1,7 +0,0 @@
-pub mod vdesktop;
-pub mod screen;
-pub mod tiling;
-
-pub use vdesktop::VirtualDesktopManager;
-pub use screen::Screen;
-pub use tiling::TilingManager;
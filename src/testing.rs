@@ -0,0 +1,54 @@
+// In-kernel test framework for `cargo qemu-test`
+// The test harness collects every `#[test_case]` function; the booted kernel
+// runs them in turn, reports over the UART and stops QEMU through semihosting
+// with exit status 0 (all passed) or 1 (a test panicked). The hosted build
+// runs the same tests as a normal Linux test binary.
+
+use core::fmt::{self, Write};
+#[cfg(not(feature = "hosted"))]
+use core::panic::PanicInfo;
+
+use crate::drivers::semihosting;
+use crate::drivers::uart::Uart;
+
+/// Formatted output for test results and panic messages
+struct TestOutput;
+
+impl Write for TestOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Uart::new().puts(s);
+        Ok(())
+    }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        let _ = write!(TestOutput, "test {} ... ", core::any::type_name::<T>());
+        self();
+        let _ = writeln!(TestOutput, "ok");
+    }
+}
+
+pub fn run_tests(tests: &[&dyn Testable]) {
+    let _ = writeln!(TestOutput, "\nrunning {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    let _ = writeln!(TestOutput, "\ntest result: ok. {} passed; 0 failed", tests.len());
+    semihosting::write0("jamos: all tests passed\n");
+    semihosting::exit(0);
+}
+
+/// A panic fails the running test and ends the run: there is no unwinding
+/// to recover from it. Hosted test runs get std's panic handling instead.
+#[cfg(not(feature = "hosted"))]
+pub fn panic(info: &PanicInfo) {
+    let _ = writeln!(TestOutput, "FAILED\n\n{}", info);
+    let _ = writeln!(TestOutput, "\ntest result: FAILED");
+    semihosting::write0("jamos: test failed\n");
+    semihosting::exit(1);
+}
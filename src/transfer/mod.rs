@@ -0,0 +1,410 @@
+// Serial file transfer between the VFS and a host terminal program
+// (lrzsz `sz`/`rz`/`sb`/`rb`/`sx`/`rx`, minicom, ...). Supports XMODEM-CRC,
+// YMODEM batch and ZMODEM. A transfer owns the port until it finishes: the
+// shell is blocked and nothing else may write to it in the meantime.
+
+pub mod xmodem;
+pub mod zmodem;
+
+use crate::drivers::console::{CharSink, CharSource};
+use crate::drivers::timer;
+use crate::filesystem::{VirtualFileSystem, MAX_FILE_SIZE};
+use crate::terminal::Screen;
+use crate::text;
+use crate::utils::print_number;
+
+const CAN: u8 = 0x18;
+const BACKSPACE: u8 = 0x08;
+
+// The user has about a minute to start the program on the host
+const START_INTERVAL_MS: u64 = 3000;
+const START_ATTEMPTS: usize = 20;
+
+const MAX_NAME_LEN: usize = 32;
+const MAX_REPORTED_FILES: usize = 8;
+
+// Received data may carry up to one block of padding past the real end of file
+const FILE_BUFFER_SIZE: usize = MAX_FILE_SIZE + xmodem::BLOCK_SIZE_1K;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Receive: YMODEM or ZMODEM, whichever the sender speaks.
+    /// Send: ZMODEM, or YMODEM if the receiver asks for blocks instead.
+    Auto,
+    Xmodem,
+    Ymodem,
+    Zmodem,
+}
+
+/// Raw byte channel the protocols run over
+pub struct Port {
+    sink: &'static dyn CharSink,
+    source: &'static dyn CharSource,
+}
+
+impl Port {
+    pub fn new(sink: &'static dyn CharSink, source: &'static dyn CharSource) -> Self {
+        Port { sink, source }
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        for &b in bytes {
+            self.sink.putc(b);
+        }
+    }
+
+    /// Wait up to `timeout_ms` for the next byte
+    fn read(&self, timeout_ms: u64) -> Option<u8> {
+        let start = timer::uptime_ms();
+        loop {
+            if let Some(b) = self.source.getc() {
+                return Some(b);
+            }
+            if timer::uptime_ms() - start >= timeout_ms {
+                return None;
+            }
+        }
+    }
+
+    /// Discard input until the line has been quiet for a second
+    fn purge(&self) {
+        while self.read(1000).is_some() {}
+    }
+
+    /// Abort the transfer on the other side: a run of CANs, then backspaces
+    /// to erase them in case the other side was not in a transfer at all
+    fn cancel(&self) {
+        self.write(&[CAN; 8]);
+        self.write(&[BACKSPACE; 8]);
+    }
+
+    /// Send `request` every few seconds until the other side answers with a
+    /// byte `accept` likes; two CANs in a row cancel the wait
+    fn handshake(&self, request: &[u8], accept: impl Fn(u8) -> bool) -> Result<u8, &'static str> {
+        let mut cancels = 0;
+        for _ in 0..START_ATTEMPTS {
+            self.write(request);
+            let start = timer::uptime_ms();
+            loop {
+                let elapsed = timer::uptime_ms() - start;
+                if elapsed >= START_INTERVAL_MS {
+                    break;
+                }
+                let Some(b) = self.read(START_INTERVAL_MS - elapsed) else { break };
+                if accept(b) {
+                    return Ok(b);
+                }
+                if b == CAN {
+                    cancels += 1;
+                    if cancels >= 2 {
+                        return Err("Cancelled");
+                    }
+                } else {
+                    cancels = 0;
+                }
+            }
+        }
+        Err("Timed out waiting for the other side")
+    }
+}
+
+/// Contents of the file currently being transferred
+struct FileData {
+    bytes: [u8; FILE_BUFFER_SIZE],
+    len: usize,
+}
+
+impl FileData {
+    const fn empty() -> Self {
+        FileData {
+            bytes: [0; FILE_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn append(&mut self, data: &[u8]) -> bool {
+        if self.len + data.len() > FILE_BUFFER_SIZE {
+            return false;
+        }
+        self.bytes[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+        true
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    fn load(&mut self, vfs: &VirtualFileSystem, name: &[u8]) -> Result<(), &'static str> {
+        let name = core::str::from_utf8(name).map_err(|_| "Invalid file name")?;
+        let inode_id = vfs.find_inode_by_name(name).ok_or("File not found")?;
+        self.len = vfs.read_file(inode_id, &mut self.bytes)?;
+        Ok(())
+    }
+}
+
+/// Store a received file under the last component of the sender's path name
+fn store_file(vfs: &mut VirtualFileSystem, path: &[u8], data: &[u8], report: &mut Report) {
+    let name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+    let (name, result) = match core::str::from_utf8(name) {
+        Ok(name) if !name.is_empty() => {
+            let name = text::truncate(name, MAX_NAME_LEN);
+            (name.as_bytes(), vfs.save_file(name, data).map(|_| ()))
+        }
+        _ => (name, Err("Invalid file name")),
+    };
+    report.record(name, data.len(), result);
+}
+
+/// Parse the "name NUL size ..." file information that YMODEM block 0 and
+/// ZMODEM's ZFILE subpacket share
+fn parse_file_info(info: &[u8]) -> (&[u8], Option<usize>) {
+    let name_end = info.iter().position(|&b| b == 0).unwrap_or(info.len());
+    let rest = info.get(name_end + 1..).unwrap_or(&[]);
+    let size_end = rest.iter().position(|&b| !b.is_ascii_digit()).unwrap_or(rest.len());
+    (&info[..name_end], crate::utils::parse_number(&rest[..size_end]))
+}
+
+#[derive(Clone, Copy)]
+struct FileRecord {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    size: usize,
+    result: Result<(), &'static str>,
+}
+
+impl FileRecord {
+    const fn empty() -> Self {
+        FileRecord {
+            name: [0; MAX_NAME_LEN],
+            name_len: 0,
+            size: 0,
+            result: Ok(()),
+        }
+    }
+}
+
+/// Outcome of each file, printed once the port is back in terminal use
+pub struct Report {
+    files: [FileRecord; MAX_REPORTED_FILES],
+    count: usize,
+}
+
+impl Report {
+    pub const fn empty() -> Self {
+        Report {
+            files: [FileRecord::empty(); MAX_REPORTED_FILES],
+            count: 0,
+        }
+    }
+
+    fn record(&mut self, name: &[u8], size: usize, result: Result<(), &'static str>) {
+        if self.count == MAX_REPORTED_FILES {
+            return;
+        }
+        let record = &mut self.files[self.count];
+        record.name_len = name.len().min(MAX_NAME_LEN);
+        record.name[..record.name_len].copy_from_slice(&name[..record.name_len]);
+        record.size = size;
+        record.result = result;
+        self.count += 1;
+    }
+
+    /// Number of files transferred successfully
+    pub fn completed(&self) -> usize {
+        self.files[..self.count].iter().filter(|f| f.result.is_ok()).count()
+    }
+
+    pub fn print(&self, screen: &mut Screen) {
+        for file in &self.files[..self.count] {
+            screen.puts("  ");
+            for &b in &file.name[..file.name_len] {
+                screen.putc(b);
+            }
+            match file.result {
+                Ok(()) => {
+                    screen.puts(" (");
+                    print_number(screen, file.size);
+                    screen.puts(" bytes)\n");
+                }
+                Err(e) => {
+                    screen.puts(": ");
+                    screen.puts(e);
+                    screen.puts("\n");
+                }
+            }
+        }
+    }
+}
+
+/// Receive files into the VFS. XMODEM carries no file name, so it is given.
+pub fn receive(port: &Port, protocol: Protocol, vfs: &mut VirtualFileSystem, xmodem_name: &[u8], report: &mut Report) -> Result<(), &'static str> {
+    match protocol {
+        Protocol::Xmodem => xmodem::receive_xmodem(port, vfs, xmodem_name, report),
+        Protocol::Ymodem => xmodem::receive_ymodem(port, vfs, report, None),
+        Protocol::Zmodem => zmodem::receive(port, vfs, report),
+        Protocol::Auto => {
+            // Ask for YMODEM blocks; a ZMODEM sender ignores that and announces
+            // itself with "rz\r" and a ZRQINIT header instead
+            let first = port.handshake(&[xmodem::CRC_REQUEST], |b| {
+                b == xmodem::SOH || b == xmodem::STX || b == zmodem::ZPAD
+            })?;
+            if first == zmodem::ZPAD {
+                zmodem::receive(port, vfs, report)
+            } else {
+                xmodem::receive_ymodem(port, vfs, report, Some(first))
+            }
+        }
+    }
+}
+
+/// Send VFS files; XMODEM can only carry a single file
+pub fn send(port: &Port, protocol: Protocol, vfs: &VirtualFileSystem, names: &[&[u8]], report: &mut Report) -> Result<(), &'static str> {
+    match protocol {
+        Protocol::Xmodem => xmodem::send_xmodem(port, vfs, names[0], report),
+        Protocol::Ymodem => xmodem::send_ymodem(port, vfs, names, report, false),
+        Protocol::Zmodem | Protocol::Auto => {
+            // Announce ZMODEM; this also starts `rz` in terminals that watch for it.
+            // A YMODEM receiver answers with 'C' instead of a ZRINIT header.
+            let auto = protocol == Protocol::Auto;
+            let first = port.handshake(&zmodem::ANNOUNCE, |b| {
+                b == zmodem::ZPAD || (auto && b == xmodem::CRC_REQUEST)
+            })?;
+            if first == zmodem::ZPAD {
+                zmodem::send(port, vfs, names, report)
+            } else {
+                xmodem::send_ymodem(port, vfs, names, report, true)
+            }
+        }
+    }
+}
+
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    // CRC-16/XMODEM: polynomial 0x1021, no reflection
+    let mut crc = crc ^ ((byte as u16) << 8);
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| crc16_update(crc, b))
+}
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    // CRC-32 (IEEE 802.3), reflected
+    let mut crc = crc ^ byte as u32;
+    for _ in 0..8 {
+        crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ring_buffer::RingBuffer;
+
+    const LOOPBACK_SIZE: usize = 4096;
+
+    // Bytes the code under test writes, and the bytes it will read. Tests must
+    // queue every byte they expect to be read: an empty line only times out.
+    static mut WRITTEN: RingBuffer<LOOPBACK_SIZE> = RingBuffer::empty();
+    static mut READABLE: RingBuffer<LOOPBACK_SIZE> = RingBuffer::empty();
+
+    fn written() -> &'static mut RingBuffer<LOOPBACK_SIZE> {
+        unsafe { &mut *core::ptr::addr_of_mut!(WRITTEN) }
+    }
+
+    fn readable() -> &'static mut RingBuffer<LOOPBACK_SIZE> {
+        unsafe { &mut *core::ptr::addr_of_mut!(READABLE) }
+    }
+
+    struct Loopback;
+
+    impl CharSink for Loopback {
+        fn putc(&self, c: u8) {
+            written().push(&[c]);
+        }
+    }
+
+    impl CharSource for Loopback {
+        fn getc(&self) -> Option<u8> {
+            let mut byte = [0u8; 1];
+            if readable().pop(&mut byte) == 1 { Some(byte[0]) } else { None }
+        }
+    }
+
+    static LOOPBACK: Loopback = Loopback;
+
+    /// A port over empty loopback buffers
+    pub(super) fn port() -> Port {
+        written().clear();
+        readable().clear();
+        Port::new(&LOOPBACK, &LOOPBACK)
+    }
+
+    /// Queue bytes for the port to read
+    pub(super) fn feed(bytes: &[u8]) {
+        assert_eq!(readable().push(bytes), bytes.len());
+    }
+
+    /// Take everything written to the port so far
+    pub(super) fn take_written(buf: &mut [u8]) -> usize {
+        written().pop(buf)
+    }
+
+    /// Read back everything written so far
+    pub(super) fn loop_back() {
+        let mut buf = [0u8; LOOPBACK_SIZE];
+        let count = take_written(&mut buf);
+        feed(&buf[..count]);
+    }
+
+    static mut VFS: VirtualFileSystem = VirtualFileSystem::empty();
+
+    #[test_case]
+    fn stored_names_are_cut_at_a_character_boundary() {
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        vfs.init();
+        let mut report = Report::empty();
+
+        // 31 ASCII bytes and a two-byte character: 33 bytes
+        let path = "dir/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaé";
+        store_file(vfs, path.as_bytes(), b"data", &mut report);
+        assert_eq!(report.completed(), 1);
+        let record = &report.files[0];
+        assert_eq!(&record.name[..record.name_len], &[b'a'; 31][..]);
+        assert!(vfs.find_inode_by_name("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").is_some());
+
+        store_file(vfs, b"bad\xff", b"data", &mut report);
+        assert_eq!(report.files[1].result, Err("Invalid file name"));
+    }
+
+    #[test_case]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test_case]
+    fn crc32_check_value() {
+        let crc = b"123456789".iter().fold(!0, |crc, &b| crc32_update(crc, b));
+        assert_eq!(!crc, 0xCBF4_3926);
+    }
+
+    #[test_case]
+    fn file_info_name_and_size() {
+        assert_eq!(parse_file_info(b"notes.txt\x00123 0 0\0"), (&b"notes.txt"[..], Some(123)));
+        assert_eq!(parse_file_info(b"bare\0"), (&b"bare"[..], None));
+        assert_eq!(parse_file_info(b"\0"), (&b""[..], None));
+    }
+}
@@ -0,0 +1,386 @@
+// XMODEM-CRC and YMODEM batch transfers (Christensen/Forsberg)
+// YMODEM is XMODEM with 1K blocks plus a block 0 carrying the file name and
+// size before each file; an empty block 0 ends the batch.
+
+use super::{crc16, parse_file_info, store_file, FileData, Port, Report, CAN, START_ATTEMPTS, START_INTERVAL_MS};
+use crate::filesystem::VirtualFileSystem;
+use crate::utils::format_number;
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const SUB: u8 = 0x1A;
+/// Sent by the receiver to ask for CRC mode (instead of NAK for checksums)
+pub const CRC_REQUEST: u8 = b'C';
+
+const BLOCK_SIZE: usize = 128;
+pub const BLOCK_SIZE_1K: usize = 1024;
+
+const BLOCK_TIMEOUT_MS: u64 = 10_000;
+const BYTE_TIMEOUT_MS: u64 = 1_000;
+const MAX_ERRORS: usize = 10;
+
+enum Block {
+    Data(u8, usize),
+    Eot,
+    Cancel,
+    Bad,
+}
+
+/// Read the rest of a block whose first byte was `header`
+fn read_block(port: &Port, header: Option<u8>, buf: &mut [u8; BLOCK_SIZE_1K]) -> Block {
+    let size = match header {
+        Some(SOH) => BLOCK_SIZE,
+        Some(STX) => BLOCK_SIZE_1K,
+        Some(EOT) => return Block::Eot,
+        Some(CAN) => {
+            return if port.read(BYTE_TIMEOUT_MS) == Some(CAN) { Block::Cancel } else { Block::Bad };
+        }
+        _ => return Block::Bad,
+    };
+
+    let mut fields = [0u8; 2];
+    for byte in fields.iter_mut() {
+        match port.read(BYTE_TIMEOUT_MS) {
+            Some(b) => *byte = b,
+            None => return Block::Bad,
+        }
+    }
+    for byte in buf[..size].iter_mut() {
+        match port.read(BYTE_TIMEOUT_MS) {
+            Some(b) => *byte = b,
+            None => return Block::Bad,
+        }
+    }
+    let crc_high = port.read(BYTE_TIMEOUT_MS);
+    let crc_low = port.read(BYTE_TIMEOUT_MS);
+
+    let (number, complement) = (fields[0], fields[1]);
+    let crc = match (crc_high, crc_low) {
+        (Some(high), Some(low)) => u16::from_be_bytes([high, low]),
+        _ => return Block::Bad,
+    };
+    if number != !complement || crc != crc16(&buf[..size]) {
+        return Block::Bad;
+    }
+    Block::Data(number, size)
+}
+
+/// Receive numbered blocks into `file` until EOT. `header` is the first byte
+/// of block 1 when the handshake already read it.
+fn receive_blocks(port: &Port, mut header: Option<u8>, file: &mut FileData) -> Result<(), &'static str> {
+    let mut buf = [0u8; BLOCK_SIZE_1K];
+    let mut expected: u8 = 1;
+    let mut errors = 0;
+    loop {
+        let first = header.take().or_else(|| port.read(BLOCK_TIMEOUT_MS));
+        match read_block(port, first, &mut buf) {
+            Block::Data(number, size) if number == expected => {
+                if !file.append(&buf[..size]) {
+                    port.cancel();
+                    return Err("File too large");
+                }
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                port.write(&[ACK]);
+            }
+            Block::Data(number, _) if number == expected.wrapping_sub(1) => {
+                // Our ACK was lost and the sender repeated the block
+                port.write(&[ACK]);
+            }
+            Block::Data(..) => {
+                port.cancel();
+                return Err("Lost block synchronisation");
+            }
+            Block::Eot => {
+                port.write(&[ACK]);
+                return Ok(());
+            }
+            Block::Cancel => return Err("Cancelled by sender"),
+            Block::Bad => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    port.cancel();
+                    return Err("Too many errors");
+                }
+                port.purge();
+                port.write(&[NAK]);
+            }
+        }
+    }
+}
+
+pub fn receive_xmodem(port: &Port, vfs: &mut VirtualFileSystem, name: &[u8], report: &mut Report) -> Result<(), &'static str> {
+    let mut file = FileData::empty();
+    let first = port.handshake(&[CRC_REQUEST], |b| b == SOH || b == STX || b == EOT)?;
+    receive_blocks(port, Some(first), &mut file)?;
+
+    strip_padding(&mut file);
+    store_file(vfs, name, file.as_slice(), report);
+    Ok(())
+}
+
+/// Without a size from the sender, trailing SUB characters are taken to be
+/// the padding of the last block
+fn strip_padding(file: &mut FileData) {
+    let mut len = file.as_slice().len();
+    while len > 0 && file.as_slice()[len - 1] == SUB {
+        len -= 1;
+    }
+    file.truncate(len);
+}
+
+/// Receive a YMODEM batch. `first` is the first byte of block 0 when the
+/// caller's handshake already read it.
+pub fn receive_ymodem(port: &Port, vfs: &mut VirtualFileSystem, report: &mut Report, mut first: Option<u8>) -> Result<(), &'static str> {
+    let mut buf = [0u8; BLOCK_SIZE_1K];
+    let mut file = FileData::empty();
+    let mut errors = 0;
+    loop {
+        // Block 0: file name and size, or an empty name at the end of the batch
+        let header = match first.take() {
+            Some(b) => b,
+            None => port.handshake(&[CRC_REQUEST], |b| b == SOH || b == STX || b == EOT)?,
+        };
+        let size = match read_block(port, Some(header), &mut buf) {
+            Block::Data(0, size) => size,
+            Block::Cancel => return Err("Cancelled by sender"),
+            Block::Eot => {
+                // Repeated EOT of the previous file after our ACK got lost
+                port.write(&[ACK]);
+                continue;
+            }
+            _ => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    port.cancel();
+                    return Err("Too many errors");
+                }
+                port.purge();
+                continue;
+            }
+        };
+        port.write(&[ACK]);
+
+        let (name, length) = parse_file_info(&buf[..size]);
+        if name.is_empty() {
+            return Ok(());
+        }
+        if let Some(length) = length {
+            if length > crate::filesystem::MAX_FILE_SIZE {
+                // YMODEM has no way to skip a single file
+                port.cancel();
+                report.record(name, length, Err("File too large"));
+                return Err("Transfer cancelled");
+            }
+        }
+
+        // Data blocks follow after a fresh CRC request
+        file.clear();
+        let header = port.handshake(&[CRC_REQUEST], |b| b == SOH || b == STX || b == EOT)?;
+        receive_blocks(port, Some(header), &mut file)?;
+        match length {
+            Some(length) => file.truncate(length),
+            None => strip_padding(&mut file),
+        }
+        store_file(vfs, name, file.as_slice(), report);
+        errors = 0;
+    }
+}
+
+/// Send a block until it is acknowledged. Data that fits goes out as a
+/// 128-byte block to save padding, anything longer as a 1K block.
+fn send_block(port: &Port, number: u8, data: &[u8], pad: u8) -> Result<(), &'static str> {
+    let size = if data.len() > BLOCK_SIZE { BLOCK_SIZE_1K } else { BLOCK_SIZE };
+    let mut block = [pad; BLOCK_SIZE_1K];
+    block[..data.len()].copy_from_slice(data);
+    let crc = crc16(&block[..size]);
+
+    for _ in 0..MAX_ERRORS {
+        port.write(&[if size == BLOCK_SIZE { SOH } else { STX }, number, !number]);
+        port.write(&block[..size]);
+        port.write(&crc.to_be_bytes());
+        match wait_response(port) {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err("Cancelled by receiver"),
+            _ => {}
+        }
+    }
+    Err("Too many errors")
+}
+
+/// Next ACK, NAK or CAN from the receiver, skipping line noise
+fn wait_response(port: &Port) -> Option<u8> {
+    loop {
+        match port.read(BLOCK_TIMEOUT_MS)? {
+            CAN => {
+                return if port.read(BYTE_TIMEOUT_MS) == Some(CAN) { Some(CAN) } else { None };
+            }
+            b @ (ACK | NAK) => return Some(b),
+            _ => {}
+        }
+    }
+}
+
+fn send_data(port: &Port, data: &[u8]) -> Result<(), &'static str> {
+    let mut number: u8 = 1;
+    let mut offset = 0;
+    while offset < data.len() {
+        // 1K blocks while a full one remains, 128-byte blocks for the tail
+        let size = if data.len() - offset >= BLOCK_SIZE_1K { BLOCK_SIZE_1K } else { BLOCK_SIZE };
+        let end = (offset + size).min(data.len());
+        send_block(port, number, &data[offset..end], SUB)?;
+        number = number.wrapping_add(1);
+        offset = end;
+    }
+    for _ in 0..MAX_ERRORS {
+        port.write(&[EOT]);
+        match wait_response(port) {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err("Cancelled by receiver"),
+            _ => {}
+        }
+    }
+    Err("No acknowledgement for EOT")
+}
+
+fn wait_crc_request(port: &Port) -> Result<(), &'static str> {
+    let start = crate::drivers::timer::uptime_ms();
+    let limit = START_INTERVAL_MS * START_ATTEMPTS as u64;
+    while crate::drivers::timer::uptime_ms() - start < limit {
+        match port.read(BLOCK_TIMEOUT_MS) {
+            Some(CRC_REQUEST) => return Ok(()),
+            Some(CAN) if port.read(BYTE_TIMEOUT_MS) == Some(CAN) => return Err("Cancelled by receiver"),
+            _ => {}
+        }
+    }
+    Err("Timed out waiting for the receiver")
+}
+
+pub fn send_xmodem(port: &Port, vfs: &VirtualFileSystem, name: &[u8], report: &mut Report) -> Result<(), &'static str> {
+    let mut file = FileData::empty();
+    if let Err(e) = file.load(vfs, name) {
+        report.record(name, 0, Err(e));
+        return Err(e);
+    }
+    wait_crc_request(port)?;
+    send_data(port, file.as_slice())?;
+    report.record(name, file.as_slice().len(), Ok(()));
+    Ok(())
+}
+
+/// Send a YMODEM batch. `started` is set when the caller's handshake already
+/// consumed the receiver's first CRC request.
+pub fn send_ymodem(port: &Port, vfs: &VirtualFileSystem, names: &[&[u8]], report: &mut Report, mut started: bool) -> Result<(), &'static str> {
+    let mut file = FileData::empty();
+    for &name in names {
+        if let Err(e) = file.load(vfs, name) {
+            report.record(name, 0, Err(e));
+            continue;
+        }
+        if !started {
+            wait_crc_request(port)?;
+        }
+        started = false;
+
+        // Block 0: "name NUL size NUL"
+        let mut info = [0u8; BLOCK_SIZE];
+        let name_len = name.len().min(BLOCK_SIZE - 24);
+        info[..name_len].copy_from_slice(&name[..name_len]);
+        format_number(file.as_slice().len(), &mut info[name_len + 1..]);
+        send_block(port, 0, &info, 0)?;
+
+        wait_crc_request(port)?;
+        send_data(port, file.as_slice())?;
+        report.record(name, file.as_slice().len(), Ok(()));
+    }
+
+    // An empty block 0 ends the batch
+    if !started {
+        wait_crc_request(port)?;
+    }
+    send_block(port, 0, &[], 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::tests::{feed, loop_back, port, take_written};
+
+    #[test_case]
+    fn short_data_goes_out_as_a_padded_128_byte_block() {
+        let port = port();
+        feed(&[ACK]);
+        send_block(&port, 1, b"hello", SUB).unwrap();
+
+        let mut out = [0u8; 256];
+        assert_eq!(take_written(&mut out), 3 + BLOCK_SIZE + 2);
+        assert_eq!(&out[..3], &[SOH, 1, 0xFE]);
+        assert_eq!(&out[3..8], b"hello");
+        assert!(out[8..3 + BLOCK_SIZE].iter().all(|&b| b == SUB));
+        let crc = crc16(&out[3..3 + BLOCK_SIZE]);
+        assert_eq!(&out[3 + BLOCK_SIZE..3 + BLOCK_SIZE + 2], &crc.to_be_bytes());
+    }
+
+    #[test_case]
+    fn longer_data_goes_out_as_a_1k_block() {
+        let port = port();
+        feed(&[ACK]);
+        send_block(&port, 2, &[0x55; BLOCK_SIZE + 1], SUB).unwrap();
+        loop_back();
+
+        let mut buf = [0u8; BLOCK_SIZE_1K];
+        let header = port.read(BYTE_TIMEOUT_MS);
+        assert_eq!(header, Some(STX));
+        assert!(matches!(read_block(&port, header, &mut buf), Block::Data(2, BLOCK_SIZE_1K)));
+        assert_eq!(buf[BLOCK_SIZE], 0x55);
+        assert_eq!(buf[BLOCK_SIZE + 1], SUB);
+    }
+
+    #[test_case]
+    fn blocks_with_a_bad_crc_or_number_are_rejected() {
+        let mut buf = [0u8; BLOCK_SIZE_1K];
+        let mut frame = [0u8; 3 + BLOCK_SIZE + 2];
+        for corrupt in [None, Some(2), Some(50), Some(3 + BLOCK_SIZE + 1)] {
+            let port = port();
+            feed(&[ACK]);
+            send_block(&port, 1, b"hello", SUB).unwrap();
+            take_written(&mut frame);
+            if let Some(i) = corrupt {
+                frame[i] ^= 1;
+            }
+            feed(&frame[1..]);
+            let block = read_block(&port, Some(frame[0]), &mut buf);
+            if corrupt.is_none() {
+                assert!(matches!(block, Block::Data(1, BLOCK_SIZE)));
+            } else {
+                assert!(matches!(block, Block::Bad));
+            }
+        }
+    }
+
+    #[test_case]
+    fn received_blocks_are_acknowledged_and_repeats_skipped() {
+        let port = port();
+        feed(&[ACK; 3]);
+        send_block(&port, 1, b"first", SUB).unwrap();
+        send_block(&port, 2, b"second", SUB).unwrap();
+        // The second block again, as after a lost ACK
+        send_block(&port, 2, b"second", SUB).unwrap();
+        loop_back();
+        feed(&[EOT]);
+
+        let mut file = FileData::empty();
+        receive_blocks(&port, None, &mut file).unwrap();
+        let mut out = [0u8; 8];
+        assert_eq!(take_written(&mut out), 4);
+        assert_eq!(&out[..4], &[ACK; 4]);
+
+        strip_padding(&mut file);
+        assert_eq!(file.as_slice().len(), BLOCK_SIZE + 6);
+        assert_eq!(&file.as_slice()[..5], b"first");
+        assert_eq!(&file.as_slice()[BLOCK_SIZE..], b"second");
+    }
+}
@@ -0,0 +1,628 @@
+// ZMODEM (Forsberg, 1988) without crash recovery, compression or remote
+// commands. Headers and data are accepted with 16- or 32-bit CRCs; we only
+// send 16-bit CRCs, which every receiver has to support.
+
+use super::{crc16, crc16_update, crc32_update, parse_file_info, store_file, FileData, Port, Report};
+use crate::filesystem::{VirtualFileSystem, MAX_FILE_SIZE};
+use crate::drivers::timer;
+use crate::utils::format_number;
+
+pub const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCAN: u8 = 16;
+
+// Data subpacket terminators
+const ZCRCE: u8 = b'h'; // end of frame, header follows
+const ZCRCG: u8 = b'i'; // frame continues, no response
+const ZCRCQ: u8 = b'j'; // frame continues, ZACK expected
+const ZCRCW: u8 = b'k'; // end of frame, ZACK expected
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// ZRINIT capabilities (ZF0)
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+const DLE: u8 = 0x10;
+
+const HEADER_TIMEOUT_MS: u64 = 10_000;
+const BYTE_TIMEOUT_MS: u64 = 2_000;
+const MAX_ERRORS: usize = 10;
+const SUBPACKET_SIZE: usize = 1024;
+
+/// "rz\r" followed by a hex ZRQINIT header; terminal programs that see it
+/// start their ZMODEM receiver
+pub const ANNOUNCE: [u8; 24] = *b"rz\r**\x18B00000000000000\r\x8a\x11";
+
+#[derive(Clone, Copy)]
+struct Header {
+    kind: u8,
+    // ZP0..ZP3, or ZF3..ZF0 for flag headers
+    data: [u8; 4],
+    crc32: bool,
+}
+
+impl Header {
+    fn new(kind: u8, data: [u8; 4]) -> Self {
+        Header { kind, data, crc32: false }
+    }
+
+    fn with_position(kind: u8, position: usize) -> Self {
+        Header::new(kind, (position as u32).to_le_bytes())
+    }
+
+    fn position(&self) -> usize {
+        u32::from_le_bytes(self.data) as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Error {
+    Timeout,
+    Bad,
+    Cancelled,
+}
+
+enum Unescaped {
+    Byte(u8),
+    End(u8),
+}
+
+fn needs_escape(b: u8) -> bool {
+    matches!(b & 0x7f, ZDLE | DLE | XON | XOFF)
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0x0f) as usize]
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+fn read_raw(port: &Port, timeout_ms: u64) -> Result<u8, Error> {
+    port.read(timeout_ms).ok_or(Error::Timeout)
+}
+
+/// Read one byte of ZDLE-encoded data, or a subpacket terminator
+fn read_escaped(port: &Port) -> Result<Unescaped, Error> {
+    loop {
+        match read_raw(port, BYTE_TIMEOUT_MS)? {
+            ZDLE => break,
+            // Flow control characters are never data unless escaped
+            XON | XOFF | 0x91 | 0x93 => continue,
+            b => return Ok(Unescaped::Byte(b)),
+        }
+    }
+    // Five CANs (ZDLE is CAN) in a row abort the session
+    let mut cancels = 1;
+    loop {
+        match read_raw(port, BYTE_TIMEOUT_MS)? {
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(Error::Cancelled);
+                }
+            }
+            XON | XOFF | 0x91 | 0x93 => continue,
+            c @ (ZCRCE | ZCRCG | ZCRCQ | ZCRCW) => return Ok(Unescaped::End(c)),
+            ZRUB0 => return Ok(Unescaped::Byte(0x7f)),
+            ZRUB1 => return Ok(Unescaped::Byte(0xff)),
+            c if c & 0x60 == 0x40 => return Ok(Unescaped::Byte(c ^ 0x40)),
+            _ => return Err(Error::Bad),
+        }
+    }
+}
+
+fn read_escaped_bytes(port: &Port, buf: &mut [u8]) -> Result<(), Error> {
+    for byte in buf.iter_mut() {
+        match read_escaped(port)? {
+            Unescaped::Byte(b) => *byte = b,
+            Unescaped::End(_) => return Err(Error::Bad),
+        }
+    }
+    Ok(())
+}
+
+/// Hunt for the next header (ZPAD ... ZDLE format) and decode it
+fn read_header(port: &Port, timeout_ms: u64) -> Result<Header, Error> {
+    let start = timer::uptime_ms();
+    let mut padded = false;
+    let mut cancels = 0;
+    let format = loop {
+        let elapsed = timer::uptime_ms() - start;
+        if elapsed >= timeout_ms {
+            return Err(Error::Timeout);
+        }
+        let c = read_raw(port, timeout_ms - elapsed)?;
+        match c {
+            ZPAD | 0xaa => padded = true,
+            ZDLE if padded => break read_raw(port, BYTE_TIMEOUT_MS)?,
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(Error::Cancelled);
+                }
+            }
+            _ => {
+                padded = false;
+                cancels = 0;
+            }
+        }
+    };
+
+    let mut raw = [0u8; 9];
+    match format {
+        ZBIN => {
+            read_escaped_bytes(port, &mut raw[..7])?;
+            // Running the CRC over the data and its CRC leaves zero
+            if crc16(&raw[..7]) != 0 {
+                return Err(Error::Bad);
+            }
+            Ok(Header { kind: raw[0], data: [raw[1], raw[2], raw[3], raw[4]], crc32: false })
+        }
+        ZBIN32 => {
+            read_escaped_bytes(port, &mut raw)?;
+            let crc = !raw[..5].iter().fold(0xFFFF_FFFF, |crc, &b| crc32_update(crc, b));
+            if crc.to_le_bytes() != raw[5..9] {
+                return Err(Error::Bad);
+            }
+            Ok(Header { kind: raw[0], data: [raw[1], raw[2], raw[3], raw[4]], crc32: true })
+        }
+        ZHEX => {
+            for byte in raw[..7].iter_mut() {
+                let high = hex_value(read_raw(port, BYTE_TIMEOUT_MS)? & 0x7f).ok_or(Error::Bad)?;
+                let low = hex_value(read_raw(port, BYTE_TIMEOUT_MS)? & 0x7f).ok_or(Error::Bad)?;
+                *byte = (high << 4) | low;
+            }
+            if crc16(&raw[..7]) != 0 {
+                return Err(Error::Bad);
+            }
+            // Hex headers end with CR LF; a subpacket may follow directly after
+            if port.read(BYTE_TIMEOUT_MS).is_some_and(|c| c & 0x7f == b'\r') {
+                let _ = port.read(BYTE_TIMEOUT_MS);
+            }
+            Ok(Header { kind: raw[0], data: [raw[1], raw[2], raw[3], raw[4]], crc32: false })
+        }
+        _ => Err(Error::Bad),
+    }
+}
+
+/// Read a data subpacket into `buf`; returns its length and terminator
+fn read_subpacket(port: &Port, buf: &mut [u8], crc32: bool) -> Result<(usize, u8), Error> {
+    let mut len = 0;
+    loop {
+        match read_escaped(port)? {
+            Unescaped::Byte(b) => {
+                if len == buf.len() {
+                    return Err(Error::Bad);
+                }
+                buf[len] = b;
+                len += 1;
+            }
+            Unescaped::End(end) => {
+                let valid = if crc32 {
+                    let mut received = [0u8; 4];
+                    read_escaped_bytes(port, &mut received)?;
+                    let crc = buf[..len].iter().fold(0xFFFF_FFFF, |crc, &b| crc32_update(crc, b));
+                    !crc32_update(crc, end) == u32::from_le_bytes(received)
+                } else {
+                    let mut received = [0u8; 2];
+                    read_escaped_bytes(port, &mut received)?;
+                    crc16_update(crc16(&buf[..len]), end) == u16::from_be_bytes(received)
+                };
+                return if valid { Ok((len, end)) } else { Err(Error::Bad) };
+            }
+        }
+    }
+}
+
+fn write_escaped(port: &Port, data: &[u8]) {
+    for &b in data {
+        if needs_escape(b) {
+            port.write(&[ZDLE, b ^ 0x40]);
+        } else {
+            port.write(&[b]);
+        }
+    }
+}
+
+fn send_hex_header(port: &Port, header: Header) {
+    let mut raw = [0u8; 7];
+    raw[0] = header.kind;
+    raw[1..5].copy_from_slice(&header.data);
+    let crc = crc16(&raw[..5]);
+    raw[5..7].copy_from_slice(&crc.to_be_bytes());
+
+    let mut out = [0u8; 21];
+    out[..4].copy_from_slice(&[ZPAD, ZPAD, ZDLE, ZHEX]);
+    for (i, &b) in raw.iter().enumerate() {
+        out[4 + i * 2] = hex_digit(b >> 4);
+        out[5 + i * 2] = hex_digit(b);
+    }
+    out[18] = b'\r';
+    out[19] = b'\n' | 0x80;
+    // XON restarts a sender that stopped on a stray XOFF; not after ZACK or ZFIN
+    out[20] = XON;
+    let len = if header.kind == ZACK || header.kind == ZFIN { 20 } else { 21 };
+    port.write(&out[..len]);
+}
+
+fn send_binary_header(port: &Port, header: Header) {
+    let mut raw = [0u8; 7];
+    raw[0] = header.kind;
+    raw[1..5].copy_from_slice(&header.data);
+    let crc = crc16(&raw[..5]);
+    raw[5..7].copy_from_slice(&crc.to_be_bytes());
+    port.write(&[ZPAD, ZDLE, ZBIN]);
+    write_escaped(port, &raw);
+}
+
+fn send_subpacket(port: &Port, data: &[u8], end: u8) {
+    write_escaped(port, data);
+    port.write(&[ZDLE, end]);
+    let crc = crc16_update(crc16(data), end);
+    write_escaped(port, &crc.to_be_bytes());
+    if end == ZCRCW {
+        port.write(&[XON]);
+    }
+}
+
+fn send_rinit(port: &Port) {
+    // Buffer size 0: the sender may stream the whole file without waiting
+    send_hex_header(port, Header::new(ZRINIT, [0, 0, 0, CANFDX | CANOVIO]));
+}
+
+/// Receive a batch of files. The sender's ZRQINIT may already have been
+/// partly consumed; a ZRINIT from us starts the session either way.
+pub fn receive(port: &Port, vfs: &mut VirtualFileSystem, report: &mut Report) -> Result<(), &'static str> {
+    let mut buf = [0u8; SUBPACKET_SIZE];
+    let mut name = [0u8; SUBPACKET_SIZE];
+    let mut name_len = 0;
+    let mut file = FileData::empty();
+    let mut receiving = false;
+    let mut errors = 0;
+
+    send_rinit(port);
+    loop {
+        let header = match read_header(port, HEADER_TIMEOUT_MS) {
+            Ok(header) => header,
+            Err(Error::Cancelled) => return Err("Cancelled by sender"),
+            Err(_) => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    port.cancel();
+                    return Err("Too many errors");
+                }
+                if receiving {
+                    send_hex_header(port, Header::with_position(ZRPOS, file.as_slice().len()));
+                } else {
+                    send_rinit(port);
+                }
+                continue;
+            }
+        };
+
+        match header.kind {
+            ZRQINIT => send_rinit(port),
+            ZSINIT => {
+                // The attention string is of no use to us; just acknowledge it
+                let _ = read_subpacket(port, &mut buf, header.crc32);
+                send_hex_header(port, Header::new(ZACK, [0; 4]));
+            }
+            ZFILE => match read_subpacket(port, &mut buf, header.crc32) {
+                Ok((len, _)) => {
+                    let (path, size) = parse_file_info(&buf[..len]);
+                    if size.is_some_and(|size| size > MAX_FILE_SIZE) {
+                        report.record(path, size.unwrap_or(0), Err("File too large"));
+                        send_hex_header(port, Header::new(ZSKIP, [0; 4]));
+                    } else {
+                        name_len = path.len();
+                        name[..name_len].copy_from_slice(path);
+                        file.clear();
+                        receiving = true;
+                        send_hex_header(port, Header::with_position(ZRPOS, 0));
+                    }
+                }
+                Err(Error::Cancelled) => return Err("Cancelled by sender"),
+                Err(_) => send_hex_header(port, Header::new(ZNAK, [0; 4])),
+            },
+            ZDATA if receiving => {
+                if header.position() != file.as_slice().len() {
+                    // Data we cannot use; ask for a restart where we are
+                    send_hex_header(port, Header::with_position(ZRPOS, file.as_slice().len()));
+                    continue;
+                }
+                loop {
+                    match read_subpacket(port, &mut buf, header.crc32) {
+                        Ok((len, end)) => {
+                            if !file.append(&buf[..len]) {
+                                port.cancel();
+                                report.record(&name[..name_len], file.as_slice().len(), Err("File too large"));
+                                return Err("Transfer cancelled");
+                            }
+                            errors = 0;
+                            let position = file.as_slice().len();
+                            match end {
+                                ZCRCG => continue,
+                                ZCRCQ => {
+                                    send_hex_header(port, Header::with_position(ZACK, position));
+                                    continue;
+                                }
+                                ZCRCW => send_hex_header(port, Header::with_position(ZACK, position)),
+                                _ => {}
+                            }
+                            break;
+                        }
+                        Err(Error::Cancelled) => return Err("Cancelled by sender"),
+                        Err(_) => {
+                            errors += 1;
+                            if errors >= MAX_ERRORS {
+                                port.cancel();
+                                return Err("Too many errors");
+                            }
+                            send_hex_header(port, Header::with_position(ZRPOS, file.as_slice().len()));
+                            break;
+                        }
+                    }
+                }
+            }
+            ZEOF => {
+                // A ZEOF for another position is stale and gets ignored
+                if receiving && header.position() == file.as_slice().len() {
+                    store_file(vfs, &name[..name_len], file.as_slice(), report);
+                    receiving = false;
+                    send_rinit(port);
+                } else if !receiving {
+                    send_rinit(port);
+                }
+            }
+            ZFIN => {
+                send_hex_header(port, Header::new(ZFIN, [0; 4]));
+                // The sender signs off with "OO"
+                let _ = port.read(1000);
+                let _ = port.read(100);
+                return Ok(());
+            }
+            ZCAN | ZABORT | ZFERR => {
+                port.cancel();
+                return Err("Cancelled by sender");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Wait for a header, skipping any of the given kinds
+fn wait_header(port: &Port, ignore: &[u8]) -> Result<Header, Error> {
+    loop {
+        let header = read_header(port, HEADER_TIMEOUT_MS)?;
+        if !ignore.contains(&header.kind) {
+            return Ok(header);
+        }
+    }
+}
+
+/// Send one file; returns false if the receiver skipped it
+fn send_file(port: &Port, name: &[u8], data: &[u8]) -> Result<bool, &'static str> {
+    // File information: "name NUL size NUL"
+    let mut info = [0u8; 64];
+    let name_len = name.len().min(info.len() - 24);
+    info[..name_len].copy_from_slice(&name[..name_len]);
+    let size_len = format_number(data.len(), &mut info[name_len + 1..]);
+    let info_len = name_len + 1 + size_len + 1;
+
+    let mut errors = 0;
+    let mut position = loop {
+        send_binary_header(port, Header::new(ZFILE, [0; 4]));
+        send_subpacket(port, &info[..info_len], ZCRCW);
+        match wait_header(port, &[ZRINIT, ZACK]) {
+            Ok(header) if header.kind == ZRPOS => break header.position(),
+            Ok(header) if header.kind == ZSKIP => return Ok(false),
+            Ok(header) if header.kind == ZCAN || header.kind == ZABORT => return Err("Cancelled by receiver"),
+            Err(Error::Cancelled) => return Err("Cancelled by receiver"),
+            _ => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    return Err("No response to ZFILE");
+                }
+            }
+        }
+    };
+
+    loop {
+        // Stream from `position` to the end, then report the end of file
+        let start = position.min(data.len());
+        send_binary_header(port, Header::with_position(ZDATA, start));
+        let mut chunks = data[start..].chunks(SUBPACKET_SIZE).peekable();
+        if chunks.peek().is_none() {
+            send_subpacket(port, &[], ZCRCE);
+        }
+        while let Some(chunk) = chunks.next() {
+            send_subpacket(port, chunk, if chunks.peek().is_some() { ZCRCG } else { ZCRCE });
+        }
+        send_binary_header(port, Header::with_position(ZEOF, data.len()));
+
+        match wait_header(port, &[ZACK]) {
+            Ok(header) if header.kind == ZRINIT => return Ok(true),
+            Ok(header) if header.kind == ZSKIP => return Ok(false),
+            Ok(header) if header.kind == ZRPOS => position = header.position(),
+            Ok(header) if header.kind == ZCAN || header.kind == ZABORT => return Err("Cancelled by receiver"),
+            Err(Error::Cancelled) => return Err("Cancelled by receiver"),
+            _ => position = data.len(),
+        }
+        errors += 1;
+        if errors >= MAX_ERRORS {
+            return Err("Too many errors");
+        }
+    }
+}
+
+/// Send files once the receiver has started answering our ZRQINIT
+pub fn send(port: &Port, vfs: &VirtualFileSystem, names: &[&[u8]], report: &mut Report) -> Result<(), &'static str> {
+    let mut errors = 0;
+    loop {
+        match wait_header(port, &[ZRQINIT]) {
+            Ok(header) if header.kind == ZRINIT => break,
+            Err(Error::Cancelled) => return Err("Cancelled by receiver"),
+            _ => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    return Err("No response from receiver");
+                }
+                send_hex_header(port, Header::new(ZRQINIT, [0; 4]));
+            }
+        }
+    }
+
+    let mut file = FileData::empty();
+    for &name in names {
+        if let Err(e) = file.load(vfs, name) {
+            report.record(name, 0, Err(e));
+            continue;
+        }
+        match send_file(port, name, file.as_slice()) {
+            Ok(true) => report.record(name, file.as_slice().len(), Ok(())),
+            Ok(false) => report.record(name, file.as_slice().len(), Err("Skipped by receiver")),
+            Err(e) => {
+                port.cancel();
+                report.record(name, file.as_slice().len(), Err(e));
+                return Err(e);
+            }
+        }
+    }
+
+    for _ in 0..MAX_ERRORS {
+        send_hex_header(port, Header::new(ZFIN, [0; 4]));
+        match wait_header(port, &[ZRINIT, ZACK]) {
+            Ok(header) if header.kind == ZFIN => {
+                port.write(b"OO");
+                return Ok(());
+            }
+            Err(Error::Cancelled) => return Err("Cancelled by receiver"),
+            _ => {}
+        }
+    }
+    Err("No response to ZFIN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::tests::{feed, loop_back, port, take_written};
+
+    #[test_case]
+    fn every_byte_survives_zdle_escaping() {
+        let port = port();
+        let mut data = [0u8; 256];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        write_escaped(&port, &data);
+
+        let mut out = [0u8; 512];
+        let count = take_written(&mut out);
+        // ZDLE, DLE, XON and XOFF (and their high-bit forms) are sent as pairs
+        assert_eq!(count, 256 + 8);
+        for pair in out[..count].windows(2) {
+            if pair[0] != ZDLE {
+                assert!(!needs_escape(pair[1]) || pair[1] == ZDLE);
+            }
+        }
+
+        feed(&out[..count]);
+        for &b in &data {
+            assert!(matches!(read_escaped(&port), Ok(Unescaped::Byte(c)) if c == b));
+        }
+    }
+
+    #[test_case]
+    fn five_cans_cancel() {
+        let port = port();
+        feed(&[ZDLE; 5]);
+        assert!(matches!(read_escaped(&port), Err(Error::Cancelled)));
+    }
+
+    #[test_case]
+    fn subpackets_round_trip_and_check_their_crc() {
+        let data = [b'h', ZDLE, XON, b'i', 0x93, 0x7f, 0xff];
+        let mut buf = [0u8; 16];
+
+        let line = port();
+        send_subpacket(&line, &data, ZCRCW);
+        loop_back();
+        assert!(matches!(read_subpacket(&line, &mut buf, false), Ok((7, ZCRCW))));
+        assert_eq!(&buf[..7], &data);
+
+        let line = port();
+        send_subpacket(&line, &data, ZCRCG);
+        let mut out = [0u8; 32];
+        let count = take_written(&mut out);
+        out[0] ^= 1;
+        feed(&out[..count]);
+        assert!(matches!(read_subpacket(&line, &mut buf, false), Err(Error::Bad)));
+    }
+
+    #[test_case]
+    fn hex_header_matches_the_announcement() {
+        let port = port();
+        send_hex_header(&port, Header::new(ZRQINIT, [0; 4]));
+        let mut out = [0u8; 32];
+        let count = take_written(&mut out);
+        assert_eq!(&out[..count], &ANNOUNCE[3..]);
+    }
+
+    #[test_case]
+    fn headers_round_trip() {
+        // Position bytes that all need escaping in a binary header
+        let position = u32::from_le_bytes([ZDLE, XON, XOFF, DLE]) as usize;
+
+        let line = port();
+        send_binary_header(&line, Header::with_position(ZRPOS, position));
+        loop_back();
+        let header = read_header(&line, HEADER_TIMEOUT_MS).ok().unwrap();
+        assert_eq!(header.kind, ZRPOS);
+        assert_eq!(header.position(), position);
+        assert!(!header.crc32);
+
+        let line = port();
+        send_hex_header(&line, Header::with_position(ZACK, position));
+        loop_back();
+        let header = read_header(&line, HEADER_TIMEOUT_MS).ok().unwrap();
+        assert_eq!(header.kind, ZACK);
+        assert_eq!(header.position(), position);
+
+        // A damaged CRC is refused
+        let line = port();
+        send_binary_header(&line, Header::with_position(ZRPOS, 5));
+        let mut out = [0u8; 32];
+        let count = take_written(&mut out);
+        out[count - 1] ^= 1;
+        feed(&out[..count]);
+        assert!(matches!(read_header(&line, HEADER_TIMEOUT_MS), Err(Error::Bad)));
+    }
+}
@@ -0,0 +1,109 @@
+// Fixed-capacity byte ring buffer used for stream data (TCP windows, session output)
+
+pub struct RingBuffer<const N: usize> {
+    data: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn empty() -> Self {
+        RingBuffer {
+            data: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn free(&self) -> usize {
+        N - self.len
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Append as much of `bytes` as fits, returning the number of bytes stored
+    pub fn push(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(self.free());
+        for (i, &b) in bytes[..count].iter().enumerate() {
+            self.data[(self.head + self.len + i) % N] = b;
+        }
+        self.len += count;
+        count
+    }
+
+    /// Copy bytes starting `offset` bytes into the buffer without consuming them
+    pub fn peek_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let count = buf.len().min(self.len - offset);
+        for (i, slot) in buf[..count].iter_mut().enumerate() {
+            *slot = self.data[(self.head + offset + i) % N];
+        }
+        count
+    }
+
+    /// Drop up to `count` bytes from the front
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.head = (self.head + count) % N;
+        self.len -= count;
+    }
+
+    /// Move bytes from the front into `buf`
+    pub fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let count = self.peek_at(0, buf);
+        self.consume(count);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn push_stops_when_full() {
+        let mut ring: RingBuffer<4> = RingBuffer::empty();
+        assert_eq!(ring.push(b"abcdef"), 4);
+        assert_eq!(ring.free(), 0);
+        assert_eq!(ring.push(b"g"), 0);
+    }
+
+    #[test_case]
+    fn data_wraps_around() {
+        let mut ring: RingBuffer<4> = RingBuffer::empty();
+        let mut buf = [0u8; 4];
+        ring.push(b"abc");
+        assert_eq!(ring.pop(&mut buf[..2]), 2);
+        assert_eq!(ring.push(b"def"), 3);
+        assert_eq!(ring.pop(&mut buf), 4);
+        assert_eq!(&buf, b"cdef");
+        assert!(ring.is_empty());
+    }
+
+    #[test_case]
+    fn peek_does_not_consume() {
+        let mut ring: RingBuffer<8> = RingBuffer::empty();
+        let mut buf = [0u8; 8];
+        ring.push(b"hello");
+        assert_eq!(ring.peek_at(1, &mut buf[..3]), 3);
+        assert_eq!(&buf[..3], b"ell");
+        assert_eq!(ring.peek_at(5, &mut buf), 0);
+        ring.consume(4);
+        assert_eq!(ring.len(), 1);
+        assert_eq!(ring.pop(&mut buf), 1);
+        assert_eq!(buf[0], b'o');
+    }
+}